@@ -62,18 +62,15 @@ type ComponentThread = fn (LocalConfig, ThreadData, i32) -> ();
 
 fn move_bot(effector: &mut Effector, x: f64, y: f64)
 {
-	effector.set_float("display-location-x", x);
-	effector.set_float("display-location-y", y);
+	effector.display().location(x, y);
 	log_debug!(effector, "moved to {:.2}, {:.2}", x, y);
 }
 
 fn offset_bot(state: &SimState, id: ComponentID, effector: &mut Effector, dx: f64, dy: f64)
 {
-	let x = state.get_float(id, "display-location-x");
-	let y = state.get_float(id, "display-location-y");
+	let (x, y) = state.display_location(id);
 
-	effector.set_float("display-location-x", x + dx);
-	effector.set_float("display-location-y", y + dy);
+	effector.display().location(x + dx, y + dy);
 	log_debug!(effector, "moved to {:.1}, {:.1}", x + dx, y + dy);
 }
 
@@ -86,11 +83,10 @@ fn randomize_location(local: &LocalConfig, rng: &mut StdRng, effector: &mut Effe
 
 fn bot_dist_squared(local: &LocalConfig, state: &SimState, id1: ComponentID, id2: ComponentID, delta: &(f64, f64)) -> (f64, f64, f64)
 {
-	let x1 = state.get_float(id1, "display-location-x");
-	let y1 = state.get_float(id1, "display-location-y");
+	let (x1, y1) = state.display_location(id1);
 	
-	let x2 = state.get_float(id2, "display-location-x") + delta.0;
-	let y2 = state.get_float(id2, "display-location-y") + delta.1;
+	let (x2, y2) = state.display_location(id2);
+	let (x2, y2) = (x2 + delta.0, y2 + delta.1);
 	
 	let x2 = x2.max(0.0).min(local.width);
 	let y2 = y2.max(0.0).min(local.height);
@@ -104,7 +100,7 @@ fn bot_dist_squared(local: &LocalConfig, state: &SimState, id1: ComponentID, id2
 // do-nothing thread so that it stops responding to events and also adds a removed flag to the store).
 fn is_bot(state: &SimState, id: ComponentID) -> bool
 {
-	state.contains(id, "display-location-x") && state.get_int(id, "energy") > 0 && !state.was_removed(id)
+	state.contains(id, DISPLAY_LOCATION_X) && state.get_int(id, "energy") > 0 && !state.was_removed(id)
 }
 
 fn count_bots(state: &SimState) -> i64
@@ -373,8 +369,7 @@ fn bots_have_changed(locations: &mut HashMap<String, i64>, state: &SimState) ->
 	let mut moved = false;
 
 	for (id, _) in state.components.iter() {
-		let path = state.components.full_path(id);
-		let path = path + "energy";
+		let path = state.path(id).to_string() + "energy";
 		
 		if state.contains(id, "energy") {
 			let new_energy = state.get_int(id, "energy");
@@ -393,29 +388,19 @@ fn bots_have_changed(locations: &mut HashMap<String, i64>, state: &SimState) ->
 	moved
 }
 
-fn watchdog_thread(data: ThreadData)
+// The longest action bots take is movement so if none of the bots do anything
+// for a bit longer then that then we have reached a steady state and can stop
+// the sim.
+fn bots_are_idle() -> Box<FnMut (&SimState) -> bool + Send>
 {
-	thread::spawn(move || {
-		let mut locations = HashMap::new();
-
-		process_events!(data, event, state, effector,
-			"init 0" => {
-				let event = Event::new("timer");
-				effector.schedule_after_secs(event, data.id, 1.1*MOVE_DELAY);
-			},
-			"timer" => {
-				// The longest action bots take is movement so if none of the bots do anything
-				// for a bit longer then that then we have reached a steady state and can stop
-				// the sim.
-				if !bots_have_changed(&mut locations, &state) {
-					effector.exit();
-				} else {
-					let event = Event::new("timer");
-					effector.schedule_after_secs(event, data.id, 1.1*MOVE_DELAY);
-				}
-			}
-		);
-	});
+	let mut locations = HashMap::new();
+	let mut last_change = 0.0;
+	Box::new(move |state| {
+		if bots_have_changed(&mut locations, state) {
+			last_change = state.time;
+		}
+		state.time - last_change > 1.1*MOVE_DELAY
+	})
 }
 
 fn world_thread(local: LocalConfig, data: ThreadData)
@@ -483,8 +468,7 @@ fn create_sim(local: LocalConfig, config: Config) -> Simulation
 		let (_, bot_data) = sim.add_active_component(&name, world_id);
 		thread(local.clone(), bot_data, i);
 	}
-	let (_, watch_data) = sim.add_active_component("watch-dog", world_id);
-	watchdog_thread(watch_data);
+	sim.stop_when(StopCondition::Predicate(bots_are_idle()));
 		
 	sim
 }
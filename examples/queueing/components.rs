@@ -0,0 +1,218 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Building blocks for queueing networks. Customers flow from a [`Source`] (or a
+//! [`Population`] for closed networks) through [`Station`]s and end up at a [`Sink`].
+//! Everything that moves a customer along uses an "enqueue" event so any of these can
+//! feed a score::models::Queue.
+use rand::{SeedableRng, StdRng};
+use rand::distributions::{Exp, IndependentSample};
+use score::*;
+use score::models::*;
+use std::thread;
+
+/// The payload that moves through the network.
+#[derive(Clone)]
+pub struct Customer
+{
+	/// Seconds at which the customer entered the network.
+	pub created: f64,
+}
+
+/// Sends "enqueue" events with Poisson arrivals (i.e. exponential inter-arrival times).
+pub struct Source
+{
+	data: ThreadData,
+	pub output: OutPort<Customer>,
+	rate: f64,
+	limit: usize,
+}
+
+impl Source
+{
+	/// Rate is the mean number of arrivals per second. Limit is the number of customers
+	/// to create.
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID, rate: f64, limit: usize) -> Source
+	{
+		let (id, data) = sim.add_active_component("source", parent_id);
+		let source = Source{data, output: OutPort::new(), rate, limit};
+		sim.register_out_port(id, "output", &source.output);
+		source
+	}
+
+	pub fn start(self)
+	{
+		let mut rng = StdRng::from_seed(&[self.data.seed]);
+		let exp = Exp::new(self.rate);
+		let mut count = 0;
+		thread::spawn(move || {
+			process_events!(self.data, event, state, effector,
+				"init 0" => {
+					effector.schedule_after_secs(Event::new("arrival"), self.data.id, exp.ind_sample(&mut rng));
+				},
+				"arrival" => {
+					self.output.send_payload(&mut effector, "enqueue", Customer{created: state.time});
+					effector.counter("arrivals").inc(1);
+					count += 1;
+					if count < self.limit {
+						effector.schedule_after_secs(Event::new("arrival"), self.data.id, exp.ind_sample(&mut rng));
+					}
+				}
+			);
+		});
+	}
+}
+
+/// Sends a fixed number of customers into a closed network when the simulation starts.
+pub struct Population
+{
+	data: ThreadData,
+	pub output: OutPort<Customer>,
+	size: usize,
+}
+
+impl Population
+{
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID, size: usize) -> Population
+	{
+		let (id, data) = sim.add_active_component("population", parent_id);
+		let population = Population{data, output: OutPort::new(), size};
+		sim.register_out_port(id, "output", &population.output);
+		population
+	}
+
+	pub fn start(self)
+	{
+		thread::spawn(move || {
+			process_events!(self.data, event, state, effector,
+				"init 0" => {
+					for _ in 0..self.size {
+						self.output.send_payload(&mut effector, "enqueue", Customer{created: state.time});
+					}
+				}
+			);
+		});
+	}
+}
+
+/// A single exponential server that pulls customers from a score::models::Queue.
+struct Server
+{
+	data: ThreadData,
+	input: InPort<Customer>,
+	ready: OutPort<()>,
+	output: OutPort<Customer>,
+	rate: f64,
+}
+
+impl Server
+{
+	fn new(sim: &mut Simulation, parent_id: ComponentID, rate: f64) -> Server
+	{
+		let (id, data) = sim.add_active_component("server", parent_id);
+		let server = Server{data, input: InPort::new(id), ready: OutPort::new(), output: OutPort::new(), rate};
+		sim.register_in_port(id, "input", &server.input);
+		sim.register_out_port(id, "ready", &server.ready);
+		sim.register_out_port(id, "output", &server.output);
+		server
+	}
+
+	fn start(self)
+	{
+		let mut rng = StdRng::from_seed(&[self.data.seed]);
+		let exp = Exp::new(self.rate);
+		thread::spawn(move || {
+			process_events!(self.data, event, state, effector,
+				"init 0" => {
+				},
+				"dequeued" => {
+					let customer = event.take_payload::<Customer>();
+					let event = Event::with_payload("done", customer);
+					effector.schedule_after_secs(event, self.data.id, exp.ind_sample(&mut rng));
+				},
+				"done" => {
+					let customer = event.take_payload::<Customer>();
+					self.output.send_payload(&mut effector, "enqueue", customer);
+					self.ready.send(&mut effector, "ready");
+					effector.counter("completed").inc(1);
+				}
+			);
+		});
+	}
+}
+
+/// An unbounded FIFO queue feeding an exponential server, i.e. the ./M/1 part of M/M/1.
+pub struct Station
+{
+	pub input: InPort<Customer>,
+	pub output: OutPort<Customer>,
+	queue: Queue<Customer>,
+	server: Server,
+}
+
+impl Station
+{
+	/// Rate is the mean number of customers served per second.
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID, name: &str, rate: f64) -> Station
+	{
+		let id = sim.add_component(name, parent_id);
+		let queue = Queue::new(sim, id, "queue", 1_000_000, DropPolicy::TailDrop);
+		let server = Server::new(sim, id, rate);
+		Station{input: queue.input.clone(), output: OutPort::new(), queue, server}
+	}
+
+	pub fn start(mut self)
+	{
+		self.queue.output.connect_to(&self.server.input);
+		self.server.ready.connect_to(&self.queue.ready);
+		self.server.output = self.output;
+
+		self.queue.start();
+		self.server.start();
+	}
+}
+
+/// Records how long customers were in the network using a histogram named "sojourn".
+pub struct Sink
+{
+	data: ThreadData,
+	pub input: InPort<Customer>,
+}
+
+impl Sink
+{
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID) -> Sink
+	{
+		let (id, data) = sim.add_active_component("sink", parent_id);
+		let sink = Sink{data, input: InPort::new(id)};
+		sim.register_in_port(id, "input", &sink.input);
+		sink
+	}
+
+	pub fn start(self)
+	{
+		thread::spawn(move || {
+			process_events!(self.data, event, state, effector,
+				"init 0" => {
+				},
+				"enqueue" => {
+					let customer = event.payload_ref::<Customer>("should be a Customer");
+					effector.histogram("sojourn").record(state.time - customer.created);
+				}
+			);
+		});
+	}
+}
@@ -0,0 +1,263 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Classic queueing networks built from score::models::Queue: an M/M/1 queue, tandem
+//! M/M/1 queues, and a closed network of two stations. These have well known analytic
+//! results so the tests (run with `cargo test --example queueing`) check that the
+//! simulated results match. The components in components.rs are a reasonable starting
+//! point for larger networks.
+#[macro_use]
+extern crate clap;
+extern crate rand;
+extern crate score;
+
+mod components;
+
+use clap::{App, ArgMatches};
+use components::*;
+use score::*;
+use std::process;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Model
+{
+	MM1,
+	Tandem,
+	Closed,
+}
+
+#[derive(Clone)]
+struct LocalConfig
+{
+	model: Model,
+
+	// Arrivals per second (for the open networks).
+	arrival_rate: f64,
+
+	// Customers served per second at each station.
+	service_rates: Vec<f64>,
+
+	// Number of customers that pass through an open network.
+	customers: usize,
+
+	// Number of customers within the closed network.
+	population: usize,
+}
+
+impl LocalConfig
+{
+	fn new(model: Model) -> LocalConfig
+	{
+		LocalConfig {
+			model,
+			arrival_rate: 0.5,
+			service_rates: if model == Model::Tandem {vec![1.0, 0.8]} else if model == Model::Closed {vec![1.0, 0.5]} else {vec![1.0]},
+			customers: 20_000,
+			population: 5,
+		}
+	}
+}
+
+// Mean time a customer spends in an open network of M/M/1 stations (by Jackson's theorem
+// each station behaves like an independent M/M/1 queue).
+fn open_sojourn(arrival_rate: f64, service_rates: &[f64]) -> f64
+{
+	service_rates.iter().map(|mu| 1.0/(mu - arrival_rate)).sum()
+}
+
+// Throughput of a closed network of single server stations using Mean Value Analysis.
+fn closed_throughput(population: usize, service_rates: &[f64]) -> f64
+{
+	let mut queue_lengths = vec![0.0; service_rates.len()];
+	let mut throughput = 0.0;
+	for n in 1..population + 1 {
+		let residence: Vec<f64> = service_rates.iter().zip(queue_lengths.iter()).map(|(mu, q)| (1.0 + q)/mu).collect();
+		throughput = (n as f64)/residence.iter().sum::<f64>();
+		queue_lengths = residence.iter().map(|r| throughput*r).collect();
+	}
+	throughput
+}
+
+fn create_sim(local: &LocalConfig, config: Config) -> Simulation
+{
+	let mut sim = Simulation::new(config);
+	let world = sim.add_component("world", NO_COMPONENT);
+
+	let mut stations: Vec<Station> = local.service_rates.iter().enumerate()
+		.map(|(i, &rate)| Station::new(&mut sim, world, &format!("station{}", i + 1), rate))
+		.collect();
+	for i in 1..stations.len() {
+		let input = stations[i].input.clone();
+		stations[i - 1].output.connect_to(&input);
+	}
+
+	if local.model == Model::Closed {
+		let mut population = Population::new(&mut sim, world, local.population);
+		population.output.connect_to(&stations[0].input);
+		let input = stations[0].input.clone();
+		stations.last_mut().unwrap().output.connect_to(&input);
+		population.start();
+	} else {
+		let mut source = Source::new(&mut sim, world, local.arrival_rate, local.customers);
+		let sink = Sink::new(&mut sim, world);
+		source.output.connect_to(&stations[0].input);
+		stations.last_mut().unwrap().output.connect_to(&sink.input);
+		source.start();
+		sink.start();
+	}
+
+	for station in stations.drain(..) {
+		station.start();
+	}
+	sim
+}
+
+// Returns the simulated and analytic results.
+fn run(local: &LocalConfig, mut config: Config) -> (f64, f64)
+{
+	if local.model == Model::Closed && config.max_secs.is_infinite() {
+		config.max_secs = 20_000.0;
+	}
+	let max_secs = config.max_secs;
+	let mut sim = create_sim(local, config);
+	sim.run();
+
+	if local.model == Model::Closed {
+		let completed = sim.store.get_int("world.station1.server.completed") as f64;
+		(completed/max_secs, closed_throughput(local.population, &local.service_rates))
+	} else {
+		let sojourn = sim.store.get_float("world.sink.sojourn.mean");
+		(sojourn, open_sojourn(local.arrival_rate, &local.service_rates))
+	}
+}
+
+fn fatal_err(message: &str) -> !
+{
+	eprintln!("{}", message);
+	process::exit(1);
+}
+
+fn match_num<T>(matches: &ArgMatches, name: &str) -> T
+		where T: std::str::FromStr
+{
+	match value_t!(matches.value_of(name), T) {
+		Ok(value) => value,
+		_ => fatal_err(&format!("--{} should be a number", name)),
+	}
+}
+
+fn parse_options() -> (LocalConfig, Config)
+{
+	let usage = "--model=[MODEL] 'mm1, tandem, or closed [mm1]'
+		--arrival-rate=[RATE] 'Arrivals per second for mm1 and tandem [0.5]'
+		--customers=[N] 'Number of customers for mm1 and tandem [20000]'
+		--population=[N] 'Number of customers for closed [5]'
+		--log-level=[LEVEL] 'Default log level [info]'
+		--max-time=[TIME] 'Maximum time to run the simulation [20000s for closed]'
+		--seed=[N] 'Random number generator seed [1]'";
+
+	let matches = App::new("queueing")
+		.version("1.0")
+		.author("Jesse Jones <jesse9jones@gmail.com>")
+		.about("Simulates classic queueing networks and compares them with analytic results.")
+		.args_from_usage(usage)
+	.get_matches();
+
+	let model = match matches.value_of("model").unwrap_or("mm1") {
+		"mm1" => Model::MM1,
+		"tandem" => Model::Tandem,
+		"closed" => Model::Closed,
+		_ => fatal_err("--model should be mm1, tandem, or closed"),
+	};
+	let mut local = LocalConfig::new(model);
+	let mut config = Config::with_seed(1);
+
+	if matches.is_present("arrival-rate") {
+		local.arrival_rate = match_num(&matches, "arrival-rate");
+		if local.service_rates.iter().any(|&mu| local.arrival_rate >= mu) {
+			fatal_err("--arrival-rate should be less than the service rates (or the queues will grow without bound)");
+		}
+	}
+	if matches.is_present("customers") {
+		local.customers = match_num(&matches, "customers");
+	}
+	if matches.is_present("population") {
+		local.population = match_num(&matches, "population");
+	}
+	if matches.is_present("seed") {
+		config.seed = match_num(&matches, "seed");
+	}
+	if let Some(level) = matches.value_of("log-level") {
+		if let Some(e) = config.parse_log_level(level) {
+			fatal_err(e);
+		}
+	}
+	if let Some(max_secs) = matches.value_of("max-time") {
+		if let Some(e) = config.parse_max_secs(max_secs) {
+			fatal_err(e);
+		}
+	}
+
+	(local, config)
+}
+
+fn main()
+{
+	let (local, config) = parse_options();
+	let (simulated, analytic) = run(&local, config);
+	let name = if local.model == Model::Closed {"throughput"} else {"mean sojourn time"};
+	println!("{}: simulated {:.3}, analytic {:.3}", name, simulated, analytic);
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn check(model: Model)
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let (simulated, analytic) = run(&LocalConfig::new(model), config);
+		let error = (simulated - analytic).abs()/analytic;
+		assert!(error < 0.1, "{:?}: simulated {:.3}, analytic {:.3}", model, simulated, analytic);
+	}
+
+	#[test]
+	fn analytic_results()
+	{
+		assert!((open_sojourn(0.5, &[1.0]) - 2.0).abs() < 1.0e-9);
+		assert!((closed_throughput(1, &[1.0, 0.5]) - 1.0/3.0).abs() < 1.0e-9);
+	}
+
+	#[test]
+	fn mm1()
+	{
+		check(Model::MM1);
+	}
+
+	#[test]
+	fn tandem()
+	{
+		check(Model::Tandem);
+	}
+
+	#[test]
+	fn closed()
+	{
+		check(Model::Closed);
+	}
+}
@@ -110,10 +110,7 @@ impl SenderDevice
 		// Set some state for the device. We could use a thread to do this but it's simpler
 		// to just use an Effector.
 		let mut effector = Effector::new();
-		effector.set_string("display-name", "sender-0");
-		effector.set_string("display-color", "blue");
-		effector.set_float("display-location-x", START_X);
-		effector.set_float("display-location-y", START_Y);
+		effector.display().name("sender-0").color("blue").location(START_X, START_Y);
 		sim.apply(self.id, effector);
 	}
 }
@@ -145,7 +142,7 @@ impl RepeaterDevice
 			inbound: InPort::new(id),
 			outbound: OutPort::new(),
 		};
-		device.inbound = device.mangler.input.clone();
+		device.inbound = sim.export_in_port(id, "inbound", &device.mangler.input);
 		device
 	}
 	
@@ -169,9 +166,7 @@ impl RepeaterDevice
 		
 		// Set our state.
 		let mut effector = Effector::new();
-		effector.set_string("display-name", &format!("repeat-{}", self.index));
-		effector.set_float("display-location-x", START_X);
-		effector.set_float("display-location-y", START_Y + DY*(self.index + 1) as f64);
+		effector.display().name(&format!("repeat-{}", self.index)).location(START_X, START_Y + DY*(self.index + 1) as f64);
 		sim.apply(self.id, effector);
 	}
 }
@@ -196,7 +191,7 @@ impl ReceiverDevice
 			mangler: ManglerComponent::new(sim, id, error_rate),
 			inbound: InPort::empty(),
 		};
-		device.inbound = device.mangler.input.clone();
+		device.inbound = sim.export_in_port(id, "inbound", &device.mangler.input);
 		device
 	}
 	
@@ -208,10 +203,7 @@ impl ReceiverDevice
 		self.mangler.start();
 		
 		let mut effector = Effector::new();
-		effector.set_string("display-name", "receiver-0");
-		effector.set_string("display-color", "green");
-		effector.set_float("display-location-x", START_X);
-		effector.set_float("display-location-y", START_Y + DY*(num_repeaters + 1) as f64);
+		effector.display().name("receiver-0").color("green").location(START_X, START_Y + DY*(num_repeaters + 1) as f64);
 		sim.apply(self.id, effector);
 	}
 }
@@ -367,7 +359,7 @@ impl StatsComponent
 		let (id, data) = sim.add_active_component("stats", parent_id);
 		StatsComponent {
 			data: data,
-			err_percent: FloatValue{},
+			err_percent: FloatValue::new(id, "err_percent"),
 
 			upper_in: InPort::new(id),
 			upper_out: OutPort::new(),
@@ -507,9 +499,7 @@ fn create_sim(local: LocalConfig, config: Config) -> Simulation
 		
 	// This is used by GUIs, e.g. sdebug.
 	let mut effector = Effector::new();
-	effector.set_float("display-size-x", DISPLAY_WIDTH);
-	effector.set_float("display-size-y", DISPLAY_HEIGHT);
-	effector.set_string("display-title", "telephone");
+	effector.display().size(DISPLAY_WIDTH, DISPLAY_HEIGHT).title("telephone");
 	sim.apply(world_id, effector);
 
 	// and spin up their threads.
@@ -0,0 +1,450 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! `SimulationBuilder` is a declarative alternative to calling add_component,
+//! connect_to, and then starting threads by hand: the description is validated
+//! before anything is created and the steps are always done in the right order.
+use component::*;
+use config::*;
+use effector::*;
+use event::*;
+use ports::*;
+use sim_time::*;
+use sim_state::*;
+use simulation::*;
+use store::*;
+use thread_data::*;
+use std::any::{self, Any};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// The ports connected to an active component, see [`SimulationBuilder`]'s connect method.
+/// Start functions should take all of their ports (building fails otherwise).
+pub struct Ports
+{
+	path: String,
+	inputs: HashMap<String, Box<Any + Send>>,
+	outputs: HashMap<String, Box<Any + Send>>,
+}
+
+impl Ports
+{
+	/// Panics if there is no such port or it has a different type.
+	pub fn input<T: Any + Send>(&mut self, name: &str) -> InPort<T>
+	{
+		match self.inputs.remove(name).map(|port| port.downcast::<InPort<T>>()) {
+			Some(Ok(port)) => *port,
+			Some(Err(_)) => panic!("{} input port '{}' isn't an InPort<{}>", self.path, name, any::type_name::<T>()),
+			None => panic!("{} has no input port '{}'", self.path, name),
+		}
+	}
+
+	/// Panics if there is no such port or it has a different type.
+	pub fn output<T: Any + Send>(&mut self, name: &str) -> OutPort<T>
+	{
+		match self.outputs.remove(name).map(|port| port.downcast::<OutPort<T>>()) {
+			Some(Ok(port)) => *port,
+			Some(Err(_)) => panic!("{} output port '{}' isn't an OutPort<{}>", self.path, name, any::type_name::<T>()),
+			None => panic!("{} has no output port '{}'", self.path, name),
+		}
+	}
+
+	fn untaken(&self) -> Vec<String>
+	{
+		let mut names: Vec<String> = self.inputs.keys().chain(self.outputs.keys()).cloned().collect();
+		names.sort();
+		names
+	}
+}
+
+/// Declarative setup for a [`Simulation`]. Components are named using their full
+/// paths and ports using the component path followed by the port name.
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+/// use std::thread;
+///
+/// let sim = SimulationBuilder::new(Config::new())
+/// 	.component("world")
+/// 	.active("world.sender", |data, ports| {
+/// 		let output: OutPort<String> = ports.output("output");
+/// 		thread::spawn(move || {
+/// 			process_events!(data, event, state, effector,
+/// 				"init 0" => {
+/// 					output.send_payload(&mut effector, "text", "hello".to_string());
+/// 				}
+/// 			);
+/// 		});
+/// 	})
+/// 	.active("world.receiver", |data, ports| {
+/// 		let input: InPort<String> = ports.input("input");
+/// 		thread::spawn(move || {
+/// 			process_events!(data, event, state, effector,
+/// 				"init 0" => {},
+/// 				"text" => {
+/// 					let text = input.take(&mut event, &state);
+/// 					effector.set_string("text", &text);
+/// 				}
+/// 			);
+/// 		});
+/// 	})
+/// 	.connect::<String>("world.sender.output", "world.receiver.input")
+/// 	.init_value("world.receiver.text", StoreValue::String("".to_string()))
+/// 	.build();
+/// assert!(sim.is_ok());
+/// ```
+pub struct SimulationBuilder
+{
+	config: Config,
+	components: Vec<(String, Kind)>,
+	connections: Vec<Connection>,
+	values: Vec<(String, StoreValue)>,
+}
+
+impl SimulationBuilder
+{
+	pub fn new(config: Config) -> SimulationBuilder
+	{
+		SimulationBuilder{config, components: Vec::new(), connections: Vec::new(), values: Vec::new()}
+	}
+
+	/// Adds a component that doesn't receive events, e.g. "world" or "world.devices".
+	pub fn component(mut self, path: &str) -> SimulationBuilder
+	{
+		self.components.push((path.to_string(), Kind::Passive));
+		self
+	}
+
+	/// Adds a component with a thread. Start is called after all the components have
+	/// been added and connected and should take the component's ports and spin up
+	/// the thread.
+	pub fn active<F>(mut self, path: &str, start: F) -> SimulationBuilder
+		where F: FnOnce (ThreadData, &mut Ports) + 'static
+	{
+		self.components.push((path.to_string(), Kind::Thread(Box::new(start))));
+		self
+	}
+
+	/// Adds a component whose events are processed on the simulation thread, see
+	/// [`Simulation`]'s add_handler_component. Start is called like it is for active
+	/// and returns the handler.
+	pub fn handler<F, H>(mut self, path: &str, start: F) -> SimulationBuilder
+		where F: FnOnce (&mut Ports) -> H + 'static, H: FnMut (Event, &SimState, &mut Effector) -> () + Send + 'static
+	{
+		self.components.push((path.to_string(), Kind::Handler(Box::new(|ports: &mut Ports| {
+			let handler: Box<FnMut (Event, &SimState, &mut Effector) -> () + Send> = Box::new(start(ports));
+			handler
+		}))));
+		self
+	}
+
+	/// Connects an output port to an input port, e.g. "world.sender.output" to
+	/// "world.receiver.input". Input ports may have more than one connection.
+	pub fn connect<T: Any + Send>(mut self, from: &str, to: &str) -> SimulationBuilder
+	{
+		self.connections.push(Connection{from: from.to_string(), to: to.to_string(), new_input: new_input::<T>, new_output: new_output::<T>});
+		self
+	}
+
+	/// Sets a store value at time zero. The key's prefix should be the path of a
+	/// component, e.g. "world.receiver.count".
+	pub fn init_value(mut self, key: &str, value: StoreValue) -> SimulationBuilder
+	{
+		self.values.push((key.to_string(), value));
+		self
+	}
+
+	/// Returns an error if the description is invalid, e.g. a parent is missing, a
+	/// port is connected with different types, or a thread didn't take all of its ports.
+	pub fn build(self) -> Result<Simulation, String>
+	{
+		self.validate()?;
+		let mut sim = Simulation::new(self.config);
+
+		// Components can be declared in any order so parents are added first.
+		let mut ids = HashMap::new();
+		let mut pending = self.components;
+		let mut starts = Vec::new();
+		while !pending.is_empty() {
+			let count = pending.len();
+			for (path, kind) in pending.split_off(0) {
+				let parent = parent_path(&path);
+				if parent.is_empty() || ids.contains_key(parent) {
+					let parent_id = if parent.is_empty() {NO_COMPONENT} else {ids[parent]};
+					let name = &path[path.rfind('.').map_or(0, |i| i + 1)..];
+					let id = match kind {
+						Kind::Passive => sim.add_component(name, parent_id),
+						kind => {
+							let (id, data) = sim.add_active_component(name, parent_id);
+							starts.push((id, data, kind));
+							id
+						},
+					};
+					ids.insert(path.clone(), id);
+				} else {
+					pending.push((path, kind));
+				}
+			}
+			if pending.len() == count {
+				return Err(format!("'{}' has no parent", pending[0].0));
+			}
+		}
+
+		let mut ports: HashMap<ComponentID, Ports> = HashMap::new();
+		for c in self.connections.iter() {
+			let (to_path, to_port) = split_port(&c.to);
+			let to_id = ids[to_path];
+			let input = ports.entry(to_id).or_insert_with(|| new_ports(to_path)).inputs
+				.entry(to_port.to_string()).or_insert_with(|| (c.new_input)(to_id, to_port));
+			let output = (c.new_output)(&**input).ok_or_else(|| format!("{} is connected to {} using a different type", c.from, c.to))?;
+
+			let (from_path, from_port) = split_port(&c.from);
+			let outputs = &mut ports.entry(ids[from_path]).or_insert_with(|| new_ports(from_path)).outputs;
+			if outputs.insert(from_port.to_string(), output).is_some() {
+				return Err(format!("{} is connected more than once", c.from));
+			}
+		}
+
+		for (key, value) in self.values {
+			let store = Arc::make_mut(&mut sim.store);
+			match value {
+				StoreValue::Int(value) => store.set_int(&key, value, Time(0)),
+				StoreValue::Float(value) => store.set_float(&key, value, Time(0)),
+				StoreValue::String(value) => store.set_string(&key, &value, Time(0)),
+				StoreValue::Bool(value) => store.set_bool(&key, value, Time(0)),
+				StoreValue::IntList(value) => store.set_vec_int(&key, &value, Time(0)),
+				StoreValue::FloatList(value) => store.set_vec_float(&key, &value, Time(0)),
+				StoreValue::StringList(value) => store.set_vec_string(&key, &value, Time(0)),
+			}
+		}
+
+		for (id, data, kind) in starts {
+			let mut ports = ports.remove(&id).unwrap_or_else(|| new_ports(sim.components.path(id)));
+			match kind {
+				Kind::Thread(start) => start(data, &mut ports),
+				Kind::Handler(start) => sim.set_handler(id, start(&mut ports)),
+				Kind::Passive => unreachable!(),
+			}
+			let untaken = ports.untaken();
+			if !untaken.is_empty() {
+				return Err(format!("{} didn't take ports {}", ports.path, untaken.join(", ")));
+			}
+		}
+		Ok(sim)
+	}
+
+	fn validate(&self) -> Result<(), String>
+	{
+		let mut paths = HashSet::new();
+		for &(ref path, _) in self.components.iter() {
+			if path.is_empty() || path.split('.').any(|name| name.is_empty()) {
+				return Err(format!("'{}' isn't a valid component path", path));
+			}
+			if !paths.insert(path.as_str()) {
+				return Err(format!("'{}' was added more than once", path));
+			}
+		}
+		let roots = paths.iter().filter(|p| !p.contains('.')).count();
+		if roots > 1 {
+			return Err("there can only be one root component".to_string());
+		}
+
+		let active: HashSet<&str> = self.components.iter().filter(|c| !c.1.is_passive()).map(|c| c.0.as_str()).collect();
+		for c in self.connections.iter() {
+			for port in [&c.from, &c.to].iter() {
+				let (path, name) = split_port(port);
+				if name.is_empty() || !active.contains(path) {
+					return Err(format!("'{}' isn't a port on an active component", port));
+				}
+			}
+		}
+
+		let mut keys = HashSet::new();
+		for &(ref key, _) in self.values.iter() {
+			if !paths.contains(parent_path(key)) {
+				return Err(format!("'{}' doesn't start with a component path", key));
+			}
+			if !keys.insert(key.as_str()) {
+				return Err(format!("'{}' was initialized more than once", key));
+			}
+		}
+		Ok(())
+	}
+}
+
+enum Kind
+{
+	Passive,
+	Thread(Box<FnOnce (ThreadData, &mut Ports)>),
+	Handler(Box<FnOnce (&mut Ports) -> Box<FnMut (Event, &SimState, &mut Effector) -> () + Send>>),
+}
+
+impl Kind
+{
+	fn is_passive(&self) -> bool
+	{
+		match *self {
+			Kind::Passive => true,
+			_ => false,
+		}
+	}
+}
+
+struct Connection
+{
+	from: String,
+	to: String,
+	new_input: fn (ComponentID, &str) -> Box<Any + Send>,
+	new_output: fn (&(Any + Send)) -> Option<Box<Any + Send>>,	// None if the input has a different type
+}
+
+fn new_input<T: Any + Send>(id: ComponentID, port: &str) -> Box<Any + Send>
+{
+	Box::new(InPort::<T>::with_port_name(id, port))
+}
+
+fn new_output<T: Any + Send>(input: &(Any + Send)) -> Option<Box<Any + Send>>
+{
+	input.downcast_ref::<InPort<T>>().map(|input| {
+		let mut output = OutPort::<T>::new();
+		output.connect_to(input);
+		let output: Box<Any + Send> = Box::new(output);
+		output
+	})
+}
+
+fn new_ports(path: &str) -> Ports
+{
+	Ports{path: path.to_string(), inputs: HashMap::new(), outputs: HashMap::new()}
+}
+
+// Returns an empty string for the root.
+fn parent_path(path: &str) -> &str
+{
+	path.rfind('.').map_or("", |i| &path[..i])
+}
+
+// "world.sender.output" => ("world.sender", "output")
+fn split_port(port: &str) -> (&str, &str)
+{
+	match port.rfind('.') {
+		Some(i) => (&port[..i], &port[i + 1..]),
+		None => ("", port),
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::thread;
+
+	fn start_sender(data: ThreadData, ports: &mut Ports)
+	{
+		let output: OutPort<i64> = ports.output("output");
+		thread::spawn(move || {
+			for (_, _) in data.rx.iter() {
+				let mut effector = ::effector::Effector::new();
+				output.send_payload(&mut effector, "count", 3);
+				drop(data.tx.send(effector));
+			}
+		});
+	}
+
+	fn start_receiver(data: ThreadData, ports: &mut Ports)
+	{
+		let _input: InPort<i64> = ports.input("input");
+		thread::spawn(move || {
+			for (_, _) in data.rx.iter() {
+				drop(data.tx.send(::effector::Effector::new()));
+			}
+		});
+	}
+
+	#[test]
+	fn builds_in_order()
+	{
+		let sim = SimulationBuilder::new(Config::new())
+			.active("world.devices.receiver", start_receiver)
+			.active("world.sender", start_sender)
+			.component("world.devices")
+			.component("world")
+			.connect::<i64>("world.sender.output", "world.devices.receiver.input")
+			.init_value("world.devices.count", StoreValue::Int(2))
+			.build()
+			.unwrap();
+		assert_eq!(sim.components.path(ComponentID(0)), "world");
+		assert_eq!(sim.store.get_int("world.devices.count"), 2);
+	}
+
+	#[test]
+	fn builds_handlers()
+	{
+		let mut sim = SimulationBuilder::new(Config::new())
+			.component("world")
+			.handler("world.sender", |ports| {
+				let output: OutPort<i64> = ports.output("output");
+				move |event: Event, _: &SimState, effector: &mut Effector| {
+					if event.name == "init 0" {
+						output.send_payload(effector, "count", 3);
+					}
+				}
+			})
+			.handler("world.receiver", |ports| {
+				let input: InPort<i64> = ports.input("input");
+				move |mut event: Event, state: &SimState, effector: &mut Effector| {
+					if event.name == "count" {
+						let count = input.take(&mut event, state);
+						effector.set_int("count", count);
+					}
+				}
+			})
+			.connect::<i64>("world.sender.output", "world.receiver.input")
+			.build()
+			.unwrap();
+		sim.run();
+		assert_eq!(sim.store.get_int("world.receiver.count"), 3);
+	}
+
+	#[test]
+	fn validates()
+	{
+		let err = SimulationBuilder::new(Config::new()).component("world").component("other.child").build().err();
+		assert_eq!(err, Some("'other.child' has no parent".to_string()));
+
+		let err = SimulationBuilder::new(Config::new()).component("world").init_value("bogus.count", StoreValue::Int(1)).build().err();
+		assert_eq!(err, Some("'bogus.count' doesn't start with a component path".to_string()));
+
+		let err = SimulationBuilder::new(Config::new())
+			.component("world")
+			.active("world.sender", start_sender)
+			.active("world.receiver", start_receiver)
+			.connect::<i64>("world.sender.output", "world.receiver.input")
+			.connect::<String>("world.sender.output", "world.receiver.input")
+			.build().err();
+		assert_eq!(err, Some("world.sender.output is connected to world.receiver.input using a different type".to_string()));
+
+		let err = SimulationBuilder::new(Config::new())
+			.component("world")
+			.active("world.sender", |_, _| ())
+			.active("world.receiver", start_receiver)
+			.connect::<i64>("world.sender.output", "world.receiver.input")
+			.build().err();
+		assert_eq!(err, Some("world.sender didn't take ports output".to_string()));
+	}
+}
@@ -0,0 +1,121 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Normally the `Simulation` advances time as fast as it can. An external clock can be
+//! used (via the `Simulation`'s set_clock method) to make score follow a master clock,
+//! e.g. for hardware in the loop rigs or lockstep co-simulation.
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+
+/// Returned by [`Clock`]'s request method.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClockGrant
+{
+	/// The simulation may advance up to (and including) this time (in seconds).
+	Until(f64),
+
+	/// The simulation should exit.
+	Stop,
+	
+	/// The clock failed, e.g. because the connection to the master clock was lost. The
+	/// simulation logs the error and exits.
+	Error(String),
+}
+
+/// Before the `Simulation` processes events past the last granted time it calls
+/// request with the time of the next event. Implementations will typically block
+/// until the master clock allows time to advance. Note that a grant earlier than the
+/// requested time is fine: the simulation time will advance to the granted time and
+/// request will be called again.
+pub trait Clock: Send
+{
+	fn request(&mut self, secs: f64) -> ClockGrant;
+}
+
+impl<F> Clock for F where F: FnMut (f64) -> ClockGrant + Send
+{
+	fn request(&mut self, secs: f64) -> ClockGrant
+	{
+		self(secs)
+	}
+}
+
+/// Clock that is driven by a master over TCP using a simple line based protocol:
+/// score sends "request SECS" and the master responds with "grant SECS" or "stop".
+pub struct SocketClock
+{
+	reader: BufReader<TcpStream>,
+	writer: TcpStream,
+}
+
+impl SocketClock
+{
+	/// Address is something like "127.0.0.1:9100".
+	pub fn connect(address: &str) -> Result<SocketClock, String>
+	{
+		let stream = TcpStream::connect(address).map_err(|err| format!("Couldn't connect to {}: {}", address, err))?;
+		let writer = stream.try_clone().map_err(|err| format!("Couldn't clone the socket for {}: {}", address, err))?;
+		Ok(SocketClock{reader: BufReader::new(stream), writer})
+	}
+}
+
+impl Clock for SocketClock
+{
+	fn request(&mut self, secs: f64) -> ClockGrant
+	{
+		if let Err(err) = write!(self.writer, "request {}\n", secs) {
+			return ClockGrant::Error(format!("clock request failed: {}", err));
+		}
+
+		let mut line = String::new();
+		match self.reader.read_line(&mut line) {
+			Ok(0) => ClockGrant::Stop,
+			Ok(_) => match parse_reply(&line) {
+				Ok(grant) => grant,
+				Err(err) => ClockGrant::Error(err),
+			},
+			Err(err) => ClockGrant::Error(format!("clock reply failed: {}", err)),
+		}
+	}
+}
+
+fn parse_reply(line: &str) -> Result<ClockGrant, String>
+{
+	let parts: Vec<&str> = line.split_whitespace().collect();
+	if parts.len() == 1 && parts[0] == "stop" {
+		Ok(ClockGrant::Stop)
+	} else if parts.len() == 2 && parts[0] == "grant" {
+		f64::from_str(parts[1]).map(ClockGrant::Until).map_err(|_| format!("bad grant time from clock: {}", line.trim()))
+	} else {
+		Err(format!("expected 'grant SECS' or 'stop' from clock but got: {}", line.trim()))
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn parses_replies()
+	{
+		assert_eq!(parse_reply("grant 1.5\n"), Ok(ClockGrant::Until(1.5)));
+		assert_eq!(parse_reply("stop\n"), Ok(ClockGrant::Stop));
+		assert!(parse_reply("grant\n").is_err());
+		assert!(parse_reply("grant soon\n").is_err());
+	}
+}
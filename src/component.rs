@@ -25,6 +25,7 @@ use std::fmt;
 ///
 /// Typically type safe structs are defined for components with the aid of [`OutPort`],
 /// [`InPort`], [`IntValue`], etc.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Component
 {
 	/// The name of the component. Note that, in general, these are not unique.
@@ -39,6 +40,7 @@ pub struct Component
 /// To make lifetime management easier components are referenced using a small
 /// integer instead of a rust reference.
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct ComponentID(pub usize);
 
 /// The id of the root component.
@@ -14,13 +14,35 @@
 // along with this program; if not, write to the Free Software Foundation,
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
 use component::*;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// Contains all the `Component`s used within the `Simulation`.
 pub struct Components
 {
 	components: Vec<Component>,
 	max_log_path: usize,
+
+	// Components are only added during setup so we can cache paths as they are appended
+	// instead of walking the tree each time a path is needed.
+	paths: Vec<String>,
+	ids: HashMap<String, ComponentID>,
+	#[cfg(test)]
+	pub(crate) path_lookups: AtomicUsize,	// path is called a lot so this is only counted in unit tests
+	id_lookups: AtomicUsize,
+	id_misses: AtomicUsize,
+	next_entity: AtomicUsize,	// shared by every EntitySource so ids are unique across the simulation
+}
+
+/// Returned by [`SimState`]'s cache_stats method.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathCacheStats
+{
+	/// Number of path to id lookups.
+	pub id_lookups: usize,
+
+	/// Number of path to id lookups for a path that doesn't exist.
+	pub id_misses: usize,
 }
 
 pub struct ComponentsIterator<'a>
@@ -33,7 +55,17 @@ impl Components
 {
 	pub(crate) fn new(max_log_path: usize) -> Components
 	{
-		Components {components: Vec::new(), max_log_path}
+		Components {
+			components: Vec::new(),
+			max_log_path,
+			paths: Vec::new(),
+			ids: HashMap::new(),
+			#[cfg(test)]
+			path_lookups: AtomicUsize::new(0),
+			id_lookups: AtomicUsize::new(0),
+			id_misses: AtomicUsize::new(0),
+			next_entity: AtomicUsize::new(1),
+		}
 	}
 	
 	/// Dump state to stdout.
@@ -187,18 +219,47 @@ impl Components
 	}
 	
 	/// Returns the path from the top component downwards. Returns "removed"
-	/// if id or a parent of id has been removed.
-	pub fn full_path(&self, mut id: ComponentID) -> String
+	/// if id or a parent of id has been removed and "" for NO_COMPONENT.
+	pub fn full_path(&self, id: ComponentID) -> String
 	{
-		let mut path = Vec::new();
-		
-		while id != NO_COMPONENT {
-			let c = self.get(id);
-			path.insert(0, c.name.clone());
-			id = c.parent;
+		self.path(id).to_string()
+	}
+	
+	/// Like full_path except that a reference to the cached path is returned.
+	pub fn path(&self, id: ComponentID) -> &str
+	{
+		if id == NO_COMPONENT {
+			return "";
 		}
-		
-		path.join(".")
+		#[cfg(test)]
+		self.path_lookups.fetch_add(1, Ordering::Relaxed);
+		&self.paths[id.0]
+	}
+	
+	/// Returns the component with the specified full path.
+	pub fn id(&self, path: &str) -> Option<ComponentID>
+	{
+		self.id_lookups.fetch_add(1, Ordering::Relaxed);
+		let id = self.ids.get(path).cloned();
+		if id.is_none() {
+			self.id_misses.fetch_add(1, Ordering::Relaxed);
+		}
+		id
+	}
+	
+	pub fn cache_stats(&self) -> PathCacheStats
+	{
+		PathCacheStats {
+			id_lookups: self.id_lookups.load(Ordering::Relaxed),
+			id_misses: self.id_misses.load(Ordering::Relaxed),
+		}
+	}
+	
+	// Entity ids are handed out from here because every component thread shares the
+	// same Components. Zero is never used.
+	pub(crate) fn next_entity_id(&self) -> u64
+	{
+		self.next_entity.fetch_add(1, Ordering::Relaxed) as u64
 	}
 				
 	/// Like path except that the path is truncated from the left using max_log_path
@@ -222,12 +283,20 @@ impl Components
 			self.check_for_dupes(parent, &component);
 		}
 
+		let path = if parent != NO_COMPONENT {
+			format!("{}.{}", self.paths[parent.0], component.name)
+		} else {
+			component.name.clone()
+		};
+
 		if parent != NO_COMPONENT {
 			let mut p = self.components.get_mut(parent.0).unwrap();
 			p.children.push(id);
 		}
 		
 		self.components.push(component);
+		self.ids.insert(path.clone(), id);
+		self.paths.push(path);
 	}
 	
 	#[cfg(debug_assertions)]
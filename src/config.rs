@@ -14,18 +14,38 @@
 // along with this program; if not, write to the Free Software Foundation,
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
 use glob::Pattern;
+use history::*;
 use logging::*;
+use run_mode::*;
+use sim_time::*;
 use std::collections::HashMap;
 use std::f64::INFINITY;
 use std::str::FromStr;
 
-/// Used to configure the `Simulation`.
+/// Controls how the events at a time slice are dispatched to components.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum DispatchStrategy
+{
+	/// Events are processed one at a time. This is the easiest to debug.
+	Sequential,
+	
+	/// All of the events are sent at once so each component thread runs in parallel.
+	Threaded,
+	
+	/// Like Threaded except that at most this many events are outstanding at once.
+	Pooled(usize),
+}
+
+/// Used to configure the `Simulation`. Note that when the serialize feature is enabled
+/// glob patterns are serialized as strings.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Config
 {
 	/// If set then score will startup a web server to control the simulation
 	/// and serve up this file when a browser hits "/". Files relative
 	/// to home_path's directory will also be served up but not files above the
-	/// directory.
+	/// directory. Requires the rest feature.
 	pub home_path: String,
 	
 	/// The address of the web server (used if home_path is set). Defaults to
@@ -37,9 +57,44 @@ pub struct Config
 	/// Defaults to micro-second resolution.
 	pub time_units: f64,
 	
+	/// Note that the strategy doesn't affect results: all the events at a time slice
+	/// see the same state regardless of how they are dispatched. Defaults to Threaded.
+	pub dispatch: DispatchStrategy,
+	
+	/// If true then the number of events dispatched and the number of components that
+	/// were able to run in parallel are recorded for each time slice (using the
+	/// "simulation.slice-events" and "simulation.parallelism" store keys, see [`Histogram`]).
+	/// For the Pooled and Sequential strategies "simulation.utilization" is also recorded:
+	/// the fraction of the cap that was used. Defaults to false.
+	pub record_parallelism: bool,
+	
+	/// If true then simulator gauges are maintained in the store and updated each time
+	/// slice: "simulation.events.pending" (events waiting to be dispatched),
+	/// "simulation.events.dispatched" (events dispatched so far), "simulation.events.tick"
+	/// (events dispatched at the last time), and "simulation.subtree-events.<path>" (events
+	/// dispatched to the components under each top level component). Defaults to false.
+	pub event_gauges: bool,
+	
+	/// How seconds (e.g. from schedule_after_secs) are converted into ticks. Defaults to
+	/// Floor.
+	pub tick_rounding: TickRounding,
+	
+	/// If a conversion into ticks is within this many ticks of an integer then that
+	/// integer is used regardless of tick_rounding. This prevents float error (e.g. 1.005s
+	/// becoming 1004999 micro-seconds) from skewing event ordering. Defaults to 0.0.
+	pub tick_epsilon: f64,
+	
+	/// If true then a warning is logged the first time each distinct number of seconds
+	/// loses precision when converted into ticks. Defaults to false.
+	pub warn_tick_rounding: bool,
+	
 	/// Maximum time to run the simulation for. Defaults to INFINITY.
 	pub max_secs: f64,
 	
+	/// Use SteadyState to stop the run once a long run mean has been estimated with
+	/// enough precision. Defaults to Terminating.
+	pub run_mode: RunMode,
+	
 	/// Number of times to send an "init N" event to active components.
 	/// Defaults to 1.
 	pub num_init_stages: i32,	// TODO: don't think this makes sense
@@ -55,6 +110,7 @@ pub struct Config
 	/// Overrides log_level when the glob `Pattern` matches a `Component`s
 	/// name. Defaults to empty. Note that only the first matching pattern
 	/// is used.
+	#[cfg_attr(feature = "serialize", serde(with = "::serde_support::pattern_map"))]
 	pub log_levels: HashMap<Pattern, LogLevel>,
 	
 	/// Maximum number of characters to use when logging component paths to
@@ -63,6 +119,124 @@ pub struct Config
 	/// to 20.
 	pub max_log_path: usize,
 	
+	/// Controls which store keys retain old values (see [`Store`]'s int_history method).
+	/// The first pattern matching a key's path determines how much history is retained.
+	/// Keys that don't match any of the patterns only have their current value. Defaults
+	/// to retaining all values for "*.fsm-state" (see [`Fsm`]).
+	#[cfg_attr(feature = "serialize", serde(with = "::serde_support::pattern_pairs"))]
+	pub store_history: Vec<(Pattern, RetentionPolicy)>,
+	
+	/// Number of time slices worth of [`StoreDelta`]s to retain. These record all of the
+	/// changes made to the store at each time slice and are primarily intended for GUIs,
+	/// e.g. via the /deltas REST endpoint. Zero disables recording. Defaults to 100.
+	pub max_store_deltas: usize,
+	
+	/// Number of store key changes to retain for the /state/changes REST endpoint. Clients
+	/// that poll less often than this many changes are told to re-read the whole store.
+	/// Defaults to 10_000.
+	pub max_state_changes: usize,
+	
+	/// Number of breakpoint hits to retain for the /breakpoints/hits REST endpoint. Once
+	/// this is reached the oldest hits are discarded. Defaults to 1000.
+	pub max_breakpoint_hits: usize,
+	
+	/// Number of log lines to retain for the /log REST endpoints. Once this is reached
+	/// the oldest lines are discarded (or appended to log_spill_path). Zero means that
+	/// there is no limit. Defaults to 100_000.
+	pub max_log_lines: usize,
+	
+	/// If set then log lines discarded because of max_log_lines are appended to this
+	/// file as JSON lines. Defaults to empty.
+	pub log_spill_path: String,
+	
+	/// If false then log records aren't written to stdout, e.g. because the simulation
+	/// is embedded within an application that uses add_log_sink to display them.
+	/// Defaults to true.
+	pub log_to_stdout: bool,
+	
+	/// Log records are written to stdout (and any other [`LogSink`]s) using a dedicated
+	/// thread so that a slow terminal or disk doesn't stall the simulation. This is the
+	/// maximum number of records that can be queued up for that thread. If the queue
+	/// fills up then records are dropped and a warning with the number of dropped records
+	/// is logged when the simulation exits. Defaults to 10_000.
+	pub log_queue_size: usize,
+	
+	/// If the number of pending events exceeds this then a breakdown of the most common
+	/// pending event names and the components that scheduled them is logged and written
+	/// to the store (see the "simulation.storm.*" keys). This is reported again only after
+	/// the storm subsides. Zero disables the check. Defaults to 1_000_000.
+	pub storm_pending: usize,
+	
+	/// Like storm_pending except that this is the number of events dispatched at a
+	/// single time slice. Defaults to 100_000.
+	pub storm_slice_events: usize,
+	
+	/// Number of dispatched events to remember the causes of (see [`Simulation`]'s
+	/// causal_chain method). Zero disables recording. Defaults to 10_000.
+	pub max_event_causes: usize,
+	
+	/// The finger print returned by [`Simulation`]'s run method is used to check that runs
+	/// are deterministic. By default it only includes the times, targets, and names of the
+	/// dispatched events. If this is true then the store's edition and a hash of the keys
+	/// and values written to the store are also folded in after each time slice. Defaults
+	/// to false.
+	pub finger_print_store: bool,
+	
+	/// If true then the finger print includes hashes of event payloads. Only payloads
+	/// registered with register_payload_hash or registered for tracing (e.g. with
+	/// register_traced_payload) are hashed. Defaults to false.
+	pub finger_print_payloads: bool,
+	
+	/// If true then the finger print includes the number of log lines written at each
+	/// time slice (after log levels are applied). Defaults to false.
+	pub finger_print_logs: bool,
+	
+	/// Number of per time slice finger prints to retain (see [`Simulation`]'s finger_prints
+	/// method). These make it easy to find the first time slice at which two runs diverged.
+	/// Zero disables recording. Defaults to 0.
+	pub max_finger_prints: usize,
+	
+	/// If true then events scheduled for a component that is removed before the event
+	/// is dispatched are sent back to the component that scheduled them as a
+	/// "delivery-failed" event with an [`UndeliveredEvent`] payload. This allows
+	/// components modeling reliable protocols to tell a peer that has gone away from one
+	/// that is merely silent. Otherwise the events are silently dropped. Defaults to false.
+	pub notify_undeliverable: bool,
+	
+	/// If true then the simulation stops when a component's assertion fails (see
+	/// [`Effector`]'s assert method). Defaults to true.
+	pub exit_on_assertion: bool,
+	
+	/// If true then the simulation panics when a component using process_events! receives
+	/// an event that none of its arms match. Otherwise a warning is logged, the
+	/// "simulation.dead-letters" counter is incremented, and the run continues. Defaults
+	/// to true.
+	pub panic_on_unhandled: bool,
+	
+	/// If set then a text file is written to this path if the simulation panics (this
+	/// includes component panics). The file contains the current time, the most recently
+	/// dispatched events, a summary of the pending events, and the store. Defaults to
+	/// empty (no dump).
+	pub emergency_dump: String,
+	
+	/// Number of recently dispatched events to include in the emergency dump. Defaults
+	/// to 100.
+	pub emergency_events: usize,
+	
+	/// If set then a [`RunReport`] is written to this path when the simulation exits. The
+	/// report is HTML if the path ends with ".html" and JSON otherwise. Defaults to empty
+	/// (no report).
+	pub run_report: String,
+	
+	/// If set then the store's values are written to this path (as text) when the
+	/// simulation exits. Defaults to empty (no dump).
+	pub store_dump: String,
+	
+	/// Store values matching these globs are included in the run report, e.g.
+	/// "*.sojourn.mean". Defaults to empty.
+	#[cfg_attr(feature = "serialize", serde(with = "::serde_support::patterns"))]
+	pub report_values: Vec<Pattern>,
+	
 	/// Use escape sequences to color code stdout. Defaults to true.
 	pub colorize: bool,
 
@@ -94,12 +268,42 @@ impl Config
 			home_path: "".to_string(),
 			address: "127.0.0.1:9000".to_string(),
 			time_units: 1_000_000.0,
+			dispatch: DispatchStrategy::Threaded,
+			record_parallelism: false,
+			event_gauges: false,
+			tick_rounding: TickRounding::Floor,
+			tick_epsilon: 0.0,
+			warn_tick_rounding: false,
 			max_secs: INFINITY,
+			run_mode: RunMode::Terminating,
 			num_init_stages: 1,
 			seed,
 			log_level: LogLevel::Info,
 			log_levels: HashMap::new(),
 			max_log_path: 20,
+			log_to_stdout: true,
+			log_queue_size: 10_000,
+			storm_pending: 1_000_000,
+			storm_slice_events: 100_000,
+			max_event_causes: 10_000,
+			finger_print_store: false,
+			finger_print_payloads: false,
+			finger_print_logs: false,
+			max_finger_prints: 0,
+			notify_undeliverable: false,
+			exit_on_assertion: true,
+			panic_on_unhandled: true,
+			emergency_dump: "".to_string(),
+			emergency_events: 100,
+			run_report: "".to_string(),
+			store_dump: "".to_string(),
+			report_values: Vec::new(),
+			store_history: vec![(Pattern::new("*.fsm-state").unwrap(), RetentionPolicy::All)],
+			max_store_deltas: 100,
+			max_state_changes: 10_000,
+			max_breakpoint_hits: 1000,
+			max_log_lines: 100_000,
+			log_spill_path: "".to_string(),
 			colorize: true,
 			error_escape_code: "\x1b[31;1m".to_string(),
 			warning_escape_code: "\x1b[31m".to_string(),
@@ -180,7 +384,7 @@ pub fn time_suffixes() -> &'static str
 	"s, m, h, d, or w"
 }
 
-fn do_parse_log_level(level: &str) -> Result<LogLevel, &'static str>
+pub(crate) fn do_parse_log_level(level: &str) -> Result<LogLevel, &'static str>
 {
 	match level {
 		"error" => Ok(LogLevel::Error),
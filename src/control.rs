@@ -0,0 +1,278 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use glob;
+use logging::*;
+use std::sync::mpsc;
+
+/// Commands used to control a `Simulation` from another thread. The REST server
+/// (see the rest feature) translates endpoints into these but other frontends (e.g. a
+/// unix socket or an in-process GUI) can use them via a [`ControlBackend`]. Replies
+/// are JSON, see the corresponding REST endpoints for details.
+pub enum RestCommand
+{
+	/// POST /breakpoints/{event}/{component}
+	AddBreakpoint(glob::Pattern, glob::Pattern),
+
+	/// POST /branch and /branch/seed/{seed}
+	Branch(Option<u64>),
+
+	/// POST /branch/end
+	EndBranch,
+
+	/// POST /exit
+	Exit,
+
+	/// GET /breakpoints
+	GetBreakpoints,
+
+	/// GET /breakpoints/hits and /breakpoints/hits/after/{time}, -1.0 means all hits.
+	GetBreakpointHits(f64),
+
+	/// POST /breakpoints/clear
+	ClearBreakpoints,
+
+	/// GET /compare and /compare/after/{time}, -1.0 means all differences.
+	GetComparison(f64),
+
+	/// GET /components
+	GetComponents,
+
+	/// GET /deltas and /deltas/after/{time}, -1.0 means all deltas.
+	GetDeltas(f64),
+
+	/// GET /display/schema
+	GetDisplaySchema,
+
+	/// GET /history/{key}
+	GetHistory(String),
+
+	/// GET /log?offset={offset}&limit={limit}&level={level}&path_glob={glob}
+	GetLog(LogQuery),
+
+	/// GET /log/after/{time}
+	GetLogAfter(f64),
+
+	/// GET /metadata
+	GetMetadata,
+
+	/// GET /schema
+	GetSchema,
+
+	/// GET /state/{path}
+	GetState(glob::Pattern),
+
+	/// GET /state/changes/{edition}
+	GetStateChanges(u64),
+
+	/// GET /exited
+	GetExited,
+
+	/// GET /job/{id}
+	GetJob(u64),
+
+	/// GET /time
+	GetTime,
+
+	/// GET /time/precision
+	GetTimePrecision,
+
+	/// GET /trace/{num}
+	GetTrace(u64),
+
+	/// GET /running
+	GetRunning,
+
+	/// GET /scheduled
+	GetScheduled,
+
+	/// GET /watch/hits and /watch/hits/after/{time}, -1.0 means all hits.
+	GetWatchHits(f64),
+
+	/// POST /watch/clear
+	ClearWatches,
+
+	/// POST /pause
+	Pause,
+
+	/// POST /log/level/{glob}/{level}
+	SetLogLevel(glob::Pattern, LogLevel),
+
+	/// POST /run
+	Resume,
+
+	/// POST /run/next-event
+	RunNextEvent,
+
+	/// POST /run/once
+	RunOnce,
+
+	/// POST /run/until-event/{glob} and /run/until-event/{glob}/{max_secs}, max_secs is
+	/// infinite for the first form.
+	RunUntilEvent(glob::Pattern, f64),
+
+	/// POST /state/bool/{path}/{value}
+	SetBoolState(String, bool),
+
+	/// POST /state/float/{path}/{value}
+	SetFloatState(String, f64),
+
+	/// POST /state/int/{path}/{value}
+	SetIntState(String, i64),
+
+	/// POST /state/string/{path}/{value}
+	SetStringState(String, String),
+
+	/// POST /run/until/{secs}
+	SetTime(f64),
+
+	/// POST /watch/{pattern}
+	Watch(glob::Pattern),
+}
+
+/// Selects the lines returned by GET /log. All of the parameters are optional.
+#[derive(Clone, Debug)]
+pub struct LogQuery
+{
+	/// Lines before this are skipped, see [`LogLine`]'s offset field.
+	pub offset: u64,
+
+	/// Maximum number of lines to return.
+	pub limit: usize,
+
+	/// Only lines at this level or more severe are returned.
+	pub level: Option<LogLevel>,
+
+	/// Only lines whose paths match are returned.
+	pub path: Option<glob::Pattern>,
+}
+
+impl LogQuery
+{
+	/// Returns all of the retained lines.
+	pub fn all() -> LogQuery
+	{
+		LogQuery{offset: 0, limit: usize::max_value(), level: None, path: None}
+	}
+}
+
+/// Sent back for each [`RestCommand`].
+pub struct RestReply
+{
+	/// JSON
+	pub data: String,
+
+	/// HTTP style status code, e.g. 200 or 404.
+	pub code: u16,
+}
+
+/// Frontends that drive a `Simulation` implement this and are installed using the
+/// `Simulation`'s set_control_backend method. Commands are tagged with a sequence number
+/// that is echoed back with the reply. When a backend is installed the simulation runs
+/// under its control (like it does with the REST server) until an Exit command is sent
+/// or the command sender is dropped.
+pub trait ControlBackend: Send
+{
+	/// Called once by the simulation thread before components are initialized. Backends
+	/// will typically spin up a thread that owns the channels. If an error is returned
+	/// the error is logged and the simulation exits without running.
+	fn start(&mut self, commands: mpsc::Sender<(u64, RestCommand)>, replies: mpsc::Receiver<(u64, RestReply)>) -> Result<(), String>;
+
+	/// Called when the simulation is exiting.
+	fn stop(&mut self);
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use component::*;
+	use config::*;
+	use event::*;
+	use simulation::*;
+	use std::thread;
+
+	// Hands the channels to a thread that drives the simulation like an in-process GUI would.
+	struct InProcessBackend
+	{
+		driver: Option<thread::JoinHandle<Vec<String>>>,
+		results: mpsc::Sender<Vec<String>>,
+	}
+
+	impl ControlBackend for InProcessBackend
+	{
+		fn start(&mut self, commands: mpsc::Sender<(u64, RestCommand)>, replies: mpsc::Receiver<(u64, RestReply)>) -> Result<(), String>
+		{
+			self.driver = Some(thread::spawn(move || {
+				let mut seq = 0;
+				let mut send = |command| {
+					seq += 1;
+					commands.send((seq, command)).unwrap();
+					let (s, reply) = replies.recv().unwrap();
+					assert_eq!(s, seq);
+					reply.data
+				};
+				let mut results = Vec::new();
+				results.push(send(RestCommand::Pause));
+				results.push(send(RestCommand::GetRunning));
+
+				send(RestCommand::SetTime(3.0));
+				while send(RestCommand::GetJob(1)).contains("\"result\":\"\"") {
+				}
+				results.push(send(RestCommand::GetTime));
+
+				results.push(send(RestCommand::Resume));
+				while send(RestCommand::GetRunning) == "true" {
+				}
+				results.push(send(RestCommand::GetExited));
+				results.push(send(RestCommand::GetTime));
+				results.push(send(RestCommand::Exit));
+				results
+			}));
+			Ok(())
+		}
+
+		fn stop(&mut self)
+		{
+			let results = self.driver.take().unwrap().join().unwrap();
+			self.results.send(results).unwrap();
+		}
+	}
+
+	#[test]
+	fn in_process_backends_control_the_sim()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		sim.add_handler_component("ticker", world, |event, state, effector| {
+			if event.name == "init 0" {
+				let id = state.components.id("world.ticker").unwrap();
+				for i in 1..11 {
+					effector.schedule_after_secs(Event::new("tick"), id, i as f64);
+				}
+			}
+		});
+
+		let (tx, rx) = mpsc::channel();
+		sim.set_control_backend(Box::new(InProcessBackend{driver: None, results: tx}));
+		sim.run();
+
+		let results = rx.recv().unwrap();
+		assert_eq!(results, vec!["\"ok\"", "false", "3.0", "\"ok\"", "true", "10.0", "\"exiting\""]);
+	}
+}
@@ -0,0 +1,170 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! GUIs like sdebug use store keys starting with "display-" to render components. Models
+//! should set these using [`Effector`]'s display method (instead of hard coding the key
+//! names) and GUIs can use the /display/schema REST endpoint (or display_keys) to find
+//! out which keys are supported.
+use component::*;
+use effector::*;
+use sim_state::*;
+
+/// Key for the horizontal position set by [`DisplayUpdate`]'s location method.
+pub const DISPLAY_LOCATION_X: &str = "display-location-x";
+
+/// Key for the vertical position set by [`DisplayUpdate`]'s location method.
+pub const DISPLAY_LOCATION_Y: &str = "display-location-y";
+
+/// Describes one of the display keys.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable)]
+pub struct DisplayKey
+{
+	/// The key name (relative to the component's path), e.g. "display-color".
+	pub name: &'static str,
+
+	/// The store type of the value: "float" or "string".
+	pub kind: &'static str,
+
+	/// What GUIs should do with the value.
+	pub description: &'static str,
+}
+
+/// Returns the keys that can be set using [`DisplayUpdate`].
+pub fn display_keys() -> &'static [DisplayKey]
+{
+	DISPLAY_KEYS
+}
+
+const DISPLAY_KEYS: &[DisplayKey] = &[
+	DisplayKey{name: "display-color", kind: "string", description: "An X11 color name used by map views when drawing top level components."},
+	DisplayKey{name: "display-details", kind: "string", description: "Arbitrary text used when drawing top level components and displaying component hierarchies."},
+	DisplayKey{name: DISPLAY_LOCATION_X, kind: "float", description: "Horizontal position of a top level component within a map view (the origin is at the upper left)."},
+	DisplayKey{name: DISPLAY_LOCATION_Y, kind: "float", description: "Vertical position of a top level component within a map view (the origin is at the upper left)."},
+	DisplayKey{name: "display-name", kind: "string", description: "Used instead of an icon when drawing components in map views."},
+	DisplayKey{name: "display-size-x", kind: "float", description: "Width of the map view (normally set by the root component)."},
+	DisplayKey{name: "display-size-y", kind: "float", description: "Height of the map view (normally set by the root component)."},
+	DisplayKey{name: "display-title", kind: "string", description: "Simulation specific header text (normally set by the root component)."},
+];
+
+/// Returned by [`Effector`]'s display method, e.g.
+/// `effector.display().location(10.0, 20.0).color("red");`
+pub struct DisplayUpdate<'a>
+{
+	effector: &'a mut Effector,
+}
+
+impl<'a> DisplayUpdate<'a>
+{
+	pub(crate) fn new(effector: &'a mut Effector) -> DisplayUpdate<'a>
+	{
+		DisplayUpdate{effector}
+	}
+
+	/// Sets display-color.
+	pub fn color(self, color: &str) -> DisplayUpdate<'a>
+	{
+		self.effector.set_string("display-color", color);
+		self
+	}
+
+	/// Sets display-details.
+	pub fn details(self, details: &str) -> DisplayUpdate<'a>
+	{
+		self.effector.set_string("display-details", details);
+		self
+	}
+
+	/// Sets display-location-x and display-location-y.
+	pub fn location(self, x: f64, y: f64) -> DisplayUpdate<'a>
+	{
+		self.effector.set_float(DISPLAY_LOCATION_X, x);
+		self.effector.set_float(DISPLAY_LOCATION_Y, y);
+		self
+	}
+
+	/// Sets display-name.
+	pub fn name(self, name: &str) -> DisplayUpdate<'a>
+	{
+		self.effector.set_string("display-name", name);
+		self
+	}
+
+	/// Sets display-size-x and display-size-y.
+	pub fn size(self, width: f64, height: f64) -> DisplayUpdate<'a>
+	{
+		self.effector.set_float("display-size-x", width);
+		self.effector.set_float("display-size-y", height);
+		self
+	}
+
+	/// Sets display-title.
+	pub fn title(self, title: &str) -> DisplayUpdate<'a>
+	{
+		self.effector.set_string("display-title", title);
+		self
+	}
+}
+
+impl SimState
+{
+	/// Returns the location set using [`DisplayUpdate`]'s location method. Panics if
+	/// the location hasn't been set.
+	pub fn display_location(&self, id: ComponentID) -> (f64, f64)
+	{
+		(self.get_float(id, DISPLAY_LOCATION_X), self.get_float(id, DISPLAY_LOCATION_Y))
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use components::*;
+	use services::*;
+	use sim_time::*;
+	use store::*;
+	use std::sync::Arc;
+
+	#[test]
+	fn setters_use_the_documented_keys()
+	{
+		let mut effector = Effector::new();
+		effector.display().location(1.0, 2.0).size(3.0, 4.0).color("red").details("idle").name("bot").title("arena");
+
+		let store = &effector.store;
+		for key in display_keys() {
+			assert!(store.contains(key.name), "{} wasn't set", key.name);
+			let kind = store.kind_of(key.name);
+			assert_eq!(kind, Some(key.kind), "{}", key.name);
+		}
+		assert_eq!(store.get_float("display-location-y"), 2.0);
+		assert_eq!(store.get_string("display-color"), "red");
+	}
+
+	#[test]
+	fn locations_can_be_read()
+	{
+		let bot = ComponentID(0);
+		let mut components = Components::new(20);
+		components.append(bot, Component{name: "bot".to_string(), parent: NO_COMPONENT, children: Vec::new()}, NO_COMPONENT);
+		let mut store = Store::new();
+		store.set_float("bot.display-location-x", 1.0, Time(0));
+		store.set_float("bot.display-location-y", 2.0, Time(0));
+
+		let state = SimState{components: Arc::new(components), store: Arc::new(store), services: Arc::new(Services::new()), time: 0.0};
+		assert_eq!(state.display_location(bot), (1.0, 2.0));
+	}
+}
@@ -0,0 +1,325 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Samplers for the distributions commonly used to model workloads, e.g. exponential
+//! inter-arrival times. These should be used with the component's [`SimRng`] (or a
+//! stream split off from it) so that runs are deterministic. The algorithms are
+//! implemented here (instead of relying upon rand) so that the sampled values won't
+//! change when dependencies are updated.
+//!
+//! # Examples
+//!
+//! ```
+//! use score::*;
+//!
+//! let mut rng = SimRng::new(1).stream("arrivals");
+//! let arrivals = Exponential::new(2.0);	// two arrivals per second
+//! let secs = arrivals.sample(&mut rng);
+//! assert!(secs > 0.0);
+//! ```
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Implemented by all of the samplers in this module so that library components can
+/// be parameterized by a distribution.
+pub trait Distribution
+{
+	fn sample<R: Rng>(&self, rng: &mut R) -> f64;
+}
+
+/// Time between events that happen at a constant average rate, e.g. arrivals at
+/// an M/M/1 queue.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exponential
+{
+	rate: f64,
+}
+
+impl Exponential
+{
+	/// Rate is the average number of events per unit time (so the mean is 1/rate).
+	pub fn new(rate: f64) -> Exponential
+	{
+		assert!(rate > 0.0, "rate ({:.3}) is not positive", rate);
+		Exponential{rate}
+	}
+}
+
+impl Distribution for Exponential
+{
+	fn sample<R: Rng>(&self, rng: &mut R) -> f64
+	{
+		-open_unit(rng).ln()/self.rate
+	}
+}
+
+/// Number of events that happen within a fixed interval. Samples are whole numbers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Poisson
+{
+	mean: f64,
+}
+
+impl Poisson
+{
+	pub fn new(mean: f64) -> Poisson
+	{
+		assert!(mean > 0.0, "mean ({:.3}) is not positive", mean);
+		Poisson{mean}
+	}
+}
+
+impl Distribution for Poisson
+{
+	fn sample<R: Rng>(&self, rng: &mut R) -> f64
+	{
+		if self.mean < 10.0 {
+			// Knuth's algorithm: fast for small means.
+			let limit = (-self.mean).exp();
+			let mut k = 0.0;
+			let mut p = rng.gen::<f64>();
+			while p > limit {
+				k += 1.0;
+				p *= rng.gen::<f64>();
+			}
+			k
+		} else {
+			// Hörmann's PTRS (transformed rejection with squeeze).
+			let slam = self.mean.sqrt();
+			let loglam = self.mean.ln();
+			let b = 0.931 + 2.53*slam;
+			let a = -0.059 + 0.02483*b;
+			let inv_alpha = 1.1239 + 1.1328/(b - 3.4);
+			let vr = 0.9277 - 3.6224/(b - 2.0);
+			loop {
+				let u = rng.gen::<f64>() - 0.5;
+				let v = rng.gen::<f64>();
+				let us = 0.5 - u.abs();
+				let k = ((2.0*a/us + b)*u + self.mean + 0.43).floor();
+				if us >= 0.07 && v <= vr {
+					return k;
+				}
+				if k < 0.0 || (us < 0.013 && v > us) {
+					continue;
+				}
+				if v.ln() + inv_alpha.ln() - (a/(us*us) + b).ln() <= -self.mean + k*loglam - log_factorial(k) {
+					return k;
+				}
+			}
+		}
+	}
+}
+
+/// The classic bell curve.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Normal
+{
+	mean: f64,
+	std_dev: f64,
+}
+
+impl Normal
+{
+	pub fn new(mean: f64, std_dev: f64) -> Normal
+	{
+		assert!(std_dev >= 0.0, "std_dev ({:.3}) is negative", std_dev);
+		Normal{mean, std_dev}
+	}
+}
+
+impl Distribution for Normal
+{
+	fn sample<R: Rng>(&self, rng: &mut R) -> f64
+	{
+		self.mean + self.std_dev*standard_normal(rng)
+	}
+}
+
+/// Distribution whose logarithm is normally distributed, e.g. file sizes or service
+/// times with a long tail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogNormal
+{
+	mu: f64,
+	sigma: f64,
+}
+
+impl LogNormal
+{
+	/// Mu and sigma are the mean and standard deviation of the underlying normal
+	/// distribution (not of the samples).
+	pub fn new(mu: f64, sigma: f64) -> LogNormal
+	{
+		assert!(sigma >= 0.0, "sigma ({:.3}) is negative", sigma);
+		LogNormal{mu, sigma}
+	}
+}
+
+impl Distribution for LogNormal
+{
+	fn sample<R: Rng>(&self, rng: &mut R) -> f64
+	{
+		(self.mu + self.sigma*standard_normal(rng)).exp()
+	}
+}
+
+/// Often used for failure times: shape < 1 means that the failure rate decreases
+/// over time, 1 is the exponential distribution, and > 1 means that it increases.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Weibull
+{
+	scale: f64,
+	shape: f64,
+}
+
+impl Weibull
+{
+	pub fn new(scale: f64, shape: f64) -> Weibull
+	{
+		assert!(scale > 0.0, "scale ({:.3}) is not positive", scale);
+		assert!(shape > 0.0, "shape ({:.3}) is not positive", shape);
+		Weibull{scale, shape}
+	}
+}
+
+impl Distribution for Weibull
+{
+	fn sample<R: Rng>(&self, rng: &mut R) -> f64
+	{
+		self.scale*(-open_unit(rng).ln()).powf(1.0/self.shape)
+	}
+}
+
+/// Samples from a table, e.g. packet sizes measured on a real network.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Empirical
+{
+	values: Vec<f64>,
+	cumulative: Vec<f64>,	// runs from 0.0 to 1.0
+	interpolate: bool,
+}
+
+impl Empirical
+{
+	/// Entries are (value, weight) and samples are one of the values.
+	pub fn discrete(table: &[(f64, f64)]) -> Empirical
+	{
+		assert!(!table.is_empty(), "table should not be empty");
+		assert!(table.iter().all(|e| e.1 >= 0.0), "weights should not be negative");
+		let total: f64 = table.iter().map(|e| e.1).sum();
+		assert!(total > 0.0, "weights should not all be zero");
+
+		let mut sum = 0.0;
+		let cumulative = table.iter().map(|e| {sum += e.1; sum/total}).collect();
+		Empirical{values: table.iter().map(|e| e.0).collect(), cumulative, interpolate: false}
+	}
+
+	/// Entries are (value, cumulative probability) where the first probability is 0.0
+	/// and the last is 1.0. Samples are linearly interpolated between the values.
+	pub fn continuous(table: &[(f64, f64)]) -> Empirical
+	{
+		assert!(table.len() >= 2, "table should have at least two entries");
+		assert!(table[0].1 == 0.0, "first probability should be 0.0");
+		assert!(table[table.len() - 1].1 == 1.0, "last probability should be 1.0");
+		assert!(table.windows(2).all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1), "table should be sorted");
+		Empirical{values: table.iter().map(|e| e.0).collect(), cumulative: table.iter().map(|e| e.1).collect(), interpolate: true}
+	}
+}
+
+impl Distribution for Empirical
+{
+	fn sample<R: Rng>(&self, rng: &mut R) -> f64
+	{
+		let p = rng.gen::<f64>();
+		let i = match self.cumulative.iter().position(|&c| p < c) {
+			Some(i) => i,
+			None => self.values.len() - 1,	// p is 1.0 minus a tiny bit and rounding left the last entry below that
+		};
+		if self.interpolate && i > 0 {
+			let (c0, c1) = (self.cumulative[i - 1], self.cumulative[i]);
+			let (v0, v1) = (self.values[i - 1], self.values[i]);
+			v0 + (v1 - v0)*(p - c0)/(c1 - c0)
+		} else {
+			self.values[i]
+		}
+	}
+}
+
+// Uniform on (0.0, 1.0] so that it's safe to take the log.
+fn open_unit<R: Rng>(rng: &mut R) -> f64
+{
+	1.0 - rng.gen::<f64>()
+}
+
+// Box-Muller. This only uses one of the two values it generates but that keeps
+// samplers stateless (and so trivially deterministic).
+fn standard_normal<R: Rng>(rng: &mut R) -> f64
+{
+	let u1 = open_unit(rng);
+	let u2 = rng.gen::<f64>();
+	(-2.0*u1.ln()).sqrt()*(2.0*PI*u2).cos()
+}
+
+// ln(k!) using Stirling's series for larger k.
+fn log_factorial(k: f64) -> f64
+{
+	if k < 10.0 {
+		(2..(k as u32 + 1)).map(|i| (i as f64).ln()).sum()
+	} else {
+		let k2 = k*k;
+		k*k.ln() - k + 0.5*(2.0*PI*k).ln() + 1.0/(12.0*k) - 1.0/(360.0*k2*k) + 1.0/(1260.0*k2*k2*k)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use sim_rng::*;
+
+	fn mean_of<D: Distribution>(dist: &D) -> f64
+	{
+		let mut rng = SimRng::new(1);
+		let n = 20_000;
+		(0..n).map(|_| dist.sample(&mut rng)).sum::<f64>()/(n as f64)
+	}
+
+	fn assert_near(actual: f64, expected: f64)
+	{
+		assert!((actual - expected).abs() < 0.02*expected.abs().max(1.0), "actual {} expected {}", actual, expected);
+	}
+
+	#[test]
+	fn means_match()
+	{
+		assert_near(mean_of(&Exponential::new(4.0)), 0.25);
+		assert_near(mean_of(&Poisson::new(3.0)), 3.0);
+		assert_near(mean_of(&Poisson::new(50.0)), 50.0);
+		assert_near(mean_of(&Normal::new(10.0, 2.0)), 10.0);
+		assert_near(mean_of(&LogNormal::new(0.0, 0.5)), (0.125f64).exp());
+		assert_near(mean_of(&Weibull::new(2.0, 1.0)), 2.0);
+		assert_near(mean_of(&Empirical::discrete(&[(1.0, 1.0), (4.0, 3.0)])), 3.25);
+		assert_near(mean_of(&Empirical::continuous(&[(0.0, 0.0), (10.0, 1.0)])), 5.0);
+	}
+
+	#[test]
+	fn log_factorial_is_accurate()
+	{
+		let exact: f64 = (2..21).map(|i| (i as f64).ln()).sum();
+		assert!((log_factorial(20.0) - exact).abs() < 1.0e-9);
+		assert_eq!(log_factorial(1.0), 0.0);
+	}
+}
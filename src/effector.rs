@@ -13,11 +13,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program; if not, write to the Free Software Foundation,
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+#![macro_use]
+
 use component::*;
+use display::*;
+use entity::*;
 use event::*;
+use glob::Pattern;
 use logging::*;
 use sim_time::*;
+use stats::*;
 use store::*;
+use transaction::*;
 use std::f64::EPSILON;
 
 /// Effectors are returned by [`Component`]s after they process an [`Event`].
@@ -26,7 +33,16 @@ pub struct Effector
 {
 	pub(crate) logs: Vec<LogRecord>,
 	pub(crate) events: Vec<(ComponentID, Event, f64)>,
+	pub(crate) broadcasts: Vec<(Pattern, Event, f64)>,
+	pub(crate) requests: Vec<(ComponentID, Event, f64)>,
 	pub(crate) store: Store,
+	pub(crate) transactions: Vec<Transaction>,
+	pub(crate) stats: Vec<(String, StatUpdate)>,
+	pub(crate) metadata: Vec<(String, MetadataUpdate)>,
+	pub(crate) schema: Vec<(String, SchemaUpdate)>,
+	pub(crate) entities: Vec<EntityUpdate>,
+	pub(crate) assertions: Vec<String>,
+	pub(crate) unhandled: Option<String>,
 	pub(crate) exit: bool,
 	pub(crate) removed: bool,
 }
@@ -38,13 +54,24 @@ impl Effector
 {
 	pub fn new() -> Effector
 	{
-		Effector{logs: Vec::new(), events: Vec::new(), store: Store::new(), exit: false, removed: false}
+		Effector{logs: Vec::new(), events: Vec::new(), broadcasts: Vec::new(), requests: Vec::new(), store: Store::new(), transactions: Vec::new(), stats: Vec::new(), metadata: Vec::new(), schema: Vec::new(), entities: Vec::new(), assertions: Vec::new(), unhandled: None, exit: false, removed: false}
 	}
 	
 	/// Normally you'll use one of the log macros, e.g. log_info!.
 	pub fn log(&mut self, level: LogLevel, message: &str)
 	{
-		self.logs.push(LogRecord{level, message: message.to_string()});
+		self.logs.push(LogRecord{level, path: None, message: message.to_string()});
+	}
+	
+	/// Logs using a path other than the component's path. This is useful for library
+	/// components that act on behalf of another component, e.g. a link model inside a
+	/// device can use the device's path (see [`SimState`]'s path method). Path may also
+	/// name a subsystem that isn't a component. Log level overrides from [`Config`] are
+	/// matched against the last part of the path.
+	pub fn log_as(&mut self, path: &str, level: LogLevel, message: &str)
+	{
+		assert!(!path.is_empty(), "path should not be empty");
+		self.logs.push(LogRecord{level, path: Some(path.to_string()), message: message.to_string()});
 	}
 	
 	/// Dispatch an event to a component after secs time elapses.
@@ -66,6 +93,189 @@ impl Effector
 		self.events.push((to, event, EPSILON));
 	}
 	
+	/// Schedules a group of events, e.g. arrivals read from a trace file. This is much
+	/// faster than scheduling a large number of events one at a time. Zero secs is
+	/// treated as schedule_immediately.
+	pub fn schedule_batch<I>(&mut self, events: I)
+		where I: IntoIterator<Item = (Event, ComponentID, f64)>
+	{
+		let events = events.into_iter();
+		self.events.reserve(events.size_hint().0);
+		for (event, to, secs) in events {
+			assert!(to != NO_COMPONENT);
+			assert!(secs >= 0.0, "secs ({:.3}) is negative", secs);
+			self.events.push((to, event, if secs > 0.0 {secs} else {EPSILON}));
+		}
+	}
+	
+	/// Schedules a copy of the event to every active component whose full path matches
+	/// the glob, e.g. "world.rack3.*". Events with payloads have to be registered using
+	/// the `Simulation`'s register_payload method so that they can be copied. Zero secs
+	/// is treated as schedule_immediately. Panics if the glob is malformed.
+	pub fn broadcast(&mut self, event: Event, glob: &str, secs: f64)
+	{
+		let pattern = Pattern::new(glob).unwrap_or_else(|err| panic!("bad broadcast glob '{}': {}", glob, err));
+		assert!(secs >= 0.0, "secs ({:.3}) is negative", secs);
+		self.broadcasts.push((pattern, event, if secs > 0.0 {secs} else {EPSILON}));
+	}
+	
+	/// Sends event to a component as soon as possible. The component should call reply
+	/// with the event and if it doesn't do so within timeout_secs then a "timeout" event
+	/// is sent back instead. The reply or timeout will have a [`Correlation`] with an ID
+	/// that is unique to the request and the name of the request. Replies that arrive
+	/// after the timeout are dropped.
+	pub fn request(&mut self, event: Event, to: ComponentID, timeout_secs: f64)
+	{
+		assert!(to != NO_COMPONENT);
+		assert!(timeout_secs > 0.0, "timeout_secs ({:.3}) is not positive", timeout_secs);
+		assert!(event.correlation.is_none(), "{} is already correlated", event.name);
+
+		self.requests.push((to, event, timeout_secs));
+	}
+	
+	/// Sends reply to the component that sent request as soon as possible. Panics
+	/// if request isn't a request (see the request method).
+	pub fn reply(&mut self, request: &Event, mut reply: Event)
+	{
+		match request.correlation {
+			Some(ref c) if c.kind == CorrelationKind::Request => {
+				reply.correlation = Some(Correlation{kind: CorrelationKind::Reply, ..c.clone()});
+				self.events.push((c.requester, reply, EPSILON));
+			},
+			_ => panic!("{} isn't a request", request.name),
+		}
+	}
+	
+	/// Use this to change the state of other components and/or schedule a group of
+	/// events atomically. See [`Transaction`] for more details.
+	pub fn commit(&mut self, transaction: Transaction)
+	{
+		self.transactions.push(transaction);
+	}
+	
+	/// Use these methods to record statistics. See the stats module for more details.
+	pub fn counter<'a>(&'a mut self, name: &'a str) -> Counter<'a>
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		Counter{updates: &mut self.stats, name}
+	}
+	
+	pub fn gauge<'a>(&'a mut self, name: &'a str) -> Gauge<'a>
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		Gauge{updates: &mut self.stats, name}
+	}
+	
+	pub fn histogram<'a>(&'a mut self, name: &'a str) -> Histogram<'a>
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		Histogram{updates: &mut self.stats, name}
+	}
+	
+	/// Time constant is in seconds (see [`Ewma`] for details).
+	pub fn ewma<'a>(&'a mut self, name: &'a str, time_constant: f64) -> Ewma<'a>
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		assert!(time_constant > 0.0, "time_constant ({:.3}) is not positive", time_constant);
+		Ewma{updates: &mut self.stats, name, time_constant}
+	}
+	
+	/// Window is in seconds.
+	pub fn moving_average<'a>(&'a mut self, name: &'a str, window: f64) -> MovingAverage<'a>
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		assert!(window > 0.0, "window ({:.3}) is not positive", window);
+		MovingAverage{updates: &mut self.stats, name, window}
+	}
+	
+	/// Sets simulation-level metadata, e.g. `effector.set_metadata("phase", "ramp-up")`.
+	/// Metadata is written to the "simulation.metadata.<name>" store key, logged when it
+	/// changes, and included in run reports. If components set different values for
+	/// the same name at the same time the component with the smallest id wins and a
+	/// warning is logged.
+	pub fn set_metadata(&mut self, name: &str, value: &str)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.metadata.push((name.to_string(), MetadataUpdate::Set(value.to_string())));
+	}
+	
+	/// Adds a label to a simulation-level metadata list, e.g. experiment tags. Labels
+	/// from all components are merged into a sorted set of strings. Note that a name
+	/// should be used with either set_metadata or add_metadata_label but not both.
+	pub fn add_metadata_label(&mut self, name: &str, label: &str)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		assert!(!label.is_empty(), "label should not be empty");
+		self.metadata.push((name.to_string(), MetadataUpdate::Label(label.to_string())));
+	}
+	
+	/// Declares one of the component's store keys so that writes can be checked and
+	/// GUIs can render the value (see the /schema REST endpoint), e.g.
+	/// `effector.declare_float("energy", "J", "remaining battery")`. Writes with the
+	/// wrong type are logged as errors and dropped. Units may be empty.
+	pub fn declare_float(&mut self, name: &str, units: &str, description: &str)
+	{
+		self.declare(name, StoreKind::Float, units, description);
+	}
+	
+	pub fn declare_int(&mut self, name: &str, units: &str, description: &str)
+	{
+		self.declare(name, StoreKind::Int, units, description);
+	}
+	
+	pub fn declare_string(&mut self, name: &str, description: &str)
+	{
+		self.declare(name, StoreKind::String, "", description);
+	}
+	
+	pub fn declare_bool(&mut self, name: &str, description: &str)
+	{
+		self.declare(name, StoreKind::Bool, "", description);
+	}
+	
+	pub fn declare_list_int(&mut self, name: &str, units: &str, description: &str)
+	{
+		self.declare(name, StoreKind::IntList, units, description);
+	}
+	
+	pub fn declare_list_float(&mut self, name: &str, units: &str, description: &str)
+	{
+		self.declare(name, StoreKind::FloatList, units, description);
+	}
+	
+	pub fn declare_list_string(&mut self, name: &str, description: &str)
+	{
+		self.declare(name, StoreKind::StringList, "", description);
+	}
+	
+	/// Adds a display hint to a declared key, e.g. "percent" or "log-scale". Hints are
+	/// only interpreted by GUIs.
+	pub fn add_display_hint(&mut self, name: &str, hint: &str)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		assert!(!hint.is_empty(), "hint should not be empty");
+		self.schema.push((name.to_string(), SchemaUpdate::Hint(hint.to_string())));
+	}
+	
+	/// If condition is false then the failure is logged as an error (with the component's
+	/// path and the current time), added to the run report, and, if config.exit_on_assertion
+	/// is set, the simulation stops. Normally called via the sim_assert! macro. Returns
+	/// condition.
+	pub fn assert(&mut self, condition: bool, message: &str) -> bool
+	{
+		if !condition {
+			self.assertions.push(message.to_string());
+		}
+		condition
+	}
+	
+	/// Used by process_events! when an event doesn't match any of the arms. What happens
+	/// next depends on config.panic_on_unhandled.
+	pub fn unhandled(&mut self, event_name: &str)
+	{
+		self.unhandled = Some(event_name.to_string());
+	}
+	
 	/// Exit the sim after all events at the current time have been processed.
 	pub fn exit(&mut self)
 	{
@@ -94,31 +304,101 @@ impl Effector
 		self.store.set_int(name, value, Time(0));
 	}
 	
-	/// There are several special float valued keys:
-	/// * display-location-x and y - These are used by GUIs (like sdebug) to position top level
-	/// component's within a map view (the origin is at the upper left).
-	/// * display-size-x and y - The dimensions of the map view.
+	/// Note that the display-* keys used by GUIs should be set using the display method.
 	pub fn set_float(&mut self, name: &str, value: f64)
 	{
 		assert!(!name.is_empty(), "name should not be empty");
 		self.store.set_float(name, value, Time(0));
 	}
 		
-	/// There are several special string valued keys:
-	/// * display-color - An X11 color name used by GUI map views when drawing top level components.
-	/// * display-details - Arbitrary text used when drawing top level component and displaying component hierarchies.
-	/// * display-name - For now this is used instead of an icon when drawing components in sdebug's map view.
-	/// * display-title - Used to give GUIs a simulation specific name for header text.
+	/// Note that the display-* keys used by GUIs should be set using the display method.
 	pub fn set_string(&mut self, name: &str, value: &str)
 	{
 		assert!(!name.is_empty(), "name should not be empty");
 		self.store.set_string(name, value, Time(0));
 	}
+	
+	/// Sets the keys GUIs (like sdebug) use to render the component, e.g.
+	/// `effector.display().location(x, y).color("red")`. See the display module.
+	pub fn display<'a>(&'a mut self) -> DisplayUpdate<'a>
+	{
+		DisplayUpdate::new(self)
+	}
+	
+	pub fn set_bool(&mut self, name: &str, value: bool)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store.set_bool(name, value, Time(0));
+	}
+	
+	pub fn set_vec_int(&mut self, name: &str, value: &[i64])
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store.set_vec_int(name, value, Time(0));
+	}
+	
+	pub fn set_vec_float(&mut self, name: &str, value: &[f64])
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store.set_vec_float(name, value, Time(0));
+	}
+	
+	pub fn set_vec_string(&mut self, name: &str, value: &[String])
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store.set_vec_string(name, value, Time(0));
+	}
+	
+	fn declare(&mut self, name: &str, kind: StoreKind, units: &str, description: &str)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		let schema = KeySchema{kind, units: units.to_string(), description: description.to_string(), hints: Vec::new()};
+		self.schema.push((name.to_string(), SchemaUpdate::Declare(schema)));
+	}
+}
+
+/// Calls the `Effector` assert method. Unlike assert! this doesn't panic (which would
+/// take down the component's thread) so the simulation can report the failure.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate score;
+///
+/// use score::*;
+///
+/// # fn main() {
+/// let mut effector = Effector::new();
+/// let energy = 10;
+/// sim_assert!(effector, energy >= 0);								// message is the condition
+/// sim_assert!(effector, energy < 100, "energy is {}", energy);	// message uses a format string
+/// # }
+/// ```
+#[macro_export]
+macro_rules! sim_assert
+{
+	($effector:expr, $cond:expr) => ($effector.assert($cond, concat!("sim_assert!(", stringify!($cond), ") failed")));
+	($effector:expr, $cond:expr, $msg:expr) => ($effector.assert($cond, $msg));
+	($effector:expr, $cond:expr, $fmt:expr, $($arg:tt)*) => ({let cond = $cond; $effector.assert(cond, &if cond {String::new()} else {format!($fmt, $($arg)*)})});
+}
+
+pub(crate) enum MetadataUpdate
+{
+	Set(String),
+	Label(String),
+}
+
+pub(crate) enum SchemaUpdate
+{
+	Declare(KeySchema),
+	Hint(String),
 }
 
 pub(crate) struct LogRecord
 {
 	pub(crate) level: LogLevel,
+	pub(crate) path: Option<String>,	// set if log_as was used
 	pub(crate) message: String,
 }
 
@@ -0,0 +1,140 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Entities are things that flow through components, e.g. parts moving through the
+//! stations of a factory or packets moving through routers. Entities are created using
+//! an [`EntitySource`], moved between components using ports, and disposed of using
+//! the [`Effector`]'s dispose_entity method. The `Simulation` tracks where each entity
+//! is and writes these stats (see the stats module):
+//! * PATH.entities.arrivals and departures - Counters for each component.
+//! * PATH.entities.population - Gauge with the number of entities at the component.
+//! * PATH.entities.residence - Histogram of the seconds entities spent at the component.
+//! * simulation.entities.created and disposed - Counters for the whole simulation.
+//! * simulation.entities.population - Gauge with the number of live entities.
+//! * simulation.entities.lifetime - Histogram of the seconds entities were alive.
+use component::*;
+use effector::*;
+use event::*;
+use ports::*;
+use sim_state::*;
+use store::*;
+use std::collections::BTreeMap;
+
+/// Something that flows through components.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entity
+{
+	/// Unique within the simulation.
+	pub id: u64,
+
+	/// Seconds into the simulation at which the entity was created.
+	pub created: f64,
+
+	/// Arbitrary model specific size, e.g. bytes or kilograms.
+	pub size: f64,
+
+	pub attributes: BTreeMap<String, StoreValue>,
+}
+
+impl Entity
+{
+	pub fn with(mut self, name: &str, value: StoreValue) -> Entity
+	{
+		self.attributes.insert(name.to_string(), value);
+		self
+	}
+
+	/// Returns None if the attribute hasn't been set.
+	pub fn get(&self, name: &str) -> Option<&StoreValue>
+	{
+		self.attributes.get(name)
+	}
+}
+
+pub(crate) enum EntityUpdate
+{
+	Created,
+	Arrived(u64),
+	Departed(u64),
+	Disposed(u64, f64),	// id, created
+}
+
+/// Used by a component to create [`Entity`]s.
+pub struct EntitySource
+{
+	id: ComponentID,
+}
+
+impl EntitySource
+{
+	/// Id should be the id of the component that owns the source.
+	pub fn new(id: ComponentID) -> EntitySource
+	{
+		assert!(id != NO_COMPONENT);
+		EntitySource{id}
+	}
+
+	/// Returns a new entity that is located at the source's component. Ids come from a
+	/// counter shared by the whole simulation so they stay unique even if a component
+	/// uses multiple sources or is restarted. Note that components that create entities
+	/// within the same time slice run concurrently so the order in which they are
+	/// assigned ids may change from run to run.
+	pub fn create(&mut self, state: &SimState, effector: &mut Effector, size: f64) -> Entity
+	{
+		debug_assert!(!state.was_removed(self.id), "{} was removed", state.path(self.id));
+		let id = state.components.next_entity_id();
+
+		effector.entities.push(EntityUpdate::Created);
+		effector.entities.push(EntityUpdate::Arrived(id));
+		Entity{id, created: state.time, size, attributes: BTreeMap::new()}
+	}
+}
+
+impl Effector
+{
+	/// Removes the entity from the simulation.
+	pub fn dispose_entity(&mut self, entity: Entity)
+	{
+		self.entities.push(EntityUpdate::Disposed(entity.id, entity.created));
+	}
+}
+
+impl OutPort<Entity>
+{
+	/// Moves the entity from the current component to the connected component. If the
+	/// port isn't connected the entity is disposed of.
+	pub fn send_entity(&self, effector: &mut Effector, name: &str, entity: Entity)
+	{
+		if self.is_connected() {
+			effector.entities.push(EntityUpdate::Departed(entity.id));
+		} else {
+			effector.entities.push(EntityUpdate::Disposed(entity.id, entity.created));
+		}
+		self.send_payload(effector, name, entity);
+	}
+}
+
+impl InPort<Entity>
+{
+	/// Takes the entity out of an event sent using send_entity and records that it
+	/// arrived at the current component.
+	pub fn receive_entity(&self, event: &mut Event, state: &SimState, effector: &mut Effector) -> Entity
+	{
+		let entity = self.take(event, state);
+		effector.entities.push(EntityUpdate::Arrived(entity.id));
+		entity
+	}
+}
@@ -13,50 +13,95 @@
 // You should have received a copy of the GNU General Public License
 // along with this program; if not, write to the Free Software Foundation,
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
-use std::any::Any;
+#![macro_use]
+
+use component::*;
+use event_name::*;
+use rustc_serialize::{Decodable, Encodable};
+use std::any::{self, Any};
 
 /// Events are scheduled to be sent to a `Component` at a particular `Time`.
 /// Components process the event using a thread and send an `Effector` back
 /// to the `Simulation` which encapsulates the state changes they wish to
 /// make.
+///
+/// Events have to be created using one of the constructors, e.g. with_payload, (they
+/// can't be built using a struct literal) so that the payload's type name is recorded
+/// for logs and traces. Use set_payload instead of assigning to payload for the same
+/// reason.
 pub struct Event
 {
 	/// Typically components may process different types of events so this
 	/// is what they check to decide what they need to do.
-	pub name: String,
+	pub name: EventName,
 	
 	/// If the event was delivered via a named port then this will be the field
 	/// name of the port the event came in on.
-	pub port_name: String,
+	pub port_name: EventName,
 	
-	/// Arbitrary extra information associated with the event.
+	/// Arbitrary extra information associated with the event. Prefer set_payload
+	/// when replacing the payload.
 	pub payload: Option<Box<Any + Send>>,
+	
+	/// Events scheduled for the same time are dispatched in priority order (highest
+	/// first) and then in the order in which they were scheduled. This can be used to
+	/// ensure that control events, e.g. "link-down", are processed before data events.
+	/// Defaults to 0.
+	pub priority: i32,
+	
+	/// Set for events sent using [`Effector`]'s request and reply methods and for the
+	/// "timeout" events sent when a request isn't replied to in time.
+	pub correlation: Option<Correlation>,
+	
+	// Name of the payload's type, used to describe payloads that weren't registered.
+	pub(crate) payload_type: &'static str,
 }
 
 impl Event
 {
-	pub fn new(name: &str) -> Event
+	/// Name can be a str, a String, or an [`EventName`].
+	pub fn new<N: Into<EventName>>(name: N) -> Event
 	{
-		assert!(!name.is_empty(), "name should not be empty");
-		Event{name: name.to_string(), port_name: "".to_string(), payload: None}
+		Event::with_port(name, "")
 	}
 
-	pub fn with_payload<T: Any + Send>(name: &str, payload: T) -> Event
+	pub fn with_payload<N: Into<EventName>, T: Any + Send>(name: N, payload: T) -> Event
 	{
-		assert!(!name.is_empty(), "name should not be empty");
-		Event{name: name.to_string(), port_name: "".to_string(), payload: Some(Box::new(payload))}
+		Event::with_port_payload(name, "", payload)
 	}
 
-	pub fn with_port(name: &str, port: &str) -> Event
+	pub fn with_port<N: Into<EventName>, P: Into<EventName>>(name: N, port: P) -> Event
 	{
+		let name = name.into();
 		assert!(!name.is_empty(), "name should not be empty");
-		Event{name: name.to_string(), port_name: port.to_string(), payload: None}
+		Event{name, port_name: port.into(), payload: None, payload_type: "", priority: 0, correlation: None}
 	}
 
-	pub fn with_port_payload<T: Any + Send>(name: &str, port: &str, payload: T) -> Event
+	pub fn with_port_payload<N: Into<EventName>, P: Into<EventName>, T: Any + Send>(name: N, port: P, payload: T) -> Event
 	{
-		assert!(!name.is_empty(), "name should not be empty");
-		Event{name: name.to_string(), port_name: port.to_string(), payload: Some(Box::new(payload))}
+		let mut event = Event::with_port(name, port);
+		event.set_payload(payload);
+		event
+	}
+
+	/// Replaces the event's payload (and its type name).
+	pub fn set_payload<T: Any + Send>(&mut self, payload: T)
+	{
+		self.payload = Some(Box::new(payload));
+		self.payload_type = any::type_name::<T>();
+	}
+
+	/// Returns the event with its priority set, e.g. `Event::new("link-down").with_priority(10)`.
+	pub fn with_priority(mut self, priority: i32) -> Event
+	{
+		self.priority = priority;
+		self
+	}
+
+	/// Returns true if this is a request that should be replied to (see [`Effector`]'s reply method).
+	pub fn is_request(&self) -> bool
+	{
+		self.correlation.as_ref().map_or(false, |c| c.kind == CorrelationKind::Request)
 	}
 
 	// Returns a reference to the value. Panics if there is no value or it isn't a T.
@@ -88,9 +133,59 @@ impl Event
 	}
 }
 
+/// Ties requests, replies, and timeouts together.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correlation
+{
+	/// Unique (within a run) ID for the request.
+	pub id: u64,
+	
+	/// The name of the request event, e.g. so that a requester can tell which of its
+	/// requests timed out.
+	pub request: String,
+	
+	pub(crate) requester: ComponentID,
+	pub(crate) kind: CorrelationKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CorrelationKind
+{
+	Request,
+	Reply,
+	Timeout,
+}
+
+/// Payload types can implement this so that they can be rendered in logs and traces
+/// (instead of just the event name). Use `Simulation`'s register_payload_debug method
+/// to tell the simulation about the type.
+pub trait PayloadDebug
+{
+	/// Should be a short, single line, summary of the payload.
+	fn debug_string(&self) -> String;
+}
+
+/// Payload types can implement this so that they can be written to traces (and replayed)
+/// without repeating their name and version at each registration site. Use `Simulation`'s
+/// register_payload_type method to tell the simulation about the type. Payloads that
+/// aren't registered are described in logs and traces using just their type name.
+pub trait Payload: Any + Send + Clone + Encodable + Decodable
+{
+	/// Written into traces to identify the type so it should be unique and stable
+	/// across runs.
+	fn payload_name() -> &'static str;
+	
+	/// Should be bumped whenever the type's fields change. Defaults to 1.
+	fn payload_version() -> u32
+	{
+		1
+	}
+}
+
 /// Typically `Component` threads will use this to cut down on the boiler plate involved in
-/// processing dispatched `Event`s. Note that this will panic if it tries to process an
-/// event that doesn't have an associated code block.
+/// processing dispatched `Event`s. Note that, by default, the component's thread will panic
+/// if the component is sent an event that doesn't have an associated code block (see
+/// config.panic_on_unhandled).
 ///
 /// # Examples
 ///
@@ -120,17 +215,19 @@ macro_rules! process_events
 {
 	($data:expr, $event:ident, $state:ident, $effector:ident, $($name:pat => $code:expr),+) => ({
 		for (mut $event, $state) in $data.rx.iter() {
-			$event.port_name += "";	// suppress unused_mut warning (#[allow(unused_mut)] doesn't seem to work with macros)
+			$event.priority += 0;	// suppress unused_mut warning (#[allow(unused_mut)] doesn't seem to work with macros)
 			let mut $effector = Effector::new();
 			{
-				let ename = $event.name.clone();	// annoying to clone but using a reference can cause problems with components that want to acquire a mutable reference to the event
-				match ename.as_ref() {
+				let ename = $event.name.clone();	// cheap to clone and using a reference can cause problems with components that want to acquire a mutable reference to the event
+				match ename.as_str() {
 					$($name => $code)+
 					
 					_ => {
-						if !ename.starts_with("init ") {
-							let cname = &(*$state.components).get($data.id).name;
-							panic!("component {} can't handle event {}", cname, ename);
+						if !ename.starts_with("init ") && ename != "init-recover" {
+							if $data.panic_on_unhandled {
+								panic!("component {} can't handle event {}", $state.path($data.id), ename);
+							}
+							$effector.unhandled(&ename);
 						}
 					}
 				}
@@ -0,0 +1,206 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// The type of [`Event`]'s name and port_name fields. Names are immutable and shared
+/// so copying an event name is just a reference count bump. Short names are also
+/// interned (per thread) so the names of events that are sent over and over again
+/// are normally only allocated once. Components that want to avoid even the intern
+/// lookup can create their names up front, e.g. `let tick = EventName::new("tick");`
+/// and then use `Event::new(&tick)`.
+///
+/// Names deref to str so they can be compared with and used like strings.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EventName(Arc<str>);
+
+impl EventName
+{
+	pub fn new(name: &str) -> EventName
+	{
+		if name.len() > MAX_INTERNED_LEN {
+			return EventName(Arc::from(name));
+		}
+
+		INTERNED.with(|interned| {
+			let mut interned = interned.borrow_mut();
+			if let Some(name) = interned.get(name) {
+				return EventName(name.clone());
+			}
+
+			let name: Arc<str> = Arc::from(name);
+			if interned.len() < MAX_INTERNED {
+				interned.insert(name.clone());
+			}
+			EventName(name)
+		})
+	}
+
+	pub fn as_str(&self) -> &str
+	{
+		&self.0
+	}
+}
+
+// Names that are longer than this are likely to be formatted (e.g. contain an ID) so
+// they aren't interned.
+const MAX_INTERNED_LEN: usize = 32;
+
+// Bounds the memory used if a model uses a lot of distinct short names.
+const MAX_INTERNED: usize = 4096;
+
+thread_local! {
+	static INTERNED: RefCell<HashSet<Arc<str>>> = RefCell::new(HashSet::new());
+}
+
+impl Deref for EventName
+{
+	type Target = str;
+
+	fn deref(&self) -> &str
+	{
+		&self.0
+	}
+}
+
+impl AsRef<str> for EventName
+{
+	fn as_ref(&self) -> &str
+	{
+		&self.0
+	}
+}
+
+impl Borrow<str> for EventName
+{
+	fn borrow(&self) -> &str
+	{
+		&self.0
+	}
+}
+
+impl fmt::Display for EventName
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		fmt::Display::fmt(&*self.0, f)
+	}
+}
+
+impl fmt::Debug for EventName
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		fmt::Debug::fmt(&*self.0, f)
+	}
+}
+
+impl<'a> From<&'a str> for EventName
+{
+	fn from(name: &'a str) -> EventName
+	{
+		EventName::new(name)
+	}
+}
+
+impl<'a> From<&'a String> for EventName
+{
+	fn from(name: &'a String) -> EventName
+	{
+		EventName::new(name)
+	}
+}
+
+impl From<String> for EventName
+{
+	fn from(name: String) -> EventName
+	{
+		EventName::new(&name)
+	}
+}
+
+impl<'a> From<&'a EventName> for EventName
+{
+	fn from(name: &'a EventName) -> EventName
+	{
+		name.clone()
+	}
+}
+
+impl PartialEq<str> for EventName
+{
+	fn eq(&self, other: &str) -> bool
+	{
+		&*self.0 == other
+	}
+}
+
+impl<'a> PartialEq<&'a str> for EventName
+{
+	fn eq(&self, other: &&'a str) -> bool
+	{
+		&*self.0 == *other
+	}
+}
+
+impl PartialEq<String> for EventName
+{
+	fn eq(&self, other: &String) -> bool
+	{
+		&*self.0 == other.as_str()
+	}
+}
+
+impl PartialEq<EventName> for str
+{
+	fn eq(&self, other: &EventName) -> bool
+	{
+		self == &*other.0
+	}
+}
+
+impl<'a> PartialEq<EventName> for &'a str
+{
+	fn eq(&self, other: &EventName) -> bool
+	{
+		*self == &*other.0
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn names_are_interned()
+	{
+		let a = EventName::new("tick");
+		let b = EventName::from("tick".to_string());
+		assert!(Arc::ptr_eq(&a.0, &b.0));
+		assert_eq!(a, "tick");
+		assert!(a.starts_with("ti"));
+		assert_eq!(format!("{} {:?}", a, a), "tick \"tick\"");
+
+		let long = "a-rather-long-event-name-with-an-id-0123456789";
+		assert!(!Arc::ptr_eq(&EventName::new(long).0, &EventName::new(long).0));
+		assert_eq!(EventName::new(long), EventName::new(long));
+	}
+}
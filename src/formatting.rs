@@ -0,0 +1,82 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Float formatting used by the store, the REST endpoints, and exported files. Rust's
+//! float formatting doesn't depend upon the locale or the platform but there are a few
+//! values that can print differently for runs that are otherwise identical (e.g. -0.0
+//! versus 0.0) so these normalize those. Models that write out their own files should
+//! use these so that golden file comparisons work.
+
+/// Formats with the minimum number of digits needed to round trip the value.
+pub fn float_to_string(value: f64) -> String
+{
+	match special(value) {
+		Some(text) => text.to_string(),
+		None => format!("{}", normalize(value)),
+	}
+}
+
+/// Formats with exactly precision digits after the decimal point.
+pub fn float_to_fixed(value: f64, precision: usize) -> String
+{
+	match special(value) {
+		Some(text) => text.to_string(),
+		None => {
+			let text = format!("{:.*}", precision, normalize(value));
+			if text.starts_with('-') && text[1..].chars().all(|c| c == '0' || c == '.') {
+				text[1..].to_string()	// e.g. -0.0001 with precision 3
+			} else {
+				text
+			}
+		},
+	}
+}
+
+fn special(value: f64) -> Option<&'static str>
+{
+	if value.is_nan() {
+		Some("NaN")
+	} else if value.is_infinite() {
+		Some(if value > 0.0 {"inf"} else {"-inf"})
+	} else {
+		None
+	}
+}
+
+fn normalize(value: f64) -> f64
+{
+	if value == 0.0 {0.0} else {value}	// -0.0 == 0.0
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::f64::{INFINITY, NAN};
+
+	#[test]
+	fn normalizes()
+	{
+		assert_eq!(float_to_string(-0.0), "0");
+		assert_eq!(float_to_string(0.1), "0.1");
+		assert_eq!(float_to_string(NAN), "NaN");
+		assert_eq!(float_to_string(-INFINITY), "-inf");
+
+		assert_eq!(float_to_fixed(1.0/3.0, 3), "0.333");
+		assert_eq!(float_to_fixed(-0.0001, 3), "0.000");
+		assert_eq!(float_to_fixed(-1.5, 1), "-1.5");
+	}
+}
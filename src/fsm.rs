@@ -0,0 +1,160 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! `Fsm` is a helper for components that are modeled as state machines. State
+//! changes are written to the store as "NAME.fsm-state" (by default [`Config`]
+//! retains the full history of these keys so GUIs can display state timelines)
+//! and the state graph can be exported using the DOT language.
+use effector::*;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+
+/// # Examples
+///
+/// ```
+/// use score::*;
+///
+/// fn new_connection() -> Fsm
+/// {
+/// 	let mut fsm = Fsm::new("connection", "closed");
+/// 	fsm.add_transition("closed", "open", "opening");
+/// 	fsm.add_transition("opening", "ack", "established");
+/// 	fsm.add_transition("established", "close", "closed");
+/// 	fsm
+/// }
+///
+/// fn handle_ack(fsm: &mut Fsm, effector: &mut Effector)
+/// {
+/// 	if let Err(err) = fsm.fire("ack", effector) {
+/// 		log_warning!(effector, "{}", err);
+/// 	}
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Fsm
+{
+	name: String,
+	initial: String,
+	current: String,
+	transitions: Vec<(String, String, String)>,	// from, event, to
+}
+
+impl Fsm
+{
+	/// Name is used for the store key so it should be unique within the component.
+	pub fn new(name: &str, initial: &str) -> Fsm
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		assert!(!initial.is_empty(), "initial should not be empty");
+		Fsm{name: name.to_string(), initial: initial.to_string(), current: initial.to_string(), transitions: Vec::new()}
+	}
+
+	pub fn add_transition(&mut self, from: &str, event: &str, to: &str)
+	{
+		assert!(!self.transitions.iter().any(|t| t.0 == from && t.1 == event), "{} already has a transition for {} from {}", self.name, event, from);
+		self.transitions.push((from.to_string(), event.to_string(), to.to_string()));
+	}
+
+	pub fn current(&self) -> &str
+	{
+		&self.current
+	}
+
+	/// Writes the initial state into the store. Typically called when processing "init 0".
+	pub fn start(&self, effector: &mut Effector)
+	{
+		effector.set_string(&self.state_key(), &self.current);
+	}
+
+	/// Transitions to a new state (and records the new state in the store). Returns an
+	/// error if there is no transition for event from the current state.
+	pub fn fire(&mut self, event: &str, effector: &mut Effector) -> Result<(), String>
+	{
+		let to = match self.transitions.iter().find(|t| t.0 == self.current && t.1 == event) {
+			Some(t) => t.2.clone(),
+			None => return Err(format!("{} has no transition for {} from {}", self.name, event, self.current)),
+		};
+
+		if to != self.current {
+			effector.set_string(&self.state_key(), &to);
+			self.current = to;
+		}
+		Ok(())
+	}
+
+	/// Returns the state graph using the DOT language (see http://www.graphviz.org).
+	pub fn to_dot(&self) -> String
+	{
+		let mut states = BTreeSet::new();
+		states.insert(&self.initial);
+		for t in self.transitions.iter() {
+			states.insert(&t.0);
+			states.insert(&t.2);
+		}
+
+		let mut text = format!("digraph \"{}\" {{\n", escape(&self.name));
+		for state in states.iter() {
+			let shape = if **state == self.initial {"doublecircle"} else {"circle"};
+			text += &format!("\t\"{}\" [shape={}];\n", escape(state), shape);
+		}
+		for &(ref from, ref event, ref to) in self.transitions.iter() {
+			text += &format!("\t\"{}\" -> \"{}\" [label=\"{}\"];\n", escape(from), escape(to), escape(event));
+		}
+		text += "}\n";
+		text
+	}
+
+	pub fn write_dot(&self, path: &str) -> io::Result<()>
+	{
+		let mut file = File::create(path)?;
+		file.write_all(self.to_dot().as_bytes())
+	}
+
+	fn state_key(&self) -> String
+	{
+		format!("{}.fsm-state", self.name)
+	}
+}
+
+fn escape(text: &str) -> String
+{
+	text.replace("\"", "\\\"")
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use store::*;
+
+	#[test]
+	fn fires_transitions()
+	{
+		let mut fsm = Fsm::new("conn", "closed");
+		fsm.add_transition("closed", "open", "open");
+		fsm.add_transition("open", "close", "closed");
+
+		let mut effector = Effector::new();
+		assert!(fsm.fire("close", &mut effector).is_err());
+		fsm.fire("open", &mut effector).unwrap();
+		assert_eq!(fsm.current(), "open");
+		assert_eq!(effector.store.get_string("conn.fsm-state"), "open");
+
+		assert_eq!(fsm.to_dot(), "digraph \"conn\" {\n\t\"closed\" [shape=doublecircle];\n\t\"open\" [shape=circle];\n\t\"closed\" -> \"open\" [label=\"open\"];\n\t\"open\" -> \"closed\" [label=\"close\"];\n}\n");
+	}
+}
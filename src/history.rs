@@ -0,0 +1,293 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use config::*;
+use glob::Pattern;
+use persistent_map::*;
+use sim_time::*;
+use store::*;
+use std::fmt;
+use std::sync::Arc;
+
+/// Used with [`Config`]'s store_history to control how much history is retained
+/// for store keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum RetentionPolicy
+{
+	/// Retain every change to the value.
+	All,
+
+	/// Retain the last N changes.
+	Last(usize),
+
+	/// Retain at most one change every N seconds. N is converted into ticks using
+	/// config.tick_rounding.
+	Every(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Retention
+{
+	All,
+	Last(usize),
+	Every(i64),	// in ticks
+}
+
+/// Records the old values of keys in the [`Store`]. Changes are only recorded for
+/// keys that match one of the patterns from config.store_history. Clones share
+/// structure so recording a value while a snapshot of the store is alive copies
+/// O(log n) nodes instead of the whole history.
+#[derive(Clone)]
+pub(crate) struct StoreHistory
+{
+	policies: Arc<Vec<(Pattern, Retention)>>,
+	resolved: PersistentMap<String, Option<Retention>>,	// cached so that we don't have to glob match each set
+	pub(crate) ints: PersistentMap<String, HistoryLog<i64>>,
+	pub(crate) floats: PersistentMap<String, HistoryLog<f64>>,
+	pub(crate) strings: PersistentMap<String, HistoryLog<String>>,
+	pub(crate) bools: PersistentMap<String, HistoryLog<bool>>,
+	pub(crate) lists: PersistentMap<String, HistoryLog<StoreValue>>,
+}
+
+/// The retained values for a key (oldest first). These are keyed by sequence number
+/// so that appending and dropping the oldest value copy O(log n) nodes.
+#[derive(Clone)]
+pub(crate) struct HistoryLog<T: Clone>
+{
+	values: PersistentMap<u64, (Time, T)>,
+	start: u64,	// sequence number of the oldest value
+	end: u64,	// sequence number of the next value
+}
+
+impl StoreHistory
+{
+	pub(crate) fn new(policies: &Vec<(Pattern, RetentionPolicy)>, config: &Config) -> StoreHistory
+	{
+		let policies = policies.iter().map(|&(ref pattern, policy)| {
+			let retention = match policy {
+				RetentionPolicy::All => Retention::All,
+				RetentionPolicy::Last(n) => {assert!(n > 0, "Last should be positive"); Retention::Last(n)},
+				RetentionPolicy::Every(secs) => {assert!(secs > 0.0, "Every should be positive"); Retention::Every(Duration::from_config_secs(secs, config).0)},
+			};
+			(pattern.clone(), retention)
+		}).collect();
+
+		StoreHistory {
+			policies: Arc::new(policies),
+			resolved: PersistentMap::new(),
+			ints: PersistentMap::new(),
+			floats: PersistentMap::new(),
+			strings: PersistentMap::new(),
+			bools: PersistentMap::new(),
+			lists: PersistentMap::new(),
+		}
+	}
+
+	pub(crate) fn record_int(&mut self, key: &str, time: Time, value: i64)
+	{
+		if let Some(retention) = self.retention(key) {
+			record(&mut self.ints, key, retention, time, value);
+		}
+	}
+
+	pub(crate) fn record_float(&mut self, key: &str, time: Time, value: f64)
+	{
+		if let Some(retention) = self.retention(key) {
+			record(&mut self.floats, key, retention, time, value);
+		}
+	}
+
+	pub(crate) fn record_string(&mut self, key: &str, time: Time, value: &str)
+	{
+		if let Some(retention) = self.retention(key) {
+			record(&mut self.strings, key, retention, time, value.to_string());
+		}
+	}
+
+	pub(crate) fn record_bool(&mut self, key: &str, time: Time, value: bool)
+	{
+		if let Some(retention) = self.retention(key) {
+			record(&mut self.bools, key, retention, time, value);
+		}
+	}
+
+	pub(crate) fn record_list(&mut self, key: &str, time: Time, value: &StoreValue)
+	{
+		if let Some(retention) = self.retention(key) {
+			record(&mut self.lists, key, retention, time, value.clone());
+		}
+	}
+
+	// Old's values are placed before new's values.
+	pub(crate) fn rename(&mut self, old: &str, new: &str)
+	{
+		stitch(&mut self.ints, old, new);
+		stitch(&mut self.floats, old, new);
+		stitch(&mut self.strings, old, new);
+		stitch(&mut self.bools, old, new);
+		stitch(&mut self.lists, old, new);
+	}
+
+	fn retention(&mut self, key: &str) -> Option<Retention>
+	{
+		if let Some(retention) = self.resolved.get(key) {
+			return *retention;
+		}
+
+		let retention = self.policies.iter().find(|p| p.0.matches(key)).map(|p| p.1);
+		self.resolved.insert(key.to_string(), retention);
+		retention
+	}
+}
+
+impl<T: Clone> HistoryLog<T>
+{
+	fn new() -> HistoryLog<T>
+	{
+		HistoryLog{values: PersistentMap::new(), start: 0, end: 0}
+	}
+
+	pub(crate) fn len(&self) -> usize
+	{
+		(self.end - self.start) as usize
+	}
+
+	pub(crate) fn iter(&self) -> impl Iterator<Item=&(Time, T)>
+	{
+		(self.start..self.end).map(move |i| &self.values[&i])
+	}
+
+	fn back(&self) -> Option<&(Time, T)>
+	{
+		if self.end > self.start {self.values.get(&(self.end - 1))} else {None}
+	}
+
+	fn push_back(&mut self, value: (Time, T))
+	{
+		self.values.insert(self.end, value);
+		self.end += 1;
+	}
+
+	fn pop_front(&mut self)
+	{
+		if self.end > self.start {
+			self.values.remove(&self.start);
+			self.start += 1;
+		}
+	}
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for HistoryLog<T>
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		f.debug_list().entries(self.iter()).finish()
+	}
+}
+
+impl<T: Clone + PartialEq> PartialEq<Vec<(Time, T)>> for HistoryLog<T>
+{
+	fn eq(&self, rhs: &Vec<(Time, T)>) -> bool
+	{
+		self.len() == rhs.len() && self.iter().zip(rhs.iter()).all(|(a, b)| a == b)
+	}
+}
+
+fn stitch<T: Clone>(values: &mut PersistentMap<String, HistoryLog<T>>, old: &str, new: &str)
+{
+	if let Some(mut stitched) = values.remove(old) {
+		if let Some(newer) = values.remove(new) {
+			for value in newer.iter() {
+				stitched.push_back(value.clone());
+			}
+		}
+		values.insert(new.to_string(), stitched);
+	}
+}
+
+fn record<T: Clone>(logs: &mut PersistentMap<String, HistoryLog<T>>, key: &str, retention: Retention, time: Time, value: T)
+{
+	if !logs.contains_key(key) {
+		logs.insert(key.to_string(), HistoryLog::new());
+	}
+	let values = logs.get_mut(key).unwrap();
+	match retention {
+		Retention::All => values.push_back((time, value)),
+		Retention::Last(n) => {
+			if values.len() == n {
+				values.pop_front();
+			}
+			values.push_back((time, value));
+		},
+		Retention::Every(ticks) => {
+			let sample = match values.back() {
+				Some(last) => time.0 - (last.0).0 >= ticks,
+				None => true,
+			};
+			if sample {
+				values.push_back((time, value));
+			}
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn retention_policies()
+	{
+		let policies = vec![
+			(Pattern::new("*.all").unwrap(), RetentionPolicy::All),
+			(Pattern::new("*.last").unwrap(), RetentionPolicy::Last(2)),
+			(Pattern::new("*.every").unwrap(), RetentionPolicy::Every(10.0))];
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut history = StoreHistory::new(&policies, &config);
+		for t in 0..25 {
+			history.record_int("a.all", Time(t), t);
+			history.record_int("a.last", Time(t), t);
+			history.record_int("a.every", Time(t), t);
+			history.record_int("a.none", Time(t), t);
+		}
+
+		assert_eq!(history.ints["a.all"].len(), 25);
+		assert_eq!(history.ints["a.last"], vec![(Time(23), 23), (Time(24), 24)]);
+		assert_eq!(history.ints["a.every"], vec![(Time(0), 0), (Time(10), 10), (Time(20), 20)]);
+		assert!(!history.ints.contains_key("a.none"));
+	}
+
+	#[test]
+	fn every_uses_tick_rounding()
+	{
+		// 1.5ms is 1.5 ticks so floor samples every tick and round every other tick.
+		let policies = vec![(Pattern::new("*").unwrap(), RetentionPolicy::Every(0.0015))];
+		let mut config = Config::new();
+		config.time_units = 1000.0;
+		let mut floor = StoreHistory::new(&policies, &config);
+		config.tick_rounding = TickRounding::Round;
+		let mut round = StoreHistory::new(&policies, &config);
+		for t in 0..5 {
+			floor.record_int("a", Time(t), t);
+			round.record_int("a", Time(t), t);
+		}
+
+		assert_eq!(floor.ints["a"].len(), 5);
+		assert_eq!(round.ints["a"], vec![(Time(0), 0), (Time(2), 2), (Time(4), 4)]);
+	}
+}
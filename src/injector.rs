@@ -0,0 +1,84 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use component::*;
+use event::*;
+use std::sync::mpsc;
+
+pub(crate) enum InjectTarget
+{
+	Id(ComponentID),
+	Path(String),
+}
+
+pub(crate) struct Injection
+{
+	pub(crate) event: Event,
+	pub(crate) target: InjectTarget,
+	pub(crate) secs: f64,
+}
+
+/// Allows threads outside of the simulation (e.g. GUIs, socket listeners, or test
+/// drivers) to schedule events. Use [`Simulation`]'s injector method to get one.
+/// Injected events are scheduled at the start of the next time slice (relative to
+/// the time of that slice). Note that the simulation does not wait for injected
+/// events so, unless the REST server is used, the simulation will exit if it runs
+/// out of events.
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+/// use std::thread;
+///
+/// fn start_listener(sim: &Simulation)
+/// {
+/// 	let injector = sim.injector();
+/// 	thread::spawn(move || {
+/// 		// wait for something interesting to happen
+/// 		injector.inject_to_path(Event::new("reset"), "world.router", 0.0);
+/// 	});
+/// }
+/// ```
+#[derive(Clone)]
+pub struct EventInjector
+{
+	tx: mpsc::Sender<Injection>,
+}
+
+impl EventInjector
+{
+	pub(crate) fn new(tx: mpsc::Sender<Injection>) -> EventInjector
+	{
+		EventInjector{tx}
+	}
+
+	/// Zero secs means as soon as possible. Returns false if the simulation is gone.
+	pub fn inject(&self, event: Event, to: ComponentID, secs: f64) -> bool
+	{
+		assert!(to != NO_COMPONENT);
+		assert!(secs >= 0.0, "secs ({:.3}) is negative", secs);
+		self.tx.send(Injection{event, target: InjectTarget::Id(to), secs}).is_ok()
+	}
+
+	/// Like inject except that the component is identified with a path like
+	/// "world.router". If the path doesn't match an active component then an error
+	/// is logged and the event is dropped.
+	pub fn inject_to_path(&self, event: Event, path: &str, secs: f64) -> bool
+	{
+		assert!(secs >= 0.0, "secs ({:.3}) is negative", secs);
+		self.tx.send(Injection{event, target: InjectTarget::Path(path.to_string()), secs}).is_ok()
+	}
+}
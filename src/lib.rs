@@ -17,35 +17,117 @@ extern crate glob;
 extern crate rand;
 extern crate rustc_serialize;
 extern crate time;
+extern crate uuid;
 
+#[cfg(feature = "serialize")]
+extern crate serde;
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(feature = "rest")]
 #[macro_use]
 extern crate rouille;
 
+pub mod builder;
+pub mod clock;
 pub mod component;
 pub mod components;
 pub mod config;
+pub mod control;
+pub mod display;
+pub mod distributions;
 pub mod effector;
+pub mod entity;
 pub mod event;
+pub mod event_name;
+pub mod formatting;
+pub mod fsm;
+pub mod history;
+pub mod injector;
+pub mod log_sink;
 pub mod logging;
+pub mod models;
+mod payload;
+mod persistent_map;
+pub mod process;
 pub mod ports;
+pub mod provenance;
+pub mod recorder;
+pub mod report;
+#[cfg(feature = "rest")]
+pub mod rest;
+pub mod routing;
+pub mod run_diff;
+pub mod run_dir;
+pub mod run_mode;
+pub mod runner;
+pub mod scenario;
+mod script;
+pub mod sim_component;
+pub mod sim_rng;
 pub mod simulation;
 pub mod sim_state;
+#[cfg(feature = "serialize")]
+mod serde_support;
+pub mod services;
+pub mod setup_cache;
 pub mod sim_time;
+pub mod stats;
 pub mod store;
+pub mod sweep;
+pub mod testing;
+pub mod testkit;
 pub mod thread_data;
+pub mod time_weighted;
+mod trace;
+pub mod transaction;
 pub mod values;
 
+pub use builder::*;
+pub use clock::*;
 pub use component::*;
 pub use components::*;
 pub use config::*;
+pub use control::*;
+pub use display::*;
+pub use distributions::*;
 pub use effector::*;
+pub use entity::*;
 pub use event::*;
+pub use event_name::*;
+pub use formatting::*;
+pub use fsm::*;
+pub use history::*;
+pub use injector::*;
+pub use log_sink::*;
 pub use logging::*;
 pub use ports::*;
+pub use process::*;
+pub use provenance::*;
+pub use recorder::*;
+pub use report::*;
+#[cfg(feature = "rest")]
+pub use rest::*;
+pub use routing::*;
+pub use run_diff::*;
+pub use run_dir::*;
+pub use run_mode::*;
+pub use runner::*;
+pub use scenario::*;
+pub use services::*;
+pub use setup_cache::*;
+pub use sim_component::*;
+pub use sim_rng::*;
 pub use simulation::*;
 pub use sim_state::*;
 pub use sim_time::*;
+pub use stats::*;
 pub use store::*;
+pub use sweep::*;
+pub use testkit::*;
 pub use thread_data::*;
+pub use time_weighted::*;
+pub use transaction::*;
 pub use values::*;
 
@@ -0,0 +1,227 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use config::*;
+use logging::*;
+use provenance::*;
+use rustc_serialize::json;
+use sim_time::*;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Log records that pass the `Simulation`'s log level filtering are sent to each of
+/// the registered sinks (see the `Simulation`'s add_log_sink method). Note that sinks
+/// are called from a dedicated logging thread.
+pub trait LogSink: Send
+{
+	/// Path will be "simulation" for records logged by the `Simulation` itself.
+	fn log(&mut self, time: Time, level: LogLevel, path: &str, message: &str);
+
+	/// Called when the sink is added to the `Simulation` so that files can be stamped
+	/// with the run id.
+	fn start(&mut self, _provenance: &Provenance)
+	{
+	}
+
+	/// Called when the simulation exits.
+	fn flush(&mut self)
+	{
+	}
+}
+
+/// This is the sink the `Simulation` uses to write to stdout. It's installed unless
+/// config.log_to_stdout is false and is configured using [`Config`].
+pub struct StdoutSink
+{
+	time_units: f64,
+	precision: usize,
+	colorize: bool,
+	escape_codes: [String; 5],	// indexed by LogLevel
+	max_log_path: usize,
+	largest_path: Arc<AtomicUsize>,	// updated by the Simulation as components are added
+}
+
+impl StdoutSink
+{
+	pub(crate) fn new(config: &Config, largest_path: Arc<AtomicUsize>) -> StdoutSink
+	{
+		StdoutSink {
+			time_units: config.time_units,
+			precision: time_precision(config.time_units),
+			colorize: config.colorize,
+			escape_codes: [
+				config.error_escape_code.clone(),
+				config.warning_escape_code.clone(),
+				config.info_escape_code.clone(),
+				config.debug_escape_code.clone(),
+				config.excessive_escape_code.clone()],
+			max_log_path: config.max_log_path,
+			largest_path,
+		}
+	}
+
+	fn logged_path(&self, path: &str) -> String
+	{
+		let largest_path = self.largest_path.load(Ordering::Relaxed);
+		if self.max_log_path > 0 && largest_path > self.max_log_path {
+			let len = path.len();
+			if len > self.max_log_path {
+				format!("…{}", &path[len - self.max_log_path..])
+			} else {
+				format!("{0:<1$}", path, self.max_log_path)
+			}
+		} else {
+			format!("{0:<1$}", path, largest_path)
+		}
+	}
+}
+
+impl LogSink for StdoutSink
+{
+	fn log(&mut self, time: Time, level: LogLevel, path: &str, message: &str)
+	{
+		let t = (time.0 as f64)/self.time_units;
+		let path = self.logged_path(path);
+		let stdout = io::stdout();
+		let mut stdout = stdout.lock();
+		if self.colorize {
+			let begin_escape = &self.escape_codes[level as usize];
+			let _ = write!(stdout, "{0}{1:.2$}   {3} {4}{5}\n", begin_escape, t, self.precision, path, message, end_escape());
+		} else {
+			let _ = write!(stdout, "{0:.1$}  {2} {3}  {4}\n", t, self.precision, level_prefix(level), path, message);
+		}
+	}
+
+	fn flush(&mut self)
+	{
+		let _ = io::stdout().flush();
+	}
+}
+
+/// Writes records to a file using the same format as uncolorized stdout (but with
+/// full component paths).
+pub struct FileSink
+{
+	writer: BufWriter<File>,
+	time_units: f64,
+	precision: usize,
+}
+
+impl FileSink
+{
+	/// time_units should be the same as [`Config`]'s time_units.
+	pub fn new(path: &str, time_units: f64) -> io::Result<FileSink>
+	{
+		let file = File::create(path)?;
+		Ok(FileSink{writer: BufWriter::new(file), time_units, precision: time_precision(time_units)})
+	}
+}
+
+impl LogSink for FileSink
+{
+	fn start(&mut self, provenance: &Provenance)
+	{
+		let _ = write!(self.writer, "# {}\n", provenance.summary());
+	}
+
+	fn log(&mut self, time: Time, level: LogLevel, path: &str, message: &str)
+	{
+		let t = (time.0 as f64)/self.time_units;
+		let _ = write!(self.writer, "{0:.1$}  {2} {3}  {4}\n", t, self.precision, level_prefix(level), path, message);
+	}
+
+	fn flush(&mut self)
+	{
+		let _ = self.writer.flush();
+	}
+}
+
+/// Writes each record as a JSON object on its own line, e.g.
+/// `{"time":1.5,"level":"info","path":"world.bot","message":"hello"}`.
+pub struct JsonLinesSink
+{
+	writer: BufWriter<File>,
+	time_units: f64,
+}
+
+#[derive(RustcEncodable)]
+struct JsonRecord<'a>
+{
+	time: f64,
+	level: String,
+	path: &'a str,
+	message: &'a str,
+}
+
+impl JsonLinesSink
+{
+	/// time_units should be the same as [`Config`]'s time_units.
+	pub fn new(path: &str, time_units: f64) -> io::Result<JsonLinesSink>
+	{
+		let file = File::create(path)?;
+		Ok(JsonLinesSink{writer: BufWriter::new(file), time_units})
+	}
+}
+
+#[derive(RustcEncodable)]
+struct JsonHeader<'a>
+{
+	provenance: &'a Provenance,
+}
+
+impl LogSink for JsonLinesSink
+{
+	/// The first line is a `{"provenance":{...}}` object.
+	fn start(&mut self, provenance: &Provenance)
+	{
+		let _ = write!(self.writer, "{}\n", json::encode(&JsonHeader{provenance}).unwrap());
+	}
+
+	fn log(&mut self, time: Time, level: LogLevel, path: &str, message: &str)
+	{
+		let record = JsonRecord{time: (time.0 as f64)/self.time_units, level: level.to_string(), path, message};
+		let _ = write!(self.writer, "{}\n", json::encode(&record).unwrap());
+	}
+
+	fn flush(&mut self)
+	{
+		let _ = self.writer.flush();
+	}
+}
+
+/// Number of decimal places to use when logging times.
+pub(crate) fn time_precision(time_units: f64) -> usize
+{
+	time_units.log10().max(0.0) as usize
+}
+
+fn level_prefix(level: LogLevel) -> &'static str
+{
+	match level {
+		LogLevel::Error	=> "error",
+		LogLevel::Warning	=> "warn ",
+		LogLevel::Info		=> "info ",
+		LogLevel::Debug	=> "debug",
+		LogLevel::Excessive=> "exces",
+	}
+}
+
+fn end_escape() -> &'static str
+{
+	"\x1b[0m"
+}
@@ -15,9 +15,14 @@
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
 #![macro_use]
 
+use log_sink::*;
+use sim_time::*;
 use std::fmt;
+use std::sync::mpsc;
+use std::thread;
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum LogLevel
 {
 	Error = 0,	// update log_levels if this changes
@@ -66,6 +71,97 @@ impl fmt::Display for LogLevel {
 	}
 }
 
+enum LogMessage
+{
+	Record(Time, LogLevel, String, String),
+	AddSink(Box<LogSink>),
+	Flush(mpsc::Sender<()>),
+}
+
+/// Writing to stdout can be quite slow (e.g. if the terminal is slow to render or stdout
+/// is redirected to a slow disk) so the `Simulation` uses this to call the [`LogSink`]s
+/// on a dedicated thread. Records are queued up using a bounded channel and, if the
+/// channel fills up, dropped (and counted) instead of stalling the simulation.
+pub(crate) struct LogWriter
+{
+	tx: Option<mpsc::SyncSender<LogMessage>>,
+	thread: Option<thread::JoinHandle<()>>,
+	pub(crate) dropped: u64,
+}
+
+impl LogWriter
+{
+	pub(crate) fn new(queue_size: usize, sinks: Vec<Box<LogSink>>) -> LogWriter
+	{
+		let (tx, rx) = mpsc::sync_channel(queue_size);
+		let thread = thread::spawn(move || {
+			let mut sinks = sinks;
+			for message in rx.iter() {
+				match message {
+					LogMessage::Record(time, level, path, message) => {
+						for sink in sinks.iter_mut() {
+							sink.log(time, level, &path, &message);
+						}
+					},
+					LogMessage::AddSink(sink) => {
+						sinks.push(sink);
+					},
+					LogMessage::Flush(ack) => {
+						for sink in sinks.iter_mut() {
+							sink.flush();
+						}
+						let _ = ack.send(());
+					}
+				}
+			}
+			for sink in sinks.iter_mut() {
+				sink.flush();
+			}
+		});
+		LogWriter{tx: Some(tx), thread: Some(thread), dropped: 0}
+	}
+	
+	pub(crate) fn add_sink(&mut self, sink: Box<LogSink>)
+	{
+		if let Some(ref tx) = self.tx {
+			tx.send(LogMessage::AddSink(sink)).expect("log thread has exited");
+		}
+	}
+	
+	pub(crate) fn write(&mut self, time: Time, level: LogLevel, path: String, message: String)
+	{
+		if let Some(ref tx) = self.tx {
+			match tx.try_send(LogMessage::Record(time, level, path, message)) {
+				Ok(_) => (),
+				Err(mpsc::TrySendError::Full(_)) => self.dropped += 1,
+				Err(mpsc::TrySendError::Disconnected(_)) => panic!("log thread has exited"),
+			}
+		}
+	}
+	
+	/// Blocks until all the queued up records have been written out.
+	pub(crate) fn flush(&mut self)
+	{
+		if let Some(ref tx) = self.tx {
+			let (ack_tx, ack_rx) = mpsc::channel();
+			if tx.send(LogMessage::Flush(ack_tx)).is_ok() {
+				let _ = ack_rx.recv();
+			}
+		}
+	}
+}
+
+impl Drop for LogWriter
+{
+	fn drop(&mut self)
+	{
+		self.tx = None;	// this will cause the thread to exit once it has written everything out
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
 /// Generic macro that calls the `Effector` log method. More often you'll use one of
 /// the other macros like log_info!.
 #[macro_export]
@@ -0,0 +1,349 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use component::*;
+use effector::*;
+use event::*;
+use glob::Pattern;
+use injector::*;
+use logging::*;
+use ports::*;
+use rustc_serialize::{json, Decodable, Encodable};
+use simulation::*;
+use std::any;
+use std::any::Any;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::thread;
+use thread_data::*;
+
+/// A connected socket used by a [`Bridge`].
+pub enum BridgeSocket
+{
+	/// Messages are newline delimited.
+	Tcp(TcpStream),
+
+	/// Each datagram is one message. The socket should be connected to the peer.
+	Udp(UdpSocket),
+}
+
+/// Exchanges events with a process outside the simulation, e.g. a real implementation
+/// of a protocol that is being tested against a network model. Events sent to the input
+/// port are written to the socket as JSON objects with "name" and "payload" fields (the
+/// payload is null for events without one). Messages read from the socket are injected
+/// into the simulation when they arrive and then forwarded to the output port.
+///
+/// The bridge counts "sent", "received", and "errors" (e.g. messages that couldn't be
+/// decoded or events whose payload isn't a T). Note that, like other injected events, the simulation won't wait for
+/// messages so it should normally be run with the REST server or some other source
+/// of events.
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+/// use score::models::*;
+/// use std::net::TcpStream;
+///
+/// fn add_bridge(sim: &mut Simulation, world: ComponentID, from: &mut OutPort<Vec<u8>>, to: &InPort<Vec<u8>>) -> Result<(), String>
+/// {
+/// 	let stream = TcpStream::connect("127.0.0.1:9000").map_err(|err| err.to_string())?;
+/// 	let mut bridge = Bridge::new(sim, world, "bridge", BridgeSocket::Tcp(stream)).with_filter("packet*");
+/// 	from.connect_to(&bridge.input);
+/// 	bridge.output.connect_to(to);
+/// 	bridge.start()
+/// }
+/// ```
+pub struct Bridge<T: Any + Send + Encodable + Decodable>
+{
+	pub data: ThreadData,
+	pub input: InPort<T>,
+	pub output: OutPort<T>,
+	socket: BridgeSocket,
+	injector: EventInjector,
+	filter: Option<Pattern>,
+}
+
+impl<T: Any + Send + Encodable + Decodable> Bridge<T>
+{
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID, name: &str, socket: BridgeSocket) -> Bridge<T>
+	{
+		let (id, data) = sim.add_active_component(name, parent_id);
+		let bridge = Bridge {
+			data,
+			input: InPort::with_port_name(id, "input"),
+			output: OutPort::new(),
+			socket,
+			injector: sim.injector(),
+			filter: None,
+		};
+		sim.register_in_port(id, "input", &bridge.input);
+		sim.register_out_port(id, "output", &bridge.output);
+		bridge
+	}
+
+	/// Only events whose names match glob are written to the socket (the others are
+	/// counted as "filtered"). Panics if the glob is malformed.
+	pub fn with_filter(mut self, glob: &str) -> Bridge<T>
+	{
+		self.filter = Some(Pattern::new(glob).unwrap_or_else(|err| panic!("bad bridge filter '{}': {}", glob, err)));
+		self
+	}
+
+	/// Returns an error if the socket couldn't be cloned for reading.
+	pub fn start(self) -> Result<(), String>
+	{
+		let id = self.data.id;
+		let writer = match self.socket {
+			BridgeSocket::Tcp(stream) => {
+				let reader = stream.try_clone().map_err(|err| err.to_string())?;
+				spawn_tcp_reader::<T>(reader, self.injector, id);
+				BridgeSocket::Tcp(stream)
+			},
+			BridgeSocket::Udp(socket) => {
+				let reader = socket.try_clone().map_err(|err| err.to_string())?;
+				spawn_udp_reader::<T>(reader, self.injector, id);
+				BridgeSocket::Udp(socket)
+			},
+		};
+
+		let data = self.data;
+		let output = self.output;
+		let filter = self.filter;
+		thread::spawn(move || {
+			let mut writer = writer;
+
+			// We don't use process_events because we forward events regardless of their name.
+			for (mut event, state) in data.rx.iter() {
+				let mut effector = Effector::new();
+				if event.port_name == SOCKET_PORT {
+					receive(&mut event, &output, &mut effector);
+				} else if !event.name.starts_with("init ") {
+					if filter.as_ref().map_or(true, |f| f.matches(&event.name)) {
+						match take_payload::<T>(&mut event).and_then(|payload| send(&mut writer, &encode_message(&event.name, &payload))) {
+							Ok(_) => effector.counter("sent").inc(1),
+							Err(err) => {
+								log_warning!(effector, "couldn't send '{}': {}", event.name, err);
+								effector.counter("errors").inc(1);
+							},
+						}
+					} else {
+						effector.counter("filtered").inc(1);
+					}
+				}
+
+				drop(state);
+				let _ = data.tx.send(effector);
+			}
+		});
+		Ok(())
+	}
+}
+
+// Injected events use this port so that they can be distinguished from events sent
+// to the input port.
+const SOCKET_PORT: &str = "socket";
+const SOCKET_ERROR: &str = "bridge-error";
+
+fn encode_message<T: Encodable>(name: &str, payload: &Option<T>) -> String
+{
+	format!("{{\"name\":{},\"payload\":{}}}", json::encode(&name).unwrap(), json::encode(payload).unwrap())
+}
+
+// Events without a payload are fine (they are written with a null payload) but a
+// payload of the wrong type is an error.
+fn take_payload<T: Any>(event: &mut Event) -> Result<Option<T>, String>
+{
+	match event.payload.take() {
+		Some(payload) => match payload.downcast::<T>() {
+			Ok(payload) => Ok(Some(*payload)),
+			Err(_) => Err(format!("expected a payload of type {}", any::type_name::<T>())),
+		},
+		None => Ok(None),
+	}
+}
+
+fn decode_message<T: Decodable>(text: &str) -> Result<(String, Option<T>), String>
+{
+	let bad = |reason: &str| format!("bad message '{}': {}", text.trim(), reason);
+	let message = json::Json::from_str(text.trim()).map_err(|err| bad(&err.to_string()))?;
+	let name = match message.find("name").and_then(|name| name.as_string()) {
+		Some(name) if !name.is_empty() => name.to_string(),
+		_ => return Err(bad("missing name")),
+	};
+	let payload = match message.find("payload") {
+		None | Some(&json::Json::Null) => None,
+		Some(payload) => Some(T::decode(&mut json::Decoder::new(payload.clone())).map_err(|err| bad(&err.to_string()))?),
+	};
+	Ok((name, payload))
+}
+
+fn send(writer: &mut BridgeSocket, text: &str) -> Result<(), String>
+{
+	match *writer {
+		BridgeSocket::Tcp(ref mut stream) => writeln!(stream, "{}", text).map_err(|err| err.to_string()),
+		BridgeSocket::Udp(ref socket) => socket.send(text.as_bytes()).map(|_| ()).map_err(|err| err.to_string()),
+	}
+}
+
+fn receive<T: Any + Send>(event: &mut Event, output: &OutPort<T>, effector: &mut Effector)
+{
+	if event.name == SOCKET_ERROR {
+		let err: String = event.take_payload();
+		log_warning!(effector, "{}", err);
+		effector.counter("errors").inc(1);
+	} else if let Some((remote_id, remote_port)) = output.route() {
+		effector.counter("received").inc(1);
+		let mut forwarded = Event::with_port(&event.name, remote_port);
+		forwarded.payload = event.payload.take();
+		forwarded.payload_type = event.payload_type;
+		effector.schedule_immediately(forwarded, remote_id);
+	} else {
+		log_warning!(effector, "dropping '{}' (output isn't connected)", event.name);
+	}
+}
+
+fn inject<T: Any + Send + Decodable>(injector: &EventInjector, id: ComponentID, text: &str) -> bool
+{
+	let event = match decode_message::<T>(text) {
+		Ok((name, Some(payload))) => Event::with_port_payload(&name, SOCKET_PORT, payload),
+		Ok((name, None)) => Event::with_port(&name, SOCKET_PORT),
+		Err(err) => Event::with_port_payload(SOCKET_ERROR, SOCKET_PORT, err),
+	};
+	injector.inject(event, id, 0.0)
+}
+
+fn spawn_tcp_reader<T: Any + Send + Decodable>(stream: TcpStream, injector: EventInjector, id: ComponentID)
+{
+	thread::spawn(move || {
+		for line in BufReader::new(stream).lines() {
+			match line {
+				Ok(ref line) if line.trim().is_empty() => (),
+				Ok(line) => if !inject::<T>(&injector, id, &line) {break},
+				Err(_) => break,
+			}
+		}
+	});
+}
+
+fn spawn_udp_reader<T: Any + Send + Decodable>(socket: UdpSocket, injector: EventInjector, id: ComponentID)
+{
+	thread::spawn(move || {
+		let mut buffer = vec![0; 64*1024];
+		while let Ok(count) = socket.recv(&mut buffer) {
+			let text = String::from_utf8_lossy(&buffer[..count]).into_owned();
+			if !inject::<T>(&injector, id, &text) {
+				break;
+			}
+		}
+	});
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use config::*;
+	use std::time::Duration;
+	use store::*;
+
+	#[test]
+	fn messages_round_trip()
+	{
+		let text = encode_message("packet", &Some(vec![1u8, 2, 3]));
+		assert_eq!(text, r#"{"name":"packet","payload":[1,2,3]}"#);
+		assert_eq!(decode_message::<Vec<u8>>(&text), Ok(("packet".to_string(), Some(vec![1, 2, 3]))));
+
+		let text = encode_message::<Vec<u8>>("reset", &None);
+		assert_eq!(decode_message::<Vec<u8>>(&text), Ok(("reset".to_string(), None)));
+
+		assert!(decode_message::<Vec<u8>>(r#"{"name":"packet","payload":"oops"}"#).is_err());
+		assert!(decode_message::<Vec<u8>>(r#"{"name":"","payload":null}"#).is_err());
+	}
+
+	#[test]
+	fn payloads_must_have_the_right_type()
+	{
+		let mut event = Event::with_payload("packet", vec![1u8, 2]);
+		assert_eq!(take_payload::<Vec<u8>>(&mut event), Ok(Some(vec![1, 2])));
+
+		let mut event = Event::new("reset");
+		assert_eq!(take_payload::<Vec<u8>>(&mut event), Ok(None));
+
+		let mut event = Event::with_payload("packet", "oops".to_string());
+		assert!(take_payload::<Vec<u8>>(&mut event).unwrap_err().contains("Vec<u8>"));
+	}
+
+	#[test]
+	fn udp_loopback()
+	{
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.connect(peer.local_addr().unwrap()).unwrap();
+		peer.connect(socket.local_addr().unwrap()).unwrap();
+		peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+		// The peer replies to the first message and then sends garbage.
+		let peer = thread::spawn(move || {
+			let mut buffer = vec![0; 1024];
+			let count = peer.recv(&mut buffer).unwrap();
+			peer.send(encode_message("reply", &Some(vec![4u8, 5])).as_bytes()).unwrap();
+			peer.send(b"garbage").unwrap();
+			String::from_utf8_lossy(&buffer[..count]).into_owned()
+		});
+
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		config.time_units = 1.0;
+		config.max_secs = 10_000.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let mut bridge: Bridge<Vec<u8>> = Bridge::new(&mut sim, world, "bridge", BridgeSocket::Udp(socket));
+		let bridge_id = bridge.data.id;
+
+		let mut output = OutPort::new();
+		output.connect_to(&bridge.input);
+		sim.add_handler_component("sender", world, move |event, _, effector| {
+			if event.name == "init 0" {
+				output.send_payload(effector, "packet", vec![1u8, 2, 3]);
+			}
+		});
+		let receiver = sim.add_handler_component("receiver", world, |mut event, _, effector| {
+			if event.name == "reply" {
+				let payload = event.take_payload::<Vec<u8>>();
+				effector.set_int("bytes", payload.len() as i64);
+			}
+		});
+		bridge.output.connect_to(&InPort::new(receiver));
+
+		// The simulation doesn't wait for injected events so we keep it busy until the
+		// reply and the bad message have both been processed.
+		sim.add_handler_component("ticker", world, move |_, state, effector| {
+			if !state.contains(bridge_id, "received") || !state.contains(bridge_id, "errors") {
+				thread::sleep(Duration::from_millis(1));
+				let id = state.components.id("world.ticker").unwrap();
+				effector.schedule_after_secs(Event::new("tick"), id, 1.0);
+			}
+		});
+		bridge.start().unwrap();
+		sim.run();
+
+		assert_eq!(peer.join().unwrap(), encode_message("packet", &Some(vec![1u8, 2, 3])));
+		assert_eq!(sim.store.get_int("world.bridge.sent"), 1);
+		assert_eq!(sim.store.get_int("world.bridge.received"), 1);
+		assert_eq!(sim.store.get_int("world.bridge.errors"), 1);
+		assert_eq!(sim.store.get_int("world.receiver.bytes"), 2);
+	}
+}
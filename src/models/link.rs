@@ -0,0 +1,285 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use component::*;
+use effector::*;
+use event::*;
+use logging::*;
+use ports::*;
+use rand::Rng;
+use sim_state::*;
+use simulation::*;
+use std::any::Any;
+use std::thread;
+use thread_data::*;
+
+/// Parameters for a [`Link`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkParams
+{
+	/// Propagation delay in seconds.
+	pub delay: f64,
+
+	/// In bits per second. Zero means that there is no serialization delay.
+	pub bandwidth: f64,
+
+	/// Each event is delayed by an additional random amount between zero and this.
+	/// Note that this can cause events to be re-ordered.
+	pub jitter: f64,
+
+	/// Probability that an event will be lost (from 0.0 to 1.0).
+	pub loss: f64,
+}
+
+impl LinkParams
+{
+	/// Creates params with no bandwidth limit, jitter, or loss.
+	pub fn with_delay(delay: f64) -> LinkParams
+	{
+		LinkParams{delay, bandwidth: 0.0, jitter: 0.0, loss: 0.0}
+	}
+}
+
+/// Models a network link. Events (with any name) sent to the input port are forwarded,
+/// with the same name and payload, to the output port after the link's delay. If a
+/// bandwidth is set then events are serialized onto the link one after another using
+/// the size function to get the number of bytes in each payload. Jitter and loss use
+/// separate streams from the link's random number generator.
+///
+/// The parameters are written to the store as "delay", "bandwidth", "jitter", and "loss"
+/// (and re-read as events are processed so they can be changed). The link also counts
+/// "sent" and "lost" events and records "latency" using a [`Histogram`].
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+/// use score::models::*;
+///
+/// fn add_link(sim: &mut Simulation, world: ComponentID, from: &mut OutPort<Vec<u8>>, to: &InPort<Vec<u8>>)
+/// {
+/// 	let params = LinkParams{delay: 0.010, bandwidth: 1_000_000.0, jitter: 0.001, loss: 0.01};
+/// 	let mut link = Link::new(sim, world, "link", params).with_size(|p: &Vec<u8>| p.len());
+/// 	from.connect_to(&link.input);
+/// 	link.output.connect_to(to);
+/// 	link.start();
+/// }
+/// ```
+pub struct Link<T: Any + Send>
+{
+	pub data: ThreadData,
+	pub input: InPort<T>,
+	pub output: OutPort<T>,
+	size: Option<fn (&T) -> usize>,
+}
+
+impl<T: Any + Send> Link<T>
+{
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID, name: &str, params: LinkParams) -> Link<T>
+	{
+		let (id, data) = sim.add_active_component(name, parent_id);
+		let link = Link {
+			data,
+			input: InPort::new(id),
+			output: OutPort::new(),
+			size: None,
+		};
+		sim.register_in_port(id, "input", &link.input);
+		sim.register_out_port(id, "output", &link.output);
+
+		let mut effector = Effector::new();
+		effector.set_float("delay", params.delay);
+		effector.set_float("bandwidth", params.bandwidth);
+		effector.set_float("jitter", params.jitter);
+		effector.set_float("loss", params.loss);
+		sim.apply(id, effector);
+		link
+	}
+
+	/// Returns the number of bytes in a payload. Used with bandwidth to compute
+	/// serialization delays.
+	pub fn with_size(mut self, size: fn (&T) -> usize) -> Link<T>
+	{
+		self.size = Some(size);
+		self
+	}
+
+	pub fn start(self)
+	{
+		let mut loss_rng = self.data.rng.stream("loss");
+		let mut jitter_rng = self.data.rng.stream("jitter");
+		let mut busy_until = 0.0;	// time at which the last event finishes serializing
+
+		let data = self.data;
+		let output = self.output;
+		let size = self.size;
+		thread::spawn(move || {
+			// We don't use process_events because we forward events regardless of their name.
+			for (mut event, state) in data.rx.iter() {
+				let mut effector = Effector::new();
+				if !event.name.starts_with("init ") {
+					let params = read_params(data.id, &state);
+					effector.counter("sent").inc(1);
+					if params.loss > 0.0 && loss_rng.gen::<f64>() < params.loss {
+						log_debug!(effector, "lost '{}'", event.name);
+						effector.counter("lost").inc(1);
+					} else if let Some((remote_id, remote_port)) = output.route() {
+						let bytes = match (size, event.payload.as_ref().and_then(|p| p.downcast_ref::<T>())) {
+							(Some(size), Some(payload)) => size(payload),
+							_ => 0,
+						};
+						let serialization = if params.bandwidth > 0.0 {8.0*(bytes as f64)/params.bandwidth} else {0.0};
+						let start = if busy_until > state.time {busy_until} else {state.time};
+						busy_until = start + serialization;
+
+						let jitter = if params.jitter > 0.0 {params.jitter*jitter_rng.gen::<f64>()} else {0.0};
+						let latency = busy_until - state.time + params.delay + jitter;
+						effector.histogram("latency").record(latency);
+
+						let forwarded = Event{name: event.name.clone(), port_name: remote_port.into(), payload: event.payload.take(), payload_type: event.payload_type, priority: event.priority, correlation: event.correlation.take()};
+						if latency > 0.0 {
+							effector.schedule_after_secs(forwarded, remote_id, latency);
+						} else {
+							effector.schedule_immediately(forwarded, remote_id);
+						}
+					} else {
+						log_warning!(effector, "dropping '{}' (output isn't connected)", event.name);
+					}
+				}
+
+				drop(state);
+				let _ = data.tx.send(effector);
+			}
+		});
+	}
+}
+
+fn read_params(id: ComponentID, state: &SimState) -> LinkParams
+{
+	LinkParams {
+		delay: state.get_float(id, "delay"),
+		bandwidth: state.get_float(id, "bandwidth"),
+		jitter: state.get_float(id, "jitter"),
+		loss: state.get_float(id, "loss"),
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use config::*;
+	use std::sync::{Arc, Mutex};
+	use store::*;
+
+	// Sends payloads of the given sizes at the given times and returns the time each
+	// payload arrived at along with its size (sorted by arrival time).
+	fn run_link(params: LinkParams, sends: Vec<(f64, usize)>) -> (Simulation, Vec<(f64, usize)>)
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		config.time_units = 1000.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let mut link: Link<Vec<u8>> = Link::new(&mut sim, world, "link", params).with_size(|p| p.len());
+
+		let arrivals = Arc::new(Mutex::new(Vec::new()));
+		let (_, data) = sim.add_active_component("receiver", world);
+		link.output.connect_to(&InPort::new(data.id));
+		let received = arrivals.clone();
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"msg" => {
+					let payload = event.take_payload::<Vec<u8>>();
+					received.lock().unwrap().push((state.time, payload.len()));
+				}
+			);
+		});
+
+		let (_, data) = sim.add_active_component("sender", world);
+		let mut sender = OutPort::new();
+		sender.connect_to(&link.input);
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					for &(secs, bytes) in sends.iter() {
+						sender.send_payload_after_secs(&mut effector, "msg", secs, vec![0u8; bytes]);
+					}
+				}
+			);
+		});
+
+		link.start();
+		sim.run();
+		let arrivals = arrivals.lock().unwrap().clone();
+		(sim, arrivals)
+	}
+
+	fn ms(secs: f64) -> i64
+	{
+		(secs*1000.0).round() as i64
+	}
+
+	#[test]
+	fn events_are_delayed()
+	{
+		let (sim, arrivals) = run_link(LinkParams::with_delay(0.5), vec![(1.0, 10), (2.0, 20)]);
+		let arrivals: Vec<(i64, usize)> = arrivals.iter().map(|&(t, n)| (ms(t), n)).collect();
+		assert_eq!(arrivals, vec![(1500, 10), (2500, 20)]);
+		assert_eq!(sim.store.get_int("world.link.sent"), 2);
+		assert_eq!(ms(sim.store.get_float("world.link.latency.mean")), 500);
+	}
+
+	#[test]
+	fn events_are_serialized()
+	{
+		// 8000 bits per second is 1000 bytes per second so each payload takes 0.5s to
+		// serialize. The second payload has to wait for the first and the third arrives
+		// after the link is idle again.
+		let params = LinkParams{delay: 0.1, bandwidth: 8000.0, jitter: 0.0, loss: 0.0};
+		let (_, arrivals) = run_link(params, vec![(1.0, 500), (1.0, 500), (3.0, 500)]);
+		let times: Vec<i64> = arrivals.iter().map(|&(t, _)| ms(t)).collect();
+		assert_eq!(times, vec![1600, 2100, 3600]);
+	}
+
+	#[test]
+	fn jitter_is_bounded()
+	{
+		let params = LinkParams{delay: 1.0, bandwidth: 0.0, jitter: 0.2, loss: 0.0};
+		let sends = (0..20).map(|i| (10.0*(i as f64 + 1.0), 1)).collect();
+		let (_, arrivals) = run_link(params, sends);
+		assert_eq!(arrivals.len(), 20);
+
+		let latencies: Vec<i64> = arrivals.iter().enumerate().map(|(i, &(t, _))| ms(t) - 10_000*(i as i64 + 1)).collect();
+		assert!(latencies.iter().all(|&l| l >= 1000 && l <= 1200), "latencies: {:?}", latencies);
+		assert!(latencies.iter().any(|&l| l != latencies[0]), "latencies: {:?}", latencies);
+	}
+
+	#[test]
+	fn events_are_lost()
+	{
+		let params = LinkParams{delay: 0.1, bandwidth: 0.0, jitter: 0.0, loss: 0.5};
+		let sends = (0..100).map(|i| (i as f64 + 1.0, 1)).collect();
+		let (sim, arrivals) = run_link(params, sends);
+
+		let lost = sim.store.get_int("world.link.lost");
+		assert_eq!(sim.store.get_int("world.link.sent"), 100);
+		assert_eq!(arrivals.len() as i64, 100 - lost);
+		assert!(lost > 20 && lost < 80, "lost {}", lost);
+
+		let (_, arrivals) = run_link(LinkParams{loss: 1.0, ..params}, vec![(1.0, 1), (2.0, 1)]);
+		assert!(arrivals.is_empty());
+	}
+}
@@ -0,0 +1,30 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Reusable [`Component`]s for building models, e.g. queues and resources. These are wired up
+//! using [`InPort`]s and [`OutPort`]s and publish their state to the [`Store`]
+//! (under their own path) so they can be inspected like any other component.
+//! Their parameters are also kept in the store so that they are visible to GUIs,
+//! included in dumps, and can be modified via the REST state endpoints.
+pub mod bridge;
+pub mod link;
+pub mod queue;
+pub mod resource;
+
+pub use self::bridge::*;
+pub use self::link::*;
+pub use self::queue::*;
+pub use self::resource::*;
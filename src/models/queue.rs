@@ -0,0 +1,361 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use component::*;
+use effector::*;
+use logging::*;
+use ports::*;
+use sim_state::*;
+use simulation::*;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::thread;
+use thread_data::*;
+
+/// What a [`Queue`] does when an item arrives and the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPolicy
+{
+	/// Drop the new item.
+	TailDrop,
+
+	/// Drop the oldest item to make room for the new item.
+	HeadDrop,
+
+	/// Send "blocked" to the backpressure port when the queue fills up and "unblocked"
+	/// once there is room again. Producers are expected to stop sending while blocked:
+	/// items that arrive anyway are dropped.
+	Block,
+}
+
+/// Bounded FIFO queue. Producers send "enqueue" events with a T payload to the input
+/// port. Items are forwarded to the consumer as "dequeued" events on the output port,
+/// one at a time: after processing an item the consumer sends "ready" to the ready port
+/// to get the next one (the consumer is assumed to be ready when the simulation starts).
+///
+/// The capacity and drop policy are written to the store as "capacity" and "drop-policy"
+/// ("tail-drop", "head-drop", or "block") and re-read as events are processed so they
+/// can be changed, e.g. via REST. Note that the policy can only be changed to block if
+/// the queue was created with block (otherwise the backpressure port isn't registered
+/// and the change is logged as an error and ignored). The number of queued items is published using a
+/// [`Gauge`] named "depth" and the number of dropped items using a [`Counter`] named
+/// "drops" (stats are used because several items may arrive at the same time).
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+/// use score::models::*;
+///
+/// struct Server
+/// {
+/// 	input: InPort<String>,	// receives "dequeued" events
+/// 	ready: OutPort<()>,		// sends "ready" events
+/// }
+///
+/// fn wire_up(sim: &mut Simulation, world: ComponentID, clients: &mut OutPort<String>, server: &mut Server)
+/// {
+/// 	let mut queue = Queue::new(sim, world, "queue", 100, DropPolicy::TailDrop);
+/// 	clients.connect_to(&queue.input);
+/// 	queue.output.connect_to(&server.input);
+/// 	server.ready.connect_to(&queue.ready);
+/// 	queue.start();
+/// }
+/// ```
+pub struct Queue<T: Any + Send>
+{
+	pub data: ThreadData,
+
+	/// Events sent to this port should be named "enqueue" and have a T payload.
+	pub input: InPort<T>,
+
+	/// The consumer sends "ready" events (with no payload) to this port.
+	pub ready: InPort<()>,
+
+	/// Items are sent to the consumer as "dequeued" events.
+	pub output: OutPort<T>,
+
+	/// Used with [`DropPolicy`]::Block to send "blocked" and "unblocked" events to the
+	/// producer.
+	pub backpressure: OutPort<()>,
+
+	can_block: bool,
+}
+
+impl<T: Any + Send> Queue<T>
+{
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID, name: &str, capacity: usize, policy: DropPolicy) -> Queue<T>
+	{
+		assert!(capacity > 0, "capacity should be positive");
+		let (id, data) = sim.add_active_component(name, parent_id);
+		let queue = Queue {
+			data,
+			input: InPort::new(id),
+			ready: InPort::new(id),
+			output: OutPort::new(),
+			backpressure: OutPort::new(),
+			can_block: policy == DropPolicy::Block,
+		};
+		sim.register_in_port(id, "input", &queue.input);
+		sim.register_in_port(id, "ready", &queue.ready);
+		sim.register_out_port(id, "output", &queue.output);
+		if policy == DropPolicy::Block {
+			sim.register_out_port(id, "backpressure", &queue.backpressure);
+		}
+
+		let mut effector = Effector::new();
+		effector.set_int("capacity", capacity as i64);
+		effector.set_string("drop-policy", policy.name());
+		sim.apply(id, effector);
+		queue
+	}
+
+	pub fn start(self)
+	{
+		let mut buffer = Buffer::new(1, DropPolicy::TailDrop);	// parameters are filled in from the store
+		let mut consumer_ready = true;
+
+		let data = self.data;
+		let output = self.output;
+		let backpressure = self.backpressure;
+		let can_block = self.can_block;
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					read_params(&mut buffer, can_block, data.id, &state, &mut effector);
+					publish(&buffer, 0, &mut effector);
+				},
+				"enqueue" => {
+					read_params(&mut buffer, can_block, data.id, &state, &mut effector);
+					let item = event.take_payload::<T>();
+					if consumer_ready && buffer.items.is_empty() {
+						output.send_payload(&mut effector, "dequeued", item);
+						consumer_ready = false;
+					} else {
+						let was_blocked = buffer.is_blocked();
+						let drops = buffer.drops;
+						buffer.push(item);
+						if buffer.drops > drops {
+							log_debug!(effector, "dropped an item ({} items are queued)", buffer.items.len());
+						}
+						if buffer.is_blocked() && !was_blocked {
+							backpressure.send(&mut effector, "blocked");
+						}
+						publish(&buffer, buffer.drops - drops, &mut effector);
+					}
+				},
+				"ready" => {
+					read_params(&mut buffer, can_block, data.id, &state, &mut effector);
+					let was_blocked = buffer.is_blocked();
+					match buffer.items.pop_front() {
+						Some(item) => {
+							output.send_payload(&mut effector, "dequeued", item);
+							if was_blocked {
+								backpressure.send(&mut effector, "unblocked");
+							}
+							publish(&buffer, 0, &mut effector);
+						},
+						None => consumer_ready = true,
+					}
+				}
+			);
+		});
+	}
+}
+
+impl DropPolicy
+{
+	fn name(self) -> &'static str
+	{
+		match self {
+			DropPolicy::TailDrop => "tail-drop",
+			DropPolicy::HeadDrop => "head-drop",
+			DropPolicy::Block => "block",
+		}
+	}
+
+	fn from_name(name: &str) -> Option<DropPolicy>
+	{
+		[DropPolicy::TailDrop, DropPolicy::HeadDrop, DropPolicy::Block].iter().cloned().find(|p| p.name() == name)
+	}
+}
+
+// Bad values are logged and ignored.
+fn read_params<T>(buffer: &mut Buffer<T>, can_block: bool, id: ComponentID, state: &SimState, effector: &mut Effector)
+{
+	let capacity = state.get_int(id, "capacity");
+	if capacity > 0 {
+		buffer.capacity = capacity as usize;
+	} else {
+		log_error!(effector, "capacity should be positive, not {}", capacity);
+	}
+
+	let name = state.get_string(id, "drop-policy");
+	match DropPolicy::from_name(&name) {
+		Some(DropPolicy::Block) if !can_block => log_error!(effector, "drop-policy can't be changed to block (the queue wasn't created with block)"),
+		Some(policy) => buffer.policy = policy,
+		None => log_error!(effector, "drop-policy should be tail-drop, head-drop, or block, not '{}'", name),
+	}
+}
+
+fn publish<T>(buffer: &Buffer<T>, new_drops: u64, effector: &mut Effector)
+{
+	effector.gauge("depth").set(buffer.items.len() as f64);
+	effector.counter("drops").inc(new_drops as i64);
+}
+
+struct Buffer<T>
+{
+	items: VecDeque<T>,
+	capacity: usize,
+	policy: DropPolicy,
+	drops: u64,
+}
+
+impl<T> Buffer<T>
+{
+	fn new(capacity: usize, policy: DropPolicy) -> Buffer<T>
+	{
+		Buffer{items: VecDeque::with_capacity(capacity), capacity, policy, drops: 0}
+	}
+
+	fn push(&mut self, item: T)
+	{
+		if self.items.len() < self.capacity {
+			self.items.push_back(item);
+		} else if self.policy == DropPolicy::HeadDrop {
+			self.items.pop_front();
+			self.items.push_back(item);
+			self.drops += 1;
+		} else {
+			self.drops += 1;
+		}
+	}
+
+	fn is_blocked(&self) -> bool
+	{
+		self.policy == DropPolicy::Block && self.items.len() == self.capacity
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use config::*;
+	use sim_time::*;
+	use std::sync::Arc;
+	use store::*;
+
+	#[test]
+	fn drop_policies()
+	{
+		let mut tail = Buffer::new(2, DropPolicy::TailDrop);
+		let mut head = Buffer::new(2, DropPolicy::HeadDrop);
+		let mut block = Buffer::new(2, DropPolicy::Block);
+		for i in 0..3 {
+			tail.push(i);
+			head.push(i);
+			block.push(i);
+		}
+
+		assert_eq!(tail.items, vec![0, 1]);
+		assert_eq!(head.items, vec![1, 2]);
+		assert_eq!((tail.drops, head.drops, block.drops), (1, 1, 1));
+		assert!(block.is_blocked());
+		assert!(!tail.is_blocked());
+
+		block.items.pop_front();
+		assert!(!block.is_blocked());
+
+		assert_eq!(DropPolicy::from_name(DropPolicy::HeadDrop.name()), Some(DropPolicy::HeadDrop));
+		assert_eq!(DropPolicy::from_name("bogus"), None);
+	}
+
+	#[test]
+	fn same_time_arrivals()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let mut queue: Queue<i32> = Queue::new(&mut sim, world, "queue", 1, DropPolicy::TailDrop);
+
+		// The consumer never becomes ready again so the first item is dequeued, the
+		// second is queued, and the third is dropped.
+		let (_, data) = sim.add_active_component("consumer", world);
+		queue.output.connect_to(&InPort::new(data.id));
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"dequeued" => {
+					let item = event.take_payload::<i32>();
+					log_info!(effector, "received {}", item);
+				}
+			);
+		});
+
+		let (_, data) = sim.add_active_component("producer", world);
+		let mut output = OutPort::new();
+		output.connect_to(&queue.input);
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					for i in 0..3 {
+						output.send_payload_after_secs(&mut effector, "enqueue", 1.0, i);
+					}
+				}
+			);
+		});
+
+		queue.start();
+		sim.run();
+		assert_eq!(sim.store.get_float("world.queue.depth"), 1.0);
+		assert_eq!(sim.store.get_int("world.queue.drops"), 1);
+	}
+
+	#[test]
+	fn only_blocking_queues_can_switch_to_block()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let mut queue: Queue<i32> = Queue::new(&mut sim, world, "queue", 1, DropPolicy::TailDrop);
+		Arc::make_mut(&mut sim.store).set_external("world.queue.drop-policy", StoreValue::String("block".to_string()), Time(0)).unwrap();
+
+		let (_, data) = sim.add_active_component("producer", world);
+		let mut output = OutPort::new();
+		output.connect_to(&queue.input);
+		queue.backpressure.connect_to(&InPort::new(data.id));
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					effector.set_int("blocked", 0);
+					for i in 0..3 {
+						output.send_payload_after_secs(&mut effector, "enqueue", 1.0, i);
+					}
+				},
+				"blocked" => {
+					effector.set_int("blocked", 1);
+				}
+			);
+		});
+
+		queue.start();
+		sim.run();
+		assert_eq!(sim.store.get_int("world.producer.blocked"), 0);
+		assert_eq!(sim.store.get_int("world.queue.drops"), 1);
+	}
+}
@@ -0,0 +1,235 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use component::*;
+use effector::*;
+use event::*;
+use logging::*;
+use ports::*;
+use simulation::*;
+use std::collections::VecDeque;
+use std::thread;
+use thread_data::*;
+
+/// Payload for the events sent to and from a [`Resource`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourceRequest
+{
+	/// The component that "acquired" events are sent to.
+	pub requester: ComponentID,
+
+	/// Number of units to acquire or release.
+	pub units: usize,
+}
+
+/// Counting semaphore, e.g. the machines in a machine shop or the servers in a server
+/// farm. Components send "acquire" events with a [`ResourceRequest`] payload to the
+/// input port and are sent an "acquired" event (with the same payload) once the units
+/// are available. When finished they send a "release" event to the input port.
+/// Requests are granted in FIFO order so a large request will hold up smaller requests
+/// that arrived after it.
+///
+/// The capacity is written to the store as "capacity" and re-read as events are
+/// processed so it can be changed, e.g. via REST. The number of units in use and the
+/// number of waiting requests are published using [`Gauge`]s named "in-use" and
+/// "queue-length", the time-weighted fraction of capacity in use as a gauge named
+/// "utilization", and the time requests spent waiting as a [`Histogram`] named "wait".
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+/// use score::models::*;
+///
+/// fn wire_up(sim: &mut Simulation, world: ComponentID, jobs: &mut OutPort<ResourceRequest>)
+/// {
+/// 	let machines = Resource::new(sim, world, "machines", 3);
+/// 	jobs.connect_to(&machines.input);
+/// 	machines.start();
+/// }
+/// ```
+pub struct Resource
+{
+	pub data: ThreadData,
+
+	/// Events sent to this port should be named "acquire" or "release" and have a
+	/// [`ResourceRequest`] payload.
+	pub input: InPort<ResourceRequest>,
+}
+
+impl Resource
+{
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID, name: &str, capacity: usize) -> Resource
+	{
+		assert!(capacity > 0, "capacity should be positive");
+		let (id, data) = sim.add_active_component(name, parent_id);
+		let resource = Resource {
+			data,
+			input: InPort::new(id),
+		};
+		sim.register_in_port(id, "input", &resource.input);
+
+		let mut effector = Effector::new();
+		effector.set_int("capacity", capacity as i64);
+		sim.apply(id, effector);
+		resource
+	}
+
+	pub fn start(self)
+	{
+		let mut pool = Pool::new(1);	// capacity is filled in from the store
+
+		let data = self.data;
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					pool.capacity = read_capacity(state.get_int(data.id, "capacity"), pool.capacity, &mut effector);
+					publish(&pool, state.time, &mut effector);
+				},
+				"acquire" => {
+					pool.capacity = read_capacity(state.get_int(data.id, "capacity"), pool.capacity, &mut effector);
+					let request = event.take_payload::<ResourceRequest>();
+					if request.units > pool.capacity {
+						log_warning!(effector, "request for {} units will wait until capacity is at least that", request.units);
+					}
+					let granted = pool.acquire(request, state.time);
+					grant(granted, state.time, &mut effector);
+					publish(&pool, state.time, &mut effector);
+				},
+				"release" => {
+					pool.capacity = read_capacity(state.get_int(data.id, "capacity"), pool.capacity, &mut effector);
+					let request = event.take_payload::<ResourceRequest>();
+					if request.units > pool.in_use {
+						log_error!(effector, "released {} units but only {} are in use", request.units, pool.in_use);
+					}
+					let granted = pool.release(request.units, state.time);
+					grant(granted, state.time, &mut effector);
+					publish(&pool, state.time, &mut effector);
+				}
+			);
+		});
+	}
+}
+
+// Bad values are logged and ignored.
+fn read_capacity(capacity: i64, old: usize, effector: &mut Effector) -> usize
+{
+	if capacity > 0 {
+		capacity as usize
+	} else {
+		log_error!(effector, "capacity should be positive, not {}", capacity);
+		old
+	}
+}
+
+fn grant(granted: Vec<(ResourceRequest, f64)>, time: f64, effector: &mut Effector)
+{
+	for (request, requested) in granted {
+		effector.histogram("wait").record(time - requested);
+		let to = request.requester;
+		effector.schedule_immediately(Event::with_payload("acquired", request), to);
+	}
+}
+
+fn publish(pool: &Pool, time: f64, effector: &mut Effector)
+{
+	effector.gauge("in-use").set(pool.in_use as f64);
+	effector.gauge("queue-length").set(pool.waiting.len() as f64);
+	effector.gauge("utilization").set(pool.utilization(time));
+}
+
+struct Pool
+{
+	capacity: usize,
+	in_use: usize,
+	waiting: VecDeque<(ResourceRequest, f64)>,	// request, time it was made
+
+	busy: f64,		// integral of in_use/capacity over time
+	last_time: f64,
+}
+
+impl Pool
+{
+	fn new(capacity: usize) -> Pool
+	{
+		Pool{capacity, in_use: 0, waiting: VecDeque::new(), busy: 0.0, last_time: 0.0}
+	}
+
+	// Returns the requests that were granted (with the time they were made).
+	fn acquire(&mut self, request: ResourceRequest, time: f64) -> Vec<(ResourceRequest, f64)>
+	{
+		self.advance(time);
+		self.waiting.push_back((request, time));
+		self.grant()
+	}
+
+	fn release(&mut self, units: usize, time: f64) -> Vec<(ResourceRequest, f64)>
+	{
+		self.advance(time);
+		self.in_use -= units.min(self.in_use);
+		self.grant()
+	}
+
+	fn utilization(&self, time: f64) -> f64
+	{
+		let busy = self.busy + (time - self.last_time)*(self.in_use as f64)/(self.capacity as f64);
+		if time > 0.0 {busy/time} else {0.0}
+	}
+
+	fn advance(&mut self, time: f64)
+	{
+		self.busy += (time - self.last_time)*(self.in_use as f64)/(self.capacity as f64);
+		self.last_time = time;
+	}
+
+	fn grant(&mut self) -> Vec<(ResourceRequest, f64)>
+	{
+		let mut granted = Vec::new();
+		while self.waiting.front().map_or(false, |w| self.in_use + w.0.units <= self.capacity) {
+			let (request, time) = self.waiting.pop_front().unwrap();
+			self.in_use += request.units;
+			granted.push((request, time));
+		}
+		granted
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn request(id: usize, units: usize) -> ResourceRequest
+	{
+		ResourceRequest{requester: ComponentID(id), units}
+	}
+
+	#[test]
+	fn requests_are_fifo()
+	{
+		let mut pool = Pool::new(2);
+		assert_eq!(pool.acquire(request(1, 1), 0.0), vec![(request(1, 1), 0.0)]);
+		assert_eq!(pool.acquire(request(2, 2), 1.0), vec![]);
+		assert_eq!(pool.acquire(request(3, 1), 2.0), vec![]);	// blocked behind 2
+		assert_eq!(pool.waiting.len(), 2);
+
+		assert_eq!(pool.release(1, 4.0), vec![(request(2, 2), 1.0)]);
+		assert_eq!(pool.release(2, 6.0), vec![(request(3, 1), 2.0)]);
+		assert_eq!(pool.in_use, 1);
+
+		// half busy for 4s, fully busy for 2s, and then half busy for 2s
+		assert_eq!(pool.utilization(8.0), 5.0/8.0);
+	}
+}
@@ -0,0 +1,272 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use event::*;
+use rustc_serialize::{json, Decodable, Encodable};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+type Cloner = fn (&(Any + Send)) -> Box<Any + Send>;
+type Encoder = fn (&(Any + Send)) -> String;
+type Decoder = fn (&str) -> Result<Box<Any + Send>, String>;
+type Debugger = fn (&(Any + Send)) -> String;
+type PayloadHasher = fn (&(Any + Send)) -> u64;
+
+/// [`Event`] payloads are arbitrary types so, in general, the `Simulation` cannot copy
+/// them. This is used to record the payload types that can be copied (via the
+/// `Simulation`'s register_payload method) and the types that can be written to
+/// traces (via register_traced_payload or register_versioned_payload). Payloads can
+/// also be registered so that they are hashed into the finger print.
+pub(crate) struct PayloadRegistry
+{
+	cloners: HashMap<TypeId, Cloner>,
+	encoders: HashMap<TypeId, (String, u32, Encoder)>,
+	decoders: HashMap<String, (u32, Decoder)>,
+	debuggers: HashMap<TypeId, Debugger>,
+	hashers: HashMap<TypeId, PayloadHasher>,
+}
+
+impl PayloadRegistry
+{
+	pub(crate) fn new() -> PayloadRegistry
+	{
+		PayloadRegistry{cloners: HashMap::new(), encoders: HashMap::new(), decoders: HashMap::new(), debuggers: HashMap::new(), hashers: HashMap::new()}
+	}
+
+	pub(crate) fn register<T: Any + Send + Clone>(&mut self)
+	{
+		self.cloners.insert(TypeId::of::<T>(), clone_payload::<T>);
+	}
+
+	/// Name and version are written into traces to identify the payload's type so name
+	/// should be unique and stable across runs and version should be bumped whenever
+	/// the type's encoding changes.
+	pub(crate) fn register_traced<T: Any + Send + Clone + Encodable + Decodable>(&mut self, name: &str, version: u32)
+	{
+		assert!(!self.decoders.contains_key(name), "{} has already been registered", name);
+		self.register::<T>();
+		self.encoders.insert(TypeId::of::<T>(), (name.to_string(), version, encode_payload::<T>));
+		self.decoders.insert(name.to_string(), (version, decode_payload::<T>));
+	}
+	
+	pub(crate) fn register_debug<T: Any + Send + PayloadDebug>(&mut self)
+	{
+		self.debuggers.insert(TypeId::of::<T>(), debug_payload::<T>);
+	}
+	
+	pub(crate) fn register_hash<T: Any + Send + Hash>(&mut self)
+	{
+		self.hashers.insert(TypeId::of::<T>(), hash_payload::<T>);
+	}
+	
+	/// Hashes the payload using Hash if it was registered, or its JSON if it was
+	/// registered with register_traced. Otherwise returns None.
+	pub(crate) fn hash(&self, payload: &(Any + Send)) -> Option<u64>
+	{
+		match self.hashers.get(&payload.type_id()) {
+			Some(hasher) => Some(hasher(payload)),
+			None => self.encoders.get(&payload.type_id()).map(|&(_, _, encoder)| hash_value(&encoder(payload))),
+		}
+	}
+	
+	/// Renders the payload using PayloadDebug if it was registered, or as JSON if
+	/// it was registered with register_traced. Otherwise returns None.
+	pub(crate) fn describe(&self, payload: &(Any + Send)) -> Option<String>
+	{
+		match self.debuggers.get(&payload.type_id()) {
+			Some(debugger) => Some(debugger(payload)),
+			None => self.encoders.get(&payload.type_id()).map(|&(_, _, encoder)| encoder(payload)),
+		}
+	}
+	
+	/// Returns the registered name and version for the payload type and the payload as
+	/// JSON or None if the type wasn't registered with register_traced.
+	pub(crate) fn encode(&self, payload: &(Any + Send)) -> Option<(String, u32, String)>
+	{
+		self.encoders.get(&payload.type_id()).map(|&(ref name, version, encoder)| (name.clone(), version, encoder(payload)))
+	}
+	
+	/// Returns an error if the version doesn't match the registered version: decoding
+	/// would likely either fail or, worse, silently misinterpret the payload.
+	pub(crate) fn decode(&self, name: &str, version: u32, text: &str) -> Result<Box<Any + Send>, String>
+	{
+		match self.decoders.get(name) {
+			Some(&(registered, _)) if registered != version =>
+				Err(format!("payload type '{}' was recorded as version {} but version {} is registered", name, version, registered)),
+			Some(&(_, decoder)) => decoder(text),
+			None => Err(format!("payload type '{}' wasn't registered with register_traced_payload", name)),
+		}
+	}
+	
+	/// Returns an error if the event has a payload that wasn't registered.
+	pub(crate) fn clone_event(&self, event: &Event) -> Result<Event, String>
+	{
+		let payload = match event.payload {
+			Some(ref payload) => {
+				let payload: &(Any + Send) = &**payload;
+				match self.cloners.get(&payload.type_id()) {
+					Some(cloner) => Some(cloner(payload)),
+					None => return Err(format!("event '{}' has a payload type that wasn't registered with register_payload", event.name)),
+				}
+			},
+			None => None,
+		};
+		Ok(Event{name: event.name.clone(), port_name: event.port_name.clone(), payload, payload_type: event.payload_type, priority: event.priority, correlation: event.correlation.clone()})
+	}
+}
+
+fn clone_payload<T: Any + Send + Clone>(payload: &(Any + Send)) -> Box<Any + Send>
+{
+	Box::new(payload.downcast_ref::<T>().unwrap().clone())
+}
+
+fn encode_payload<T: Any + Send + Encodable>(payload: &(Any + Send)) -> String
+{
+	json::encode(payload.downcast_ref::<T>().unwrap()).unwrap()
+}
+
+fn debug_payload<T: Any + Send + PayloadDebug>(payload: &(Any + Send)) -> String
+{
+	payload.downcast_ref::<T>().unwrap().debug_string()
+}
+
+// We don't use DefaultHasher because its output may change between Rust releases
+// (and finger prints are compared across builds).
+fn hash_value<T: Hash + ?Sized>(value: &T) -> u64
+{
+	let mut hasher = FnvHasher(0xcbf29ce484222325);
+	value.hash(&mut hasher);
+	hasher.finish()
+}
+
+// FNV-1a
+struct FnvHasher(u64);
+
+impl Hasher for FnvHasher
+{
+	fn finish(&self) -> u64
+	{
+		self.0
+	}
+
+	fn write(&mut self, bytes: &[u8])
+	{
+		for &b in bytes {
+			self.0 ^= b as u64;
+			self.0 = self.0.wrapping_mul(0x100000001b3);
+		}
+	}
+}
+
+fn hash_payload<T: Any + Send + Hash>(payload: &(Any + Send)) -> u64
+{
+	hash_value(payload.downcast_ref::<T>().unwrap())
+}
+
+fn decode_payload<T: Any + Send + Decodable>(text: &str) -> Result<Box<Any + Send>, String>
+{
+	match json::decode::<T>(text) {
+		Ok(value) => Ok(Box::new(value)),
+		Err(err) => Err(format!("couldn't decode payload: {}", err)),
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	
+	#[test]
+	fn clones_registered_payloads()
+	{
+		let mut registry = PayloadRegistry::new();
+		registry.register::<String>();
+		
+		let event = Event::with_payload("text", "hello".to_string());
+		let copy = registry.clone_event(&event).unwrap();
+		assert_eq!(copy.payload_ref::<String>("should be a String"), "hello");
+		
+		let event = Event::with_payload("count", 10i64);
+		assert!(registry.clone_event(&event).is_err());
+	}
+	
+	#[test]
+	fn encodes_traced_payloads()
+	{
+		let mut registry = PayloadRegistry::new();
+		registry.register_traced::<i64>("count", 2);
+		
+		let payload: Box<Any + Send> = Box::new(10i64);
+		let (name, version, text) = registry.encode(&*payload).unwrap();
+		assert_eq!((name.as_str(), version), ("count", 2));
+		
+		let payload = registry.decode(&name, version, &text).unwrap();
+		assert_eq!(*payload.downcast::<i64>().unwrap(), 10);
+		assert!(registry.decode("bogus", version, &text).is_err());
+		
+		let err = registry.decode(&name, 1, &text).unwrap_err();
+		assert!(err.contains("version 1"), "{}", err);
+	}
+	
+	#[test]
+	fn hashes_registered_payloads()
+	{
+		let mut registry = PayloadRegistry::new();
+		registry.register_hash::<String>();
+		registry.register_traced::<i64>("count", 1);
+		
+		let hello: Box<Any + Send> = Box::new("hello".to_string());
+		let goodbye: Box<Any + Send> = Box::new("goodbye".to_string());
+		assert_eq!(registry.hash(&*hello), Some(hash_value("hello")));
+		assert_ne!(registry.hash(&*hello), registry.hash(&*goodbye));
+		
+		let payload: Box<Any + Send> = Box::new(10i64);
+		assert_eq!(registry.hash(&*payload), Some(hash_value("10")));
+		
+		let payload: Box<Any + Send> = Box::new(10u8);
+		assert_eq!(registry.hash(&*payload), None);
+	}
+	
+	struct Message
+	{
+		seq: u32,
+	}
+	
+	impl PayloadDebug for Message
+	{
+		fn debug_string(&self) -> String
+		{
+			format!("message #{}", self.seq)
+		}
+	}
+	
+	#[test]
+	fn describes_payloads()
+	{
+		let mut registry = PayloadRegistry::new();
+		registry.register_debug::<Message>();
+		registry.register_traced::<i64>("count", 1);
+		
+		let payload: Box<Any + Send> = Box::new(Message{seq: 3});
+		assert_eq!(registry.describe(&*payload), Some("message #3".to_string()));
+		
+		let payload: Box<Any + Send> = Box::new(10i64);
+		assert_eq!(registry.describe(&*payload), Some("10".to_string()));
+		
+		let payload: Box<Any + Send> = Box::new("hello".to_string());
+		assert_eq!(registry.describe(&*payload), None);
+	}
+}
@@ -0,0 +1,498 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! A hash array mapped trie. Clones share nodes and writes copy only the (at most 13)
+//! nodes along the path to the key so the [`Store`] can be snapshotted every time slice
+//! without the next write copying the whole store.
+use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::Index;
+use std::slice;
+use std::sync::Arc;
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+pub(crate) struct PersistentMap<K, V>
+{
+	root: Arc<Node<K, V>>,
+	len: usize,
+}
+
+#[derive(Clone)]
+struct Node<K, V>
+{
+	bitmap: u32,			// bit i is set if the node has a slot for hash fragment i
+	slots: Vec<Slot<K, V>>,	// in fragment order
+}
+
+#[derive(Clone)]
+enum Slot<K, V>
+{
+	Leaf(u64, K, V),
+	Collision(u64, Vec<(K, V)>),	// keys with identical hashes
+	Branch(Arc<Node<K, V>>),
+}
+
+pub(crate) struct Iter<'a, K: 'a, V: 'a>
+{
+	stack: Vec<slice::Iter<'a, Slot<K, V>>>,
+	collision: Option<slice::Iter<'a, (K, V)>>,
+	remaining: usize,
+}
+
+// Implemented by hand so that clones don't require K and V to be Clone.
+impl<K, V> Clone for PersistentMap<K, V>
+{
+	fn clone(&self) -> PersistentMap<K, V>
+	{
+		PersistentMap{root: self.root.clone(), len: self.len}
+	}
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersistentMap<K, V>
+{
+	pub(crate) fn new() -> PersistentMap<K, V>
+	{
+		PersistentMap{root: Arc::new(Node{bitmap: 0, slots: Vec::new()}), len: 0}
+	}
+
+	pub(crate) fn is_empty(&self) -> bool
+	{
+		self.len == 0
+	}
+
+	pub(crate) fn contains_key<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> bool
+		where K: Borrow<Q>
+	{
+		self.get(key).is_some()
+	}
+
+	pub(crate) fn get<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<&V>
+		where K: Borrow<Q>
+	{
+		let hash = hash_of(key);
+		let mut node = &*self.root;
+		let mut shift = 0;
+		loop {
+			let bit = fragment_bit(hash, shift);
+			if node.bitmap & bit == 0 {
+				return None;
+			}
+			match node.slots[slot_index(node.bitmap, bit)] {
+				Slot::Leaf(h, ref k, ref v) => return if h == hash && k.borrow() == key {Some(v)} else {None},
+				Slot::Collision(h, ref entries) => return if h == hash {entries.iter().find(|e| e.0.borrow() == key).map(|e| &e.1)} else {None},
+				Slot::Branch(ref child) => {
+					node = child;
+					shift += BITS;
+				},
+			}
+		}
+	}
+
+	/// Copies the nodes along the path to key if they are shared.
+	pub(crate) fn get_mut<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<&mut V>
+		where K: Borrow<Q>
+	{
+		self.get(key)?;	// so that misses don't copy nodes
+		get_mut(&mut self.root, 0, hash_of(key), key)
+	}
+
+	/// Returns the old value if there was one.
+	pub(crate) fn insert(&mut self, key: K, value: V) -> Option<V>
+	{
+		let hash = hash_of(&key);
+		let old = insert(&mut self.root, 0, hash, key, value);
+		if old.is_none() {
+			self.len += 1;
+		}
+		old
+	}
+
+	pub(crate) fn remove<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<V>
+		where K: Borrow<Q>
+	{
+		self.get(key)?;
+		self.len -= 1;
+		remove(&mut self.root, 0, hash_of(key), key)
+	}
+
+	/// Iteration order is arbitrary (but deterministic).
+	pub(crate) fn iter<'a>(&'a self) -> Iter<'a, K, V>
+	{
+		Iter{stack: vec![self.root.slots.iter()], collision: None, remaining: self.len}
+	}
+
+	pub(crate) fn keys(&self) -> impl Iterator<Item=&K>
+	{
+		self.iter().map(|(k, _)| k)
+	}
+
+	/// Returns true if the two maps share all of their nodes.
+	#[cfg(test)]
+	pub(crate) fn ptr_eq(&self, rhs: &PersistentMap<K, V>) -> bool
+	{
+		Arc::ptr_eq(&self.root, &rhs.root)
+	}
+
+	/// Returns the number of nodes in self that aren't shared with rhs.
+	#[cfg(test)]
+	pub(crate) fn unshared_nodes(&self, rhs: &PersistentMap<K, V>) -> usize
+	{
+		unshared_nodes(&self.root, Some(&rhs.root))
+	}
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for PersistentMap<K, V>
+{
+	fn default() -> PersistentMap<K, V>
+	{
+		PersistentMap::new()
+	}
+}
+
+impl<'a, K: Hash + Eq + Clone, V: Clone> IntoIterator for &'a PersistentMap<K, V>
+{
+	type Item = (&'a K, &'a V);
+	type IntoIter = Iter<'a, K, V>;
+
+	fn into_iter(self) -> Iter<'a, K, V>
+	{
+		self.iter()
+	}
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for PersistentMap<K, V>
+{
+	fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> PersistentMap<K, V>
+	{
+		let mut map = PersistentMap::new();
+		for (key, value) in iter {
+			map.insert(key, value);
+		}
+		map
+	}
+}
+
+impl<'a, K: Hash + Eq + Clone + Borrow<Q>, Q: ?Sized + Hash + Eq, V: Clone> Index<&'a Q> for PersistentMap<K, V>
+{
+	type Output = V;
+
+	fn index(&self, key: &Q) -> &V
+	{
+		self.get(key).expect("key is missing")
+	}
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V>
+{
+	type Item = (&'a K, &'a V);
+
+	fn next(&mut self) -> Option<(&'a K, &'a V)>
+	{
+		loop {
+			if let Some(&(ref k, ref v)) = self.collision.as_mut().and_then(|c| c.next()) {
+				self.remaining -= 1;
+				return Some((k, v));
+			}
+			let slot = match self.stack.last_mut() {
+				Some(slots) => slots.next(),
+				None => return None,
+			};
+			match slot {
+				Some(&Slot::Leaf(_, ref k, ref v)) => {
+					self.remaining -= 1;
+					return Some((k, v));
+				},
+				Some(&Slot::Collision(_, ref entries)) => self.collision = Some(entries.iter()),
+				Some(&Slot::Branch(ref child)) => self.stack.push(child.slots.iter()),
+				None => {self.stack.pop();},
+			}
+		}
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>)
+	{
+		(self.remaining, Some(self.remaining))
+	}
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V>
+{
+}
+
+#[cfg(feature = "serialize")]
+mod serialization
+{
+	use super::*;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use std::collections::HashMap;
+
+	// These use the same format as HashMap so older saved stores can still be loaded.
+	impl<K: Hash + Eq + Clone + Serialize, V: Clone + Serialize> Serialize for PersistentMap<K, V>
+	{
+		fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+		{
+			serializer.collect_map(self.iter())
+		}
+	}
+
+	impl<'de, K: Hash + Eq + Clone + Deserialize<'de>, V: Clone + Deserialize<'de>> Deserialize<'de> for PersistentMap<K, V>
+	{
+		fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PersistentMap<K, V>, D::Error>
+		{
+			let map: HashMap<K, V> = HashMap::deserialize(deserializer)?;
+			Ok(map.into_iter().collect())
+		}
+	}
+}
+
+fn hash_of<Q: ?Sized + Hash>(key: &Q) -> u64
+{
+	let mut hasher = DefaultHasher::new();
+	key.hash(&mut hasher);
+	hasher.finish()
+}
+
+fn fragment_bit(hash: u64, shift: u32) -> u32
+{
+	1 << ((hash >> shift) & MASK)
+}
+
+fn slot_index(bitmap: u32, bit: u32) -> usize
+{
+	(bitmap & (bit - 1)).count_ones() as usize
+}
+
+fn get_mut<'a, K: Clone + Borrow<Q>, V: Clone, Q: ?Sized + Eq>(node: &'a mut Arc<Node<K, V>>, shift: u32, hash: u64, key: &Q) -> Option<&'a mut V>
+{
+	let node = Arc::make_mut(node);
+	let i = slot_index(node.bitmap, fragment_bit(hash, shift));
+	match node.slots[i] {
+		Slot::Leaf(_, _, ref mut v) => Some(v),
+		Slot::Collision(_, ref mut entries) => entries.iter_mut().find(|e| e.0.borrow() == key).map(|e| &mut e.1),
+		Slot::Branch(ref mut child) => get_mut(child, shift + BITS, hash, key),
+	}
+}
+
+fn insert<K: Eq + Clone, V: Clone>(node: &mut Arc<Node<K, V>>, shift: u32, hash: u64, key: K, value: V) -> Option<V>
+{
+	let node = Arc::make_mut(node);
+	let bit = fragment_bit(hash, shift);
+	let i = slot_index(node.bitmap, bit);
+	if node.bitmap & bit == 0 {
+		node.bitmap |= bit;
+		node.slots.insert(i, Slot::Leaf(hash, key, value));
+		return None;
+	}
+
+	match node.slots[i] {
+		Slot::Branch(ref mut child) => return insert(child, shift + BITS, hash, key, value),
+		Slot::Leaf(h, ref k, ref mut v) if h == hash && *k == key => return Some(mem::replace(v, value)),
+		Slot::Collision(h, ref mut entries) if h == hash => {
+			if let Some(entry) = entries.iter_mut().find(|e| e.0 == key) {
+				return Some(mem::replace(&mut entry.1, value));
+			}
+			entries.push((key, value));
+			return None;
+		},
+		_ => (),
+	}
+
+	// The slot has other keys so they're either moved into a new node or, if the
+	// hashes are identical, into a collision list.
+	let old = mem::replace(&mut node.slots[i], Slot::Collision(hash, Vec::new()));
+	node.slots[i] = match old {
+		Slot::Leaf(h, k, v) if h == hash => Slot::Collision(hash, vec![(k, v), (key, value)]),
+		old => {
+			let h = match old {
+				Slot::Leaf(h, _, _) | Slot::Collision(h, _) => h,
+				Slot::Branch(_) => unreachable!(),
+			};
+			let mut child = Arc::new(Node{bitmap: fragment_bit(h, shift + BITS), slots: vec![old]});
+			insert(&mut child, shift + BITS, hash, key, value);
+			Slot::Branch(child)
+		},
+	};
+	None
+}
+
+// Key must be in the trie. Nodes left with a single leaf are folded into their parent.
+fn remove<K: Clone + Borrow<Q>, V: Clone, Q: ?Sized + Eq>(node: &mut Arc<Node<K, V>>, shift: u32, hash: u64, key: &Q) -> Option<V>
+{
+	let node = Arc::make_mut(node);
+	let bit = fragment_bit(hash, shift);
+	let i = slot_index(node.bitmap, bit);
+	let (value, replacement) = match node.slots[i] {
+		Slot::Leaf(..) => (None, None),
+		Slot::Collision(h, ref mut entries) => {
+			let j = entries.iter().position(|e| e.0.borrow() == key).unwrap();
+			let value = entries.remove(j).1;
+			if entries.len() > 1 {
+				return Some(value);
+			}
+			let (k, v) = entries.pop().unwrap();
+			(Some(value), Some(Slot::Leaf(h, k, v)))
+		},
+		Slot::Branch(ref mut child) => {
+			let value = remove(child, shift + BITS, hash, key);
+			let is_branch = match child.slots[0] {Slot::Branch(_) => true, _ => false};
+			if child.slots.len() > 1 || is_branch {
+				return value;
+			}
+			(value, Some(child.slots[0].clone()))
+		},
+	};
+
+	match replacement {
+		Some(slot) => {
+			node.slots[i] = slot;
+			value
+		},
+		None => {
+			node.bitmap &= !bit;
+			match node.slots.remove(i) {
+				Slot::Leaf(_, _, v) => Some(v),
+				_ => unreachable!(),
+			}
+		},
+	}
+}
+
+#[cfg(test)]
+fn unshared_nodes<K, V>(node: &Arc<Node<K, V>>, rhs: Option<&Arc<Node<K, V>>>) -> usize
+{
+	if let Some(rhs) = rhs {
+		if Arc::ptr_eq(node, rhs) {
+			return 0;
+		}
+	}
+
+	let mut count = 1;
+	for (i, slot) in node.slots.iter().enumerate() {
+		if let Slot::Branch(ref child) = *slot {
+			// Find the matching slot in rhs using the fragment for slot i.
+			let bit = nth_bit(node.bitmap, i);
+			let other = rhs.and_then(|rhs| if rhs.bitmap & bit != 0 {
+				match rhs.slots[slot_index(rhs.bitmap, bit)] {
+					Slot::Branch(ref other) => Some(other),
+					_ => None,
+				}
+			} else {
+				None
+			});
+			count += unshared_nodes(child, other);
+		}
+	}
+	count
+}
+
+#[cfg(test)]
+fn nth_bit(bitmap: u32, n: usize) -> u32
+{
+	let mut bits = bitmap;
+	for _ in 0..n {
+		bits &= bits - 1;	// clear the lowest set bit
+	}
+	bits & bits.wrapping_neg()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::collections::HashMap;
+
+	// All keys hash to the same value so that collision lists are exercised.
+	#[derive(Clone, Debug, PartialEq, Eq)]
+	struct Colliding(u32);
+
+	impl Hash for Colliding
+	{
+		fn hash<H: Hasher>(&self, state: &mut H)
+		{
+			0.hash(state);
+		}
+	}
+
+	#[test]
+	fn matches_hash_map()
+	{
+		let mut map = PersistentMap::new();
+		let mut expected = HashMap::new();
+		for i in 0..5000 {
+			let key = format!("key{}", (i*7919) % 3000);
+			assert_eq!(map.insert(key.clone(), i), expected.insert(key, i));
+		}
+		for i in 0..1000 {
+			let key = format!("key{}", (i*31) % 4000);
+			assert_eq!(map.remove(&key), expected.remove(&key));
+		}
+
+		assert_eq!(map.iter().len(), expected.len());
+		assert_eq!(map.iter().count(), expected.len());
+		for (key, value) in map.iter() {
+			assert_eq!(expected.get(key), Some(value));
+		}
+		for (key, value) in expected.iter() {
+			assert_eq!(map.get(key), Some(value));
+		}
+	}
+
+	#[test]
+	fn handles_collisions()
+	{
+		let mut map = PersistentMap::new();
+		for i in 0..10 {
+			map.insert(Colliding(i), i);
+		}
+		assert_eq!(map.insert(Colliding(3), 30), Some(3));
+		assert_eq!(map.remove(&Colliding(4)), Some(4));
+		assert_eq!(map.remove(&Colliding(4)), None);
+		assert_eq!(map.iter().len(), 9);
+		assert_eq!(map.get(&Colliding(3)), Some(&30));
+
+		for i in 0..10 {
+			map.remove(&Colliding(i));
+		}
+		assert!(map.is_empty());
+		assert_eq!(map.iter().count(), 0);
+	}
+
+	#[test]
+	fn clones_share_nodes()
+	{
+		let mut map = PersistentMap::new();
+		for i in 0..10000 {
+			map.insert(i, i);
+		}
+
+		let snapshot = map.clone();
+		*map.get_mut(&17).unwrap() = -17;
+		map.remove(&18);
+		map.insert(10000, 10000);
+		assert_eq!(snapshot.get(&17), Some(&17));
+		assert_eq!(snapshot.get(&18), Some(&18));
+		assert_eq!(snapshot.get(&10000), None);
+		assert_eq!(map[&17], -17);
+
+		// Three writes copy at most three paths.
+		assert!(map.unshared_nodes(&snapshot) <= 3*5, "{} nodes were copied", map.unshared_nodes(&snapshot));
+	}
+}
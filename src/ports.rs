@@ -20,8 +20,12 @@ use component::*;
 use effector::*;
 use logging::*;
 use event::*;
-use std::any::Any;
+use event_name::*;
+use sim_state::*;
+use std::any::{self, Any};
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// OutPort's are connected to InPort's.
 #[derive(Clone)]
@@ -39,15 +43,48 @@ pub struct OutPort<T: Any + Send>
 	// whine at us if we don't use it somewhere so we include this zero-sized
 	// field.
 	dummy: PhantomData<T>,
+	
+	// Number of times connect_to has been called. This is shared with clones so
+	// that the Simulation can validate registered ports after they are wired up.
+	pub(crate) connections: Arc<AtomicUsize>,
+	
+	// The component and port the OutPort was last connected to. This is shared with
+	// clones so that the Simulation can export the topology. It's not used when sending.
+	pub(crate) remote: Arc<Mutex<(ComponentID, String)>>,
+	
+	// Set by Simulation's connect method (before the run starts). Events sent to this
+	// component use wire as their port name which the Simulation replaces with the
+	// InPort's name when the event is scheduled. This way sends don't need to lock.
+	pub(crate) wired_id: Arc<AtomicUsize>,
+	pub(crate) wire: EventName,
 }
 
+// Port names starting with this are placeholders for ports wired up by the Simulation.
+pub(crate) const WIRE_PREFIX: char = '\u{1}';
+
+static NEXT_WIRE: AtomicUsize = AtomicUsize::new(0);
+
 /// Use OutPort's connect_to method to connect up ports.
-#[derive(Clone)]
 pub struct InPort<T: Any + Send>
 {
-	target_id: ComponentID,
+	pub(crate) target_id: ComponentID,
 	pub target_port: String,
 	dummy: PhantomData<T>,
+	pub(crate) connections: Arc<AtomicUsize>,	// number of OutPorts connected to this
+}
+
+// Implemented by hand so that ports can be cloned even when T isn't Clone.
+impl<T: Any + Send> Clone for InPort<T>
+{
+	fn clone(&self) -> InPort<T>
+	{
+		InPort {
+			target_id: self.target_id,
+			target_port: self.target_port.clone(),
+			dummy: PhantomData,
+			connections: self.connections.clone(),
+		}
+	}
 }
 
 impl<T: Any + Send> InPort<T>
@@ -78,7 +115,7 @@ impl<T: Any + Send> InPort<T>
 	/// 			nested: Nested::new(sim, id),
 	/// 			inbound: InPort::empty(),
 	/// 		};
-	/// 		device.inbound = device.nested.input.clone();
+	/// 		device.inbound = sim.export_in_port(id, "inbound", &device.nested.input);
 	/// 		device
 	/// 	}
 	///
@@ -132,6 +169,7 @@ impl<T: Any + Send> InPort<T>
 			target_id: NO_COMPONENT,
 			target_port: "".to_string(),
 			dummy: PhantomData,
+			connections: Arc::new(AtomicUsize::new(0)),
 		}
 	}
 
@@ -141,6 +179,7 @@ impl<T: Any + Send> InPort<T>
 			target_id: id,
 			target_port: "".to_string(),
 			dummy: PhantomData,
+			connections: Arc::new(AtomicUsize::new(0)),
 		}
 	}
 
@@ -152,8 +191,56 @@ impl<T: Any + Send> InPort<T>
 			target_id: id,
 			target_port: port.to_string(),
 			dummy: PhantomData,
+			connections: Arc::new(AtomicUsize::new(0)),
+		}
+	}
+	
+	/// Returns true if the event arrived on this port.
+	pub fn received(&self, event: &Event) -> bool
+	{
+		event.port_name == self.target_port
+	}
+	
+	/// Returns a reference to the event's payload. Panics, with a message that includes
+	/// the component's path, if the event didn't arrive on this port or the payload
+	/// isn't a T.
+	pub fn expect<'a>(&self, event: &'a Event, state: &SimState) -> &'a T
+	{
+		self.check_port(event, state);
+		match event.payload {
+			Some(ref payload) => match payload.downcast_ref::<T>() {
+				Some(value) => value,
+				None => panic!("{} expected a payload of type {}", self.describe(event, state), any::type_name::<T>()),
+			},
+			None => panic!("{} expected a payload of type {} but there was no payload", self.describe(event, state), any::type_name::<T>()),
+		}
+	}
+	
+	/// Like expect except that the payload is moved out of the event.
+	pub fn take(&self, event: &mut Event, state: &SimState) -> T
+	{
+		self.check_port(event, state);
+		match event.payload.take() {
+			Some(payload) => match payload.downcast::<T>() {
+				Ok(value) => *value,
+				Err(_) => panic!("{} expected a payload of type {}", self.describe(event, state), any::type_name::<T>()),
+			},
+			None => panic!("{} expected a payload of type {} but there was no payload", self.describe(event, state), any::type_name::<T>()),
+		}
+	}
+	
+	fn check_port(&self, event: &Event, state: &SimState)
+	{
+		if !self.received(event) {
+			panic!("{} expected port '{}'", self.describe(event, state), self.target_port);
 		}
 	}
+	
+	fn describe(&self, event: &Event, state: &SimState) -> String
+	{
+		let path = if self.target_id != NO_COMPONENT {state.path(self.target_id)} else {"unconnected port"};
+		format!("{} event '{}' on port '{}'", path, event.name, event.port_name)
+	}
 }
 
 impl<T: Any + Send> OutPort<T>
@@ -164,6 +251,10 @@ impl<T: Any + Send> OutPort<T>
 			remote_id: NO_COMPONENT,
 			remote_port: "".to_string(),
 			dummy: PhantomData,
+			connections: Arc::new(AtomicUsize::new(0)),
+			remote: Arc::new(Mutex::new((NO_COMPONENT, "".to_string()))),
+			wired_id: Arc::new(AtomicUsize::new(NO_COMPONENT.0)),
+			wire: EventName::from(format!("{}wire{}", WIRE_PREFIX, NEXT_WIRE.fetch_add(1, Ordering::Relaxed))),
 		}
 	}
 
@@ -171,9 +262,9 @@ impl<T: Any + Send> OutPort<T>
 	/// Drops the event if the port isn't connected to an `InPort`.
 	pub fn send_payload(&self, effector: &mut Effector, name: &str, payload: T)
 	{
-		if self.remote_id != NO_COMPONENT {
-			let event = Event::with_port_payload(name, &self.remote_port, payload);
-			effector.schedule_immediately(event, self.remote_id);
+		if let Some((remote_id, remote_port)) = self.route() {
+			let event = Event::with_port_payload(name, remote_port, payload);
+			effector.schedule_immediately(event, remote_id);
 		} else {
 			effector.log(LogLevel::Warning, &format!("Dropping event '{}' (out port isn't connected)", name));
 		}
@@ -183,9 +274,9 @@ impl<T: Any + Send> OutPort<T>
 	/// Drops the event if the port isn't connected to an `InPort`.
 	pub fn send_payload_after_secs(&self, effector: &mut Effector, name: &str, secs: f64, payload: T)
 	{
-		if self.remote_id != NO_COMPONENT {
-			let event = Event::with_port_payload(name, &self.remote_port, payload);
-			effector.schedule_after_secs(event, self.remote_id, secs);
+		if let Some((remote_id, remote_port)) = self.route() {
+			let event = Event::with_port_payload(name, remote_port, payload);
+			effector.schedule_after_secs(event, remote_id, secs);
 		} else {
 			effector.log(LogLevel::Warning, &format!("Dropping event '{}' (out port isn't connected)", name));
 		}
@@ -196,11 +287,39 @@ impl<T: Any + Send> OutPort<T>
 		assert!(port.target_id != NO_COMPONENT);
 		self.remote_id = port.target_id;
 		self.remote_port = port.target_port.to_string();	// can be empty
+		self.connections.fetch_add(1, Ordering::Relaxed);
+		port.connections.fetch_add(1, Ordering::Relaxed);
+		*self.remote.lock().unwrap() = (self.remote_id, self.remote_port.clone());
 	}
 
 	pub fn is_connected(&self) -> bool
 	{
-		self.remote_id != NO_COMPONENT
+		self.route().is_some()
+	}
+	
+	/// Returns the ID of the component and the port name the OutPort is connected to.
+	/// Normally this is remote_id and remote_port but ports wired up using [`Simulation`]'s
+	/// connect method are connected via state shared with the Simulation.
+	pub fn remote(&self) -> Option<(ComponentID, String)>
+	{
+		if self.remote_id != NO_COMPONENT {
+			Some((self.remote_id, self.remote_port.clone()))
+		} else {
+			let remote = self.remote.lock().unwrap();
+			if remote.0 != NO_COMPONENT {Some(remote.clone())} else {None}
+		}
+	}
+	
+	// Like remote except that it doesn't lock or allocate so it's used when sending.
+	// The port name will be a placeholder for ports wired up by the Simulation.
+	pub(crate) fn route(&self) -> Option<(ComponentID, &str)>
+	{
+		if self.remote_id != NO_COMPONENT {
+			Some((self.remote_id, &self.remote_port))
+		} else {
+			let id = self.wired_id.load(Ordering::Acquire);
+			if id != NO_COMPONENT.0 {Some((ComponentID(id), &self.wire))} else {None}
+		}
 	}
 }
 
@@ -210,9 +329,9 @@ impl OutPort<()>
 	/// Drops the event if the port isn't connected to an `InPort`.
 	pub fn send(&self, effector: &mut Effector, name: &str)
 	{
-		if self.remote_id != NO_COMPONENT {
-			let event = Event::with_port(name, &self.remote_port);
-			effector.schedule_immediately(event, self.remote_id);
+		if let Some((remote_id, remote_port)) = self.route() {
+			let event = Event::with_port(name, remote_port);
+			effector.schedule_immediately(event, remote_id);
 		} else {
 			effector.log(LogLevel::Warning, &format!("Dropping event '{}' (out port isn't connected)", name));
 		}
@@ -222,11 +341,66 @@ impl OutPort<()>
 	/// Drops the event if the port isn't connected to an `InPort`.
 	pub fn send_after_secs(&self, effector: &mut Effector, name: &str, secs: f64)
 	{
-		if self.remote_id != NO_COMPONENT {
-			let event = Event::with_port(name, &self.remote_port);
-			effector.schedule_after_secs(event, self.remote_id, secs);
+		if let Some((remote_id, remote_port)) = self.route() {
+			let event = Event::with_port(name, remote_port);
+			effector.schedule_after_secs(event, remote_id, secs);
 		} else {
 			effector.log(LogLevel::Warning, &format!("Dropping event '{}' (out port isn't connected)", name));
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use components::*;
+	use services::*;
+	use store::*;
+	use std::panic;
+
+	fn new_state() -> SimState
+	{
+		let mut components = Components::new(20);
+		components.append(ComponentID(0), Component{name: "world".to_string(), parent: NO_COMPONENT, children: Vec::new()}, NO_COMPONENT);
+		components.append(ComponentID(1), Component{name: "receiver".to_string(), parent: ComponentID(0), children: Vec::new()}, ComponentID(0));
+		SimState{components: Arc::new(components), store: Arc::new(Store::new()), services: Arc::new(Services::new()), time: 0.0}
+	}
+
+	#[test]
+	fn expect_checks_port_and_payload()
+	{
+		let state = new_state();
+		let port: InPort<String> = InPort::with_port_name(ComponentID(1), "input");
+
+		let mut event = Event::with_port_payload("text", "input", "hello".to_string());
+		assert_eq!(port.expect(&event, &state), "hello");
+		assert_eq!(port.take(&mut event, &state), "hello");
+
+		let event = Event::with_port_payload("text", "control", "hello".to_string());
+		let err = panic::catch_unwind(panic::AssertUnwindSafe(|| {port.expect(&event, &state);})).unwrap_err();
+		assert_eq!(err.downcast_ref::<String>().unwrap(), "world.receiver event 'text' on port 'control' expected port 'input'");
+
+		let event = Event::with_port_payload("text", "input", 10);
+		let err = panic::catch_unwind(panic::AssertUnwindSafe(|| {port.expect(&event, &state);})).unwrap_err();
+		assert_eq!(err.downcast_ref::<String>().unwrap(), "world.receiver event 'text' on port 'input' expected a payload of type alloc::string::String");
+	}
+
+	#[test]
+	fn reconnecting_replaces_the_remote()
+	{
+		let mut output: OutPort<String> = OutPort::new();
+		let clone = output.clone();
+		let input: InPort<String> = InPort::with_port_name(ComponentID(1), "input");
+		output.connect_to(&input);
+		assert_eq!(output.route(), Some((ComponentID(1), "input")));
+		assert_eq!(clone.remote(), Some((ComponentID(1), "input".to_string())));
+		assert_eq!(clone.route(), None);
+
+		let other: InPort<String> = InPort::new(ComponentID(0));
+		output.connect_to(&other);
+		assert_eq!(output.connections.load(Ordering::Relaxed), 2);
+		assert_eq!(other.connections.load(Ordering::Relaxed), 1);
+		assert_eq!(output.remote(), Some((ComponentID(0), "".to_string())));
+	}
+}
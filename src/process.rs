@@ -0,0 +1,332 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use component::*;
+use effector::*;
+use event::*;
+use sim_state::*;
+use std::collections::VecDeque;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use thread_data::*;
+
+const WAKEUP: &str = "process-wakeup";
+
+/// Alternative to process_events! where a component is written as a sequential function
+/// that waits for events and delays, e.g. `ctx.wait(2.5)` and `ctx.recv("text")`. This is
+/// much easier to follow for multi-step protocols than a set of event handlers.
+///
+/// Each active component already has its own thread so wait and recv simply send the
+/// effector for the current event back to the `Simulation` and then block until the
+/// `Simulation` dispatches the event the process is waiting for. So processes are just
+/// as deterministic as event handlers. Events that arrive while the process is waiting
+/// for something else are queued up in a mailbox and returned by later recv calls.
+///
+/// The body starts running when the component receives "init 0" (later init stages are
+/// ignored). Once the body returns events sent to the component are ignored.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate score;
+///
+/// use score::*;
+///
+/// fn client(data: ThreadData, server: ComponentID)
+/// {
+/// 	Process::spawn(data, move |ctx| {
+/// 		for i in 0..3 {
+/// 			let id = ctx.id;
+/// 			ctx.effector.schedule_immediately(Event::with_payload("request", id), server);
+/// 			match ctx.recv_timeout("reply", 2.0) {
+/// 				Some(_) => log_info!(ctx.effector, "got reply {}", i),
+/// 				None => log_warning!(ctx.effector, "request {} timed out", i),
+/// 			}
+/// 			ctx.wait(10.0);
+/// 		}
+/// 	});
+/// }
+/// # fn main() {
+/// # }
+/// ```
+pub struct Process
+{
+	/// The ID of the component the process is running for.
+	pub id: ComponentID,
+
+	/// Used to change the simulation state. This is sent to the `Simulation` (and replaced
+	/// with a new effector) whenever the process blocks.
+	pub effector: Effector,
+
+	data: ThreadData,
+	state: Option<SimState>,
+	mailbox: VecDeque<Event>,
+	next_token: u64,
+	pending: Option<u64>,	// token of the wakeup the process is blocked on
+}
+
+// Used to unwind the process thread when the Simulation goes away.
+struct SimulationExited;
+
+impl Process
+{
+	/// Spawns a thread for the component that runs body.
+	pub fn spawn<F>(data: ThreadData, body: F) -> thread::JoinHandle<()>
+		where F: FnOnce (&mut Process) + Send + 'static
+	{
+		thread::spawn(move || {
+			let id = data.id;
+			let mut process = Process{id, effector: Effector::new(), data, state: None, mailbox: VecDeque::new(), next_token: 0, pending: None};
+			let result = panic::catch_unwind(AssertUnwindSafe(|| {
+				process.start();
+				body(&mut process);
+				process.finish();
+			}));
+			if let Err(err) = result {
+				if !err.is::<SimulationExited>() {
+					panic::resume_unwind(err);
+				}
+			}
+		})
+	}
+
+	/// The state of the simulation at the current time. Note that this changes whenever
+	/// the process blocks.
+	pub fn state(&self) -> &SimState
+	{
+		self.state.as_ref().unwrap()
+	}
+
+	/// Seconds into the simulation.
+	pub fn time(&self) -> f64
+	{
+		self.state().time
+	}
+
+	/// Suspends the process for secs.
+	pub fn wait(&mut self, secs: f64)
+	{
+		let token = self.schedule_wakeup(secs);
+		loop {
+			let event = self.next_event();
+			if is_wakeup(&event, token) {
+				self.pending = None;
+				return;
+			}
+			self.mailbox.push_back(event);
+		}
+	}
+
+	/// Returns the oldest event in the mailbox with the name or, if there isn't one,
+	/// blocks until an event with the name arrives.
+	pub fn recv(&mut self, name: &str) -> Event
+	{
+		if let Some(event) = self.take_mail(name) {
+			return event;
+		}
+		loop {
+			let event = self.next_event();
+			if event.name == name {
+				return event;
+			}
+			self.mailbox.push_back(event);
+		}
+	}
+
+	/// Like recv except that None is returned if the event doesn't arrive within secs.
+	pub fn recv_timeout(&mut self, name: &str, secs: f64) -> Option<Event>
+	{
+		if let Some(event) = self.take_mail(name) {
+			return Some(event);
+		}
+		let token = self.schedule_wakeup(secs);
+		loop {
+			let event = self.next_event();
+			if event.name == name {
+				self.pending = None;	// so the wakeup will be ignored when it arrives
+				return Some(event);
+			} else if is_wakeup(&event, token) {
+				self.pending = None;
+				return None;
+			}
+			self.mailbox.push_back(event);
+		}
+	}
+
+	fn start(&mut self)
+	{
+		loop {
+			let (event, state) = self.receive();
+			if event.name == "init 0" {
+				self.state = Some(state);
+				return;
+			}
+			self.reply(state, Effector::new());	// shouldn't happen but if it does there isn't much we can do with it
+		}
+	}
+
+	fn finish(&mut self)
+	{
+		let state = self.state.take().unwrap();
+		let effector = mem::replace(&mut self.effector, Effector::new());
+		self.reply(state, effector);
+
+		loop {
+			let (_, state) = self.receive();
+			self.reply(state, Effector::new());
+		}
+	}
+
+	fn schedule_wakeup(&mut self, secs: f64) -> u64
+	{
+		let token = self.next_token;
+		self.next_token += 1;
+		self.pending = Some(token);
+		self.effector.schedule_after_secs(Event::with_payload(WAKEUP, token), self.id, secs);
+		token
+	}
+
+	fn take_mail(&mut self, name: &str) -> Option<Event>
+	{
+		let index = self.mailbox.iter().position(|e| e.name == name);
+		index.and_then(|i| self.mailbox.remove(i))
+	}
+
+	// Sends the current effector to the Simulation and returns the next event that
+	// isn't an init or a stale wakeup.
+	fn next_event(&mut self) -> Event
+	{
+		let state = self.state.take().unwrap();
+		let effector = mem::replace(&mut self.effector, Effector::new());
+		self.reply(state, effector);
+
+		loop {
+			let (event, state) = self.receive();
+			if event.name.starts_with("init ") || event.name == WAKEUP && self.is_stale(&event) {
+				self.reply(state, Effector::new());
+			} else {
+				self.state = Some(state);
+				return event;
+			}
+		}
+	}
+
+	// Wakeups other than the one the process is blocked on are from recv_timeouts that
+	// were satisfied.
+	fn is_stale(&self, event: &Event) -> bool
+	{
+		self.pending.map_or(true, |token| !is_wakeup(event, token))
+	}
+
+	fn receive(&mut self) -> (Event, SimState)
+	{
+		match self.data.rx.recv() {
+			Ok(received) => received,
+			Err(_) => panic::resume_unwind(Box::new(SimulationExited)),
+		}
+	}
+
+	fn reply(&mut self, state: SimState, effector: Effector)
+	{
+		drop(state);	// drop our references before the Simulation processes the send
+		let _ = self.data.tx.send(effector);
+	}
+}
+
+fn is_wakeup(event: &Event, token: u64) -> bool
+{
+	event.name == WAKEUP && event.payload.as_ref().and_then(|p| p.downcast_ref::<u64>()) == Some(&token)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use config::*;
+	use logging::*;
+	use simulation::*;
+	use store::*;
+
+	#[test]
+	fn processes_block()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (server_id, server_data) = sim.add_active_component("server", world);
+		let (_, client_data) = sim.add_active_component("client", world);
+
+		// Server replies to the first two requests after a second and ignores the rest.
+		Process::spawn(server_data, move |ctx| {
+			for _ in 0..2 {
+				let client = ctx.recv("request").take_payload::<ComponentID>();
+				ctx.wait(1.0);
+				ctx.effector.schedule_immediately(Event::new("reply"), client);
+			}
+		});
+
+		// Client sends a request every 5s and records the time it got the reply.
+		Process::spawn(client_data, move |ctx| {
+			for i in 0..3 {
+				let id = ctx.id;
+				ctx.effector.schedule_immediately(Event::with_payload("request", id), server_id);
+				let result = match ctx.recv_timeout("reply", 2.0) {
+					Some(_) => ctx.time(),
+					None => -1.0,
+				};
+				ctx.effector.set_float(&format!("reply{}", i), result);
+				ctx.wait(5.0);
+			}
+		});
+		sim.run();
+
+		let times: Vec<f64> = (0..3).map(|i| sim.store.get_float(&format!("world.client.reply{}", i))).collect();
+		let start = times[0] - 1.0;	// requests are sent at init + epsilon
+		assert!((times[1] - times[0] - 6.0).abs() < 1.0e-3, "{:?}", times);
+		assert!(start >= 0.0 && start < 1.0e-3, "{:?}", times);
+		assert_eq!(times[2], -1.0);
+	}
+
+	#[test]
+	fn stale_wakeups_are_dropped()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (client_id, client_data) = sim.add_active_component("client", world);
+		let (_, server_data) = sim.add_active_component("server", world);
+
+		Process::spawn(server_data, move |ctx| {
+			ctx.effector.schedule_after_secs(Event::new("reply"), client_id, 1.0);
+			ctx.effector.schedule_after_secs(Event::new("other"), client_id, 3.0);
+		});
+
+		// The recv_timeout is satisfied at 1s so its wakeup arrives (at 2s) while the
+		// process is blocked in recv.
+		Process::spawn(client_data, move |ctx| {
+			assert!(ctx.recv_timeout("reply", 2.0).is_some());
+			ctx.recv("other");
+			let mail = ctx.mailbox.len() as i64;
+			ctx.effector.set_int("mail", mail);
+		});
+		sim.run();
+
+		assert_eq!(sim.store.get_int("world.client.mail"), 0);
+	}
+}
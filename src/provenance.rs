@@ -0,0 +1,85 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use config::*;
+use sim_time::*;
+use time;
+use uuid::Uuid;
+
+/// Identifies a simulation run. This is written into the store (using keys like
+/// "simulation.run-id"), logged when the simulation starts running, and written
+/// at the start of files produced by [`LogSink`]s and [`Recorder`]s so that output
+/// from different runs can be correlated.
+#[derive(Clone, Debug, RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Provenance
+{
+	/// Unique id for the run.
+	pub run_id: String,
+
+	/// Version of the score crate.
+	pub version: String,
+
+	/// Seed used for the run. If the [`Config`] seed was zero this is the seed
+	/// that was derived from the time.
+	pub seed: usize,
+
+	/// Hash of the [`Config`] settings that affect simulation results (e.g. logging
+	/// settings are not included).
+	pub config_hash: String,
+
+	/// Wall clock time the simulation was created at (in RFC 3339 format).
+	pub start_time: String,
+}
+
+impl Provenance
+{
+	pub(crate) fn new(config: &Config, seed: usize) -> Provenance
+	{
+		Provenance {
+			run_id: Uuid::new_v4().to_hyphenated_string(),
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			seed,
+			config_hash: format!("{:016X}", config_hash(config)),
+			start_time: time::now_utc().rfc3339().to_string(),
+		}
+	}
+
+	/// Returns a one line summary suitable for logging or for file headers.
+	pub fn summary(&self) -> String
+	{
+		format!("run {} (score {}, seed {}, config {}, started {})", self.run_id, self.version, self.seed, self.config_hash, self.start_time)
+	}
+}
+
+// We don't use DefaultHasher because its output may change between Rust releases.
+fn config_hash(config: &Config) -> u64
+{
+	let mut text = format!("{}/{}/{}/{}/{}", config.time_units, config.max_secs, config.num_init_stages, config.seed, config.max_store_deltas);
+	if config.tick_rounding != TickRounding::Floor || config.tick_epsilon != 0.0 {
+		text += &format!("/{:?}:{}", config.tick_rounding, config.tick_epsilon);	// only added when not the default so that older hashes are unchanged
+	}
+	for &(ref pattern, policy) in config.store_history.iter() {
+		text += &format!("/{}:{:?}", pattern.as_str(), policy);
+	}
+
+	// FNV-1a
+	let mut hash = 0xcbf29ce484222325u64;
+	for b in text.bytes() {
+		hash ^= b as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
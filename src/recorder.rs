@@ -0,0 +1,216 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! `Recorder` is a standard [`Component`] that appends the [`Record`]s sent to it to
+//! a file. This allows simulations to dump domain specific results without having to
+//! do file handling inside of component threads.
+use component::*;
+use effector::*;
+use formatting::*;
+use logging::*;
+use ports::*;
+use provenance::*;
+use rustc_serialize::json::{self, Json, ToJson};
+use simulation::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::thread;
+use thread_data::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordFormat
+{
+	/// Each record is written out as a JSON object on its own line.
+	JsonLines,
+
+	/// Column names are taken from the first record.
+	Csv,
+}
+
+/// Payload for the events sent to a [`Recorder`].
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+///
+/// fn packet_sent(recorder: &OutPort<Record>, effector: &mut Effector, size: i64)
+/// {
+/// 	let record = Record::new().with("size", &size).with("kind", "data");
+/// 	recorder.send_payload(effector, "record", record);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Record
+{
+	pub fields: Vec<(String, Json)>,
+}
+
+impl Record
+{
+	pub fn new() -> Record
+	{
+		Record{fields: Vec::new()}
+	}
+
+	pub fn with<T: ToJson + ?Sized>(mut self, name: &str, value: &T) -> Record
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		assert!(name != "time", "time is reserved for the sim time");
+		self.fields.push((name.to_string(), value.to_json()));
+		self
+	}
+}
+
+/// Writes each [`Record`] it receives to a file along with the time at which the
+/// record was received. Output is flushed after each record so that nothing is
+/// lost when the simulation exits. The first line of the file identifies the run
+/// (see [`Provenance`]): for JSON lines it's a `{"provenance":{...}}` object and
+/// for CSV it's a comment starting with '#'.
+pub struct Recorder
+{
+	pub data: ThreadData,
+
+	/// Events sent to this port should be named "record" and have a [`Record`]
+	/// payload.
+	pub input: InPort<Record>,
+
+	path: String,
+	format: RecordFormat,
+	provenance: Provenance,
+}
+
+impl Recorder
+{
+	pub fn new(sim: &mut Simulation, parent_id: ComponentID, name: &str, path: &str, format: RecordFormat) -> Recorder
+	{
+		let (id, data) = sim.add_active_component(name, parent_id);
+		let recorder = Recorder {
+			data,
+			input: InPort::new(id),
+			path: path.to_string(),
+			format,
+			provenance: sim.provenance().clone(),
+		};
+		sim.register_in_port(id, "input", &recorder.input);
+		recorder
+	}
+
+	pub fn start(self)
+	{
+		let file = File::create(&self.path).unwrap_or_else(|err| panic!("Couldn't create {}: {}", self.path, err));
+		let mut writer = BufWriter::new(file);
+		let header = match self.format {
+			RecordFormat::JsonLines => json::encode(&JsonHeader{provenance: &self.provenance}).unwrap() + "\n",
+			RecordFormat::Csv => format!("# {}\n", self.provenance.summary()),
+		};
+		writer.write_all(header.as_bytes()).unwrap_or_else(|err| panic!("Couldn't write to {}: {}", self.path, err));
+		let mut columns: Option<Vec<String>> = None;
+
+		let data = self.data;
+		let path = self.path;
+		let format = self.format;
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+				},
+				"record" => {
+					let record = event.payload_ref::<Record>("record should have a Record payload");
+					let line = match format {
+						RecordFormat::JsonLines => json_line(state.time, record),
+						RecordFormat::Csv => csv_line(state.time, record, &mut columns),
+					};
+					if let Err(err) = writer.write_all(line.as_bytes()).and_then(|_| writer.flush()) {
+						log_error!(effector, "failed to write to {}: {}", path, err);
+					}
+				}
+			);
+		});
+	}
+}
+
+#[derive(RustcEncodable)]
+struct JsonHeader<'a>
+{
+	provenance: &'a Provenance,
+}
+
+fn json_line(time: f64, record: &Record) -> String
+{
+	let mut object = BTreeMap::new();
+	object.insert("time".to_string(), time.to_json());
+	for &(ref name, ref value) in record.fields.iter() {
+		object.insert(name.clone(), value.clone());
+	}
+	json::encode(&Json::Object(object)).unwrap() + "\n"
+}
+
+// The header is written out with the first record. Subsequent records should have the
+// same fields (missing fields are written as empty cells and extra fields are ignored).
+fn csv_line(time: f64, record: &Record, columns: &mut Option<Vec<String>>) -> String
+{
+	let mut result = String::new();
+	if columns.is_none() {
+		let names: Vec<String> = record.fields.iter().map(|f| f.0.clone()).collect();
+		result += "time";
+		for name in names.iter() {
+			result += ",";
+			result += &csv_cell(name);
+		}
+		result += "\n";
+		*columns = Some(names);
+	}
+
+	result += &time.to_string();
+	for name in columns.as_ref().unwrap().iter() {
+		result += ",";
+		if let Some(&(_, ref value)) = record.fields.iter().find(|f| f.0 == *name) {
+			match *value {
+				Json::String(ref s) => result += &csv_cell(s),
+				Json::F64(v) => result += &float_to_string(v),
+				ref v => result += &v.to_string(),
+			}
+		}
+	}
+	result + "\n"
+}
+
+fn csv_cell(text: &str) -> String
+{
+	if text.contains(',') || text.contains('"') || text.contains('\n') {
+		format!("\"{}\"", text.replace("\"", "\"\""))
+	} else {
+		text.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	
+	#[test]
+	fn csv_has_header()
+	{
+		let mut columns = None;
+		let record = Record::new().with("size", &10).with("kind", "a,b");
+		assert_eq!(csv_line(1.5, &record, &mut columns), "time,size,kind\n1.5,10,\"a,b\"\n");
+		
+		let record = Record::new().with("kind", "c");
+		assert_eq!(csv_line(2.0, &record, &mut columns), "2,,c\n");
+	}
+}
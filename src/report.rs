@@ -0,0 +1,158 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use provenance::*;
+use rustc_serialize::json;
+use std::fs::File;
+use std::io::Write;
+
+/// Summary of a simulation run. This is written when the simulation exits if
+/// config.run_report is set so that CI jobs can archive and compare runs.
+#[derive(Clone, Debug, RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct RunReport
+{
+	pub provenance: Provenance,
+
+	/// Why the simulation exited, e.g. "no events".
+	pub exit_reason: String,
+
+	/// Wall clock time the run took.
+	pub elapsed_secs: f64,
+
+	/// Simulated time at exit.
+	pub sim_secs: f64,
+
+	/// Number of events dispatched.
+	pub events: u64,
+
+	pub events_per_sec: f64,
+
+	/// As hex, see the `Simulation`'s run method.
+	pub finger_print: String,
+
+	/// Number of events dispatched to each active component (sorted by path).
+	pub component_events: Vec<(String, u64)>,
+
+	/// Store values matching config.report_values (sorted by key).
+	pub values: Vec<ReportValue>,
+
+	/// Simulation-level metadata set by components (sorted by name).
+	pub metadata: Vec<(String, String)>,
+
+	/// Assertions that failed (see [`Effector`]'s assert method). If this isn't empty
+	/// then the run should be considered a failure.
+	pub assertion_failures: Vec<AssertionFailure>,
+}
+
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct AssertionFailure
+{
+	/// Path of the component that made the assertion.
+	pub path: String,
+
+	/// Seconds into the run.
+	pub time: f64,
+
+	pub message: String,
+}
+
+#[derive(Clone, Debug, RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ReportValue
+{
+	pub key: String,
+	pub value: String,
+
+	/// "int", "float", or "string".
+	pub kind: String,
+}
+
+impl RunReport
+{
+	pub fn to_json(&self) -> String
+	{
+		json::as_pretty_json(self).to_string()
+	}
+
+	pub fn to_html(&self) -> String
+	{
+		let mut html = String::new();
+		html += "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>score run report</title></head>\n<body>\n";
+		html += &format!("<h1>Run {}</h1>\n<table>\n", escape(&self.provenance.run_id));
+		let rows = [
+			("score version", self.provenance.version.clone()),
+			("seed", self.provenance.seed.to_string()),
+			("config hash", self.provenance.config_hash.clone()),
+			("started", self.provenance.start_time.clone()),
+			("exit reason", self.exit_reason.clone()),
+			("elapsed", format!("{:.3}s", self.elapsed_secs)),
+			("simulated", format!("{}s", self.sim_secs)),
+			("events", self.events.to_string()),
+			("events/sec", format!("{:.1}", self.events_per_sec)),
+			("finger print", self.finger_print.clone()),
+		];
+		for &(name, ref value) in rows.iter() {
+			html += &format!("<tr><th align=\"left\">{}</th><td>{}</td></tr>\n", name, escape(value));
+		}
+		html += "</table>\n";
+
+		html += "<h2>Events per Component</h2>\n<table>\n";
+		for &(ref path, count) in self.component_events.iter() {
+			html += &format!("<tr><td>{}</td><td align=\"right\">{}</td></tr>\n", escape(path), count);
+		}
+		html += "</table>\n";
+
+		if !self.assertion_failures.is_empty() {
+			html += "<h2>Assertion Failures</h2>\n<table>\n";
+			for failure in self.assertion_failures.iter() {
+				html += &format!("<tr><td>{}</td><td>{}s</td><td>{}</td></tr>\n", escape(&failure.path), failure.time, escape(&failure.message));
+			}
+			html += "</table>\n";
+		}
+
+		if !self.metadata.is_empty() {
+			html += "<h2>Metadata</h2>\n<table>\n";
+			for &(ref name, ref value) in self.metadata.iter() {
+				html += &format!("<tr><td>{}</td><td>{}</td></tr>\n", escape(name), escape(value));
+			}
+			html += "</table>\n";
+		}
+
+		if !self.values.is_empty() {
+			html += "<h2>Values</h2>\n<table>\n";
+			for value in self.values.iter() {
+				html += &format!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n", escape(&value.key), escape(&value.value), value.kind);
+			}
+			html += "</table>\n";
+		}
+		html += "</body>\n</html>\n";
+		html
+	}
+
+	/// Writes HTML if the path ends with ".html" or ".htm" and JSON otherwise.
+	pub fn write(&self, path: &str) -> Result<(), String>
+	{
+		let text = if path.ends_with(".html") || path.ends_with(".htm") {self.to_html()} else {self.to_json()};
+		let mut file = File::create(path).map_err(|err| format!("Couldn't create {}: {}", path, err))?;
+		file.write_all(text.as_bytes()).map_err(|err| format!("Couldn't write to {}: {}", path, err))
+	}
+}
+
+fn escape(text: &str) -> String
+{
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
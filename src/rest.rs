@@ -0,0 +1,341 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! The web server used when config.home_path is set. This is only available with the
+//! rest feature (which is on by default).
+use config::*;
+use control::*;
+use glob;
+use rouille;
+use std::f64;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{self, AtomicBool};
+use std::thread;
+use std::time::Duration;
+
+/// [`ControlBackend`] that uses a rouille web server to translate REST endpoints into
+/// [`RestCommand`]s.
+pub struct RestBackend
+{
+	address: String,
+	home_path: String,
+	shutdown: Arc<AtomicBool>,
+	server: Option<thread::JoinHandle<()>>,
+}
+
+impl RestBackend
+{
+	/// The server binds to address and serves up home_path when a browser hits "/".
+	pub fn new(address: &str, home_path: &str) -> RestBackend
+	{
+		RestBackend{address: address.to_string(), home_path: home_path.to_string(), shutdown: Arc::new(AtomicBool::new(false)), server: None}
+	}
+}
+
+impl ControlBackend for RestBackend
+{
+	fn start(&mut self, commands: mpsc::Sender<(u64, RestCommand)>, replies: mpsc::Receiver<(u64, RestReply)>) -> Result<(), String>
+	{
+		self.server = Some(spin_up_rest(&self.address, &self.home_path, commands, replies, self.shutdown.clone())?);
+		Ok(())
+	}
+
+	fn stop(&mut self)
+	{
+		self.shutdown.store(true, atomic::Ordering::SeqCst);
+		if let Some(server) = self.server.take() {
+			let _ = server.join();
+		}
+	}
+}
+
+fn file_response(request: &rouille::Request, path: &Path) -> rouille::Response
+{
+	match File::open(&path) {
+		Ok(file) => rouille::Response::from_file("text/html; charset=utf8", file),
+		Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+			eprintln!("Didn't find file for {} {}", request.method(), request.url());
+			rouille::Response::empty_404()
+		},
+		Err(ref err) => {
+			let mesg: &str = &format!("{:?}", err.kind());
+			eprintln!("Error reading file for {} {}: {}", request.method(), request.url(), mesg);
+			rouille::Response::text(mesg).with_status_code(403)
+		},
+	}
+}
+
+// For debugging can do stuff like:
+//    curl http://127.0.0.1:9000/log/all
+//    curl -X POST http://127.0.0.1:9000/time/10
+//    curl -X POST http://127.0.0.1:9000/exit
+fn spin_up_rest(address: &str, home_path: &str, tx_command: mpsc::Sender<(u64, RestCommand)>, rx_reply: mpsc::Receiver<(u64, RestReply)>, shutdown: Arc<AtomicBool>) -> Result<thread::JoinHandle<()>, String>
+{
+	let addr = address.to_string();
+	let home_path = home_path.to_string();
+	
+	// rouille will spawn up a thread for each client that attaches and there's no good
+	// way to clone the channels into them so we need to use a mutex to serialize access.
+	let channel = Mutex::new(RestChannel{tx_command, rx_reply, next_seq: 0});
+
+	let server = rouille::Server::new(&addr, move |request| {
+		let path = Path::new(&home_path);
+		let root_dir = path.parent().unwrap();
+
+//		println!("{} {}", request.method(), request.url());
+		router!(request,
+			(GET) (/) => {
+				file_response(&request, path)
+			},
+			// In theory REST endpoints can conflict with file names within root_dir but none of
+			// the REST endpoints have an extension so this shouldn't be a problem in practice.
+			(GET) (/components) => {
+				handle_endpoint(RestCommand::GetComponents, &channel)
+			},
+			(GET) (/compare) => {
+				handle_endpoint(RestCommand::GetComparison(-1.0), &channel)
+			},
+			(GET) (/compare/after/{time: f64}) => {
+				handle_endpoint(RestCommand::GetComparison(time), &channel)
+			},
+			(GET) (/breakpoints) => {
+				handle_endpoint(RestCommand::GetBreakpoints, &channel)
+			},
+			(POST) (/breakpoints/clear) => {
+				handle_endpoint(RestCommand::ClearBreakpoints, &channel)
+			},
+			(GET) (/breakpoints/hits) => {
+				handle_endpoint(RestCommand::GetBreakpointHits(-1.0), &channel)
+			},
+			(GET) (/breakpoints/hits/after/{time: f64}) => {
+				handle_endpoint(RestCommand::GetBreakpointHits(time), &channel)
+			},
+			(POST) (/breakpoints/{event: String}/{component: String}) => {
+				match (glob::Pattern::new(&event), glob::Pattern::new(&component)) {
+					(Ok(event), Ok(component)) => handle_endpoint(RestCommand::AddBreakpoint(event, component), &channel),
+					_ => rouille::Response::empty_400(),
+				}
+			},
+			(POST) (/branch) => {
+				handle_endpoint(RestCommand::Branch(None), &channel)
+			},
+			(POST) (/branch/seed/{seed: u64}) => {
+				handle_endpoint(RestCommand::Branch(Some(seed)), &channel)
+			},
+			(POST) (/branch/end) => {
+				handle_endpoint(RestCommand::EndBranch, &channel)
+			},
+			(GET) (/deltas) => {
+				handle_endpoint(RestCommand::GetDeltas(-1.0), &channel)
+			},
+			(GET) (/deltas/after/{time: f64}) => {
+				handle_endpoint(RestCommand::GetDeltas(time), &channel)
+			},
+			(POST) (/exit) => {
+				handle_endpoint(RestCommand::Exit, &channel)
+			},
+			(GET) (/exited) => {
+				handle_endpoint(RestCommand::GetExited, &channel)
+			},
+			(GET) (/job/{id: u64}) => {
+				handle_endpoint(RestCommand::GetJob(id), &channel)
+			},
+			(GET) (/history/{key: String}) => {
+				handle_endpoint(RestCommand::GetHistory(key), &channel)
+			},
+			(GET) (/log) => {
+				match parse_log_query(&request) {
+					Ok(query) => handle_endpoint(RestCommand::GetLog(query), &channel),
+					Err(_) => rouille::Response::empty_400(),
+				}
+			},
+			(GET) (/log/after/{time: f64}) => {
+				handle_endpoint(RestCommand::GetLogAfter(time), &channel)
+			},
+			(POST) (/log/level/{pattern: String}/{level: String}) => {
+				match (glob::Pattern::new(&pattern), do_parse_log_level(&level)) {
+					(Ok(pattern), Ok(level)) => handle_endpoint(RestCommand::SetLogLevel(pattern, level), &channel),
+					_ => rouille::Response::empty_400(),
+				}
+			},
+			(GET) (/metadata) => {
+				handle_endpoint(RestCommand::GetMetadata, &channel)
+			},
+			(GET) (/display/schema) => {
+				handle_endpoint(RestCommand::GetDisplaySchema, &channel)
+			},
+			(GET) (/schema) => {
+				handle_endpoint(RestCommand::GetSchema, &channel)
+			},
+			(POST) (/pause) => {
+				handle_endpoint(RestCommand::Pause, &channel)
+			},
+			(POST) (/run) => {
+				handle_endpoint(RestCommand::Resume, &channel)
+			},
+			(GET) (/running) => {
+				handle_endpoint(RestCommand::GetRunning, &channel)
+			},
+			(GET) (/scheduled) => {
+				handle_endpoint(RestCommand::GetScheduled, &channel)
+			},
+			(POST) (/run/next-event) => {
+				handle_endpoint(RestCommand::RunNextEvent, &channel)
+			},
+			(POST) (/run/once) => {
+				handle_endpoint(RestCommand::RunOnce, &channel)
+			},
+			(POST) (/run/until-event/{pattern: String}) => {	// returns a job id, use /job/{id} to get the matching event
+				if let Ok(pattern) = glob::Pattern::new(&pattern) {
+					handle_endpoint(RestCommand::RunUntilEvent(pattern, f64::INFINITY), &channel)
+				} else {
+					rouille::Response::empty_400()
+				}
+			},
+			(POST) (/run/until-event/{pattern: String}/{max_secs: f64}) => {	// gives up after max_secs
+				if let Ok(pattern) = glob::Pattern::new(&pattern) {
+					handle_endpoint(RestCommand::RunUntilEvent(pattern, max_secs), &channel)
+				} else {
+					rouille::Response::empty_400()
+				}
+			},
+			(POST) (/run/until/{secs: f64}) => {	// returns a job id, use /job/{id} to check on progress
+				handle_endpoint(RestCommand::SetTime(secs), &channel)
+			},			
+			// These really should be PUTs but crest doesn't support PUT...
+			(POST) (/state/bool/{path: String}/{value: bool}) => {
+				handle_endpoint(RestCommand::SetBoolState(path, value), &channel)
+			},
+			(POST) (/state/float/{path: String}/{value: f64}) => {
+				handle_endpoint(RestCommand::SetFloatState(path, value), &channel)
+			},
+			(POST) (/state/int/{path: String}/{value: i64}) => {
+				handle_endpoint(RestCommand::SetIntState(path, value), &channel)
+			},
+			(GET) (/state/changes/{edition: u64}) => {
+				handle_endpoint(RestCommand::GetStateChanges(edition), &channel)
+			},
+			(GET) (/state/{path: String}) => {
+				if let Ok(path) = glob::Pattern::new(&path) {
+					handle_endpoint(RestCommand::GetState(path), &channel)
+				} else {
+					rouille::Response::empty_400()
+				}
+			},
+			(POST) (/state/string/{path: String}/{value: String}) => {
+				handle_endpoint(RestCommand::SetStringState(path, value), &channel)
+			},
+			(GET) (/time) => {
+				handle_endpoint(RestCommand::GetTime, &channel)
+			},
+			(GET) (/time/precision) => {
+				handle_endpoint(RestCommand::GetTimePrecision, &channel)
+			},
+			(GET) (/trace/{num: u64}) => {
+				handle_endpoint(RestCommand::GetTrace(num), &channel)
+			},
+			(POST) (/watch/clear) => {
+				handle_endpoint(RestCommand::ClearWatches, &channel)
+			},
+			(GET) (/watch/hits) => {
+				handle_endpoint(RestCommand::GetWatchHits(-1.0), &channel)
+			},
+			(GET) (/watch/hits/after/{time: f64}) => {
+				handle_endpoint(RestCommand::GetWatchHits(time), &channel)
+			},
+			(POST) (/watch/{pattern: String}) => {
+				if let Ok(pattern) = glob::Pattern::new(&pattern) {
+					handle_endpoint(RestCommand::Watch(pattern), &channel)
+				} else {
+					rouille::Response::empty_400()
+				}
+			},
+			_ => {
+				let response = rouille::match_assets(&request, &root_dir);
+				if !response.is_success() {
+					eprintln!("Failed to read file for {} {}", request.method(), request.url());
+				}
+				response.with_no_cache()	// TODO: might want to do this just in debug (altho the client and server are normally both local so it shouldn't matter much)
+			}
+		)
+	}).map_err(|err| format!("Failed to start the web server at {}: {}", address, err))?;
+	
+	// start_server never returns so we poll instead which allows us to shutdown when
+	// the simulation is told to exit. Dropping the server closes the listening socket.
+	Ok(thread::spawn(move|| {
+		while !shutdown.load(atomic::Ordering::SeqCst) {
+			server.poll();
+			thread::sleep(Duration::from_millis(5));
+		}
+	}))
+}
+
+// Missing parameters use the defaults from LogQuery::all.
+fn parse_log_query(request: &rouille::Request) -> Result<LogQuery, String>
+{
+	let mut query = LogQuery::all();
+	if let Some(offset) = request.get_param("offset") {
+		query.offset = offset.parse().map_err(|_| format!("bad offset: {}", offset))?;
+	}
+	if let Some(limit) = request.get_param("limit") {
+		query.limit = limit.parse().map_err(|_| format!("bad limit: {}", limit))?;
+	}
+	if let Some(level) = request.get_param("level") {
+		query.level = Some(do_parse_log_level(&level)?);
+	}
+	if let Some(glob) = request.get_param("path_glob") {
+		query.path = Some(glob::Pattern::new(&glob).map_err(|err| err.to_string())?);
+	}
+	Ok(query)
+}
+
+struct RestChannel
+{
+	tx_command: mpsc::Sender<(u64, RestCommand)>,
+	rx_reply: mpsc::Receiver<(u64, RestReply)>,
+	next_seq: u64,
+}
+
+// The lock is held until the reply arrives so commands are processed one at a time.
+// If a handler dies while waiting (e.g. rouille unwinds it) the lock is poisoned and its
+// reply is left in the channel. We don't want that to wedge the server so we ignore
+// poisoning and use sequence numbers to discard stale replies.
+fn handle_endpoint(command: RestCommand, channel: &Mutex<RestChannel>) -> rouille::Response
+{
+	let mut channel = channel.lock().unwrap_or_else(|err| err.into_inner());
+	let seq = channel.next_seq;
+	channel.next_seq += 1;
+	
+	if channel.tx_command.send((seq, command)).is_err() {
+		return rouille::Response::text("simulation has stopped").with_status_code(503);
+	}
+	let reply = loop {
+		match channel.rx_reply.recv() {
+			Ok((s, reply)) => if s == seq {break reply},
+			Err(_) => return rouille::Response::text("simulation has stopped").with_status_code(503),
+		}
+	};
+	
+	rouille::Response {
+		status_code: reply.code,
+		headers: vec![("Content-Type".into(), "application/json".into())],
+		data: rouille::ResponseBody::from_data(reply.data),
+		upgrade: None,
+	}
+}
@@ -0,0 +1,271 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use component::*;
+use effector::*;
+use event::*;
+use logging::*;
+use ports::*;
+use std::any::Any;
+use std::collections::HashMap;
+use std::f64::EPSILON;
+
+/// Routing tables for multi-hop network models. Node components are wired together with
+/// links (a one-way hop to another node's [`InPort`] with a delay) and addresses are
+/// assigned to nodes. Once the topology is complete finish is called to compute the
+/// routing tables and the routes are provided as a service (see the `Simulation`'s
+/// provide method). Nodes then call route to forward an event one hop closer to its
+/// destination. Routes minimize the total delay (ties go to the route with fewer hops
+/// and then to the link added first).
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+/// use std::thread;
+///
+/// struct Packet
+/// {
+/// 	dest: String,
+/// }
+///
+/// fn node_thread(data: ThreadData)
+/// {
+/// 	thread::spawn(move || {
+/// 		process_events!(data, event, state, effector,
+/// 			"packet" => {
+/// 				let dest = event.payload_ref::<Packet>("should have a Packet").dest.clone();
+/// 				let routes = state.service::<Routes>();
+/// 				if routes.is_local(data.id, &dest) {
+/// 					log_info!(effector, "received packet");
+/// 				} else {
+/// 					routes.route(&mut effector, data.id, &dest, event);
+/// 				}
+/// 			}
+/// 		);
+/// 	});
+/// }
+///
+/// let mut sim = Simulation::new(Config::new());
+/// let world = sim.add_component("world", NO_COMPONENT);
+/// let (a, data) = sim.add_active_component("a", world);
+/// node_thread(data);
+/// let (b, data) = sim.add_active_component("b", world);
+/// node_thread(data);
+///
+/// let mut routes = Routes::new();
+/// routes.add_address("10.0.0.2", b);
+/// routes.add_link::<Packet>(a, &InPort::new(b), 0.01);
+/// sim.provide(routes.finish());
+/// ```
+pub struct Routes
+{
+	addresses: HashMap<String, ComponentID>,
+	links: Vec<RouteLink>,
+	next_hops: HashMap<ComponentID, HashMap<ComponentID, usize>>,	// dest node => from node => link index
+	finished: bool,	// false if the topology changed after finish was called
+}
+
+/// One-way hop from a node to another node's [`InPort`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteLink
+{
+	pub from: ComponentID,
+	pub to: ComponentID,
+
+	/// Assigned to the port_name field of routed events.
+	pub port_name: String,
+
+	/// Seconds it takes an event to traverse the link.
+	pub delay: f64,
+}
+
+impl Routes
+{
+	pub fn new() -> Routes
+	{
+		Routes{addresses: HashMap::new(), links: Vec::new(), next_hops: HashMap::new(), finished: false}
+	}
+
+	/// Nodes may have more than one address but an address may only be used once.
+	pub fn add_address(&mut self, addr: &str, node: ComponentID)
+	{
+		assert!(node != NO_COMPONENT);
+		if let Some(old) = self.addresses.insert(addr.to_string(), node) {
+			assert!(old == node, "address '{}' was assigned to both {} and {}", addr, old, node);
+		}
+		self.finished = false;
+	}
+
+	/// Adds a one-way hop from node to port. Use two calls for a full duplex link.
+	pub fn add_link<T: Any + Send>(&mut self, from: ComponentID, port: &InPort<T>, delay: f64)
+	{
+		assert!(from != NO_COMPONENT);
+		assert!(port.target_id != NO_COMPONENT);
+		assert!(delay >= 0.0, "delay ({:.3}) is negative", delay);
+		self.links.push(RouteLink{from, to: port.target_id, port_name: port.target_port.clone(), delay});
+		self.finished = false;
+	}
+
+	/// Computes the routing tables. This should be called after the last address and link
+	/// are added (and before the routes are provided) so that routing is a lookup.
+	pub fn finish(mut self) -> Routes
+	{
+		let mut dests: Vec<ComponentID> = self.addresses.values().cloned().collect();
+		dests.sort();
+		dests.dedup();
+		self.next_hops = dests.iter().map(|&dest| (dest, self.find_routes(dest))).collect();
+		self.finished = true;
+		self
+	}
+
+	/// Returns the node the address was assigned to.
+	pub fn node(&self, addr: &str) -> Option<ComponentID>
+	{
+		self.addresses.get(addr).cloned()
+	}
+
+	/// Returns true if the address was assigned to node.
+	pub fn is_local(&self, node: ComponentID, addr: &str) -> bool
+	{
+		self.node(addr) == Some(node)
+	}
+
+	/// Returns the link node should use to forward events to addr or None if the
+	/// address is unknown, the address is local, or there is no route. Panics if
+	/// finish wasn't called after the topology last changed.
+	pub fn next_hop(&self, node: ComponentID, addr: &str) -> Option<RouteLink>
+	{
+		assert!(self.finished, "Routes::finish wasn't called after the last add_address or add_link");
+		let dest = self.node(addr)?;
+		self.next_hops.get(&dest).and_then(|table| table.get(&node)).map(|&i| self.links[i].clone())
+	}
+
+	/// Schedules the event to the next node on the route to addr after the hop's delay.
+	/// If there is no route the event is dropped (with a warning) and false is returned.
+	pub fn route(&self, effector: &mut Effector, node: ComponentID, addr: &str, mut event: Event) -> bool
+	{
+		assert!(!self.is_local(node, addr), "'{}' is local to {} so it can't be routed", addr, node);
+		if let Some(link) = self.next_hop(node, addr) {
+			event.port_name = link.port_name.into();
+			if link.delay > EPSILON {
+				effector.schedule_after_secs(event, link.to, link.delay);
+			} else {
+				effector.schedule_immediately(event, link.to);
+			}
+			true
+		} else {
+			effector.log(LogLevel::Warning, &format!("Dropping event '{}' (no route to '{}')", event.name, addr));
+			false
+		}
+	}
+
+	// Dijkstra run backwards from the destination. Networks are typically small enough
+	// that the quadratic version is fine.
+	fn find_routes(&self, dest: ComponentID) -> HashMap<ComponentID, usize>
+	{
+		let mut best: HashMap<ComponentID, (f64, usize)> = HashMap::new();	// node => (delay, hops) to dest
+		let mut next = HashMap::new();
+		let mut done = vec![dest];
+		best.insert(dest, (0.0, 0));
+
+		let mut current = dest;
+		loop {
+			let (delay, hops) = best[&current];
+			for (i, link) in self.links.iter().enumerate() {
+				if link.to == current && !done.contains(&link.from) {
+					let candidate = (delay + link.delay, hops + 1);
+					let better = match best.get(&link.from) {
+						Some(&(d, h)) => candidate.0 < d || (candidate.0 == d && candidate.1 < h) || (candidate.0 == d && candidate.1 == h && i < next[&link.from]),
+						None => true,
+					};
+					if better {
+						best.insert(link.from, candidate);
+						next.insert(link.from, i);
+					}
+				}
+			}
+
+			let pending = best.iter()
+				.filter(|&(n, _)| !done.contains(n))
+				.min_by(|&(n1, &(d1, h1)), &(n2, &(d2, h2))| d1.partial_cmp(&d2).unwrap().then(h1.cmp(&h2)).then(n1.cmp(n2)));
+			match pending {
+				Some((&n, _)) => {current = n; done.push(n)},
+				None => break,
+			}
+		}
+		next
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn link(routes: &mut Routes, from: usize, to: usize, delay: f64)
+	{
+		routes.add_link::<()>(ComponentID(from), &InPort::with_port_name(ComponentID(to), &format!("from{}", from)), delay);
+	}
+
+	#[test]
+	fn routes_use_the_smallest_delay()
+	{
+		// 0 -> 1 -> 3 is slower than 0 -> 2 -> 3
+		let mut routes = Routes::new();
+		routes.add_address("x", ComponentID(3));
+		link(&mut routes, 0, 1, 1.0);
+		link(&mut routes, 1, 3, 1.0);
+		link(&mut routes, 0, 2, 0.5);
+		link(&mut routes, 2, 3, 0.5);
+		link(&mut routes, 3, 0, 0.1);
+		let routes = routes.finish();
+
+		assert_eq!(routes.next_hop(ComponentID(0), "x").unwrap().to, ComponentID(2));
+		assert_eq!(routes.next_hop(ComponentID(1), "x").unwrap().to, ComponentID(3));
+		assert_eq!(routes.next_hop(ComponentID(3), "x"), None);
+		assert_eq!(routes.next_hop(ComponentID(0), "y"), None);
+
+		let mut effector = Effector::new();
+		assert!(routes.route(&mut effector, ComponentID(0), "x", Event::new("packet")));
+		let (to, ref event, secs) = effector.events[0];
+		assert_eq!((to, event.port_name.as_ref(), secs), (ComponentID(2), "from0", 0.5));
+	}
+
+	#[test]
+	fn unreachable_addresses_are_dropped()
+	{
+		let mut routes = Routes::new();
+		routes.add_address("x", ComponentID(1));
+		link(&mut routes, 1, 0, 1.0);
+		let routes = routes.finish();
+
+		let mut effector = Effector::new();
+		assert!(!routes.route(&mut effector, ComponentID(0), "x", Event::new("packet")));
+		assert!(effector.events.is_empty());
+		assert_eq!(effector.logs.len(), 1);
+	}
+
+	#[test]
+	#[should_panic(expected = "finish wasn't called")]
+	fn changed_routes_must_be_finished()
+	{
+		let mut routes = Routes::new();
+		routes.add_address("x", ComponentID(1));
+		let mut routes = routes.finish();
+		link(&mut routes, 0, 1, 1.0);
+		routes.next_hop(ComponentID(0), "x");
+	}
+}
@@ -0,0 +1,403 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Compares two runs time slice by time slice. Runs are described by a trace (see the
+//! `Simulation`'s record_trace method) and, optionally, the store deltas (see record_deltas).
+//! Use diff_runs to compare two recorded runs or the `Simulation`'s compare_with method
+//! to compare a live run against a golden run using the /compare REST endpoints.
+use sim_time::*;
+use store::*;
+use trace::*;
+use rustc_serialize::json;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::collections::Bound::Excluded;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// The differences between two runs at one time. Events are formatted like "'text' to
+/// world.receiver" (with the JSON payload, if any, in parens after the name) and state
+/// like "world.receiver.count = 3". Only the events and state changes that don't
+/// appear in the other run are included. This is returned by the /compare REST endpoints.
+/// deltas_unavailable is set if the live run's store deltas for the slice were discarded
+/// (because of config.max_store_deltas) before they could be compared.
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SliceDiff
+{
+	pub time: f64,
+	pub left_events: Vec<String>,
+	pub right_events: Vec<String>,
+	pub left_state: Vec<String>,
+	pub right_state: Vec<String>,
+	pub deltas_unavailable: bool,
+}
+
+pub(crate) struct RunArtifacts
+{
+	pub(crate) events: Vec<TraceEntry>,
+	pub(crate) deltas: Vec<StoreDelta>,
+}
+
+impl RunArtifacts
+{
+	/// Deltas may be empty in which case state isn't compared.
+	pub(crate) fn load(trace: &str, deltas: &str) -> Result<RunArtifacts, String>
+	{
+		let events = read_trace(trace)?;
+		let deltas = if deltas.is_empty() {Vec::new()} else {read_json_lines(deltas)?};
+		Ok(RunArtifacts{events, deltas})
+	}
+}
+
+/// Returns the time slices at which two recorded runs differ. The delta paths may be
+/// empty in which case only events are compared. Time units should be the config.time_units
+/// the runs used.
+pub fn diff_runs(left_trace: &str, left_deltas: &str, right_trace: &str, right_deltas: &str, time_units: f64) -> Result<Vec<SliceDiff>, String>
+{
+	let left = RunArtifacts::load(left_trace, left_deltas)?;
+	let right = RunArtifacts::load(right_trace, right_deltas)?;
+	Ok(diff_artifacts(&left, &right, time_units, Time(i64::max_value())))
+}
+
+// Slices after until are ignored (this allows a live run to be compared with a
+// completed run).
+pub(crate) fn diff_artifacts(left: &RunArtifacts, right: &RunArtifacts, time_units: f64, until: Time) -> Vec<SliceDiff>
+{
+	let left = slices(left, time_units, until);
+	let right = slices(right, time_units, until);
+	let empty = (Vec::new(), Vec::new());
+
+	let mut times: Vec<&i64> = left.keys().chain(right.keys()).collect();
+	times.sort();
+	times.dedup();
+
+	let mut diffs = Vec::new();
+	for &time in times {
+		let &(ref left_events, ref left_state) = left.get(&time).unwrap_or(&empty);
+		let &(ref right_events, ref right_state) = right.get(&time).unwrap_or(&empty);
+		if let Some(diff) = diff_slice(Time(time).to_secs(time_units), left_events, right_events, Some((left_state, right_state))) {
+			diffs.push(diff);
+		}
+	}
+	diffs
+}
+
+// State is None if it isn't available for the left run.
+fn diff_slice(time: f64, left_events: &Vec<String>, right_events: &Vec<String>, state: Option<(&Vec<String>, &Vec<String>)>) -> Option<SliceDiff>
+{
+	let diff = SliceDiff {
+		time,
+		left_events: missing_from(left_events, right_events),
+		right_events: missing_from(right_events, left_events),
+		left_state: state.map_or(Vec::new(), |(left, right)| missing_from(left, right)),
+		right_state: state.map_or(Vec::new(), |(left, right)| missing_from(right, left)),
+		deltas_unavailable: state.is_none(),
+	};
+	if !diff.left_events.is_empty() || !diff.right_events.is_empty() || !diff.left_state.is_empty() || !diff.right_state.is_empty() {
+		Some(diff)
+	} else {
+		None
+	}
+}
+
+/// Compares a live run against a golden run for the /compare REST endpoints. Each
+/// update only reads the part of the live trace that was written since the last
+/// update and only diffs the time slices that completed since then.
+pub(crate) struct GoldenComparison
+{
+	golden: BTreeMap<i64, (Vec<String>, Vec<String>)>,
+	compare_state: bool,			// false if either run isn't recording deltas
+	time_units: f64,
+	
+	offset: u64,					// amount of the live trace that has been read
+	events: BTreeMap<i64, Vec<String>>,	// live events for slices that haven't been compared yet
+	state: BTreeMap<i64, Vec<String>>,	// live state for slices that haven't been compared yet
+	deltas_seen: Option<(f64, u64)>,	// time and edition of the last live delta copied into state
+	lost: Vec<(i64, i64)>,			// [first, last] ranges where live deltas may have been discarded
+	compared: i64,					// slices at or before this time have been compared
+	
+	diffs: Vec<SliceDiff>,
+	current: Option<SliceDiff>,		// the diff for the slice at the current time (which may not be complete)
+}
+
+impl GoldenComparison
+{
+	pub(crate) fn new(golden: &RunArtifacts, time_units: f64, compare_state: bool) -> GoldenComparison
+	{
+		GoldenComparison {
+			golden: slices(golden, time_units, Time(i64::max_value())),
+			compare_state: compare_state && !golden.deltas.is_empty(),
+			time_units,
+			offset: 0,
+			events: BTreeMap::new(),
+			state: BTreeMap::new(),
+			deltas_seen: None,
+			lost: Vec::new(),
+			compared: -1,
+			diffs: Vec::new(),
+			current: None,
+		}
+	}
+	
+	/// The diffs for the slices up to the last update.
+	pub(crate) fn diffs<'a>(&'a self) -> impl Iterator<Item = &'a SliceDiff>
+	{
+		self.diffs.iter().chain(self.current.iter())
+	}
+	
+	/// Trace is the live run's trace and deltas are the live run's retained store deltas.
+	/// Evicted is the time and edition of the newest delta that the live run discarded.
+	/// Slices before until are complete and won't be compared again.
+	pub(crate) fn update(&mut self, trace: &str, deltas: &VecDeque<StoreDelta>, evicted: Option<(f64, u64)>, until: Time) -> Result<(), String>
+	{
+		self.read_trace(trace)?;
+		if self.compare_state {
+			self.copy_deltas(deltas, evicted);
+		}
+		
+		let mut times: Vec<i64> = self.golden.range((Excluded(self.compared), Excluded(until.0))).map(|(&t, _)| t)
+			.chain(self.events.range((Excluded(self.compared), Excluded(until.0))).map(|(&t, _)| t))
+			.chain(self.state.range((Excluded(self.compared), Excluded(until.0))).map(|(&t, _)| t))
+			.collect();
+		times.sort();
+		times.dedup();
+		for time in times {
+			let events = self.events.remove(&time).unwrap_or_default();
+			let state = self.state.remove(&time).unwrap_or_default();
+			if let Some(diff) = self.diff(time, events, state) {
+				self.diffs.push(diff);
+			}
+		}
+		if until.0 > self.compared {
+			self.compared = until.0 - 1;
+		}
+		let compared = self.compared;
+		self.lost.retain(|&(_, last)| last > compared);
+		
+		let events = self.events.get(&until.0).cloned().unwrap_or_default();
+		let state = self.state.get(&until.0).cloned().unwrap_or_default();
+		self.current = self.diff(until.0, events, state);
+		Ok(())
+	}
+	
+	fn read_trace(&mut self, path: &str) -> Result<(), String>
+	{
+		let mut text = String::new();
+		let mut file = File::open(path).map_err(|err| format!("Couldn't open {}: {}", path, err))?;
+		file.seek(SeekFrom::Start(self.offset)).and_then(|_| file.read_to_string(&mut text)).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+		
+		let end = text.rfind('\n').map_or(0, |i| i + 1);	// the last line may still be being written
+		for line in text[..end].lines() {
+			if !line.trim().is_empty() && !line.starts_with("{\"provenance\":") {
+				let entry: TraceEntry = json::decode(line).map_err(|err| format!("{}: {}", path, err))?;
+				self.events.entry(entry.time).or_insert_with(Vec::new).push(describe_event(&entry));
+			}
+		}
+		self.offset += end as u64;
+		Ok(())
+	}
+	
+	fn copy_deltas(&mut self, deltas: &VecDeque<StoreDelta>, evicted: Option<(f64, u64)>)
+	{
+		let seen = self.deltas_seen.map(|(_, edition)| edition);
+		if let Some((time, edition)) = evicted {
+			if seen.map_or(true, |seen| edition > seen) {
+				let first = self.deltas_seen.map_or(-1, |(t, _)| self.to_ticks(t));
+				self.lost.push((first, self.to_ticks(time)));
+			}
+		}
+		for d in deltas.iter().filter(|d| seen.map_or(true, |seen| d.edition > seen)) {
+			let time = self.to_ticks(d.time);
+			self.state.entry(time).or_insert_with(Vec::new).extend(describe_changes(d));
+			self.deltas_seen = Some((d.time, d.edition));
+		}
+	}
+	
+	// The times came from ticks so there's no need to truncate.
+	fn to_ticks(&self, secs: f64) -> i64
+	{
+		TickRounding::Round.to_ticks(secs*self.time_units, 0.0)
+	}
+	
+	fn diff(&self, time: i64, mut events: Vec<String>, mut state: Vec<String>) -> Option<SliceDiff>
+	{
+		let empty = (Vec::new(), Vec::new());
+		let &(ref golden_events, ref golden_state) = self.golden.get(&time).unwrap_or(&empty);
+		events.sort();
+		state.sort();
+		
+		let secs = Time(time).to_secs(self.time_units);
+		if !self.compare_state {
+			diff_slice(secs, &events, golden_events, Some((&empty.1, &empty.1)))
+		} else if self.lost.iter().any(|&(first, last)| time >= first && time <= last) {
+			let diff = diff_slice(secs, &events, golden_events, None);
+			if diff.is_none() && !golden_state.is_empty() {
+				Some(SliceDiff{time: secs, left_events: Vec::new(), right_events: Vec::new(), left_state: Vec::new(), right_state: Vec::new(), deltas_unavailable: true})
+			} else {
+				diff
+			}
+		} else {
+			diff_slice(secs, &events, golden_events, Some((&state, golden_state)))
+		}
+	}
+}
+
+fn describe_event(e: &TraceEntry) -> String
+{
+	match e.payload {
+		Some(ref payload) => format!("'{}' ({}) to {}", e.name, payload, e.to),
+		None => format!("'{}' to {}", e.name, e.to),
+	}
+}
+
+fn describe_changes<'a>(d: &'a StoreDelta) -> impl Iterator<Item = String> + 'a
+{
+	d.changes.iter().map(|c| format!("{} = {}", c.key, c.new_value))
+}
+
+// Returns sorted event and state descriptions keyed by time (in ticks).
+fn slices(run: &RunArtifacts, time_units: f64, until: Time) -> BTreeMap<i64, (Vec<String>, Vec<String>)>
+{
+	let mut slices = BTreeMap::new();
+	for e in run.events.iter().filter(|e| e.time <= until.0) {
+		slices.entry(e.time).or_insert_with(|| (Vec::new(), Vec::new())).0.push(describe_event(e));
+	}
+	for d in run.deltas.iter() {
+		let time = TickRounding::Round.to_ticks(d.time*time_units, 0.0);	// the times came from ticks so there's no need to truncate
+		if time <= until.0 {
+			let state = &mut slices.entry(time).or_insert_with(|| (Vec::new(), Vec::new())).1;
+			state.extend(describe_changes(d));
+		}
+	}
+	for (_, &mut (ref mut events, ref mut state)) in slices.iter_mut() {
+		events.sort();
+		state.sort();
+	}
+	slices
+}
+
+// Both vectors are sorted and may contain duplicates.
+fn missing_from(items: &Vec<String>, other: &Vec<String>) -> Vec<String>
+{
+	let mut missing = Vec::new();
+	let mut j = 0;
+	for item in items.iter() {
+		while j < other.len() && other[j] < *item {
+			j += 1;
+		}
+		match other.get(j).map(|o| o.cmp(item)) {
+			Some(Ordering::Equal) => j += 1,
+			_ => missing.push(item.clone()),
+		}
+	}
+	missing
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::env;
+	use std::fs::OpenOptions;
+	use std::io::Write;
+
+	fn event(time: i64, name: &str, to: &str) -> TraceEntry
+	{
+		TraceEntry{slice: 0, time, to: to.to_string(), name: name.to_string(), port: "".to_string(), payload_type: None, payload_version: None, payload: None, description: None}
+	}
+
+	fn delta(time: f64, key: &str, value: &str) -> StoreDelta
+	{
+		StoreDelta{time, edition: 0, changes: vec![StoreChange{key: key.to_string(), old_value: None, new_value: value.to_string(), kind: "int".to_string()}]}
+	}
+	
+	fn append(path: &str, text: &str)
+	{
+		let mut file = OpenOptions::new().create(true).append(true).open(path).unwrap();
+		file.write_all(text.as_bytes()).unwrap();
+	}
+	
+	fn line(time: i64, name: &str, to: &str) -> String
+	{
+		json::encode(&event(time, name, to)).unwrap() + "\n"
+	}
+
+	#[test]
+	fn diffs_slices()
+	{
+		let left = RunArtifacts {
+			events: vec![event(1, "ping", "world.a"), event(1, "ping", "world.a"), event(2, "ping", "world.b"), event(3, "ping", "world.a")],
+			deltas: vec![delta(1.0, "world.a.count", "2"), delta(2.0, "world.b.count", "1")],
+		};
+		let right = RunArtifacts {
+			events: vec![event(1, "ping", "world.a"), event(2, "ping", "world.b"), event(3, "pong", "world.a")],
+			deltas: vec![delta(1.0, "world.a.count", "1"), delta(2.0, "world.b.count", "1")],
+		};
+
+		let diffs = diff_artifacts(&left, &right, 1.0, Time(2));
+		assert_eq!(diffs, vec![SliceDiff{time: 1.0, left_events: vec!["'ping' to world.a".to_string()], right_events: vec![],
+			left_state: vec!["world.a.count = 2".to_string()], right_state: vec!["world.a.count = 1".to_string()], deltas_unavailable: false}]);
+
+		let diffs = diff_artifacts(&left, &right, 1.0, Time(i64::max_value()));
+		assert_eq!(diffs.len(), 2);
+		assert_eq!((diffs[1].left_events.len(), diffs[1].right_events.len()), (1, 1));
+	}
+	
+	#[test]
+	fn golden_comparisons_are_incremental()
+	{
+		let golden = RunArtifacts {
+			events: vec![event(1, "ping", "world.a"), event(2, "ping", "world.b"), event(3, "ping", "world.c")],
+			deltas: vec![delta(1.0, "world.a.count", "1"), delta(2.0, "world.b.count", "1"), delta(3.0, "world.c.count", "1")],
+		};
+		let mut comparison = GoldenComparison::new(&golden, 1.0, true);
+		
+		let path = env::temp_dir().join(format!("score-incremental-{}.trace", ::std::process::id()));
+		let path = path.to_str().unwrap();
+		let _ = ::std::fs::remove_file(path);
+		append(path, "{\"provenance\":{}}\n");
+		append(path, &line(1, "ping", "world.a"));
+		
+		let mut deltas = VecDeque::new();
+		deltas.push_back(StoreDelta{edition: 1, ..delta(1.0, "world.a.count", "2")});
+		comparison.update(path, &deltas, None, Time(1)).unwrap();
+		let times: Vec<f64> = comparison.diffs().map(|d| d.time).collect();
+		assert_eq!(times, vec![1.0]);	// slice 1 may not be complete so it'll be compared again
+		
+		let partial = line(2, "pong", "world.b");
+		append(path, &partial[..10]);
+		deltas.pop_front();
+		deltas.push_back(StoreDelta{edition: 2, ..delta(2.0, "world.b.count", "1")});
+		comparison.update(path, &deltas, Some((1.0, 1)), Time(2)).unwrap();
+		assert_eq!(comparison.diffs.len(), 1);
+		assert_eq!(comparison.diffs[0].left_state, vec!["world.a.count = 2".to_string()]);
+		assert_eq!(comparison.current.as_ref().unwrap().right_events, vec!["'ping' to world.b".to_string()]);
+		
+		append(path, &partial[10..]);
+		append(path, &line(3, "ping", "world.c"));
+		deltas.pop_front();
+		deltas.push_back(StoreDelta{edition: 4, ..delta(3.0, "world.c.count", "1")});
+		comparison.update(path, &deltas, Some((3.0, 3)), Time(4)).unwrap();
+		let diffs: Vec<&SliceDiff> = comparison.diffs().collect();
+		assert_eq!(diffs.len(), 3);
+		assert_eq!((diffs[1].time, &diffs[1].left_events), (2.0, &vec!["'pong' to world.b".to_string()]));
+		assert_eq!((diffs[2].time, diffs[2].deltas_unavailable), (3.0, true));	// edition 3 was discarded before it was compared
+		assert!(diffs[2].left_state.is_empty() && diffs[2].right_state.is_empty());
+		
+		let _ = ::std::fs::remove_file(path);
+	}
+}
@@ -0,0 +1,176 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use log_sink::*;
+use simulation::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use time;
+
+/// An output directory for a simulation run. The directory is created within a root
+/// directory and a "latest" symlink in the root is pointed at it (on unix). Use attach
+/// to route the simulation's output files into the directory and file to get paths
+/// for other outputs, e.g. [`Recorder`] CSVs.
+///
+/// # Examples
+///
+/// ```no_run
+/// use score::*;
+///
+/// fn run(mut sim: Simulation) -> Result<(), String>
+/// {
+/// 	let dir = RunDir::timestamped("runs")?;
+/// 	dir.attach(&mut sim)?;
+/// 	dir.record_traces(&mut sim)?;
+/// 	sim.run();
+/// 	Ok(())
+/// }
+/// ```
+pub struct RunDir
+{
+	path: PathBuf,
+}
+
+impl RunDir
+{
+	/// Creates a directory named after the current local time, e.g. "2017-06-30_14-05-09".
+	/// If that directory already exists then a numeric suffix is appended.
+	pub fn timestamped(root: &str) -> Result<RunDir, String>
+	{
+		let stamp = time::strftime("%Y-%m-%d_%H-%M-%S", &time::now()).unwrap();
+		create_root(root)?;
+		for i in 0.. {
+			let name = if i == 0 {stamp.clone()} else {format!("{}-{}", stamp, i)};
+			let path = Path::new(root).join(&name);
+			match fs::create_dir(&path) {
+				Ok(_) => return RunDir::new(root, &name, path),
+				Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+				Err(err) => return Err(format!("Couldn't create {}: {}", path.display(), err)),
+			}
+		}
+		unreachable!()
+	}
+
+	/// Creates a directory with the specified name (or re-uses it if it already exists).
+	pub fn named(root: &str, name: &str) -> Result<RunDir, String>
+	{
+		assert!(!name.is_empty() && name != "latest", "'{}' isn't a valid run name", name);
+		let path = Path::new(root).join(name);
+		fs::create_dir_all(&path).map_err(|err| format!("Couldn't create {}: {}", path.display(), err))?;
+		RunDir::new(root, name, path)
+	}
+
+	pub fn path(&self) -> &Path
+	{
+		&self.path
+	}
+
+	/// Returns the path of a file within the directory.
+	pub fn file(&self, name: &str) -> String
+	{
+		self.path.join(name).to_string_lossy().into_owned()
+	}
+
+	/// Log lines are written to "log.txt", the run report to "report.html", and the
+	/// store to "store.txt" when the run exits. Should be called before run.
+	pub fn attach(&self, sim: &mut Simulation) -> Result<(), String>
+	{
+		let path = self.file("log.txt");
+		let sink = FileSink::new(&path, sim.config.time_units).map_err(|err| format!("Couldn't create {}: {}", path, err))?;
+		sim.add_log_sink(Box::new(sink));
+		sim.config.run_report = self.file("report.html");
+		sim.config.store_dump = self.file("store.txt");
+		Ok(())
+	}
+
+	/// Records a trace to "trace.jsonl" and the store deltas to "deltas.jsonl", see
+	/// [`Simulation`]'s record_trace and record_deltas methods.
+	pub fn record_traces(&self, sim: &mut Simulation) -> Result<(), String>
+	{
+		sim.record_trace(&self.file("trace.jsonl"))?;
+		sim.record_deltas(&self.file("deltas.jsonl"))
+	}
+
+	fn new(root: &str, name: &str, path: PathBuf) -> Result<RunDir, String>
+	{
+		update_latest(&Path::new(root).join("latest"), name)?;
+		Ok(RunDir{path})
+	}
+}
+
+fn create_root(root: &str) -> Result<(), String>
+{
+	fs::create_dir_all(root).map_err(|err| format!("Couldn't create {}: {}", root, err))
+}
+
+#[cfg(unix)]
+fn update_latest(link: &Path, name: &str) -> Result<(), String>
+{
+	use std::os::unix::fs::symlink;
+
+	match fs::symlink_metadata(link) {
+		Ok(ref metadata) if metadata.file_type().is_symlink() => {
+			fs::remove_file(link).map_err(|err| format!("Couldn't remove {}: {}", link.display(), err))?;
+		},
+		Ok(_) => return Err(format!("{} exists and isn't a symlink", link.display())),
+		Err(_) => (),
+	}
+	symlink(name, link).map_err(|err| format!("Couldn't create {}: {}", link.display(), err))	// relative so that the root can be moved
+}
+
+#[cfg(not(unix))]
+fn update_latest(_link: &Path, _name: &str) -> Result<(), String>
+{
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use component::*;
+	use config::*;
+	use std::env;
+
+	#[test]
+	fn runs_write_into_the_directory()
+	{
+		let root = env::temp_dir().join(format!("score-run-dir-{}", ::std::process::id()));
+		let root = root.to_str().unwrap();
+		let first = RunDir::timestamped(root).unwrap();
+		let second = RunDir::timestamped(root).unwrap();
+		assert_ne!(first.path(), second.path());
+
+		let dir = RunDir::named(root, "baseline").unwrap();
+		assert_eq!(fs::read_link(Path::new(root).join("latest")).unwrap(), Path::new("baseline"));
+
+		let mut config = Config::with_seed(1);
+		config.log_to_stdout = false;
+		let mut sim = Simulation::new(config);
+		sim.add_handler_component("world", NO_COMPONENT, |_, _, effector| effector.set_int("runs", 1));
+		dir.attach(&mut sim).unwrap();
+		dir.record_traces(&mut sim).unwrap();
+		sim.run();
+
+		for name in ["log.txt", "report.html", "store.txt", "trace.jsonl", "deltas.jsonl"].iter() {
+			assert!(dir.path().join(name).is_file(), "{} is missing", name);
+		}
+		let log = fs::read_to_string(dir.file("log.txt")).unwrap();
+		assert!(log.contains("finger print"), "{}", log);
+
+		fs::remove_dir_all(root).unwrap();
+	}
+}
@@ -0,0 +1,214 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Simulations are normally either terminating (e.g. a bank that is open from 9 to 5)
+//! or steady-state (e.g. a network router that runs forever). Terminating runs are
+//! analyzed by running independent replications (see [`Runner`] and
+//! [`replication_estimate`]). Steady-state runs discard a warm-up period and then use
+//! the method of batch means to decide when the estimate of a long run mean is precise
+//! enough to stop.
+use std::f64;
+
+/// Controls how the `Simulation` decides when to stop (see config.run_mode).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum RunMode
+{
+	/// The run stops when there are no more events, config.max_secs is reached, or
+	/// effector.exit is called. This is the default.
+	Terminating,
+
+	/// Like Terminating except that the run also stops once a confidence interval for
+	/// a histogram's mean is narrow enough.
+	SteadyState(SteadyState),
+}
+
+/// Parameters for steady-state runs. While the run progresses the current estimate is
+/// written to the "simulation.steady-state.mean", "simulation.steady-state.half-width",
+/// and "simulation.steady-state.batches" store keys.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SteadyState
+{
+	/// Full path of the histogram to watch, e.g. "world.sink.sojourn" (see
+	/// [`Effector`]'s histogram method).
+	pub key: String,
+
+	/// Samples recorded before this many seconds are discarded. Defaults to 0.0.
+	pub warm_up: f64,
+
+	/// Number of samples averaged together to form each batch. This should be large
+	/// enough that the batch means are roughly independent. Defaults to 100.
+	pub batch_size: usize,
+
+	/// Minimum number of batches before the stopping rule is checked. Defaults to 10.
+	pub min_batches: usize,
+
+	/// The run stops once the 95% confidence interval's half-width divided by the mean
+	/// is at most this, e.g. 0.05 for 5%.
+	pub relative_half_width: f64,
+}
+
+impl SteadyState
+{
+	pub fn new(key: &str, relative_half_width: f64) -> SteadyState
+	{
+		assert!(relative_half_width > 0.0, "relative_half_width ({:.3}) is not positive", relative_half_width);
+		SteadyState{key: key.to_string(), warm_up: 0.0, batch_size: 100, min_batches: 10, relative_half_width}
+	}
+}
+
+/// A mean together with the half-width of its 95% confidence interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate
+{
+	pub mean: f64,
+
+	/// NaN if there are fewer than two samples.
+	pub half_width: f64,
+
+	pub count: usize,
+}
+
+impl Estimate
+{
+	/// Uses Student's t distribution so the samples should be independent and roughly
+	/// normal, e.g. batch means or the results of independent replications.
+	pub fn new(samples: &[f64]) -> Estimate
+	{
+		let count = samples.len();
+		let mean = if count > 0 {samples.iter().sum::<f64>()/(count as f64)} else {f64::NAN};
+		let half_width = if count > 1 {
+			let variance = samples.iter().map(|x| (x - mean)*(x - mean)).sum::<f64>()/((count - 1) as f64);
+			t_quantile(count - 1)*(variance/(count as f64)).sqrt()
+		} else {
+			f64::NAN
+		};
+		Estimate{mean, half_width, count}
+	}
+
+	pub fn relative_half_width(&self) -> f64
+	{
+		self.half_width/self.mean.abs()
+	}
+}
+
+/// Tracks the batch means for a steady-state run.
+#[derive(Clone)]
+pub(crate) struct BatchMeans
+{
+	pub(crate) params: SteadyState,
+	sum: f64,
+	count: usize,
+	means: Vec<f64>,
+	changed: bool,
+}
+
+impl BatchMeans
+{
+	pub(crate) fn new(params: &SteadyState) -> BatchMeans
+	{
+		assert!(params.batch_size > 0, "batch_size should be positive");
+		assert!(params.min_batches > 1, "min_batches should be at least two");
+		BatchMeans{params: params.clone(), sum: 0.0, count: 0, means: Vec::new(), changed: false}
+	}
+
+	/// Time is the current time in seconds.
+	pub(crate) fn record(&mut self, time: f64, value: f64)
+	{
+		if time >= self.params.warm_up {
+			self.sum += value;
+			self.count += 1;
+			if self.count == self.params.batch_size {
+				self.means.push(self.sum/(self.count as f64));
+				self.sum = 0.0;
+				self.count = 0;
+				self.changed = true;
+			}
+		}
+	}
+
+	/// Returns the current estimate if a batch has completed since the last call.
+	pub(crate) fn take_changed(&mut self) -> Option<Estimate>
+	{
+		if self.changed {
+			self.changed = false;
+			Some(Estimate::new(&self.means))
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn is_precise(&self, estimate: &Estimate) -> bool
+	{
+		estimate.count >= self.params.min_batches && estimate.relative_half_width() <= self.params.relative_half_width
+	}
+}
+
+// Two-sided 95% quantiles for Student's t distribution.
+fn t_quantile(df: usize) -> f64
+{
+	const TABLE: [f64; 30] = [
+		12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+		2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+		2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042];
+	match df {
+		0 => f64::NAN,
+		1..=30 => TABLE[df - 1],
+		31..=40 => 2.021,
+		41..=60 => 2.000,
+		61..=120 => 1.980,
+		_ => 1.960,
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn estimates()
+	{
+		let estimate = Estimate::new(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+		assert_eq!(estimate.mean, 3.0);
+		assert!((estimate.half_width - 2.776*(2.5f64/5.0).sqrt()).abs() < 1.0e-9);
+		assert!(Estimate::new(&[1.0]).half_width.is_nan());
+	}
+
+	#[test]
+	fn batches_skip_the_warm_up()
+	{
+		let mut params = SteadyState::new("a.latency", 0.1);
+		params.warm_up = 10.0;
+		params.batch_size = 2;
+		params.min_batches = 2;
+		let mut batches = BatchMeans::new(&params);
+
+		batches.record(5.0, 100.0);
+		batches.record(10.0, 1.0);
+		assert_eq!(batches.take_changed(), None);
+		batches.record(11.0, 3.0);
+		let estimate = batches.take_changed().unwrap();
+		assert_eq!((estimate.mean, estimate.count), (2.0, 1));
+		assert!(!batches.is_precise(&estimate));
+
+		batches.record(12.0, 2.0);
+		batches.record(13.0, 2.0);
+		let estimate = batches.take_changed().unwrap();
+		assert!(batches.is_precise(&estimate));
+	}
+}
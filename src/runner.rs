@@ -0,0 +1,243 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! `Runner` is used for Monte Carlo studies: it runs a simulation multiple times using
+//! a different seed for each replication and summarizes the results.
+use formatting::*;
+use run_mode::*;
+use simulation::*;
+use store::*;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The results of one replication.
+#[derive(Clone, Debug)]
+pub struct RunResult
+{
+	pub replication: usize,
+	pub seed: usize,
+	pub finger_print: u64,
+
+	/// Values of the keys passed into [`Runner`]'s keys method (formatted as strings).
+	/// None if the key wasn't in the store when the simulation exited.
+	pub values: Vec<Option<String>>,
+}
+
+/// Runs a simulation multiple times.
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+///
+/// fn build(_replication: usize, seed: usize) -> Simulation
+/// {
+/// 	let mut config = Config::with_seed(seed);
+/// 	config.max_secs = 10.0;
+/// 	let mut sim = Simulation::new(config);
+/// 	// add components here
+/// 	sim
+/// }
+///
+/// fn study()
+/// {
+/// 	let results = Runner::new(100, build)
+/// 		.threads(4)
+/// 		.keys(&["world.receiver.err_percent"])
+/// 		.run();
+/// 	write_results_csv("results.csv", &["world.receiver.err_percent"], &results).unwrap();
+/// }
+/// ```
+pub struct Runner<F>
+	where F: Fn (usize, usize) -> Simulation + Send + Sync + 'static
+{
+	build: Arc<F>,
+	replications: usize,
+	base_seed: usize,
+	threads: usize,
+	keys: Vec<String>,
+}
+
+impl<F> Runner<F>
+	where F: Fn (usize, usize) -> Simulation + Send + Sync + 'static
+{
+	/// Build is called with the replication index and the seed to use (which should
+	/// be used to create the [`Config`]).
+	pub fn new(replications: usize, build: F) -> Runner<F>
+	{
+		assert!(replications > 0, "replications should be positive");
+		Runner{build: Arc::new(build), replications, base_seed: 1, threads: 1, keys: Vec::new()}
+	}
+
+	/// Replication i uses base_seed + i as its seed. Defaults to 1.
+	pub fn base_seed(mut self, seed: usize) -> Runner<F>
+	{
+		assert!(seed > 0, "seed should be positive (zero means seed with entropy)");
+		self.base_seed = seed;
+		self
+	}
+
+	/// Number of simulations to run at once (each in its own thread). Note that each
+	/// simulation uses a thread per active component. Defaults to 1.
+	pub fn threads(mut self, count: usize) -> Runner<F>
+	{
+		assert!(count > 0, "count should be positive");
+		self.threads = count;
+		self
+	}
+
+	/// Store keys whose final values should be collected.
+	pub fn keys(mut self, keys: &[&str]) -> Runner<F>
+	{
+		self.keys = keys.iter().map(|k| k.to_string()).collect();
+		self
+	}
+
+	/// Runs all the replications and returns the results sorted by replication.
+	pub fn run(self) -> Vec<RunResult>
+	{
+		let next = Arc::new(Mutex::new(0));
+		let results = Arc::new(Mutex::new(Vec::with_capacity(self.replications)));
+		let keys = Arc::new(self.keys);
+
+		let mut handles = Vec::with_capacity(self.threads);
+		for _ in 0..self.threads {
+			let next = next.clone();
+			let results = results.clone();
+			let build = self.build.clone();
+			let keys = keys.clone();
+			let (replications, base_seed) = (self.replications, self.base_seed);
+			handles.push(thread::spawn(move || {
+				loop {
+					let replication = {
+						let mut next = next.lock().unwrap();
+						if *next == replications {
+							break;
+						}
+						*next += 1;
+						*next - 1
+					};
+
+					let seed = base_seed + replication;
+					let mut sim = build(replication, seed);
+					let finger_print = sim.run();
+					let values = keys.iter().map(|k| value_string(&sim.store, k)).collect();
+					results.lock().unwrap().push(RunResult{replication, seed, finger_print, values});
+				}
+			}));
+		}
+
+		for handle in handles {
+			if let Err(err) = handle.join() {
+				panic!("replication thread panicked: {:?}", err);
+			}
+		}
+
+		let mut results = Arc::try_unwrap(results).unwrap().into_inner().unwrap();
+		results.sort_by_key(|r| r.replication);
+		results
+	}
+}
+
+/// Writes a CSV file with replication, seed, and finger print columns, and a column for
+/// each key (keys should be the same as those passed into [`Runner`]'s keys method).
+pub fn write_results_csv(path: &str, keys: &[&str], results: &Vec<RunResult>) -> io::Result<()>
+{
+	let mut file = File::create(path)?;
+	file.write_all(csv(keys, results).as_bytes())
+}
+
+/// Returns the mean and 95% confidence interval of a key across replications. Index is
+/// the index of the key within the keys passed into [`Runner`]'s keys method. Values that
+/// are missing or aren't numbers are skipped. This is the standard way to analyze
+/// terminating simulations.
+pub fn replication_estimate(results: &Vec<RunResult>, index: usize) -> Estimate
+{
+	let samples: Vec<f64> = results.iter()
+		.filter_map(|r| r.values[index].as_ref().and_then(|v| v.parse::<f64>().ok()))
+		.collect();
+	Estimate::new(&samples)
+}
+
+pub(crate) fn value_string(store: &Store, key: &str) -> Option<String>
+{
+	if let Some(value) = store.int_data.get(key) {
+		Some(value.1.to_string())
+	} else if let Some(value) = store.float_data.get(key) {
+		Some(float_to_string(value.1))
+	} else if let Some(value) = store.string_data.get(key) {
+		Some(value.1.clone())
+	} else if let Some(value) = store.bool_data.get(key) {
+		Some(value.1.to_string())
+	} else if let Some(value) = store.list_data.get(key) {
+		Some(list_to_string(&value.1))
+	} else {
+		None
+	}
+}
+
+fn csv(keys: &[&str], results: &Vec<RunResult>) -> String
+{
+	let mut text = "replication,seed,finger_print".to_string();
+	for key in keys.iter() {
+		text += ",";
+		text += &csv_cell(key);
+	}
+	text += "\n";
+
+	for result in results.iter() {
+		text += &format!("{},{},{:X}", result.replication, result.seed, result.finger_print);
+		for value in result.values.iter() {
+			text += ",";
+			if let Some(ref value) = *value {
+				text += &csv_cell(value);
+			}
+		}
+		text += "\n";
+	}
+	text
+}
+
+pub(crate) fn csv_cell(text: &str) -> String
+{
+	if text.contains(',') || text.contains('"') || text.contains('\n') {
+		format!("\"{}\"", text.replace("\"", "\"\""))
+	} else {
+		text.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn formats_csv()
+	{
+		let results = vec![
+			RunResult{replication: 0, seed: 1, finger_print: 0xAB, values: vec![Some("1.5".to_string()), None]},
+			RunResult{replication: 1, seed: 2, finger_print: 0xCD, values: vec![Some("2".to_string()), Some("a,b".to_string())]}];
+		assert_eq!(csv(&["x", "y"], &results), "replication,seed,finger_print,x,y\n0,1,AB,1.5,\n1,2,CD,2,\"a,b\"\n");
+		
+		let estimate = replication_estimate(&results, 0);
+		assert_eq!((estimate.mean, estimate.count), (1.75, 2));
+		assert_eq!(replication_estimate(&results, 1).count, 0);
+	}
+}
@@ -0,0 +1,363 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Builds the component tree, port wiring, and initial store values from a JSON
+//! description so that a model can be rearranged without recompiling. Component types
+//! are mapped to Rust code using [`ScenarioFactories`]. A scenario looks like:
+//!
+//! ```text
+//! {
+//!    "components": [
+//!       {"name": "world", "children": [
+//!          {"name": "source", "type": "source", "params": {"rate": 2.0}},
+//!          {"name": "machines", "type": "resource", "values": {"capacity": 3}}
+//!       ]}
+//!    ],
+//!    "wires": [
+//!       {"from": "world.source.output", "to": "world.machines.input"}
+//!    ]
+//! }
+//! ```
+//!
+//! Components without a type are passive components (see the `Simulation`'s
+//! add_component method). Params are passed into the factory and values are written
+//! to the store using the component's path. Wires connect an OutPort to an InPort
+//! using the component path followed by the port name.
+use component::*;
+use effector::*;
+use ports::*;
+use rustc_serialize::json::{Json, Object};
+use simulation::*;
+use std::any::Any;
+use std::collections::HashMap;
+
+/// Returned by the functions registered with [`ScenarioFactories`] so that the
+/// components can be wired together and then started.
+pub trait ScenarioComponent
+{
+	/// Returns a clone of the named InPort (e.g. a `Box<InPort<T>>`) or None if the
+	/// component has no such port.
+	fn in_port(&self, name: &str) -> Option<Box<Any>>;
+
+	/// Connects the named OutPort to a port returned by in_port. This is normally
+	/// implemented using [`connect_port`].
+	fn connect(&mut self, out_port: &str, in_port: Box<Any>) -> Result<(), String>;
+
+	/// Called after all the components have been wired up.
+	fn start(self: Box<Self>);
+}
+
+/// Connects an OutPort to a port returned by [`ScenarioComponent`]'s in_port method.
+/// Returns an error if the payload types don't match or out_port is already connected.
+pub fn connect_port<T: Any + Send>(out_port: &mut OutPort<T>, in_port: Box<Any>) -> Result<(), String>
+{
+	if out_port.is_connected() {
+		return Err("the OutPort is already connected".to_string());
+	}
+	match in_port.downcast::<InPort<T>>() {
+		Ok(in_port) => {out_port.connect_to(&in_port); Ok(())},
+		Err(_) => Err("the ports have different payload types".to_string()),
+	}
+}
+
+/// The params object from a scenario file.
+pub struct ScenarioParams
+{
+	values: Object,
+}
+
+impl ScenarioParams
+{
+	/// Returns the value of the named param or default if it's missing. Panics if the
+	/// param isn't an integer.
+	pub fn int(&self, name: &str, default: i64) -> i64
+	{
+		match self.values.get(name) {
+			Some(value) => value.as_i64().unwrap_or_else(|| panic!("param '{}' should be an integer", name)),
+			None => default,
+		}
+	}
+
+	/// Integers are converted to floats.
+	pub fn float(&self, name: &str, default: f64) -> f64
+	{
+		match self.values.get(name) {
+			Some(value) => value.as_f64().unwrap_or_else(|| panic!("param '{}' should be a number", name)),
+			None => default,
+		}
+	}
+
+	pub fn string(&self, name: &str, default: &str) -> String
+	{
+		match self.values.get(name) {
+			Some(value) => value.as_string().unwrap_or_else(|| panic!("param '{}' should be a string", name)).to_string(),
+			None => default.to_string(),
+		}
+	}
+}
+
+/// Maps the component types used in scenario files to functions which add the
+/// component to the `Simulation`. Factories are passed the parent's id, the
+/// component's name, and the component's params.
+pub struct ScenarioFactories
+{
+	factories: HashMap<String, Box<Fn (&mut Simulation, ComponentID, &str, &ScenarioParams) -> Box<ScenarioComponent>>>,
+}
+
+impl ScenarioFactories
+{
+	pub fn new() -> ScenarioFactories
+	{
+		ScenarioFactories{factories: HashMap::new()}
+	}
+
+	pub fn add<F>(&mut self, type_name: &str, factory: F)
+		where F: Fn (&mut Simulation, ComponentID, &str, &ScenarioParams) -> Box<ScenarioComponent> + 'static
+	{
+		assert!(!type_name.is_empty(), "type_name should not be empty");
+		self.factories.insert(type_name.to_string(), Box::new(factory));
+	}
+}
+
+/// Adds the components described by text to sim, wires them up, and starts them.
+pub fn build_scenario(sim: &mut Simulation, text: &str, factories: &ScenarioFactories) -> Result<(), String>
+{
+	let root = Json::from_str(text).map_err(|err| err.to_string())?;
+	let mut built = HashMap::new();
+	for component in array(&root, "components")?.iter() {
+		add_component(sim, NO_COMPONENT, "", component, factories, &mut built)?;
+	}
+
+	for wire in array(&root, "wires")?.iter() {
+		let from = string(wire, "from")?;
+		let to = string(wire, "to")?;
+		let (from_path, out_name) = split_port(&from)?;
+		let (to_path, in_name) = split_port(&to)?;
+
+		let in_port = match built.get(to_path) {
+			Some(component) => component_in_port(component, in_name, &to)?,
+			None => return Err(format!("wire to '{}' doesn't target a typed component", to)),
+		};
+		match built.get_mut(from_path) {
+			Some(component) => component.connect(out_name, in_port).map_err(|err| format!("couldn't connect '{}' to '{}': {}", from, to, err))?,
+			None => return Err(format!("wire from '{}' doesn't start at a typed component", from)),
+		}
+	}
+
+	for (_, component) in built.drain() {
+		component.start();
+	}
+	Ok(())
+}
+
+fn add_component(sim: &mut Simulation, parent: ComponentID, parent_path: &str, json: &Json, factories: &ScenarioFactories, built: &mut HashMap<String, Box<ScenarioComponent>>) -> Result<(), String>
+{
+	let name = string(json, "name")?;
+	let path = if parent_path.is_empty() {name.clone()} else {format!("{}.{}", parent_path, name)};
+	match json.find("type") {
+		Some(type_name) => {
+			let type_name = type_name.as_string().ok_or(format!("{}'s type should be a string", path))?;
+			let factory = factories.factories.get(type_name).ok_or(format!("{} has unknown type '{}'", path, type_name))?;
+			let params = match json.find("params") {
+				Some(params) => params.as_object().ok_or(format!("{}'s params should be an object", path))?.clone(),
+				None => Object::new(),
+			};
+			let component = factory(sim, parent, &name, &ScenarioParams{values: params});
+			built.insert(path.clone(), component);
+		},
+		None => {sim.add_component(&name, parent);},
+	}
+	let id = sim.components.id(&path).ok_or(format!("the factory for {} didn't add a component with that name", path))?;
+
+	if let Some(values) = json.find("values") {
+		let values = values.as_object().ok_or(format!("{}'s values should be an object", path))?;
+		let mut effector = Effector::new();
+		for (key, value) in values.iter() {
+			match *value {
+				Json::I64(v) => effector.set_int(key, v),
+				Json::U64(v) => effector.set_int(key, v as i64),
+				Json::F64(v) => effector.set_float(key, v),
+				Json::String(ref v) => effector.set_string(key, v),
+				Json::Boolean(v) => effector.set_bool(key, v),
+				_ => return Err(format!("{}.{} should be a number, a string, or a bool", path, key)),
+			}
+		}
+		sim.apply(id, effector);
+	}
+
+	if let Some(children) = json.find("children") {
+		let children = children.as_array().ok_or(format!("{}'s children should be an array", path))?;
+		for child in children.iter() {
+			add_component(sim, id, &path, child, factories, built)?;
+		}
+	}
+	Ok(())
+}
+
+fn component_in_port(component: &Box<ScenarioComponent>, name: &str, full_name: &str) -> Result<Box<Any>, String>
+{
+	component.in_port(name).ok_or(format!("'{}' isn't an InPort", full_name))
+}
+
+fn split_port(path: &str) -> Result<(&str, &str), String>
+{
+	match path.rfind('.') {
+		Some(i) => Ok((&path[..i], &path[i + 1..])),
+		None => Err(format!("'{}' should be a component path followed by a port name", path)),
+	}
+}
+
+// Missing arrays are treated as empty.
+fn array<'a>(json: &'a Json, name: &str) -> Result<&'a [Json], String>
+{
+	match json.find(name) {
+		Some(value) => value.as_array().map(|a| &a[..]).ok_or(format!("{} should be an array", name)),
+		None => Ok(&[]),
+	}
+}
+
+fn string(json: &Json, name: &str) -> Result<String, String>
+{
+	json.find(name).and_then(|v| v.as_string()).map(|s| s.to_string()).ok_or(format!("missing {} string in {}", name, json))
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use config::*;
+	use logging::*;
+	use store::*;
+	use thread_data::*;
+	use std::thread;
+
+	struct Source
+	{
+		data: ThreadData,
+		output: OutPort<()>,
+	}
+
+	impl ScenarioComponent for Source
+	{
+		fn in_port(&self, _: &str) -> Option<Box<Any>>
+		{
+			None
+		}
+
+		fn connect(&mut self, out_port: &str, in_port: Box<Any>) -> Result<(), String>
+		{
+			match out_port {
+				"output" => connect_port(&mut self.output, in_port),
+				_ => Err(format!("no OutPort named {}", out_port)),
+			}
+		}
+
+		fn start(self: Box<Self>)
+		{
+			let source = *self;
+			let (data, output) = (source.data, source.output);
+			thread::spawn(move || {
+				process_events!(data, event, state, effector,
+					"init 0" => {
+						let count = state.get_int(data.id, "count");
+						for _ in 0..count {
+							output.send(&mut effector, "ping");
+						}
+					}
+				);
+			});
+		}
+	}
+
+	struct Sink
+	{
+		data: ThreadData,
+		input: InPort<()>,
+	}
+
+	impl ScenarioComponent for Sink
+	{
+		fn in_port(&self, name: &str) -> Option<Box<Any>>
+		{
+			if name == "input" {Some(Box::new(self.input.clone()))} else {None}
+		}
+
+		fn connect(&mut self, out_port: &str, _: Box<Any>) -> Result<(), String>
+		{
+			Err(format!("no OutPort named {}", out_port))
+		}
+
+		fn start(self: Box<Self>)
+		{
+			let data = self.data;
+			thread::spawn(move || {
+				process_events!(data, event, state, effector,
+					"ping" => {
+						effector.counter("pings").inc(1);
+					}
+				);
+			});
+		}
+	}
+
+	fn factories() -> ScenarioFactories
+	{
+		let mut factories = ScenarioFactories::new();
+		factories.add("source", |sim, parent, name, _| {
+			let (_, data) = sim.add_active_component(name, parent);
+			Box::new(Source{data, output: OutPort::new()})
+		});
+		factories.add("sink", |sim, parent, name, _| {
+			let (id, data) = sim.add_active_component(name, parent);
+			Box::new(Sink{data, input: InPort::new(id)})
+		});
+		factories
+	}
+
+	#[test]
+	fn builds_and_wires_components()
+	{
+		let text = r#"{
+			"components": [
+				{"name": "world", "children": [
+					{"name": "source", "type": "source", "values": {"count": 3}},
+					{"name": "sink", "type": "sink"}
+				]}
+			],
+			"wires": [{"from": "world.source.output", "to": "world.sink.input"}]
+		}"#;
+		let mut config = Config::new();
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		build_scenario(&mut sim, text, &factories()).unwrap();
+		sim.run();
+
+		assert_eq!(sim.store.get_int("world.sink.pings"), 3);
+	}
+
+	#[test]
+	fn reports_bad_wiring()
+	{
+		let text = r#"{
+			"components": [
+				{"name": "world", "children": [{"name": "source", "type": "source"}, {"name": "sink", "type": "sink"}]}
+			],
+			"wires": [{"from": "world.source.output", "to": "world.sink.bogus"}]
+		}"#;
+		let mut sim = Simulation::new(Config::new());
+		assert_eq!(build_scenario(&mut sim, text, &factories()), Err("'world.sink.bogus' isn't an InPort".to_string()));
+	}
+}
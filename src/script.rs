@@ -0,0 +1,303 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Rules allow experiment logic to be kept out of compiled model code. Rules are
+//! evaluated by the `Simulation` after each time slice and look like this:
+//!
+//! ```text
+//! # Comments start with a hash.
+//! when world.receiver.err_percent > 50 send alarm to world.sender
+//! when world.bots-left <= 1 and world.phase == 'fighting' exit
+//! when world.sender.sent >= 100 log sent enough packets
+//! ```
+//!
+//! Conditions compare a store key against a number or a quoted string using `<`,
+//! `<=`, `>`, `>=`, `==`, or `!=`. Conditions involving missing keys are false.
+//! Rules fire when their conditions become true (and can fire again once their
+//! conditions have become false).
+use store::*;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Action
+{
+	Send(String, String),	// event name, component path
+	Exit,
+	Log(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op
+{
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual,
+	Equal,
+	NotEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand
+{
+	Number(f64),
+	Text(String),
+}
+
+#[derive(Clone, Debug)]
+struct Condition
+{
+	key: String,
+	op: Op,
+	value: Operand,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Rule
+{
+	pub(crate) text: String,
+	conditions: Vec<Condition>,
+	pub(crate) action: Action,
+	active: bool,	// true if the conditions were true the last time we checked
+}
+
+impl Rule
+{
+	pub(crate) fn parse(text: &str) -> Result<Rule, String>
+	{
+		let tokens = tokenize(text)?;
+		let mut tokens = tokens.iter().map(|t| t.as_str());
+
+		if tokens.next() != Some("when") {
+			return Err(format!("rule should start with 'when': {}", text));
+		}
+
+		let mut conditions = Vec::new();
+		loop {
+			let key = tokens.next().ok_or_else(|| format!("expected a store key: {}", text))?;
+			let op = parse_op(tokens.next().unwrap_or(""), text)?;
+			let value = parse_operand(tokens.next().ok_or_else(|| format!("expected a value: {}", text))?);
+			conditions.push(Condition{key: key.to_string(), op, value});
+
+			match tokens.next() {
+				Some("and") => continue,
+				Some("send") => {
+					let name = tokens.next().ok_or_else(|| format!("expected an event name: {}", text))?;
+					if tokens.next() != Some("to") {
+						return Err(format!("expected 'to' after the event name: {}", text));
+					}
+					let path = tokens.next().ok_or_else(|| format!("expected a component path: {}", text))?;
+					let action = Action::Send(unquote(name), path.to_string());
+					return finish(text, conditions, action, tokens.next());
+				},
+				Some("exit") => return finish(text, conditions, Action::Exit, tokens.next()),
+				Some("log") => {
+					let message: Vec<String> = tokens.map(|t| unquote(t)).collect();
+					return finish(text, conditions, Action::Log(message.join(" ")), None);
+				},
+				Some(token) => return Err(format!("expected 'and', 'send', 'exit', or 'log' but found '{}': {}", token, text)),
+				None => return Err(format!("rule is missing an action: {}", text)),
+			}
+		}
+	}
+
+	/// Returns true if the rule should fire.
+	pub(crate) fn evaluate(&mut self, store: &Store) -> bool
+	{
+		let matched = self.conditions.iter().all(|c| c.evaluate(store));
+		let fire = matched && !self.active;
+		self.active = matched;
+		fire
+	}
+}
+
+/// Parses a file containing one rule per line.
+pub(crate) fn parse_rules(text: &str) -> Result<Vec<Rule>, String>
+{
+	let mut rules = Vec::new();
+	for (i, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if !line.is_empty() && !line.starts_with('#') {
+			let rule = Rule::parse(line).map_err(|err| format!("line {}: {}", i + 1, err))?;
+			rules.push(rule);
+		}
+	}
+	Ok(rules)
+}
+
+impl Condition
+{
+	fn evaluate(&self, store: &Store) -> bool
+	{
+		if let Some(value) = store.int_data.get(&self.key) {
+			self.compare_number(value.1 as f64)
+		} else if let Some(value) = store.float_data.get(&self.key) {
+			self.compare_number(value.1)
+		} else if let Some(value) = store.string_data.get(&self.key) {
+			match self.value {
+				Operand::Text(ref text) => compare(&value.1, self.op, text),
+				Operand::Number(_) => false,
+			}
+		} else if let Some(value) = store.bool_data.get(&self.key) {
+			match self.value {
+				Operand::Text(ref text) => compare(&value.1.to_string(), self.op, text),
+				Operand::Number(_) => false,
+			}
+		} else {
+			false
+		}
+	}
+
+	fn compare_number(&self, lhs: f64) -> bool
+	{
+		match self.value {
+			Operand::Number(rhs) => compare(&lhs, self.op, &rhs),
+			Operand::Text(_) => false,
+		}
+	}
+}
+
+fn compare<T: PartialOrd + ?Sized>(lhs: &T, op: Op, rhs: &T) -> bool
+{
+	match op {
+		Op::Less => lhs < rhs,
+		Op::LessEqual => lhs <= rhs,
+		Op::Greater => lhs > rhs,
+		Op::GreaterEqual => lhs >= rhs,
+		Op::Equal => lhs == rhs,
+		Op::NotEqual => lhs != rhs,
+	}
+}
+
+fn finish(text: &str, conditions: Vec<Condition>, action: Action, extra: Option<&str>) -> Result<Rule, String>
+{
+	if let Some(token) = extra {
+		return Err(format!("unexpected '{}' after the action: {}", token, text));
+	}
+	Ok(Rule{text: text.to_string(), conditions, action, active: false})
+}
+
+fn parse_op(token: &str, text: &str) -> Result<Op, String>
+{
+	match token {
+		"<" => Ok(Op::Less),
+		"<=" => Ok(Op::LessEqual),
+		">" => Ok(Op::Greater),
+		">=" => Ok(Op::GreaterEqual),
+		"==" => Ok(Op::Equal),
+		"!=" => Ok(Op::NotEqual),
+		_ => Err(format!("expected <, <=, >, >=, ==, or != but found '{}': {}", token, text)),
+	}
+}
+
+fn parse_operand(token: &str) -> Operand
+{
+	if is_quoted(token) {
+		Operand::Text(unquote(token))
+	} else if let Ok(value) = f64::from_str(token) {
+		Operand::Number(value)
+	} else {
+		Operand::Text(token.to_string())
+	}
+}
+
+fn is_quoted(token: &str) -> bool
+{
+	token.len() >= 2 && (token.starts_with('\'') && token.ends_with('\'') || token.starts_with('"') && token.ends_with('"'))
+}
+
+fn unquote(token: &str) -> String
+{
+	if is_quoted(token) {
+		token[1..token.len()-1].to_string()
+	} else {
+		token.to_string()
+	}
+}
+
+// Splits on white space except within quotes (which are retained).
+fn tokenize(text: &str) -> Result<Vec<String>, String>
+{
+	let mut tokens = Vec::new();
+	let mut token = String::new();
+	let mut quote = None;
+	for ch in text.chars() {
+		match quote {
+			Some(q) => {
+				token.push(ch);
+				if ch == q {
+					quote = None;
+				}
+			},
+			None if ch == '\'' || ch == '"' => {
+				token.push(ch);
+				quote = Some(ch);
+			},
+			None if ch.is_whitespace() => {
+				if !token.is_empty() {
+					tokens.push(token);
+					token = String::new();
+				}
+			},
+			None => token.push(ch),
+		}
+	}
+	if quote.is_some() {
+		return Err(format!("unterminated quote: {}", text));
+	}
+	if !token.is_empty() {
+		tokens.push(token);
+	}
+	Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use sim_time::*;
+
+	#[test]
+	fn fires_on_transition()
+	{
+		let mut rule = Rule::parse("when world.err > 50 and world.phase == 'running late' send alarm to world.sender").unwrap();
+		assert_eq!(rule.action, Action::Send("alarm".to_string(), "world.sender".to_string()));
+
+		let mut store = Store::new();
+		store.set_float("world.err", 10.0, Time(0));
+		store.set_string("world.phase", "running late", Time(0));
+		assert!(!rule.evaluate(&store));
+
+		store.set_float("world.err", 60.0, Time(1));
+		assert!(rule.evaluate(&store));
+		assert!(!rule.evaluate(&store));	// still true so we don't fire again
+
+		store.set_float("world.err", 10.0, Time(2));
+		assert!(!rule.evaluate(&store));
+		store.set_float("world.err", 70.0, Time(3));
+		assert!(rule.evaluate(&store));
+	}
+
+	#[test]
+	fn bad_rules()
+	{
+		assert!(Rule::parse("world.err > 50 exit").is_err());
+		assert!(Rule::parse("when world.err >> 50 exit").is_err());
+		assert!(Rule::parse("when world.err > 50").is_err());
+		assert!(Rule::parse("when world.err > 50 exit now").is_err());
+		assert!(parse_rules("# comment\n\nwhen x < 'a exit").is_err());
+	}
+}
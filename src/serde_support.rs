@@ -0,0 +1,78 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! glob's Pattern doesn't support serde so these are used (via serde's with attribute)
+//! to serialize patterns as strings.
+
+pub mod pattern_map
+{
+	use glob::Pattern;
+	use logging::*;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use serde::de::Error;
+	use std::collections::{BTreeMap, HashMap};
+
+	pub fn serialize<S: Serializer>(map: &HashMap<Pattern, LogLevel>, serializer: S) -> Result<S::Ok, S::Error>
+	{
+		let map: BTreeMap<&str, LogLevel> = map.iter().map(|(p, l)| (p.as_str(), *l)).collect();	// BTreeMap so the output is deterministic
+		map.serialize(serializer)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<Pattern, LogLevel>, D::Error>
+	{
+		let map: HashMap<String, LogLevel> = HashMap::deserialize(deserializer)?;
+		map.into_iter().map(|(p, l)| Pattern::new(&p).map(|p| (p, l)).map_err(D::Error::custom)).collect()
+	}
+}
+
+pub mod pattern_pairs
+{
+	use glob::Pattern;
+	use history::*;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use serde::de::Error;
+
+	pub fn serialize<S: Serializer>(pairs: &Vec<(Pattern, RetentionPolicy)>, serializer: S) -> Result<S::Ok, S::Error>
+	{
+		let pairs: Vec<(&str, RetentionPolicy)> = pairs.iter().map(|&(ref p, r)| (p.as_str(), r)).collect();
+		pairs.serialize(serializer)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(Pattern, RetentionPolicy)>, D::Error>
+	{
+		let pairs: Vec<(String, RetentionPolicy)> = Vec::deserialize(deserializer)?;
+		pairs.into_iter().map(|(p, r)| Pattern::new(&p).map(|p| (p, r)).map_err(D::Error::custom)).collect()
+	}
+}
+
+pub mod patterns
+{
+	use glob::Pattern;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use serde::de::Error;
+
+	pub fn serialize<S: Serializer>(patterns: &Vec<Pattern>, serializer: S) -> Result<S::Ok, S::Error>
+	{
+		let patterns: Vec<&str> = patterns.iter().map(|p| p.as_str()).collect();
+		patterns.serialize(serializer)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Pattern>, D::Error>
+	{
+		let patterns: Vec<String> = Vec::deserialize(deserializer)?;
+		patterns.into_iter().map(|p| Pattern::new(&p).map_err(D::Error::custom)).collect()
+	}
+}
@@ -0,0 +1,79 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Shared read-only resources, e.g. routing tables, terrain maps, or parsed trace files.
+/// Services are registered using the `Simulation`'s provide method and components access
+/// them using [`SimState`]'s service method. There is at most one service of each type.
+/// This is much cheaper than cloning large data into every component thread and much
+/// saner than smuggling it through the store as strings.
+///
+/// Note that services should be immutable: the `Simulation` cannot track changes made to
+/// them (e.g. via a Mutex) so results would depend upon thread scheduling.
+#[derive(Clone)]
+pub struct Services
+{
+	services: HashMap<TypeId, Arc<Any + Send + Sync>>,
+}
+
+impl Services
+{
+	pub(crate) fn new() -> Services
+	{
+		Services{services: HashMap::new()}
+	}
+
+	pub(crate) fn provide<T: Any + Send + Sync>(&mut self, service: T)
+	{
+		self.services.insert(TypeId::of::<T>(), Arc::new(service));
+	}
+
+	pub fn get<T: Any + Send + Sync>(&self) -> Option<&T>
+	{
+		self.services.get(&TypeId::of::<T>()).and_then(|s| s.downcast_ref::<T>())
+	}
+
+	pub fn contains<T: Any + Send + Sync>(&self) -> bool
+	{
+		self.services.contains_key(&TypeId::of::<T>())
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	struct Routes
+	{
+		hops: Vec<(u32, u32)>,
+	}
+
+	#[test]
+	fn finds_services_by_type()
+	{
+		let mut services = Services::new();
+		assert!(services.get::<Routes>().is_none());
+
+		services.provide(Routes{hops: vec![(1, 2)]});
+		services.provide(10u64);
+		assert_eq!(services.get::<Routes>().unwrap().hops, vec![(1, 2)]);
+		assert_eq!(services.get::<u64>(), Some(&10));
+		assert!(!services.contains::<String>());
+	}
+}
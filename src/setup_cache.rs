@@ -0,0 +1,162 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use rustc_serialize::{Decodable, Encodable, json};
+use rustc_serialize::json::Json;
+use sim_rng::fnv1a;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+
+/// Saves expensive but deterministic setup artifacts (e.g. parsed traces, generated
+/// topologies, or routing tables) to disk so that they can be reused by later runs,
+/// e.g. the replications of a [`Runner`] sweep. Artifacts are stored as JSON files
+/// named after the artifact and a hash of its key (the key itself is saved with the
+/// artifact so hash collisions are detected). The key should include everything
+/// the artifact depends upon, e.g. the input file names and the relevant [`Config`]
+/// settings (see [`Provenance`]'s config_hash).
+///
+/// # Examples
+///
+/// ```
+/// extern crate score;
+///
+/// use score::*;
+/// use std::env;
+///
+/// fn main() {
+/// 	let dir = env::temp_dir().join("score-doc-cache");
+/// 	let cache = SetupCache::new(dir.to_str().unwrap());
+/// 	let table: Vec<u32> = cache.get_or_build("squares", "n=100", || (0..100).map(|i| i*i).collect()).unwrap();
+/// 	assert_eq!(table[9], 81);
+/// }
+/// ```
+pub struct SetupCache
+{
+	dir: PathBuf,
+}
+
+impl SetupCache
+{
+	/// The directory is created when the first artifact is saved.
+	pub fn new(dir: &str) -> SetupCache
+	{
+		SetupCache{dir: PathBuf::from(dir)}
+	}
+
+	/// Returns the artifact saved for name and key or, if there isn't one, calls build
+	/// and saves the result. Saved artifacts that can't be read (or were saved for a
+	/// different key) are rebuilt. Returns an error if the artifact couldn't be saved.
+	pub fn get_or_build<T, F>(&self, name: &str, key: &str, build: F) -> Result<T, String>
+		where T: Encodable + Decodable, F: FnOnce () -> T
+	{
+		let path = self.path(name, key);
+		if let Some(value) = self.load(&path, key) {
+			return Ok(value);
+		}
+
+		let value = build();
+		self.save(&path, key, &value).map_err(|err| format!("couldn't cache {}: {}", path.display(), err))?;
+		Ok(value)
+	}
+
+	/// Deletes the saved artifact for name and key (if any).
+	pub fn invalidate(&self, name: &str, key: &str)
+	{
+		let _ = fs::remove_file(self.path(name, key));
+	}
+
+	fn path(&self, name: &str, key: &str) -> PathBuf
+	{
+		self.dir.join(format!("{}-{:016X}.json", name, fnv1a(key)))
+	}
+
+	fn load<T: Decodable>(&self, path: &PathBuf, key: &str) -> Option<T>
+	{
+		let mut text = String::new();
+		File::open(path).and_then(|mut file| file.read_to_string(&mut text)).ok()?;
+		let mut cached = Json::from_str(&text).ok()?;
+		let cached = cached.as_object_mut()?;
+		if cached.get("key").and_then(|k| k.as_string()) != Some(key) {
+			return None;
+		}
+		let value = cached.remove("value")?;
+		Decodable::decode(&mut json::Decoder::new(value)).ok()
+	}
+
+	// Replications often run in parallel so the artifact is written to a temporary
+	// file and then renamed so that other threads never see a partial file.
+	fn save<T: Encodable>(&self, path: &PathBuf, key: &str, value: &T) -> Result<(), String>
+	{
+		let value = json::encode(value).map_err(|err| err.to_string())?;
+		let text = format!("{{\"key\":{},\"value\":{}}}", Json::String(key.to_string()), value);
+		fs::create_dir_all(&self.dir).map_err(|err| err.to_string())?;
+
+		let thread: String = format!("{:?}", thread::current().id()).chars().filter(|c| c.is_digit(10)).collect();
+		let temp = path.with_extension(format!("{}-{}.tmp", process::id(), thread));
+		File::create(&temp).and_then(|mut file| file.write_all(text.as_bytes())).map_err(|err| err.to_string())?;
+		fs::rename(&temp, path).map_err(|err| err.to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::env;
+
+	#[test]
+	fn artifacts_are_reused()
+	{
+		let dir = env::temp_dir().join("score-setup-cache");
+		let cache = SetupCache::new(dir.to_str().unwrap());
+		cache.invalidate("table", "a");
+
+		let mut builds = 0;
+		let first: Vec<i64> = cache.get_or_build("table", "a", || {builds += 1; vec![1, 2, 3]}).unwrap();
+		let second: Vec<i64> = cache.get_or_build("table", "a", || {builds += 1; vec![]}).unwrap();
+		assert_eq!(first, second);
+		assert_eq!(builds, 1);
+
+		let other: Vec<i64> = cache.get_or_build("table", "b", || vec![4]).unwrap();
+		assert_eq!(other, vec![4]);
+	}
+
+	#[test]
+	fn keys_are_verified()
+	{
+		let dir = env::temp_dir().join(format!("score-setup-cache-keys-{}", process::id()));
+		let cache = SetupCache::new(dir.to_str().unwrap());
+
+		// Simulate a hash collision by saving an artifact for a different key.
+		let path = cache.path("table", "a");
+		cache.save(&path, "b", &vec![1i64]).unwrap();
+		let value: Vec<i64> = cache.get_or_build("table", "a", || vec![2]).unwrap();
+		assert_eq!(value, vec![2]);
+
+		let value: Vec<i64> = cache.get_or_build("table", "a", || vec![3]).unwrap();
+		assert_eq!(value, vec![2]);
+
+		let file = File::create(dir.join("blocked")).unwrap();
+		drop(file);
+		let blocked = SetupCache::new(dir.join("blocked").to_str().unwrap());
+		let result: Result<Vec<i64>, String> = blocked.get_or_build("table", "a", || vec![4]);
+		assert!(result.unwrap_err().starts_with("couldn't cache "));
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
@@ -0,0 +1,124 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use effector::*;
+use event::*;
+use sim_state::*;
+use std::thread;
+use thread_data::*;
+
+/// Alternative to process_events! where a component is an ordinary struct. This makes
+/// it easy to keep state in fields and to unit test the component by calling on_event
+/// directly. Use [`Simulation`]'s add_sim_component method (or spawn) to run the
+/// component on its own thread.
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+///
+/// struct Counter
+/// {
+/// 	count: i64,
+/// }
+///
+/// impl SimComponent for Counter
+/// {
+/// 	fn on_event(&mut self, event: &Event, _state: &SimState, effector: &mut Effector)
+/// 	{
+/// 		if event.name == "tick" {
+/// 			self.count += 1;
+/// 			effector.set_int("count", self.count);
+/// 		}
+/// 	}
+/// }
+///
+/// fn add_counter(sim: &mut Simulation, world: ComponentID) -> ComponentID
+/// {
+/// 	sim.add_sim_component("counter", world, Counter{count: 0})
+/// }
+/// ```
+pub trait SimComponent: Send + 'static
+{
+	/// Called for each event sent to the component (including the init events).
+	fn on_event(&mut self, event: &Event, state: &SimState, effector: &mut Effector);
+
+	/// Spawns a thread for the component that calls on_event.
+	fn spawn(mut self, data: ThreadData) -> thread::JoinHandle<()>
+		where Self: Sized
+	{
+		thread::spawn(move || {
+			for (event, state) in data.rx.iter() {
+				let mut effector = Effector::new();
+				self.on_event(&event, &state, &mut effector);
+
+				drop(state);
+				let _ = data.tx.send(effector);
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use component::*;
+	use config::*;
+	use simulation::*;
+	use store::*;
+
+	struct Pinger
+	{
+		target: ComponentID,
+		sent: i64,
+	}
+
+	impl SimComponent for Pinger
+	{
+		fn on_event(&mut self, event: &Event, _state: &SimState, effector: &mut Effector)
+		{
+			if event.name == "init 0" || (event.name == "pong" && self.sent < 3) {
+				self.sent += 1;
+				effector.set_int("sent", self.sent);
+				effector.request(Event::new("ping"), self.target, 1.0);
+			}
+		}
+	}
+
+	struct Ponger;
+
+	impl SimComponent for Ponger
+	{
+		fn on_event(&mut self, event: &Event, _state: &SimState, effector: &mut Effector)
+		{
+			if event.name == "ping" {
+				effector.reply(event, Event::new("pong"));
+			}
+		}
+	}
+
+	#[test]
+	fn components_are_driven_by_threads()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let ponger = sim.add_sim_component("ponger", world, Ponger);
+		sim.add_sim_component("pinger", world, Pinger{target: ponger, sent: 0});
+
+		sim.run();
+		assert_eq!(sim.store.get_int("world.pinger.sent"), 3);
+	}
+}
@@ -0,0 +1,228 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use rand::{Rng, SeedableRng, StdRng};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Deterministic random number generator handed out to components via [`ThreadData`].
+/// Each component gets a generator derived from the simulation seed and the component's
+/// path and can split off independent named substreams, e.g. one for arrivals and one
+/// for service times. Because substreams depend only upon their name (and not upon how
+/// many numbers were drawn from the parent) adding a draw to one stream won't perturb
+/// the values produced by any other stream.
+///
+/// Generators handed out by a [`Simulation`] are also re-seeded when the simulation
+/// starts a branch using branch_with_seed and revert to their original state when that
+/// branch ends.
+///
+/// # Examples
+///
+/// ```
+/// extern crate rand;
+/// extern crate score;
+///
+/// use rand::Rng;
+/// use score::*;
+///
+/// fn main() {
+/// 	let rng = SimRng::new(1);
+/// 	let mut arrivals = rng.stream("arrivals");
+/// 	let mut service = rng.stream("service");
+///
+/// 	let a = arrivals.gen::<f64>();
+/// 	let _ = service.gen::<f64>();	// doesn't change the next arrival
+/// 	assert_eq!(a, rng.stream("arrivals").gen::<f64>());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SimRng
+{
+	key: u64,
+	rng: StdRng,
+	reseeds: Option<Arc<Reseeds>>,
+	generation: usize,			// generation of reseeds that was last applied
+	saved: Vec<(usize, StdRng)>,	// applied branches and the state from before they were applied
+}
+
+impl SimRng
+{
+	pub fn new(seed: u64) -> SimRng
+	{
+		let mut state = seed;
+		SimRng::with_key(splitmix64(&mut state), None)
+	}
+
+	pub(crate) fn with_reseeds(seed: u64, reseeds: Arc<Reseeds>) -> SimRng
+	{
+		let mut state = seed;
+		SimRng::with_key(splitmix64(&mut state), Some(reseeds))
+	}
+
+	/// Returns a new generator whose values are independent of this generator and of
+	/// other substreams with different names. Calling this with the same name always
+	/// returns a generator that starts with the same values.
+	pub fn stream(&self, name: &str) -> SimRng
+	{
+		let mut state = self.key ^ fnv1a(name);
+		SimRng::with_key(splitmix64(&mut state), self.reseeds.clone())
+	}
+
+	fn with_key(key: u64, reseeds: Option<Arc<Reseeds>>) -> SimRng
+	{
+		SimRng{key, rng: new_std_rng(key), reseeds, generation: 0, saved: Vec::new()}
+	}
+
+	// Re-seeds (or restores) the generator if branch seeds were pushed or popped since
+	// the last draw.
+	fn sync(&mut self)
+	{
+		let reseeds = match self.reseeds {
+			Some(ref reseeds) => reseeds.clone(),
+			None => return,
+		};
+		let generation = reseeds.generation.load(Ordering::Acquire);
+		if generation == self.generation {
+			return;
+		}
+
+		let branches = reseeds.branches.lock().unwrap();
+		let common = self.saved.iter().zip(branches.iter()).take_while(|&(saved, branch)| saved.0 == branch.0).count();
+		while self.saved.len() > common {
+			self.rng = self.saved.pop().unwrap().1;
+		}
+		for &(id, seed) in branches[common..].iter() {
+			let mut state = self.key ^ seed;
+			let rng = new_std_rng(splitmix64(&mut state));
+			self.saved.push((id, mem::replace(&mut self.rng, rng)));
+		}
+		self.generation = generation;
+	}
+}
+
+impl Rng for SimRng
+{
+	fn next_u32(&mut self) -> u32
+	{
+		self.sync();
+		self.rng.next_u32()
+	}
+
+	fn next_u64(&mut self) -> u64
+	{
+		self.sync();
+		self.rng.next_u64()
+	}
+
+	fn fill_bytes(&mut self, dest: &mut [u8])
+	{
+		self.sync();
+		self.rng.fill_bytes(dest)
+	}
+}
+
+// The active branch_with_seed branches. These are shared with the component generators
+// which check the generation before each draw.
+pub(crate) struct Reseeds
+{
+	generation: AtomicUsize,
+	branches: Mutex<Vec<(usize, u64)>>,	// (generation of the push, seed)
+}
+
+impl Reseeds
+{
+	pub(crate) fn new() -> Reseeds
+	{
+		Reseeds{generation: AtomicUsize::new(1), branches: Mutex::new(Vec::new())}
+	}
+
+	pub(crate) fn push(&self, seed: u64)
+	{
+		let mut branches = self.branches.lock().unwrap();
+		let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+		branches.push((generation, seed));
+	}
+
+	pub(crate) fn pop(&self)
+	{
+		let mut branches = self.branches.lock().unwrap();
+		branches.pop();
+		self.generation.fetch_add(1, Ordering::AcqRel);
+	}
+}
+
+// StdRng accepts an arbitrary number of seed words so, unlike seeding with a single
+// word, we can give it a full 256 bits.
+fn new_std_rng(key: u64) -> StdRng
+{
+	let mut state = key;
+	let words: Vec<usize> = (0..4).map(|_| splitmix64(&mut state) as usize).collect();
+	StdRng::from_seed(&words[..])
+}
+
+// See http://xoshiro.di.unimi.it/splitmix64.c
+fn splitmix64(state: &mut u64) -> u64
+{
+	*state = state.wrapping_add(0x9E3779B97F4A7C15);
+	let mut z = *state;
+	z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+	z ^ (z >> 31)
+}
+
+// We don't use DefaultHasher because its output may change between Rust releases.
+pub(crate) fn fnv1a(text: &str) -> u64
+{
+	let mut hash = 0xcbf29ce484222325u64;
+	for b in text.bytes() {
+		hash ^= b as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn draws(rng: &mut SimRng) -> Vec<u32>
+	{
+		(0..4).map(|_| rng.next_u32()).collect()
+	}
+
+	#[test]
+	fn streams_are_independent()
+	{
+		let rng = SimRng::new(7);
+		let mut a = rng.stream("a");
+		let mut b = rng.stream("b");
+		assert_ne!(draws(&mut a), draws(&mut b));
+
+		// Drawing from the parent doesn't change its streams.
+		let mut parent = rng.clone();
+		let _ = draws(&mut parent);
+		assert_eq!(draws(&mut parent.stream("a")), draws(&mut rng.stream("a")));
+	}
+
+	#[test]
+	fn seeds_are_deterministic()
+	{
+		assert_eq!(draws(&mut SimRng::new(1)), draws(&mut SimRng::new(1)));
+		assert_ne!(draws(&mut SimRng::new(1)), draws(&mut SimRng::new(2)));
+		assert_ne!(draws(&mut SimRng::new(1).stream("x").stream("y")), draws(&mut SimRng::new(1).stream("y").stream("x")));
+	}
+}
@@ -15,7 +15,12 @@
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
 use component::*;
 use components::*;
+use glob::Pattern;
+use services::*;
+use sim_time::*;
 use store::*;
+use values::*;
+use std::any::Any;
 use std::borrow::Borrow;
 use std::sync::Arc;
 
@@ -31,44 +36,121 @@ pub struct SimState
 	/// finished processing.
 	pub store: Arc<Store>,
 
+	/// Shared read-only resources registered with the `Simulation`'s provide method.
+	pub services: Arc<Services>,
+
 	/// Seconds into the simulation at which the event was dispatched.
 	pub time: f64,
 }
 
 impl SimState
 {
+	/// Returns the full path for a component. This is cached so it's much faster
+	/// than building the path by walking up the component tree.
+	pub fn path(&self, id: ComponentID) -> &str
+	{
+		self.components.path(id)
+	}
+
+	/// Returns the component with the specified full path, e.g. "world.sender".
+	pub fn id(&self, path: &str) -> Option<ComponentID>
+	{
+		self.components.id(path)
+	}
+
+	/// Returns the number of id lookups that have been made (by all threads).
+	pub fn cache_stats(&self) -> PathCacheStats
+	{
+		self.components.cache_stats()
+	}
+
 	pub fn was_removed(&self, id: ComponentID) -> bool
 	{
 		let store:&Store = self.store.borrow();
-		let key = self.components.full_path(id) + ".removed";
+		let key = format!("{}.removed", self.components.path(id));
 		store.contains(&key)
 	}
 
 	pub fn contains(&self, id: ComponentID, key: &str) -> bool
 	{
 		let store:&Store = self.store.borrow();
-		let path = format!("{}.{}", self.components.full_path(id), key);
+		let path = format!("{}.{}", self.components.path(id), key);
 		store.contains(&path)
 	}
 
+	/// Type safe way to read an [`IntValue`], [`FloatValue`], or [`StringValue`]. Note
+	/// that the value knows which component it belongs to so this can be used to read
+	/// the state of other components (e.g. siblings).
+	pub fn read<V: ReadableValue>(&self, value: &V) -> V::Value
+	{
+		value.read_value(self)
+	}
+
+	/// Returns the values for the component's keys that match the glob, e.g. "rx-*".
+	/// Note that the returned keys are full paths.
+	pub fn query(&self, id: ComponentID, glob: &str) -> Result<Vec<(String, StoreValue, Time)>, String>
+	{
+		let pattern = Pattern::new(glob).map_err(|err| format!("bad glob '{}': {}", glob, err))?;
+		let prefix = format!("{}.", self.components.path(id));
+		Ok(self.store.query_with(|key| key.starts_with(&prefix) && pattern.matches(&key[prefix.len()..])))
+	}
+
+	/// Returns the service registered using the `Simulation`'s provide method. Panics
+	/// if there isn't a service of that type.
+	pub fn service<T: Any + Send + Sync>(&self) -> &T
+	{
+		match self.services.get::<T>() {
+			Some(service) => service,
+			None => panic!("no service of that type was provided"),
+		}
+	}
+
 	pub fn get_int(&self, id: ComponentID, key: &str) -> i64
 	{
 		let store:&Store = self.store.borrow();
-		let path = format!("{}.{}", self.components.full_path(id), key);
+		let path = format!("{}.{}", self.components.path(id), key);
 		store.get_int(&path)
 	}
 
 	pub fn get_float(&self, id: ComponentID, key: &str) -> f64
 	{
 		let store:&Store = self.store.borrow();
-		let path = format!("{}.{}", self.components.full_path(id), key);
+		let path = format!("{}.{}", self.components.path(id), key);
 		store.get_float(&path)
 	}
 
 	pub fn get_string(&self, id: ComponentID, key: &str) -> String
 	{
 		let store:&Store = self.store.borrow();
-		let path = format!("{}.{}", self.components.full_path(id), key);
+		let path = format!("{}.{}", self.components.path(id), key);
 		store.get_string(&path)
 	}
+
+	pub fn get_bool(&self, id: ComponentID, key: &str) -> bool
+	{
+		let store:&Store = self.store.borrow();
+		let path = format!("{}.{}", self.components.path(id), key);
+		store.get_bool(&path)
+	}
+
+	pub fn get_vec_int(&self, id: ComponentID, key: &str) -> Vec<i64>
+	{
+		let store:&Store = self.store.borrow();
+		let path = format!("{}.{}", self.components.path(id), key);
+		store.get_vec_int(&path)
+	}
+
+	pub fn get_vec_float(&self, id: ComponentID, key: &str) -> Vec<f64>
+	{
+		let store:&Store = self.store.borrow();
+		let path = format!("{}.{}", self.components.path(id), key);
+		store.get_vec_float(&path)
+	}
+
+	pub fn get_vec_string(&self, id: ComponentID, key: &str) -> Vec<String>
+	{
+		let store:&Store = self.store.borrow();
+		let path = format!("{}.{}", self.components.path(id), key);
+		store.get_vec_string(&path)
+	}
 }
@@ -13,9 +13,247 @@
 // You should have received a copy of the GNU General Public License
 // along with this program; if not, write to the Free Software Foundation,
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use config::*;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub};
 
 /// To better support deterministic execution time is stored
 /// using 64-bit integers. By default the units are in micro-
 /// seconds.
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Time(pub i64);	// unsigned would give us more range, but makes it awkward to use times in the past
+
+/// How seconds are converted into ticks by the `Simulation`, see config.tick_rounding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum TickRounding
+{
+	/// Rounds down, e.g. 1.005s with micro-second units is 1004999 ticks because of float
+	/// error. This matches `Time`'s from_secs method.
+	Floor,
+
+	/// Rounds to the nearest tick.
+	Round,
+
+	/// Rounds up.
+	Ceil,
+}
+
+impl TickRounding
+{
+	/// Converts a fractional tick count into ticks. If ticks is within epsilon ticks of
+	/// an integer then that integer is used regardless of the rounding mode.
+	pub fn to_ticks(self, ticks: f64, epsilon: f64) -> i64
+	{
+		let nearest = ticks.round();
+		if (ticks - nearest).abs() <= epsilon {
+			return nearest as i64;
+		}
+		match self {
+			TickRounding::Floor => ticks.floor() as i64,
+			TickRounding::Round => nearest as i64,
+			TickRounding::Ceil => ticks.ceil() as i64,
+		}
+	}
+}
+
+/// Statistics on the precision lost when converting seconds into ticks, see the
+/// `Simulation`'s tick_rounding_stats method. Errors are in ticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TickRoundingStats
+{
+	/// Number of conversions from seconds to ticks.
+	pub conversions: u64,
+
+	/// Number of conversions that weren't exact.
+	pub inexact: u64,
+
+	/// Sum of the signed errors, i.e. how far the rounding has skewed time.
+	pub net_error: f64,
+
+	/// Sum of the absolute errors.
+	pub total_error: f64,
+
+	/// Largest absolute error.
+	pub max_error: f64,
+}
+
+impl TickRoundingStats
+{
+	pub(crate) fn record(&mut self, exact: f64, ticks: i64)
+	{
+		let error = (ticks as f64) - exact;
+		self.conversions += 1;
+		if error != 0.0 {
+			self.inexact += 1;
+			self.net_error += error;
+			self.total_error += error.abs();
+			self.max_error = self.max_error.max(error.abs());
+		}
+	}
+}
+
+/// The difference between two [`Time`]s (in the same units as Time).
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Duration(pub i64);
+
+impl Time
+{
+	/// Units should be config.time_units. Note that this truncates (like Floor with no
+	/// epsilon) so 1.005s with micro-second units is 1004999 ticks. Use from_config_secs
+	/// to convert seconds the way the `Simulation` does.
+	pub fn from_secs(secs: f64, units: f64) -> Time
+	{
+		Time((secs*units) as i64)
+	}
+
+	/// Converts secs into ticks using config.time_units, config.tick_rounding, and
+	/// config.tick_epsilon.
+	pub fn from_config_secs(secs: f64, config: &Config) -> Time
+	{
+		Time(config.tick_rounding.to_ticks(secs*config.time_units, config.tick_epsilon))
+	}
+
+	pub fn to_secs(self, units: f64) -> f64
+	{
+		(self.0 as f64)/units
+	}
+
+	/// Returns an object that formats the time as seconds, e.g.
+	/// `println!("{}", time.display(config.time_units, 3))` prints "1.500s".
+	pub fn display(self, units: f64, precision: usize) -> TimeDisplay
+	{
+		TimeDisplay{time: self, units, precision}
+	}
+}
+
+impl Duration
+{
+	/// Units should be config.time_units. Like `Time`'s from_secs this truncates.
+	pub fn from_secs(secs: f64, units: f64) -> Duration
+	{
+		Duration((secs*units) as i64)
+	}
+
+	/// Converts secs into ticks using config.time_units, config.tick_rounding, and
+	/// config.tick_epsilon.
+	pub fn from_config_secs(secs: f64, config: &Config) -> Duration
+	{
+		Duration(config.tick_rounding.to_ticks(secs*config.time_units, config.tick_epsilon))
+	}
+
+	pub fn to_secs(self, units: f64) -> f64
+	{
+		(self.0 as f64)/units
+	}
+}
+
+impl Add<Duration> for Time
+{
+	type Output = Time;
+
+	fn add(self, rhs: Duration) -> Time
+	{
+		Time(self.0 + rhs.0)
+	}
+}
+
+impl Sub<Duration> for Time
+{
+	type Output = Time;
+
+	fn sub(self, rhs: Duration) -> Time
+	{
+		Time(self.0 - rhs.0)
+	}
+}
+
+impl Sub<Time> for Time
+{
+	type Output = Duration;
+
+	fn sub(self, rhs: Time) -> Duration
+	{
+		Duration(self.0 - rhs.0)
+	}
+}
+
+impl AddAssign<Duration> for Time
+{
+	fn add_assign(&mut self, rhs: Duration)
+	{
+		self.0 += rhs.0;
+	}
+}
+
+/// Time doesn't know its units so this formats the raw ticks. Use the display
+/// method to format seconds.
+impl fmt::Display for Time
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "{} ticks", self.0)
+	}
+}
+
+/// Returned by [`Time`]'s display method.
+pub struct TimeDisplay
+{
+	time: Time,
+	units: f64,
+	precision: usize,
+}
+
+impl fmt::Display for TimeDisplay
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "{:.*}s", self.precision, self.time.to_secs(self.units))
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn arithmetic()
+	{
+		let units = 1000.0;
+		let t = Time::from_secs(1.5, units);
+		assert_eq!(t, Time(1500));
+		assert_eq!(t + Duration::from_secs(0.25, units), Time(1750));
+		assert_eq!(t - Time(500), Duration(1000));
+		assert!(Time(3) > Time(2));
+		assert_eq!(t.display(units, 3).to_string(), "1.500s");
+		assert_eq!(t.to_string(), "1500 ticks");
+	}
+
+	#[test]
+	fn rounds_ticks()
+	{
+		let ticks = 1.005*1_000_000.0;	// 1004999.9999999999
+		assert_eq!(TickRounding::Floor.to_ticks(ticks, 0.0), 1004999);
+		assert_eq!(TickRounding::Floor.to_ticks(ticks, 1.0e-6), 1005000);
+		assert_eq!(TickRounding::Round.to_ticks(2.5, 0.0), 3);
+		assert_eq!(TickRounding::Ceil.to_ticks(2.1, 0.0), 3);
+		assert_eq!(TickRounding::Ceil.to_ticks(2.1, 0.2), 2);
+
+		let mut config = Config::new();
+		assert_eq!(Time::from_secs(1.005, config.time_units), Time(1004999));
+		assert_eq!(Time::from_config_secs(1.005, &config), Time(1004999));
+		config.tick_epsilon = 1.0e-6;
+		assert_eq!(Time::from_config_secs(1.005, &config), Time(1005000));
+		config.tick_rounding = TickRounding::Ceil;
+		assert_eq!(Duration::from_config_secs(0.0000015, &config), Duration(2));
+
+		let mut stats = TickRoundingStats::default();
+		stats.record(2.25, 2);
+		stats.record(3.0, 3);
+		stats.record(4.5, 5);
+		assert_eq!(stats, TickRoundingStats{conversions: 3, inexact: 2, net_error: 0.25, total_error: 0.75, max_error: 0.5});
+	}
+}
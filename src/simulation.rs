@@ -13,31 +13,61 @@
 // You should have received a copy of the GNU General Public License
 // along with this program; if not, write to the Free Software Foundation,
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use clock::*;
 use component::*;
 use components::*;
 use config::*;
+use control::*;
+use display::*;
 use effector::*;
+use entity::*;
 use event::*;
+use event_name::*;
+use formatting::*;
 use glob;
+use history::*;
+use injector::*;
+use log_sink::*;
 use logging::*;
+use payload::*;
+use ports::*;
+use provenance::*;
+#[cfg(feature = "rest")]
+use rest::*;
+use report::*;
 use rand::{Rng, SeedableRng, StdRng};
-use rouille;
+use run_diff::*;
+use run_mode::*;
+use scenario::*;
 use rustc_serialize;
+use rustc_serialize::{Decodable, Encodable};
+use script::*;
+use sim_component::*;
+use sim_rng::*;
+use services::*;
 use sim_state::*;
 use sim_time::*;
+use stats::*;
 use store::*;
 use thread_data::*;
+use time_weighted::*;
+use trace::*;
+use transaction::*;
+use std::any::{Any, TypeId};
 use std::cmp::{max, min, Ordering};
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, BTreeMap, BTreeSet, HashMap, HashSet};
 use std::collections::VecDeque;
-use std::io;
+use std::hash::Hash;
 use std::fs::File;
+use std::io::{Read, Write};
+use std::panic;
+use std::mem;
 use std::path::Path;
-use std::process;
-use std::sync::Arc;
-use std::sync::{mpsc, Mutex};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc;
 use std::time::{Duration};
-use std::thread;
 use time;
 
 /// This is the top-level data structure. Once an exe initializes
@@ -47,21 +77,98 @@ pub struct Simulation
 {
 	pub store: Arc<Store>,				// TODO: can we make this private?
 	pub components: Arc<Components>,	// Components and vectors are indexed by ComponentID
+	services: Arc<Services>,
 	event_senders: Vec<Option<mpsc::Sender<(Event, SimState)>>>,
 	effector_receivers: Vec<Option<mpsc::Receiver<Effector>>>,
-	config: Config,
+	event_counts: Vec<u64>,			// number of events dispatched to each component
+	pub(crate) config: Config,
 	precision: usize,	// number of decimal places to include when logging, derived from config.time_units
 	current_time: Time,
 	exited: Option<String>,
 	scheduled: BinaryHeap<ScheduledEvent>,
+	next_seq: u64,
+	pending_requests: HashSet<u64>,	// requests that haven't been replied to or timed out
+	next_request_id: u64,
 	rng: Box<Rng + Send>,
-	largest_path: usize,
+	component_rng: SimRng,	// components get streams from this based on their path
+	reseeds: Arc<Reseeds>,	// shared with component_rng streams, see branch_with_seed
+	largest_path: Arc<AtomicUsize>,	// shared with StdoutSink
 	start_time: time::Timespec,
 	event_num: u64,
 	finger_print: u64,
+	finger_prints: VecDeque<SliceFingerPrint>,
+	slice_log_count: u64,	// number of lines logged during the current time slice
 
-	// These are used when the REST server is running.
-	log_lines: Vec<LogLine>,
+	log_writer: LogWriter,
+	store_deltas: VecDeque<StoreDelta>,
+	payloads: PayloadRegistry,
+	branches: Vec<Checkpoint>,
+	rules: Vec<Rule>,
+	stats: Stats,
+	pending_stats: BTreeMap<String, StatValue>,
+	pending_metadata: BTreeMap<String, PendingMetadata>,
+	schema: BTreeMap<String, KeySchema>,
+	assertion_failures: Vec<AssertionFailure>,
+	time_weighted: Vec<TimeWeightedKeys>,
+	tick_stats: TickRoundingStats,
+	entity_locations: HashMap<u64, (ComponentID, Time)>,	// where each live entity is and when it arrived there
+	entity_populations: HashMap<ComponentID, i64>,
+	live_entities: i64,
+	warned_secs: HashSet<u64>,		// bits of the secs that have been warned about
+	batch_means: Option<BatchMeans>,	// used for steady-state runs
+	daemons: Vec<Daemon>,
+	supervisors: HashMap<ComponentID, Supervisor>,
+	handlers: HashMap<ComponentID, Box<FnMut (Event, &SimState, &mut Effector) -> () + Send>>,
+	removed: HashSet<ComponentID>,
+	tick_events: (Time, i64),
+	subtree_events: BTreeMap<ComponentID, i64>,
+	gauges_time: Time,
+	invariants: Vec<Invariant>,
+	stop_conditions: Vec<StopCondition>,
+	stop_event_counts: HashMap<String, u64>,	// only has entries for the events StopCondition::EventCount uses
+	provenance: Provenance,
+	slice_num: u64,
+	tracer: Option<TraceWriter>,
+	delta_writer: Option<TraceWriter>,
+	golden: Option<GoldenComparison>,
+	evicted_delta: Option<(f64, u64)>,	// time and edition of the last delta dropped because of max_store_deltas
+	replay: VecDeque<TraceEntry>,
+	replaying: bool,
+	replay_dropped: u64,
+	ports: Vec<RegisteredPort>,
+	wired_ports: HashMap<EventName, EventName>,	// OutPort placeholder => InPort name
+	clock: Option<Box<Clock>>,
+	phases: Vec<String>,
+	component_phases: HashMap<ComponentID, usize>,
+	granted: Time,
+	watches: Vec<Watch>,
+	recent_events: VecDeque<String>,	// only used if config.emergency_dump is set
+	causes: VecDeque<CauseRecord>,		// indexed by event_num - causes[0].num
+	current_parent: Option<u64>,		// event_num of the effects being applied
+	current_source: ComponentID,		// component whose effects are being applied
+	pending_storm: bool,				// true while storm_pending is exceeded
+	slice_storm: bool,
+	storm_report: Option<(String, String, String)>,	// reason, top events, and top schedulers waiting to be written to the store
+	injector_tx: mpsc::Sender<Injection>,
+	injector_rx: mpsc::Receiver<Injection>,
+	
+	// These are used when the REST server (or another ControlBackend) is running.
+	backend: Option<Box<ControlBackend>>,
+	running: bool,
+	log_lines: VecDeque<LogLine>,	// bounded by config.max_log_lines
+	next_log_offset: u64,
+	log_spill: Option<File>,
+	spill_failed: bool,	// set when spilling a log line fails, cleared once a line is spilled
+	watch_hits: Vec<WatchHit>,
+	breakpoints: Vec<Breakpoint>,
+	breakpoint_hits: VecDeque<BreakpointHit>,
+	runtime_log_levels: Vec<(glob::Pattern, LogLevel)>,	// see set_log_level
+	break_slice: Option<u64>,	// slice_num when the last breakpoint was hit
+	break_hit: bool,			// set when dispatch_events stops at a breakpoint
+	single_event: bool,		// true if time slices should dispatch only one event
+	stepped: Option<SteppedEvent>,	// set by dispatch_events when single_event is set
+	jobs: VecDeque<RunJob>,		// the last entry may be active
+	next_job: u64,
 }
 	
 impl Simulation
@@ -71,28 +178,141 @@ impl Simulation
 		assert!(config.time_units > 0.0, "time units ({}) are not positive", config.time_units);
 		assert!(config.num_init_stages > 0, "num_init_stages ({}) is not positive", config.num_init_stages);	// need an init step to schedule at least one event to process
 				
-		let precision = config.time_units.log10().max(0.0) as usize;
-		let seed = config.seed;
+		let precision = time_precision(config.time_units);
+		let seed = resolve_seed(config.seed);
+		let largest_path = Arc::new(AtomicUsize::new(0));
+		let mut sinks: Vec<Box<LogSink>> = Vec::new();
+		if config.log_to_stdout {
+			sinks.push(Box::new(StdoutSink::new(&config, largest_path.clone())));
+		}
+		let log_writer = LogWriter::new(config.log_queue_size, sinks);
+		let mut store = Store::new();
+		if config.max_store_deltas > 0 {
+			store.changes = Some(Vec::new());
+		}
+		if config.max_state_changes > 0 {
+			store.change_log = Some(ChangeLog::new(config.max_state_changes));
+		}
+		if config.finger_print_store {
+			store.change_hash = Some(0);
+		}
+		if !config.store_history.is_empty() {
+			store.history = Some(StoreHistory::new(&config.store_history, &config));
+		}
+		let provenance = Provenance::new(&config, seed);
+		let (injector_tx, injector_rx) = mpsc::channel();
+		let batch_means = match config.run_mode {
+			RunMode::Terminating => None,
+			RunMode::SteadyState(ref params) => Some(BatchMeans::new(params)),
+		};
+		store.set_string("simulation.run-id", &provenance.run_id, Time(0));
+		store.set_string("simulation.version", &provenance.version, Time(0));
+		store.set_int("simulation.seed", provenance.seed as i64, Time(0));
+		store.set_string("simulation.config-hash", &provenance.config_hash, Time(0));
+		store.set_string("simulation.start-time", &provenance.start_time, Time(0));
+		let reseeds = Arc::new(Reseeds::new());
 		Simulation {
-			store: Arc::new(Store::new()),
+			store: Arc::new(store),
 			components: Arc::new(Components::new(config.max_log_path)),
+			services: Arc::new(Services::new()),
 			event_senders: Vec::new(),
 			effector_receivers: Vec::new(),
+			event_counts: Vec::new(),
 			config: config,
 			precision,
 			current_time: Time(0),
 			exited: None,
 			scheduled: BinaryHeap::new(),
+			next_seq: 0,
+			pending_requests: HashSet::new(),
+			next_request_id: 1,
 			rng: Box::new(new_rng(seed, 10_000)),
-			largest_path: 0,
+			component_rng: SimRng::with_reseeds(get_seed(seed, 0) as u64, reseeds.clone()),
+			reseeds,
+			largest_path,
 			start_time: time::get_time(),
 			event_num: 0,
 			finger_print: 0,
+			finger_prints: VecDeque::new(),
+			slice_log_count: 0,
+			log_writer,
+			store_deltas: VecDeque::new(),
+			payloads: PayloadRegistry::new(),
+			branches: Vec::new(),
+			rules: Vec::new(),
+			stats: Stats::new(),
+			pending_stats: BTreeMap::new(),
+			pending_metadata: BTreeMap::new(),
+			schema: BTreeMap::new(),
+			assertion_failures: Vec::new(),
+			time_weighted: Vec::new(),
+			tick_stats: TickRoundingStats::default(),
+			entity_locations: HashMap::new(),
+			entity_populations: HashMap::new(),
+			live_entities: 0,
+			warned_secs: HashSet::new(),
+			batch_means,
+			daemons: Vec::new(),
+			supervisors: HashMap::new(),
+			handlers: HashMap::new(),
+			removed: HashSet::new(),
+			tick_events: (Time(-1), 0),
+			subtree_events: BTreeMap::new(),
+			gauges_time: Time(-1),
+			invariants: Vec::new(),
+			stop_conditions: Vec::new(),
+			stop_event_counts: HashMap::new(),
+			provenance,
+			slice_num: 0,
+			tracer: None,
+			delta_writer: None,
+			golden: None,
+			evicted_delta: None,
+			replay: VecDeque::new(),
+			replaying: false,
+			replay_dropped: 0,
+			ports: Vec::new(),
+			wired_ports: HashMap::new(),
+			clock: None,
+			phases: Vec::new(),
+			component_phases: HashMap::new(),
+			granted: Time(0),
+			watches: Vec::new(),
+			recent_events: VecDeque::new(),
+			causes: VecDeque::new(),
+			current_parent: None,
+			current_source: NO_COMPONENT,
+			pending_storm: false,
+			slice_storm: false,
+			storm_report: None,
+			injector_tx,
+			injector_rx,
 			
-			log_lines: Vec::new(),
+			backend: None,
+			running: false,
+			log_lines: VecDeque::new(),
+			next_log_offset: 0,
+			log_spill: None,
+			spill_failed: false,
+			watch_hits: Vec::new(),
+			breakpoints: Vec::new(),
+			breakpoint_hits: VecDeque::new(),
+			runtime_log_levels: Vec::new(),
+			break_slice: None,
+			break_hit: false,
+			single_event: false,
+			stepped: None,
+			jobs: VecDeque::new(),
+			next_job: 1,
 		}
 	}
 	
+	/// Returns the number of seconds into the simulation.
+	pub fn current_secs(&self) -> f64
+	{
+		self.current_time.to_secs(self.config.time_units)
+	}
+	
 	/// Dump simulation state to stdout.
 	pub fn print(&self)
 	{
@@ -102,18 +322,27 @@ impl Simulation
 		println!("Store:");
 		self.store.print(self.config.time_units, self.precision);
 
-		let t = (self.current_time.0 as f64)/self.config.time_units;
+		let t = self.current_time.to_secs(self.config.time_units);
 		println!("Current Time:");
 		println!("   {:.1$}s", t, self.precision);
 
 		println!("Scheduled:");
-		for s in self.scheduled.iter() {
-			let t = (s.time.0 as f64)/self.config.time_units;
-			let path = self.components.full_path(s.to);
-			println!("   {:.1$}s {2} -> {3}", t, self.precision, s.event.name, path);
+		for (time, to, name) in self.scheduled_events() {
+			let t = time.to_secs(self.config.time_units);
+			let path = self.components.full_path(to);
+			println!("   {:.1$}s {2} -> {3}", t, self.precision, name, path);
 		}
 	}
 	
+	/// Returns the events that have not been dispatched yet in the order in which
+	/// they will be dispatched (ignoring phases but including priorities).
+	pub fn scheduled_events(&self) -> impl Iterator<Item=(Time, ComponentID, &str)>
+	{
+		let mut events: Vec<&ScheduledEvent> = self.scheduled.iter().collect();
+		events.sort_by(|a, b| b.cmp(a));
+		events.into_iter().map(|e| (e.time, e.to, e.event.name.as_str()))
+	}
+	
 	/// Adds a [`Component`] that is not intended to receive `Event`s.
 	/// These can be used to organize related components together which
 	/// can make navigation nicer within GUIs.
@@ -134,8 +363,10 @@ impl Simulation
 		components.append(id, component, parent);
 		}
 		let path = self.components.full_path(id);
-		self.largest_path = max(path.len(), self.largest_path);
+		let largest = max(path.len(), self.largest_path.load(atomic::Ordering::Relaxed));
+		self.largest_path.store(largest, atomic::Ordering::Relaxed);
 		self.event_senders.push(None);
+		self.event_counts.push(0);
 		self.effector_receivers.push(None);
 		id
 	}
@@ -162,12 +393,190 @@ impl Simulation
 		components.append(id, component, parent);
 		}
 		let path = self.components.full_path(id);
-		self.largest_path = max(path.len(), self.largest_path);
+		let largest = max(path.len(), self.largest_path.load(atomic::Ordering::Relaxed));
+		self.largest_path.store(largest, atomic::Ordering::Relaxed);
 		self.event_senders.push(Some(txd));
+		self.event_counts.push(0);
 		self.effector_receivers.push(Some(rxe));
 		
-		let seed = get_seed(self.config.seed, id.0 as usize);
-		(id, ThreadData::new(id, rxd, txe, seed))
+		let seed = get_seed(self.provenance.seed, id.0 as usize);
+		let rng = self.component_rng.stream(&path);
+		(id, ThreadData::new(id, rxd, txe, seed, rng, self.config.panic_on_unhandled))
+	}
+	
+	/// Makes the simulation follow an external clock: events are only processed once
+	/// the clock has granted their time. Note that initialization (at time zero) does
+	/// not wait for the clock.
+	pub fn set_clock(&mut self, clock: Box<Clock>)
+	{
+		self.clock = Some(clock);
+	}
+	
+	/// Phases are used to order the dispatching of events sent at the same time, e.g.
+	/// "sensors", "controllers", "actuators". Events sent to components in an earlier
+	/// phase are dispatched, and their effects applied, before events to components in
+	/// later phases. This allows components in later phases to see the store changes
+	/// made by earlier phases within the same tick. Events to components which have not
+	/// been assigned a phase are dispatched before any of the named phases.
+	pub fn define_phases(&mut self, phases: &[&str])
+	{
+		assert!(self.component_phases.is_empty(), "phases should be defined before set_phase is called");
+		self.phases = phases.iter().map(|p| p.to_string()).collect();
+	}
+	
+	/// Assigns a component, and its children, to a phase passed into define_phases.
+	/// Children may be assigned to a different phase.
+	pub fn set_phase(&mut self, id: ComponentID, phase: &str)
+	{
+		match self.phases.iter().position(|p| p == phase) {
+			Some(index) => {self.component_phases.insert(id, index);},
+			None => panic!("phase '{}' wasn't passed into define_phases", phase),
+		}
+	}
+	
+	/// Registering ports allows validate to report ports which were not wired up.
+	/// Name is the name of the port's field. Note that this should be called after
+	/// any InPort::empty ports have been replaced.
+	pub fn register_in_port<T: Any + Send>(&mut self, owner: ComponentID, name: &str, port: &InPort<T>)
+	{
+		self.ports.push(RegisteredPort{owner, name: name.to_string(), is_input: true, type_id: TypeId::of::<T>(), target: port.target_id, target_port: port.target_port.clone(), connections: port.connections.clone(), remote: None, wire: None});
+	}
+	
+	pub fn register_out_port<T: Any + Send>(&mut self, owner: ComponentID, name: &str, port: &OutPort<T>)
+	{
+		self.ports.push(RegisteredPort{owner, name: name.to_string(), is_input: false, type_id: TypeId::of::<T>(), target: NO_COMPONENT, target_port: "".to_string(), connections: port.connections.clone(), remote: Some(port.remote.clone()), wire: Some((port.wired_id.clone(), port.wire.clone()))});
+	}
+	
+	/// Connects a registered OutPort to a registered InPort using their full paths, e.g.
+	/// `sim.connect("world.sender.output", "world.receiver.input")`. The paths are a
+	/// component's path followed by the name the port was registered with. This allows
+	/// models to be wired up from topology files or scripts. Returns an error if either
+	/// port wasn't registered, the ports have different payload types, or the OutPort
+	/// was already connected.
+	pub fn connect(&mut self, from: &str, to: &str) -> Result<(), String>
+	{
+		let out_index = self.find_port(from, false)?;
+		let in_index = self.find_port(to, true)?;
+		
+		let (out_port, in_port) = (&self.ports[out_index], &self.ports[in_index]);
+		if out_port.type_id != in_port.type_id {
+			return Err(format!("can't connect {} to {}: the ports have different payload types", from, to));
+		}
+		if in_port.target == NO_COMPONENT {
+			return Err(format!("can't connect {} to {}: the InPort is empty", from, to));
+		}
+		
+		{
+			let mut remote = out_port.remote.as_ref().unwrap().lock().unwrap();
+			if remote.0 != NO_COMPONENT {
+				return Err(format!("can't connect {} to {}: the OutPort is already connected", from, to));
+			}
+			*remote = (in_port.target, in_port.target_port.clone());
+		}
+		out_port.connections.fetch_add(1, atomic::Ordering::Relaxed);
+		in_port.connections.fetch_add(1, atomic::Ordering::Relaxed);
+		
+		let (wired_id, wire) = out_port.wire.clone().unwrap();
+		wired_id.store(in_port.target.0, atomic::Ordering::Release);
+		let in_name = EventName::from(in_port.target_port.as_str());
+		self.wired_ports.insert(wire, in_name);
+		Ok(())
+	}
+	
+	fn find_port(&self, path: &str, is_input: bool) -> Result<usize, String>
+	{
+		let kind = if is_input {"InPort"} else {"OutPort"};
+		let (owner, name) = match path.rfind('.') {
+			Some(i) => (&path[..i], &path[i+1..]),
+			None => return Err(format!("{} path '{}' should be a component path followed by a port name", kind, path)),
+		};
+		let id = match self.components.id(owner) {
+			Some(id) => id,
+			None => return Err(format!("there is no component at '{}'", owner)),
+		};
+		self.ports.iter()
+			.position(|p| p.owner == id && p.name == name && p.is_input == is_input)
+			.ok_or_else(|| format!("{} {} wasn't registered", kind, path))
+	}
+	
+	/// Used by components that wrap nested components to expose a nested InPort as
+	/// their own, e.g. `device.inbound = sim.export_in_port(id, "inbound", &device.mangler.input)`.
+	/// Connecting an OutPort to the returned port connects it to the nested port. The
+	/// exported port is registered with owner so validate will report it if it isn't
+	/// wired up. Panics if inner is empty or isn't a descendant of owner.
+	pub fn export_in_port<T: Any + Send>(&mut self, owner: ComponentID, name: &str, inner: &InPort<T>) -> InPort<T>
+	{
+		assert!(inner.target_id != NO_COMPONENT, "can't export empty InPort {}.{}", self.components.path(owner), name);
+		assert!(self.components.find_parent(inner.target_id, |id, _| id == owner).is_some(),
+			"can't export {}.{}: {} isn't a descendant", self.components.path(owner), name, self.components.path(inner.target_id));
+		
+		let port = inner.clone();
+		self.register_in_port(owner, name, &port);
+		port
+	}
+	
+	/// Returns a description of each registered port that isn't connected. This is
+	/// called by run (before components are initialized) which logs each problem
+	/// as a warning but it can also be called beforehand if mis-wiring should be
+	/// treated as fatal.
+	pub fn validate(&self) -> Vec<String>
+	{
+		let mut problems = Vec::new();
+		for port in self.ports.iter() {
+			let path = format!("{}.{}", self.components.path(port.owner), port.name);
+			if port.is_input && port.target == NO_COMPONENT {
+				problems.push(format!("InPort {} is empty (it was never replaced with a nested component's port)", path));
+			} else if port.connections.load(atomic::Ordering::Relaxed) == 0 {
+				if port.is_input {
+					problems.push(format!("InPort {} has no OutPorts connected to it", path));
+				} else {
+					problems.push(format!("OutPort {} isn't connected", path));
+				}
+			}
+		}
+		problems
+	}
+	
+	/// Writes the component tree and the connections between registered OutPorts
+	/// and InPorts as a GraphViz DOT graph, e.g. `dot -Tsvg model.dot > model.svg`.
+	/// Parent/child edges are dashed, active components are drawn as boxes, and port
+	/// edges are labeled with the port names.
+	pub fn write_dot(&self, path: &str) -> Result<(), String>
+	{
+		let text = self.dot_text();
+		File::create(path).and_then(|mut file| file.write_all(text.as_bytes())).map_err(|err| format!("Couldn't write {}: {}", path, err))
+	}
+	
+	fn dot_text(&self) -> String
+	{
+		let mut text = "digraph topology {\n".to_string();
+		for (id, component) in self.components.iter() {
+			let path = self.components.path(id);
+			let shape = if self.event_senders[id.0].is_some() {"box"} else {"ellipse"};
+			text += &format!("   \"{}\" [label=\"{}\", shape={}];\n", dot_escape(path), dot_escape(&component.name), shape);
+			if component.parent != NO_COMPONENT {
+				text += &format!("   \"{}\" -> \"{}\" [style=dashed, arrowhead=none];\n", dot_escape(self.components.path(component.parent)), dot_escape(path));
+			}
+		}
+		
+		for port in self.ports.iter() {
+			if let Some(ref remote) = port.remote {
+				let &(to, ref in_name) = &*remote.lock().unwrap();
+				if to != NO_COMPONENT {
+					let label = if in_name.is_empty() {port.name.clone()} else {format!("{} -> {}", port.name, in_name)};
+					text += &format!("   \"{}\" -> \"{}\" [label=\"{}\"];\n", dot_escape(self.components.path(port.owner)), dot_escape(self.components.path(to)), dot_escape(&label));
+				}
+			}
+		}
+		text += "}\n";
+		text
+	}
+	
+	/// Declares that a store key was renamed so that history, exports, and GUIs treat
+	/// the two keys as one (see [`Store`]'s rename_key method).
+	pub fn rename_key(&mut self, old: &str, new: &str)
+	{
+		Arc::make_mut(&mut self.store).rename_key(old, new);
 	}
 	
 	/// Use this if you want to update the store, or log, or schedule events when
@@ -198,744 +607,4956 @@ impl Simulation
 		}
 	}
 	
-	/// Use this if you want to do something random when initializing components.
-	pub fn rng(&mut self) -> &mut Box<Rng + Send>
+	/// Returns the event with the specified number (these are the numbers used when
+	/// dispatching events is logged) followed by the event whose handler scheduled it,
+	/// and so on, back to the event that started the chain. The chain is cut short
+	/// if an event is older than the last config.max_event_causes events. Empty if
+	/// the event isn't known.
+	pub fn causal_chain(&self, num: u64) -> Vec<EventCause>
 	{
-		// Using a Box was the only way I could figure out to use the Rng trait
-		// while constraining Rng to be Sized as well so clients get the full
-		// set of Rng methods. (We can't actually constrain a type to Sized but
-		// we can to Send which is evidently enough).
-		&mut self.rng
+		let mut chain = Vec::new();
+		let mut next = Some(num);
+		while let Some(num) = next {
+			match self.causes.front() {
+				Some(first) if num >= first.num && num - first.num < self.causes.len() as u64 => {
+					let record = &self.causes[(num - first.num) as usize];
+					chain.push(EventCause {
+						num: record.num,
+						parent: record.parent,
+						time: record.time.to_secs(self.config.time_units),
+						name: record.name.to_string(),
+						path: self.components.full_path(record.to),
+					});
+					next = record.parent;
+				},
+				_ => break,
+			}
+		}
+		chain
 	}
 	
-	/// Dispatches events until there are no more events left to dispatch,
-	/// config.max_secs elapses, or [`Effector`]s exit method was called.
-	/// Returns a finger print which can be used to verify that simulation
-	/// runs with the same seeds are deterministic.
-	pub fn run(&mut self) -> u64
+	/// Returns a handle that other threads can use to schedule events.
+	pub fn injector(&self) -> EventInjector
 	{
-		if self.config.home_path.is_empty() {
-			self.run_normally();
-		} else {
-			if Path::new(&self.config.home_path).is_file() {
-				self.run_server();
-			} else {
-				eprintln!("'{}' is not a file", self.config.home_path);
-				process::exit(1);
-			}
-		}
-		self.finger_print
+		EventInjector::new(self.injector_tx.clone())
 	}
 	
-	// ---- Private Functions ----------------------------------------------------------------
-	fn run_normally(&mut self)
+	/// Returns the finger prints at the end of the last config.max_finger_prints time
+	/// slices (oldest first).
+	pub fn finger_prints(&self) -> &VecDeque<SliceFingerPrint>
 	{
-		self.init_components();
-		while self.exited.is_none() {
-			self.run_time_slice()
-		}
-		
-//		self.print();
-		self.exit();
+		&self.finger_prints
 	}
 	
-	fn run_server(&mut self)
+	/// Returns the changes made to the store for the last config.max_store_deltas
+	/// time slices (oldest first).
+	pub fn store_deltas(&self) -> &VecDeque<StoreDelta>
 	{
-		let address = self.config.address.clone();
-		self.log(LogLevel::Info, NO_COMPONENT, &format!("running web server at {}", address));
-
-		let (tx_command, rx_command) = mpsc::channel();
-		let (tx_reply, rx_reply) = mpsc::channel();
-		spin_up_rest(&self.config.address, &self.config.home_path, tx_command, rx_reply);
-
-		self.init_components();
-		for command in rx_command.iter() {
-			let reply = match command {
-				RestCommand::GetComponents => {
-					if !self.components.is_empty() {
-						let lines = self.get_components();
-						let data = rustc_serialize::json::encode(&lines).unwrap();	
-						let data = data.to_string();
-						RestReply{data, code:200}
-					} else {
-						RestReply{data: "no components".to_string(), code:404}
-					}
-				}
-				RestCommand::GetExited => {
-					let data = if self.exited.is_some() {"true"} else {"false"};
-					let data = data.to_string();
-					RestReply{data, code:200}
-				}
-				RestCommand::GetLog => {
-					let lines = self.get_log_lines(-1.0);
-					let data = rustc_serialize::json::encode(&lines).unwrap();	
-					RestReply{data, code:200}
-				},
-				RestCommand::GetLogAfter(time) => {
-					let lines = self.get_log_lines(time);
-					let data = rustc_serialize::json::encode(&lines).unwrap();	
-					RestReply{data, code:200}
-				},
-				RestCommand::GetState(path) => {
-					let lines = self.get_state(&path);
-					let data = rustc_serialize::json::encode(&lines).unwrap();
-					RestReply{data, code:200}
-				},
-				RestCommand::GetTime => {
-					let t = (self.current_time.0 as f64)/self.config.time_units;
-					let data = rustc_serialize::json::encode(&t).unwrap();
-					RestReply{data, code:200}
-				},
-				RestCommand::GetTimePrecision => {
-					let data = rustc_serialize::json::encode(&self.precision).unwrap();
-					RestReply{data, code:200}
-				},
-				RestCommand::RunOnce => {
-					if self.exited.is_none() {
-						self.run_time_slice()
-					}
-					
-					let message = if self.exited.is_some() {"exited"} else {"ok"};
-					let data = rustc_serialize::json::encode(&message.to_string()).unwrap();
-					RestReply{data, code:200}
-				}
-				RestCommand::SetFloatState(path, value) => {
-					let store = Arc::get_mut(&mut self.store).expect("Has a component retained a reference to the store?");
-					store.set_float(&path, value, self.current_time);
-					let data = "\"ok\"".to_string();
-					RestReply{data, code:200}
-				}
-				RestCommand::SetIntState(path, value) => {
-					let store = Arc::get_mut(&mut self.store).expect("Has a component retained a reference to the store?");
-					store.set_int(&path, value, self.current_time);
-					let data = "\"ok\"".to_string();
-					RestReply{data, code:200}
-				}
-				RestCommand::SetStringState(path, value) => {
-					let store = Arc::get_mut(&mut self.store).expect("Has a component retained a reference to the store?");
-					store.set_string(&path, &value, self.current_time);
-					let data = "\"ok\"".to_string();
-					RestReply{data, code:200}
-				}
-				RestCommand::SetTime(secs) => {
-					let target = (secs*self.config.time_units) as i64;
-					while self.exited.is_none() && self.current_time.0 < target {
-						self.run_time_slice()
-					}
-					
-					let message = if self.exited.is_some() {"exited"} else {"ok"};
-					let data = rustc_serialize::json::encode(&message.to_string()).unwrap();
-					RestReply{data, code:200}
-				}
-			};
-			tx_reply.send(reply).unwrap();
-		}
-		
-		// Note that we don't want to exit in order to allow GUIs to inspect state at the end.
-		// TODO: but we should have some sort of /exit endpoint to allow GUIs to kill us cleanly.
-		//self.exit();
+		&self.store_deltas
 	}
 	
-	fn init_components(&mut self)
+	/// Adds a sink which will be called (on a logging thread) with each log record
+	/// that passes log level filtering. Note that a sink which writes to stdout is
+	/// installed unless config.log_to_stdout is false.
+	pub fn add_log_sink(&mut self, mut sink: Box<LogSink>)
 	{
-		assert!(self.exited.is_none());
-
-		for i in 0..self.config.num_init_stages {
-			self.schedule_init_stage(i);
-			self.dispatch_events();
-			assert!(self.current_time.0 == 0);
-			if self.exited.is_some() {
-				self.exited = Some("Effector.exit was called during initialization".to_string());
-			}
-		}
+		sink.start(&self.provenance);
+		self.log_writer.add_sink(sink);
 	}
 	
-	fn run_time_slice(&mut self)
+	/// Returns the run id and related information for this run.
+	pub fn provenance(&self) -> &Provenance
 	{
-		assert!(self.exited.is_none());
-
-		let max_time = if self.config.max_secs.is_infinite() {i64::max_value()} else {(self.config.max_secs*self.config.time_units) as i64};
-		if self.scheduled.is_empty() {
-			self.exited = Some("no events".to_string());
-		
-		} else if self.current_time.0 >= max_time {
-			self.exited = Some("reached config.max_secs".to_string());
-
-		} else {
-			self.dispatch_events();
-		}
+		&self.provenance
 	}
 	
-	fn exit(&mut self)
+	/// Event payloads must be registered in order to use features that need to copy
+	/// events, e.g. branch.
+	pub fn register_payload<T: Any + Send + Clone>(&mut self)
 	{
-		// TODO: Might want to also print events/sec, maybe at debug
-		let elapsed = (time::get_time() - self.start_time).num_milliseconds();
-		let exited = self.exited.as_ref().unwrap().clone();
-		self.log(LogLevel::Debug, NO_COMPONENT, &format!("exiting sim, run time was {}.{}s ({})",
-			elapsed/1000, elapsed%1000, exited));	// TODO: eventually will need a friendly_duration_str fn
-			
-		let finger_print = self.finger_print;
-		self.log(LogLevel::Info, NO_COMPONENT, &format!("finger print = {:X}", finger_print));
+		self.payloads.register::<T>();
 	}
 	
-	fn dispatch_events(&mut self)
+	/// Payloads registered with this will be hashed into the finger print when
+	/// config.finger_print_payloads is set.
+	pub fn register_payload_hash<T: Any + Send + Hash>(&mut self)
 	{
-		self.current_time = self.scheduled.peek().unwrap().time;
-		let mut ids = Vec::new();
-		
-		// TODO: track statistics on how parallel we are doing
-		// TODO: should cap the number of threads we use (probably via config)
-		while !self.scheduled.is_empty() && self.scheduled.peek().unwrap().time == self.current_time {	// while let can't have a guard so we use this somewhat ugly syntax
-			let e = self.scheduled.pop().unwrap();
-			self.update_finger_print(&e);
-			
-			// TODO: If we use speculative execution we'll need to be careful not to do
-			// anything wrong when REST is being used. Maybe just disable speculation.
-			if self.should_log(LogLevel::Excessive, NO_COMPONENT) {
-				let path = self.components.display_path(e.to);
-				let num = self.event_num;
-				self.log(LogLevel::Excessive, NO_COMPONENT, &format!("dispatching #{} '{}' to {}", num, e.event.name, path));
-			}
-			ids.push(e.to);
-			
-			self.event_num += 1;
-			if let Some(ref tx) = self.event_senders[e.to.0] {
-				let time = (self.current_time.0 as f64)/self.config.time_units;
-				let state = SimState{store: self.store.clone(), components: self.components.clone(), time};
-				if let Err(err) = tx.send((e.event, state)) {
-					let c = self.components.get(e.to);
-					panic!("Got an error sending to component {}: {}", c.name, err);
-				}
-			} else {
-				let c = self.components.get(e.to);
-				panic!("Attempt to send event {} to component {} which isn't an active component", e.event.name, c.name);
-			}
-		}
-		
-		// Note that it is important that we collect all of the side effects for a time t
-		// before we apply them. That way components executing at t do not affect each other.
-		let mut effects = Vec::with_capacity(ids.len());
-		for id in ids {
-			if let Some(ref rx) = self.effector_receivers[id.0] {
-				let ms = 5000;
-				match rx.recv_timeout(Duration::from_millis(ms)) {
-					Ok(e) =>  effects.push((id, e)),
-
-					// 5s should be an ample amount of time for even a complex component to respond
-					Err(mpsc::RecvTimeoutError::Timeout) => panic!("Component {} took longer than {} ms to send back effects", self.components.get(id).name, ms),
-
-					// Components should use Effector.remove if they want to become inactive.
-					Err(mpsc::RecvTimeoutError::Disconnected) => panic!("Component {} has disconnected from the simulation", self.components.get(id).name)
-				}
-			} else {
-				panic!("Failed to receive an effector from component {}", self.components.get(id).name);
-			}
-		}
-		
-		// This isn't terribly important but does keep the log ordering at a time
-		// consistent which is kind of nice.
-		effects.sort_by(|a, b| a.0.cmp(&b.0));
-		
-		for (id, mut e) in effects.drain(..) {
-			self.apply_effects(id, &mut e);
-			
-			if e.exit {
-				self.exited = Some("effector.exit was called".to_string())
-			}
-		}
+		self.payloads.register_hash::<T>();
 	}
 	
-	fn apply_effects(&mut self, id: ComponentID, effects: &mut Effector)
+	/// Payloads registered with this will be rendered (using [`PayloadDebug`]) when events
+	/// are logged or written to traces.
+	pub fn register_payload_debug<T: Any + Send + PayloadDebug>(&mut self)
 	{
-		self.apply_logs(id, &effects);
-		self.apply_events(effects);
-		self.apply_stores(&effects, id);
-
-		if effects.removed {
-			self.remove_components(id);
-		}
+		self.payloads.register_debug::<T>();
 	}
 	
-	// The finger print is used to verify that the simulation is deterministic: things like
-	// the order of hash map iteration or random number generation (assuming the same seed)
-	// should not change what happens during a simulation run. We could only compute the finger
-	// print when told to but it should be quite cheap and non-determinism is annoying enough
-	// that it's worth keeping an eye on.
-	fn update_finger_print(&mut self, sevent: &ScheduledEvent)
+	/// Adds a rule which will be evaluated after each time slice, e.g.
+	/// "when world.receiver.err_percent > 50 send alarm to world.sender". Rules
+	/// consist of one or more store comparisons (joined with "and") followed by
+	/// either "send EVENT to PATH", "exit", or "log MESSAGE". Rules fire when their
+	/// conditions become true.
+	pub fn add_rule(&mut self, rule: &str) -> Result<(), String>
 	{
-		let mut delta = sevent.time.0 as u64;
-		delta += sevent.to.0 as u64;
-		
-		let name = &sevent.event.name;
-		for b in name.bytes().take(min(name.len(), 8)) {
-			delta += b as u64;
-		}
-		
-		self.finger_print = self.finger_print.wrapping_add(delta);
+		let rule = Rule::parse(rule)?;
+		self.rules.push(rule);
+		Ok(())
 	}
 	
-	fn remove_components(&mut self, id: ComponentID)
+	/// Adds each rule within a scenario file (one rule per line, lines starting with
+	/// '#' are comments).
+	pub fn load_rules(&mut self, path: &str) -> Result<(), String>
 	{
-		{
-		self.install_removed_thread(id);
-		
-		let store = Arc::get_mut(&mut self.store).expect("Has a component retained a reference to the store?");
-		let key = self.components.full_path(id) + ".removed";
-		store.set_int(&key, 1, self.current_time);
-		}
+		let mut text = String::new();
+		File::open(path).and_then(|mut file| file.read_to_string(&mut text)).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
 		
-		let children = self.components.get(id).children.clone();
-		for child_id in children.iter() {
-			self.remove_components(*child_id);
-		}
+		let rules = parse_rules(&text).map_err(|err| format!("{} {}", path, err))?;
+		self.rules.extend(rules);
+		Ok(())
 	}
 	
-	fn install_removed_thread(&mut self, id: ComponentID)
+	/// Builds the components described by a JSON scenario file (see the scenario
+	/// module for the format).
+	pub fn load_scenario(&mut self, path: &str, factories: &ScenarioFactories) -> Result<(), String>
 	{
-		let (txd, rxd) = mpsc::channel::<(Event, SimState)>();
-		let (txe, rxe) = mpsc::channel::<Effector>();
+		let mut text = String::new();
+		File::open(path).and_then(|mut file| file.read_to_string(&mut text)).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
 		
-		self.event_senders[id.0] = Some(txd);
-		self.effector_receivers[id.0] = Some(rxe);
+		build_scenario(self, &text, factories).map_err(|err| format!("{} {}", path, err))
+	}
+	
+	/// Registers a callback that will be called on the simulation thread every
+	/// interval_secs (starting at interval_secs). This is a lightweight alternative
+	/// to an active component for housekeeping tasks like snapshotting or progress
+	/// reporting: there's no thread and no events are queued. The effector's changes
+	/// are applied as if they came from id (which does not need to be active). Note
+	/// that daemons do not keep the simulation running once events run out.
+	pub fn add_daemon<C>(&mut self, id: ComponentID, interval_secs: f64, callback: C)
+		where C: FnMut (&SimState, &mut Effector) -> () + Send + 'static
+	{
+		assert!(id != NO_COMPONENT);
+		assert!(interval_secs > 0.0, "interval_secs ({:.3}) is not positive", interval_secs);
 		
-		no_op_thread(rxd, txe);
+		let interval = max(self.secs_to_ticks(interval_secs), 1);
+		let next = Time(self.current_time.0 + interval);
+		self.daemons.push(Daemon{id, interval, next, callback: Box::new(callback)});
 	}
 	
-	fn schedule_init_stage(&mut self, stage: i32)
+	/// Adds an active component whose events are processed by handler on the simulation
+	/// thread instead of by a component thread. Handlers run one at a time so they are
+	/// easy to reason about (see [`TestSim`]) and, because there are no channels to go
+	/// through, they are much faster than threads for components that do little work
+	/// per event. Note that handlers share the simulation thread's panic semantics: if a
+	/// handler panics then the simulation panics, so handler components can't be
+	/// supervised.
+	pub fn add_handler_component<H>(&mut self, name: &str, parent: ComponentID, handler: H) -> ComponentID
+		where H: FnMut (Event, &SimState, &mut Effector) -> () + Send + 'static
 	{
-		self.log(LogLevel::Info, NO_COMPONENT, &format!("initializing components at stage {}", stage));
-		let name = format!("init {}", stage);
-		for i in 0..self.event_senders.len() {
-			if let Some(_) = self.event_senders[i] {
-				let event = Event::new(&name);
-				self.schedule(event, ComponentID(i), Time(0));
-			}
-		}
-		assert!(!self.scheduled.is_empty());	// silly to have a simulation with no active components
+		let (id, _) = self.add_active_component(name, parent);
+		self.set_handler(id, Box::new(handler));
+		id
 	}
 	
-	fn schedule(&mut self, event: Event, to: ComponentID, time: Time)
+	pub(crate) fn set_handler(&mut self, id: ComponentID, handler: Box<FnMut (Event, &SimState, &mut Effector) -> () + Send>)
 	{
-//		let path = self.components.full_path(to);
-//		let t = (time.0 as f64)/self.config.time_units;
-//		self.log(LogLevel::Debug, NO_COMPONENT, &format!("scheduling {} for {} to {:.3}", event.name, path, t));
-		
-		self.scheduled.push(ScheduledEvent{event, to, time});
+		assert!(self.event_senders[id.0].is_some(), "{} isn't an active component", self.components.full_path(id));
+		assert!(!self.supervisors.contains_key(&id), "{} is supervised so it can't use a handler", self.components.full_path(id));
+		self.handlers.insert(id, handler);
 	}
-
-	fn apply_logs(&mut self, id: ComponentID, effects: &Effector)
+	
+	/// Adds an active component and spawns a thread that calls component's on_event
+	/// method for each event sent to it.
+	pub fn add_sim_component<C: SimComponent>(&mut self, name: &str, parent: ComponentID, component: C) -> ComponentID
 	{
-		for record in effects.logs.iter() {
-			self.log(record.level, id, &record.message);
-		}
+		let (id, data) = self.add_active_component(name, parent);
+		component.spawn(data);
+		id
 	}
-
-	fn apply_events(&mut self, effects: &mut Effector)
+	
+	/// If the component's thread dies unexpectedly (e.g. because it panicked) then
+	/// start is called with a new [`ThreadData`] to spin up a replacement thread and an
+	/// "init-recover" event is sent to the new thread so that it can reload its state
+	/// from the store. The effects of the event being processed when the thread died
+	/// are lost as are any other events that were queued up for the thread. The number
+	/// of restarts is recorded in the component's "restarts" counter. If the component
+	/// dies more than max_restarts times then the simulation panics (which is what
+	/// happens for components that aren't supervised).
+	///
+	/// Fault tolerance experiments can exercise this by having the component panic,
+	/// e.g. in response to a "fault" event.
+	pub fn supervise<F>(&mut self, id: ComponentID, max_restarts: usize, start: F)
+		where F: Fn (ThreadData) -> () + Send + 'static
 	{
-		for (to, event, secs) in effects.events.drain(..) {	// we drain because we want to move the event into our list of scheduled events
-			let time = self.add_secs(secs);
-//			let path = self.components.full_path(to);
-//			self.log(LogLevel::Info, NO_COMPONENT, &format!("scheduling {} to {} at {:.3}", event.name, path, secs));
-			self.schedule(event, to, time);
+		assert!(self.event_senders[id.0].is_some(), "{} isn't an active component", self.components.full_path(id));
+		assert!(!self.handlers.contains_key(&id), "{} runs on the simulation thread so it can't be supervised", self.components.full_path(id));
+		self.supervisors.insert(id, Supervisor{start: Box::new(start), max_restarts, restarts: 0, generation: 0});
+	}
+	
+	/// Adds a check that is run against the store after each time slice, e.g. "total
+	/// energy across all the bots never increases". When the check starts failing an
+	/// error is logged and, if action is Exit, the run stops.
+	pub fn add_invariant<C>(&mut self, name: &str, action: InvariantAction, check: C)
+		where C: FnMut (&Store) -> bool + Send + 'static
+	{
+		self.invariants.push(Invariant{name: name.to_string(), action, check: InvariantCheck::Store(Box::new(check)), violated: false});
+	}
+	
+	/// Stops the simulation once the condition is met. Conditions are checked after
+	/// each time slice.
+	pub fn stop_when(&mut self, condition: StopCondition)
+	{
+		match condition {
+			StopCondition::KeyAtLeast(ref key, _) | StopCondition::KeyAtMost(ref key, _) => assert!(!key.is_empty(), "key should not be empty"),
+			StopCondition::EventCount(ref name, count) => {
+				assert!(count > 0, "count should be positive");
+				self.stop_event_counts.entry(name.clone()).or_insert(0);
+			},
+			StopCondition::Predicate(_) => (),
 		}
+		self.stop_conditions.push(condition);
 	}
-
-	fn apply_stores(&mut self, effects: &Effector, id: ComponentID)
+	
+	/// Like run except that the simulation also stops after the first time slice
+	/// for which predicate returns true.
+	pub fn run_until<P>(&mut self, predicate: P) -> u64
+		where P: FnMut (&SimState) -> bool + Send + 'static
 	{
-		let path = self.components.full_path(id);
-		let store = Arc::get_mut(&mut self.store).expect("Has a component retained a reference to the store?");
-
-		store.int_data.reserve(effects.store.int_data.len());
-		for (key, value) in effects.store.int_data.iter() {
-			let key = format!("{}.{}", path, key);
-			store.set_int(&key, value.1, self.current_time);
+		self.stop_when(StopCondition::Predicate(Box::new(predicate)));
+		self.run()
+	}
+	
+	/// Like add_invariant except that check is called for every store key matching
+	/// pattern, e.g. `sim.add_key_invariant("*.queue-depth", InvariantAction::Log, |_, v| ...)`.
+	pub fn add_key_invariant<C>(&mut self, pattern: &str, action: InvariantAction, check: C) -> Result<(), String>
+		where C: FnMut (&str, &StoreValue) -> bool + Send + 'static
+	{
+		let glob = glob::Pattern::new(pattern).map_err(|err| format!("bad invariant pattern '{}': {}", pattern, err))?;
+		self.invariants.push(Invariant{name: pattern.to_string(), action, check: InvariantCheck::Keys(glob, Box::new(check)), violated: false});
+		Ok(())
+	}
+	
+	/// Calls callback after each time slice for every store key matching pattern whose
+	/// value changed during the slice (including keys that were added). This is much
+	/// cheaper than polling the store, e.g. `sim.watch("*.queue-depth", |c| ...)` can
+	/// be used to log or exit when a queue grows too large.
+	pub fn watch<C>(&mut self, pattern: &str, callback: C) -> Result<(), String>
+		where C: FnMut (&StoreChange) -> WatchAction + Send + 'static
+	{
+		let pattern = glob::Pattern::new(pattern).map_err(|err| format!("bad watch pattern '{}': {}", pattern, err))?;
+		self.add_watch(pattern, Some(Box::new(callback)));
+		Ok(())
+	}
+	
+	fn add_watch(&mut self, pattern: glob::Pattern, callback: Option<Box<FnMut (&StoreChange) -> WatchAction + Send>>)
+	{
+		self.record_changes();
+		self.watches.push(Watch{pattern, callback});
+	}
+	
+	fn record_changes(&mut self)
+	{
+		let store = Arc::make_mut(&mut self.store);
+		if store.changes.is_none() {
+			store.changes = Some(Vec::new());
 		}
-		
-		store.float_data.reserve(effects.store.float_data.len());
-		for (key, value) in effects.store.float_data.iter() {
-			let key = format!("{}.{}", path, key);
-			store.set_float(&key, value.1, self.current_time);
+	}
+	
+	/// Tracks the int and float store keys matching glob using [`TimeWeighted`]. This is
+	/// useful for values like queue lengths where the mean over time matters, not the mean
+	/// of the samples. Each key's time-weighted mean, max, and how long it was above
+	/// threshold are logged when the simulation exits.
+	pub fn track_time_weighted(&mut self, glob: &str, threshold: f64) -> Result<(), String>
+	{
+		let pattern = glob::Pattern::new(glob).map_err(|err| format!("bad time weighted pattern '{}': {}", glob, err))?;
+		let mut trackers = BTreeMap::new();
+		for (key, value, time) in self.store.query(&pattern) {
+			let value = match value {
+				StoreValue::Int(value) => value as f64,
+				StoreValue::Float(value) => value,
+				_ => continue,
+			};
+			let mut tracker = TimeWeighted::new(threshold);
+			tracker.record(time.to_secs(self.config.time_units), value);
+			trackers.insert(key, tracker);
 		}
 		
-		store.string_data.reserve(effects.store.string_data.len());
-		for (key, value) in effects.store.string_data.iter() {
-			let key = format!("{}.{}", path, key);
-			store.set_string(&key, &value.1, self.current_time);
-		}
+		self.record_changes();
+		self.time_weighted.push(TimeWeightedKeys{pattern, threshold, trackers});
+		Ok(())
 	}
-
-	fn log(&mut self, level: LogLevel, id: ComponentID, message: &str)
+	
+	/// Returns the summaries for the keys tracked using track_time_weighted as of the
+	/// current time (sorted by key).
+	pub fn time_weighted_summaries(&self) -> Vec<(String, TimeWeightedSummary)>
 	{
-		if self.should_log(level, id) {
-			let t = (self.current_time.0 as f64)/self.config.time_units;
-			
-			let path = self.logged_path(id);
-			if self.config.colorize {
-				let begin_escape = match level {
-					LogLevel::Error	=> &self.config.error_escape_code,
-					LogLevel::Warning	=> &self.config.warning_escape_code,
-					LogLevel::Info		=> &self.config.info_escape_code,
-					LogLevel::Debug	=> &self.config.debug_escape_code,
-					LogLevel::Excessive=> &self.config.excessive_escape_code,
-				};
-				print!("{0}{1:.2$}   {3} {4}{5}\n", begin_escape, t, self.precision, path, message, end_escape());
-			} else {
-				let prefix = match level {
-					LogLevel::Error	=> "error",
-					LogLevel::Warning	=> "warn ",
-					LogLevel::Info		=> "info ",
-					LogLevel::Debug	=> "debug",
-					LogLevel::Excessive=> "exces",
-				};
-				print!("{0:.1$}  {2} {3}  {4}\n", t, self.precision, prefix, path, message);
-			}
-		}
-
-		if !self.config.home_path.is_empty() {
-			let time = (self.current_time.0 as f64)/self.config.time_units;
-			let path = if id == NO_COMPONENT {"simulation".to_string()} else {self.components.full_path(id)};
-			let index = level as u8;
-			let message = message.to_string();
-			let line = LogLine{time, path, level, index, message};
-			self.log_lines.push(line);
-		}
+		let time = self.current_time.to_secs(self.config.time_units);
+		let mut summaries: Vec<(String, TimeWeightedSummary)> = self.time_weighted.iter()
+			.flat_map(|t| t.trackers.iter().filter_map(|(key, tracker)| tracker.summary(time).map(|s| (key.clone(), s))))
+			.collect();
+		summaries.sort_by(|a, b| a.0.cmp(&b.0));
+		summaries
 	}
-
-	fn logged_path(&self, id: ComponentID) -> String
+	
+	/// When running under the REST server (or another [`ControlBackend`]) the simulation
+	/// will pause just before dispatching an event whose name matches event_glob to a
+	/// component whose path matches component_glob, e.g. `sim.break_on("lost-attack", "world.A3")`.
+	/// The store can then be inspected as it was when the event was sent. Resuming
+	/// dispatches the event. Breakpoints have no effect for normal runs.
+	pub fn break_on(&mut self, event_glob: &str, component_glob: &str) -> Result<(), String>
 	{
-		let mut path = if id == NO_COMPONENT {"simulation".to_string()} else {self.components.full_path(id)};
-		if self.config.max_log_path > 0 && self.largest_path > self.config.max_log_path {
-			let len = path.len();
-			if len > self.config.max_log_path {
-				format!("…{}", path.split_off(len - self.config.max_log_path))
-			} else {
-				format!("{0:<1$}", path, self.config.max_log_path)
-			}
-		} else {
-			format!("{0:<1$}", path, self.largest_path)
-		}
+		let event = glob::Pattern::new(event_glob).map_err(|err| format!("bad event pattern '{}': {}", event_glob, err))?;
+		let component = glob::Pattern::new(component_glob).map_err(|err| format!("bad component pattern '{}': {}", component_glob, err))?;
+		self.breakpoints.push(Breakpoint{event, component, hits: 0});
+		Ok(())
 	}
 	
-	fn should_log(&self, level: LogLevel, id: ComponentID) -> bool
+	/// Changes the log level for components whose path matches glob along with their
+	/// descendants, e.g. `sim.set_log_level("world.bots", LogLevel::Excessive)`. This can
+	/// be called while the simulation is running (e.g. via the /log/level REST endpoint)
+	/// and takes precedence over config.log_levels. When more than one glob matches the
+	/// most recently set one wins.
+	pub fn set_log_level(&mut self, glob: &str, level: LogLevel) -> Result<(), String>
 	{
-		if !self.config.log_levels.is_empty() {	// short circuit some work if we have no overrides
-			let name = if id == NO_COMPONENT {"simulation"} else {&self.components.get(id).name};
-			
-			for (pattern, clevel) in self.config.log_levels.iter() {
-				if pattern.matches(name) {
-					return level <= *clevel
-				}
-			}
-		}
-
-		level <= self.config.log_level
+		let pattern = glob::Pattern::new(glob).map_err(|err| format!("bad log pattern '{}': {}", glob, err))?;
+		self.add_log_level(pattern, level);
+		Ok(())
 	}
 	
-	fn add_secs(&self, secs: f64) -> Time
+	fn add_log_level(&mut self, pattern: glob::Pattern, level: LogLevel)
 	{
-		assert!(secs >= 0.0);
-		
-		let delta = (secs*self.config.time_units) as i64;
-		if delta > 0 {
-			Time(self.current_time.0 + delta)
-		} else {
-			Time(self.current_time.0 + 1)	// event dispatch should always take a bit of time so that all the effects at a time can be applied all at once
+		self.runtime_log_levels.retain(|entry| entry.0 != pattern);
+		self.runtime_log_levels.push((pattern, level));
+	}
+	
+	/// Removes all of the breakpoints added with break_on (or via REST).
+	pub fn clear_breakpoints(&mut self)
+	{
+		self.breakpoints.clear();
+		self.breakpoint_hits.clear();
+	}
+	
+	/// Registers a shared read-only resource that components can access using
+	/// [`SimState`]'s service method. Replaces any existing service of the same type.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use score::*;
+	/// use std::collections::HashMap;
+	///
+	/// struct RoutingTable
+	/// {
+	/// 	next_hop: HashMap<String, String>,
+	/// }
+	///
+	/// fn next_hop(state: &SimState, dest: &str) -> Option<String>
+	/// {
+	/// 	let table = state.service::<RoutingTable>();
+	/// 	table.next_hop.get(dest).cloned()
+	/// }
+	///
+	/// let mut sim = Simulation::new(Config::new());
+	/// sim.provide(RoutingTable{next_hop: HashMap::new()});
+	/// ```
+	pub fn provide<T: Any + Send + Sync>(&mut self, service: T)
+	{
+		Arc::make_mut(&mut self.services).provide(service);
+	}
+	
+	/// Like register_payload except that the payload will also be written into traces
+	/// (see record_trace). Name is used to identify the payload type within trace files
+	/// so it should be unique and stable. This is register_versioned_payload with version 1.
+	pub fn register_traced_payload<T>(&mut self, name: &str)
+		where T: Any + Send + Clone + Encodable + Decodable
+	{
+		self.payloads.register_traced::<T>(name, 1);
+	}
+	
+	/// Like register_versioned_payload except that the name and version come from the
+	/// [`Payload`] trait.
+	pub fn register_payload_type<T: Payload>(&mut self)
+	{
+		self.payloads.register_traced::<T>(T::payload_name(), T::payload_version());
+	}
+	
+	/// Like register_traced_payload except that the version is also written into traces.
+	/// The version should be bumped whenever T's fields change so that replay_trace fails
+	/// with a clear error for traces recorded with the old schema.
+	pub fn register_versioned_payload<T>(&mut self, name: &str, version: u32)
+		where T: Any + Send + Clone + Encodable + Decodable
+	{
+		self.payloads.register_traced::<T>(name, version);
+	}
+	
+	/// Writes every dispatched event to a JSON lines trace file so that the run can be
+	/// reproduced using replay_trace. Payloads must be registered using
+	/// register_traced_payload (or register_versioned_payload) in order to be replayed.
+	/// The first line is a `{"provenance":{...}}` object, see [`Provenance`].
+	pub fn record_trace(&mut self, path: &str) -> Result<(), String>
+	{
+		self.tracer = Some(TraceWriter::new(path, &self.provenance)?);
+		Ok(())
+	}
+	
+	/// Writes the changes made to the store at each time slice to a JSON lines file.
+	/// Together with a trace this allows runs to be compared using diff_runs or
+	/// compare_with.
+	pub fn record_deltas(&mut self, path: &str) -> Result<(), String>
+	{
+		self.delta_writer = Some(TraceWriter::new(path, &self.provenance)?);
+		Ok(())
+	}
+	
+	/// Compares this run against a golden run recorded using record_trace and (optionally)
+	/// record_deltas. The /compare REST endpoints will then return the time slices up to
+	/// the current time at which the runs differ, see [`SliceDiff`]. This run must be
+	/// recording a trace and its state is only compared if config.max_store_deltas is
+	/// non-zero. Slices are compared as the endpoints are polled so slices whose deltas
+	/// were discarded before then are reported with deltas_unavailable set.
+	pub fn compare_with(&mut self, trace: &str, deltas: &str) -> Result<(), String>
+	{
+		if self.tracer.is_none() {
+			return Err("compare_with requires that record_trace be called first".to_string());
 		}
+		let golden = RunArtifacts::load(trace, deltas)?;
+		self.golden = Some(GoldenComparison::new(&golden, self.config.time_units, self.config.max_store_deltas > 0));
+		Ok(())
 	}
-
-	fn get_log_lines(&self, after_time: f64) -> VecDeque<&LogLine>
+	
+	/// Dispatches the events from a trace written by record_trace instead of the events
+	/// components schedule. This should be called after components are added (with the
+	/// same names used when the trace was recorded) but before run. The component threads
+	/// are re-driven exactly as they were in the recorded run which is useful for
+	/// reproducing bugs that are sensitive to timing or ordering.
+	pub fn replay_trace(&mut self, path: &str) -> Result<(), String>
 	{
-		let mut result = VecDeque::new();
-		
-		for line in self.log_lines.iter().rev() {
-			if line.time > after_time {
-				result.push_front(line);
+		let entries = read_trace(path)?;
+		for entry in entries.iter() {
+			if self.components.id(&entry.to).is_none() {
+				return Err(format!("{} targets '{}' which isn't a component", path, entry.to));
+			}
+			if let Some(ref name) = entry.payload_type {
+				let text = entry.payload.as_ref().map(|p| p.as_str()).unwrap_or("null");
+				self.payloads.decode(name, entry.payload_version.unwrap_or(1), text).map_err(|err| format!("{} event '{}': {}", path, entry.name, err))?;
 			}
 		}
 		
-		result
+		self.scheduled.clear();
+		self.replay = entries.into_iter().collect();
+		self.replaying = true;
+		Ok(())
 	}
-
-	fn create_component_entry(&self, removed: &Vec<String>, id: ComponentID, component: &Component) -> ComponentEntry
-	{		
-		let mut children = Vec::with_capacity(component.children.len());
-		for child_id in component.children.iter() {
-			let child = self.components.get(*child_id);
-			if !removed.contains(&child.name) {
-				let child_entry = self.create_component_entry(removed, *child_id, child);
-				children.push(child_entry);
-			}
+	
+	/// Saves the current state of the simulation so that a what-if scenario can be
+	/// explored, e.g. by changing the store or scheduling an event using apply and
+	/// then running forward. Use end_branch to restore the simulation to the state it
+	/// was in when branch was called. Branches may be nested.
+	///
+	/// Note that only state owned by the simulation (e.g. the store and the scheduled
+	/// events) is saved. State kept within component threads (e.g. random number
+	/// generators) is not restored and components removed within a branch remain
+	/// inactive afterwards (but see branch_with_seed). All pending events with payloads
+	/// must have payload types that were registered using register_payload.
+	pub fn branch(&mut self) -> Result<(), String>
+	{
+		let mut scheduled = BinaryHeap::with_capacity(self.scheduled.len());
+		for s in self.scheduled.iter() {
+			let event = self.payloads.clone_event(&s.event)?;
+			scheduled.push(ScheduledEvent{time: s.time, to: s.to, event, seq: s.seq, parent: s.parent, from: s.from});
 		}
 		
-		let name = component.name.clone();
-		let path = self.components.full_path(id);
-		let key = format!("{}.display-details", path);
-		let details = if self.store.contains(&key) {self.store.get_string(&key)} else {"".to_string()};
-		ComponentEntry{path, name, details, children}
+		let checkpoint = Checkpoint {
+			store: (*self.store).clone(),
+			current_time: self.current_time,
+			exited: self.exited.clone(),
+			scheduled,
+			next_seq: self.next_seq,
+			pending_requests: self.pending_requests.clone(),
+			next_request_id: self.next_request_id,
+			event_num: self.event_num,
+			finger_print: self.finger_print,
+			finger_prints: self.finger_prints.clone(),
+			next_log_offset: self.next_log_offset,
+			store_deltas: self.store_deltas.clone(),
+			stats: self.stats.clone(),
+			batch_means: self.batch_means.clone(),
+			daemon_times: self.daemons.iter().map(|d| d.next).collect(),
+			rng: None,
+		};
+		self.branches.push(checkpoint);
+		
+		let depth = self.branches.len();
+		self.log(LogLevel::Info, NO_COMPONENT, &format!("started branch {}", depth));
+		Ok(())
 	}
-
-	fn get_components(&self) -> ComponentEntry
+	
+	/// Like branch except that random numbers within the branch are drawn using seed,
+	/// e.g. to see how a run would have diverged from the current state. The generator
+	/// returned by rng and the [`SimRng`]s handed out to components are re-seeded and,
+	/// when the branch ends, they resume from the state they were in when the branch
+	/// started.
+	pub fn branch_with_seed(&mut self, seed: u64) -> Result<(), String>
 	{
-		let mut removed = Vec::new();
-		for (key, value) in self.store.int_data.iter() {
-			if key.ends_with(".removed") && value.1 == 1 {
-				let (prefix, _) = key.split_at(key.len() - ".removed".len());
-				removed.push(prefix.to_string());
-			}
-		}
-
-		let (id, root) = self.components.get_root();
-		self.create_component_entry(&removed, id, root)
+		self.branch()?;
+		let rng = mem::replace(&mut self.rng, Box::new(SimRng::new(seed)));
+		self.branches.last_mut().unwrap().rng = Some(rng);
+		self.reseeds.push(seed);
+		Ok(())
 	}
 	
-	fn get_state(&self, path: &glob::Pattern) -> Vec<(String, String, String)>
+	/// Restores the state saved by the most recent call to branch.
+	pub fn end_branch(&mut self) -> Result<(), String>
 	{
-		let mut removed = Vec::new();
-		for (key, value) in self.store.int_data.iter() {
-			if key.ends_with(".removed") && value.1 == 1 {
-				let (prefix, _) = key.split_at(key.len() - "removed".len());
-				removed.push(prefix);
-			}
-		}
-
-		let mut result = Vec::new();
-		for (key, value) in self.store.int_data.iter() {
-			if path.matches(&key) && !removed.iter().any(|r| key.starts_with(r)) {
-				result.push((key.clone(), value.1.to_string(), "int".to_string()));
-			}
+		match self.branches.pop() {
+			Some(checkpoint) => {
+				self.store = Arc::new(checkpoint.store);
+				self.current_time = checkpoint.current_time;
+				self.exited = checkpoint.exited;
+				self.scheduled = checkpoint.scheduled;
+				self.next_seq = checkpoint.next_seq;
+				self.pending_requests = checkpoint.pending_requests;
+				self.next_request_id = checkpoint.next_request_id;
+				self.event_num = checkpoint.event_num;
+				self.finger_print = checkpoint.finger_print;
+				self.finger_prints = checkpoint.finger_prints;
+				let next_log_offset = checkpoint.next_log_offset;
+				while self.log_lines.back().map_or(false, |line| line.offset >= next_log_offset) {
+					self.log_lines.pop_back();
+				}
+				self.next_log_offset = next_log_offset;
+				self.store_deltas = checkpoint.store_deltas;
+				self.stats = checkpoint.stats;
+				self.batch_means = checkpoint.batch_means;
+				for (daemon, next) in self.daemons.iter_mut().zip(checkpoint.daemon_times) {
+					daemon.next = next;
+				}
+				if let Some(rng) = checkpoint.rng {
+					self.rng = rng;
+					self.reseeds.pop();
+				}
+				
+				let depth = self.branches.len() + 1;
+				self.log(LogLevel::Info, NO_COMPONENT, &format!("ended branch {}", depth));
+				Ok(())
+			},
+			None => Err("not in a branch".to_string())
 		}
+	}
+	
+	/// Use this if you want to do something random when initializing components.
+	pub fn rng(&mut self) -> &mut Box<Rng + Send>
+	{
+		// Using a Box was the only way I could figure out to use the Rng trait
+		// while constraining Rng to be Sized as well so clients get the full
+		// set of Rng methods. (We can't actually constrain a type to Sized but
+		// we can to Send which is evidently enough).
+		&mut self.rng
+	}
+	
+	/// Dispatches events until there are no more events left to dispatch,
+	/// config.max_secs elapses, [`Effector`]s exit method was called, or a
+	/// [`StopCondition`] was met.
+	/// Returns a finger print which can be used to verify that simulation
+	/// runs with the same seeds are deterministic.
+	pub fn run(&mut self) -> u64
+	{
+		let summary = self.provenance.summary();
+		self.log(LogLevel::Info, NO_COMPONENT, &summary);
 		
-		for (key, value) in self.store.float_data.iter() {
-			if path.matches(&key) && !removed.iter().any(|r| key.starts_with(r)) {
-				result.push((key.clone(), format!("{:.6}", value.1), "float".to_string()));
-			}
+		for problem in self.validate() {
+			self.log(LogLevel::Warning, NO_COMPONENT, &problem);
 		}
 		
-		for (key, value) in self.store.string_data.iter() {
-			if path.matches(&key) && !removed.iter().any(|r| key.starts_with(r)) {
-				result.push((key.clone(), value.1.clone(), "string".to_string()));
+		if self.config.emergency_dump.is_empty() {
+			self.run_in_mode();
+		} else {
+			// Component panics will cause the simulation thread to panic when it tries to
+			// talk to the component so we'll catch those too.
+			let result = panic::catch_unwind(panic::AssertUnwindSafe(|| self.run_in_mode()));
+			if let Err(err) = result {
+				self.write_emergency_dump(&panic_message(&*err));
+				panic::resume_unwind(err);
 			}
 		}
-		
-		result.sort_by(|a, b| a.0.cmp(&b.0));
-		result
+		self.finger_print
 	}
-}
-
-struct ScheduledEvent
-{
-	time: Time,
-	to: ComponentID,
-	event: Event,
-}
-
-impl PartialEq for ScheduledEvent
-{
-	fn eq(&self, other: &ScheduledEvent) -> bool
+	
+	/// When the REST server is used the simulation starts out paused and time slices
+	/// are run via endpoints like /run/once. Resume allows the simulation to run freely
+	/// until it exits, pause is called (e.g. via the /pause endpoint), or a watch pauses
+	/// it. These have no effect when the REST server is not used.
+	pub fn resume(&mut self)
 	{
-		self.time.0 == other.time.0
+		self.running = true;
 	}
-}
-
-impl Eq for ScheduledEvent {}
-
-impl PartialOrd for ScheduledEvent
-{
-	fn partial_cmp(&self, other: &ScheduledEvent) -> Option<Ordering>
+	
+	pub fn pause(&mut self)
 	{
-		Some(self.cmp(other))
+		self.running = false;
 	}
-}
-
+	
+	pub fn is_paused(&self) -> bool
+	{
+		!self.running
+	}
+	
+	/// Returns a summary of the run so far. This is written to config.run_report when
+	/// the simulation exits.
+	pub fn report(&self) -> RunReport
+	{
+		let elapsed = ((time::get_time() - self.start_time).num_milliseconds() as f64)/1000.0;
+		let mut component_events: Vec<(String, u64)> = self.event_counts.iter().enumerate()
+			.filter(|&(i, _)| self.event_senders[i].is_some() || self.removed.contains(&ComponentID(i)))
+			.map(|(i, &count)| (self.components.full_path(ComponentID(i)), count))
+			.collect();
+		component_events.sort();
+		
+		let patterns = &self.config.report_values;
+		let values = self.store.query_with(|key| patterns.iter().any(|p| p.matches(key))).into_iter().map(|(key, value, _)| match value {
+			StoreValue::Int(value) => ReportValue{key, value: value.to_string(), kind: "int".to_string()},
+			StoreValue::Float(value) => ReportValue{key, value: float_to_string(value), kind: "float".to_string()},
+			StoreValue::String(value) => ReportValue{key, value, kind: "string".to_string()},
+			StoreValue::Bool(value) => ReportValue{key, value: value.to_string(), kind: "bool".to_string()},
+			list => ReportValue{key, value: list_to_string(&list), kind: list.kind().to_string()},
+		}).collect();
+		
+		RunReport {
+			provenance: self.provenance.clone(),
+			exit_reason: self.exited.clone().unwrap_or_default(),
+			elapsed_secs: elapsed,
+			sim_secs: self.current_time.to_secs(self.config.time_units),
+			events: self.event_num,
+			events_per_sec: if elapsed > 0.0 {(self.event_num as f64)/elapsed} else {0.0},
+			finger_print: format!("{:X}", self.finger_print),
+			component_events,
+			values,
+			metadata: self.metadata(),
+			assertion_failures: self.assertion_failures.clone(),
+		}
+	}
+	
+	/// Returns the simulation-level metadata set using [`Effector`]'s set_metadata and
+	/// add_metadata_label methods (sorted by name). Labels are formatted as a JSON list.
+	pub fn metadata(&self) -> Vec<(String, String)>
+	{
+		self.store.query_with(|key| key.starts_with(METADATA_PREFIX)).into_iter().map(|(key, value, _)| {
+			let value = match value {
+				StoreValue::String(value) => value,
+				list => list_to_string(&list),
+			};
+			(key[METADATA_PREFIX.len()..].to_string(), value)
+		}).collect()
+	}
+	
+	/// Returns the assertions that failed, see [`Effector`]'s assert method.
+	pub fn assertion_failures(&self) -> &[AssertionFailure]
+	{
+		&self.assertion_failures
+	}
+	
+	/// Returns the store keys declared using [`Effector`]'s declare methods.
+	pub fn schema(&self) -> &BTreeMap<String, KeySchema>
+	{
+		&self.schema
+	}
+	
+	/// Returns how much precision has been lost converting seconds into ticks, see
+	/// config.tick_rounding.
+	pub fn tick_rounding_stats(&self) -> TickRoundingStats
+	{
+		self.tick_stats
+	}
+	
+	/// Installs a frontend that will control the simulation (instead of the REST server).
+	/// This should be called before run.
+	pub fn set_control_backend(&mut self, backend: Box<ControlBackend>)
+	{
+		self.backend = Some(backend);
+	}
+	
+	// ---- Private Functions ----------------------------------------------------------------
+	fn run_in_mode(&mut self)
+	{
+		if self.backend.is_some() {
+			self.run_server();
+		} else if self.config.home_path.is_empty() {
+			self.run_normally();
+		} else {
+			let backend = if Path::new(&self.config.home_path).is_file() {
+				self.rest_backend()
+			} else {
+				Err(format!("config.home_path '{}' is not a file", self.config.home_path))
+			};
+			match backend {
+				Ok(backend) => {
+					self.backend = Some(backend);
+					self.run_server();
+				},
+				Err(err) => self.fail_to_start(err),
+			}
+		}
+	}
+	
+	#[cfg(feature = "rest")]
+	fn rest_backend(&mut self) -> Result<Box<ControlBackend>, String>
+	{
+		let address = self.config.address.clone();
+		self.log(LogLevel::Info, NO_COMPONENT, &format!("running web server at {}", address));
+		Ok(Box::new(RestBackend::new(&address, &self.config.home_path)))
+	}
+	
+	#[cfg(not(feature = "rest"))]
+	fn rest_backend(&mut self) -> Result<Box<ControlBackend>, String>
+	{
+		Err("config.home_path requires score's rest feature".to_string())
+	}
+	
+	// Other simulations may be running within the process so, instead of exiting, we
+	// log the problem and stop the run without initializing components.
+	fn fail_to_start(&mut self, err: String)
+	{
+		self.log(LogLevel::Error, NO_COMPONENT, &err);
+		self.exited = Some(err);
+		self.log_writer.flush();
+	}
+	
+	fn write_emergency_dump(&self, reason: &str)
+	{
+		let path = &self.config.emergency_dump;
+		let text = self.emergency_dump_text(reason);
+		match File::create(path).and_then(|mut file| file.write_all(text.as_bytes())) {
+			Ok(_) => eprintln!("wrote emergency dump to {}", path),
+			Err(err) => eprintln!("failed to write emergency dump to {}: {}", path, err),
+		}
+	}
+	
+	fn emergency_dump_text(&self, reason: &str) -> String
+	{
+		let to_secs = |t: Time| t.to_secs(self.config.time_units);
+		let mut text = format!("# {}\n", self.provenance.summary());
+		text += &format!("panic: {}\n", reason);
+		text += &format!("time: {:.1$}s\n", to_secs(self.current_time), self.precision);
+		
+		text += &format!("\nrecent events (oldest first):\n");
+		for line in self.recent_events.iter() {
+			text += &format!("   {}\n", line);
+		}
+		
+		let mut pending: Vec<&ScheduledEvent> = self.scheduled.iter().collect();
+		pending.sort_by(|a, b| b.cmp(a));	// the heap is a max-heap so Ord is reversed
+		let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+		for e in pending.iter() {
+			*counts.entry(&e.event.name).or_insert(0) += 1;
+		}
+		text += &format!("\npending events: {}\n", pending.len());
+		for e in pending.iter().take(20) {
+			text += &format!("   {:.1$}s '{2}' to {3}\n", to_secs(e.time), self.precision, e.event.name, self.components.full_path(e.to));
+		}
+		if pending.len() > 20 {
+			text += "   ...\n";
+		}
+		for (name, count) in counts.iter() {
+			text += &format!("   {} x '{}'\n", count, name);
+		}
+		
+		text += "\nstore:\n";
+		for line in self.store_lines() {
+			text += &format!("   {}\n", line);
+		}
+		text
+	}
+	
+	// Returns "key = value @ time" for each value in the store (sorted by key).
+	fn store_lines(&self) -> Vec<String>
+	{
+		let to_secs = |t: Time| t.to_secs(self.config.time_units);
+		let mut values = Vec::new();
+		for (key, value) in self.store.int_data.iter() {
+			values.push(format!("{} = {} @ {:.3$}s", key, value.1, to_secs(value.0), self.precision));
+		}
+		for (key, value) in self.store.float_data.iter() {
+			values.push(format!("{} = {} @ {:.3$}s", key, float_to_string(value.1), to_secs(value.0), self.precision));
+		}
+		for (key, value) in self.store.string_data.iter() {
+			values.push(format!("{} = '{}' @ {:.3$}s", key, value.1, to_secs(value.0), self.precision));
+		}
+		for (key, value) in self.store.bool_data.iter() {
+			values.push(format!("{} = {} @ {:.3$}s", key, value.1, to_secs(value.0), self.precision));
+		}
+		for (key, value) in self.store.list_data.iter() {
+			values.push(format!("{} = {} @ {:.3$}s", key, list_to_string(&value.1), to_secs(value.0), self.precision));
+		}
+		values.sort();
+		values
+	}
+	
+	fn run_normally(&mut self)
+	{
+		self.init_components();
+		while self.exited.is_none() {
+			self.run_time_slice()
+		}
+		
+//		self.print();
+		self.exit();
+	}
+	
+	fn run_server(&mut self)
+	{
+		let (tx_command, rx_command) = mpsc::channel();
+		let (tx_reply, rx_reply) = mpsc::channel();
+		let mut backend = self.backend.take().unwrap();
+		if let Err(err) = backend.start(tx_command, rx_reply) {
+			self.fail_to_start(err);
+			return;
+		}
+
+		self.init_components();
+		loop {
+			// When we're running freely (or a run job is active) we process a time slice
+			// whenever there isn't a pending command.
+			let (seq, command) = if (self.running || self.has_active_job()) && self.exited.is_none() {
+				match rx_command.try_recv() {
+					Ok(command) => command,
+					Err(mpsc::TryRecvError::Empty) => {
+						self.run_job_slice();
+						if self.break_hit {
+							self.break_hit = false;
+							self.pause();
+							self.finish_job("breakpoint");
+						} else {
+							self.update_job();
+						}
+						continue;
+					},
+					Err(mpsc::TryRecvError::Disconnected) => break,
+				}
+			} else {
+				match rx_command.recv() {
+					Ok(command) => command,
+					Err(_) => break,
+				}
+			};
+			let mut exiting = false;
+			let reply = match command {
+				RestCommand::Exit => {
+					// Effects are applied as part of each time slice so there's nothing
+					// pending at this point: all we need to do is stop the server.
+					if self.exited.is_none() {
+						self.exited = Some("exit was requested via REST".to_string());
+					}
+					exiting = true;
+					let data = rustc_serialize::json::encode(&"exiting".to_string()).unwrap();
+					RestReply{data, code:200}
+				}
+				RestCommand::AddBreakpoint(event, component) => {
+					self.breakpoints.push(Breakpoint{event, component, hits: 0});
+					RestReply{data: "\"ok\"".to_string(), code:200}
+				}
+				RestCommand::Branch(seed) => {
+					let result = match seed {
+						Some(seed) => self.branch_with_seed(seed),
+						None => self.branch(),
+					};
+					match result {
+						Ok(_) => RestReply{data: "\"ok\"".to_string(), code:200},
+						Err(err) => RestReply{data: rustc_serialize::json::encode(&err).unwrap(), code:400},
+					}
+				},
+				RestCommand::EndBranch => {
+					match self.end_branch() {
+						Ok(_) => RestReply{data: "\"ok\"".to_string(), code:200},
+						Err(err) => RestReply{data: rustc_serialize::json::encode(&err).unwrap(), code:400},
+					}
+				},
+				RestCommand::GetBreakpoints => {
+					let breakpoints: Vec<BreakpointStatus> = self.breakpoints.iter().map(|b| BreakpointStatus{event: b.event.as_str().to_string(), component: b.component.as_str().to_string(), hits: b.hits}).collect();
+					let data = rustc_serialize::json::encode(&breakpoints).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetBreakpointHits(time) => {
+					let hits: Vec<&BreakpointHit> = self.breakpoint_hits.iter().filter(|h| h.time > time).collect();
+					let data = rustc_serialize::json::encode(&hits).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::ClearBreakpoints => {
+					self.clear_breakpoints();
+					RestReply{data: "\"ok\"".to_string(), code:200}
+				},
+				RestCommand::GetComponents => {
+					if !self.components.is_empty() {
+						let lines = self.get_components();
+						let data = rustc_serialize::json::encode(&lines).unwrap();	
+						let data = data.to_string();
+						RestReply{data, code:200}
+					} else {
+						RestReply{data: "no components".to_string(), code:404}
+					}
+				}
+				RestCommand::GetComparison(time) => {
+					match self.compare_to_golden() {
+						Ok(()) => {
+							let diffs: Vec<&SliceDiff> = self.golden.as_ref().unwrap().diffs().filter(|d| d.time > time).collect();
+							let data = rustc_serialize::json::encode(&diffs).unwrap();
+							RestReply{data, code:200}
+						},
+						Err(err) => RestReply{data: rustc_serialize::json::encode(&err).unwrap(), code:404},
+					}
+				},
+				RestCommand::GetDeltas(time) => {
+					let deltas: Vec<&StoreDelta> = self.store_deltas.iter().filter(|d| d.time > time).collect();
+					let data = rustc_serialize::json::encode(&deltas).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetHistory(key) => {
+					// Keys only have history if they match config.store_history.
+					let time_units = self.config.time_units;
+					let to_secs = |t: Time| t.to_secs(time_units);
+					let entries: Vec<HistoryEntry> = if self.store.int_data.contains_key(&key) {
+						self.store.int_history(&key).iter().map(|&(t, v)| HistoryEntry{time: to_secs(t), value: v.to_string()}).collect()
+					} else if self.store.float_data.contains_key(&key) {
+						self.store.float_history(&key).iter().map(|&(t, v)| HistoryEntry{time: to_secs(t), value: float_to_string(v)}).collect()
+					} else if self.store.bool_data.contains_key(&key) {
+						self.store.bool_history(&key).iter().map(|&(t, v)| HistoryEntry{time: to_secs(t), value: v.to_string()}).collect()
+					} else if self.store.list_data.contains_key(&key) {
+						self.store.list_history(&key).iter().map(|&(t, ref v)| HistoryEntry{time: to_secs(t), value: list_to_string(v)}).collect()
+					} else {
+						self.store.string_history(&key).into_iter().map(|(t, v)| HistoryEntry{time: to_secs(t), value: v}).collect()
+					};
+					let data = rustc_serialize::json::encode(&entries).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetExited => {
+					let data = if self.exited.is_some() {"true"} else {"false"};
+					let data = data.to_string();
+					RestReply{data, code:200}
+				}
+				RestCommand::GetLog(query) => {
+					let lines = self.query_log_lines(&query);
+					let data = rustc_serialize::json::encode(&lines).unwrap();	
+					RestReply{data, code:200}
+				},
+				RestCommand::GetLogAfter(time) => {
+					let lines = self.get_log_lines(time);
+					let data = rustc_serialize::json::encode(&lines).unwrap();	
+					RestReply{data, code:200}
+				},
+				RestCommand::GetStateChanges(edition) => {
+					let changes = self.store.changes_since(edition);
+					let changed = changes.changed.into_iter().map(|(key, value, _)| format_state(key, value)).collect();
+					let data = rustc_serialize::json::encode(&StateChanges{edition: self.store.edition(), resync: changes.resync, changes: changed, removed: changes.removed}).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetDisplaySchema => {
+					let data = rustc_serialize::json::encode(&display_keys()).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetSchema => {
+					let data = rustc_serialize::json::encode(&self.schema).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetMetadata => {
+					let metadata: BTreeMap<String, String> = self.metadata().into_iter().collect();
+					let data = rustc_serialize::json::encode(&metadata).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetState(path) => {
+					let lines = self.get_state(&path);
+					let data = rustc_serialize::json::encode(&lines).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetTime => {
+					let t = self.current_time.to_secs(self.config.time_units);
+					let data = rustc_serialize::json::encode(&t).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetTimePrecision => {
+					let data = rustc_serialize::json::encode(&self.precision).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetTrace(num) => {
+					let chain = self.causal_chain(num);
+					if !chain.is_empty() {
+						let data = rustc_serialize::json::encode(&chain).unwrap();
+						RestReply{data, code:200}
+					} else {
+						RestReply{data: format!("\"event #{} isn't known\"", num), code:404}
+					}
+				},
+				RestCommand::GetRunning => {
+					let data = if self.running && self.exited.is_none() {"true"} else {"false"};
+					RestReply{data: data.to_string(), code:200}
+				},
+				RestCommand::GetScheduled => {
+					let time_units = self.config.time_units;
+					let events: Vec<PendingEvent> = self.scheduled_events()
+						.map(|(time, to, name)| PendingEvent{time: time.to_secs(time_units), path: self.components.path(to).to_string(), name: name.to_string()})
+						.collect();
+					let data = rustc_serialize::json::encode(&events).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::GetWatchHits(time) => {
+					let hits: Vec<&WatchHit> = self.watch_hits.iter().filter(|h| h.time > time).collect();
+					let data = rustc_serialize::json::encode(&hits).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::ClearWatches => {
+					self.watches.retain(|w| w.callback.is_some());
+					self.watch_hits.clear();
+					RestReply{data: "\"ok\"".to_string(), code:200}
+				},
+				RestCommand::GetJob(id) => {
+					match self.jobs.iter().find(|j| j.id == id) {
+						Some(job) => {
+							let status = job.status(self.current_time, self.config.time_units);
+							let data = rustc_serialize::json::encode(&status).unwrap();
+							RestReply{data, code:200}
+						},
+						None => {
+							let data = rustc_serialize::json::encode(&format!("no job {}", id)).unwrap();
+							RestReply{data, code:404}
+						}
+					}
+				},
+				RestCommand::SetLogLevel(pattern, level) => {
+					self.log(LogLevel::Info, NO_COMPONENT, &format!("setting log level to {:?} for {}", level, pattern.as_str()));
+					self.add_log_level(pattern, level);
+					RestReply{data: "\"ok\"".to_string(), code:200}
+				},
+				RestCommand::Pause => {
+					self.pause();
+					self.finish_job("paused");
+					RestReply{data: "\"ok\"".to_string(), code:200}
+				},
+				RestCommand::Resume => {
+					self.resume();
+					let message = if self.exited.is_some() {"exited"} else {"ok"};
+					let data = rustc_serialize::json::encode(&message.to_string()).unwrap();
+					RestReply{data, code:200}
+				},
+				RestCommand::RunOnce => {
+					if self.exited.is_none() {
+						self.run_time_slice()
+					}
+					
+					let message = if self.exited.is_some() {"exited"} else {"ok"};
+					let data = rustc_serialize::json::encode(&message.to_string()).unwrap();
+					RestReply{data, code:200}
+				}
+				RestCommand::RunNextEvent => {
+					match self.step_event() {
+						Some(stepped) => RestReply{data: rustc_serialize::json::encode(&stepped).unwrap(), code:200},
+						None => RestReply{data: "\"exited\"".to_string(), code:200},
+					}
+				}
+				RestCommand::RunUntilEvent(pattern, max_secs) => {
+					// Like /run/until this returns a job id so that the REST server remains
+					// responsive (e.g. to /pause) while the sim looks for a matching event.
+					let target = if max_secs.is_finite() {Time(self.current_time.0.saturating_add(self.secs_to_ticks(max_secs)))} else {Time(i64::MAX)};
+					let id = self.start_job(target, Some(pattern));
+					let data = rustc_serialize::json::encode(&id).unwrap();
+					RestReply{data, code:200}
+				}
+				RestCommand::SetBoolState(path, value) => self.set_external_state(&path, StoreValue::Bool(value)),
+				RestCommand::SetFloatState(path, value) => self.set_external_state(&path, StoreValue::Float(value)),
+				RestCommand::SetIntState(path, value) => self.set_external_state(&path, StoreValue::Int(value)),
+				RestCommand::SetStringState(path, value) => self.set_external_state(&path, StoreValue::String(value)),
+				RestCommand::SetTime(secs) => {
+					// Long runs can take longer than browsers are willing to wait so we return
+					// a job id and advance the sim between commands, see update_job.
+					let target = Time(self.secs_to_ticks(secs));
+					let id = self.start_job(target, None);
+					let data = rustc_serialize::json::encode(&id).unwrap();
+					RestReply{data, code:200}
+				}
+				RestCommand::Watch(pattern) => {
+					self.add_watch(pattern, None);
+					RestReply{data: "\"ok\"".to_string(), code:200}
+				}
+			};
+			self.update_job();
+			if tx_reply.send((seq, reply)).is_err() {
+				self.log(LogLevel::Warning, NO_COMPONENT, "REST server went away");	// all the handlers hold the receiver so this shouldn't happen
+				break;
+			}
+			
+			// Note that we don't exit when the sim finishes in order to allow GUIs to inspect
+			// state at the end. Instead GUIs use the /exit endpoint to kill us cleanly.
+			if exiting {
+				break;
+			}
+		}
+		
+		// We'll also land here if the server thread died (because that drops the command
+		// sender) in which case there's no way for a GUI to talk to us so we exit.
+		if self.exited.is_none() {
+			self.exited = Some("REST server stopped".to_string());
+		}
+		backend.stop();
+		self.exit();
+	}
+	
+	fn set_external_state(&mut self, key: &str, value: StoreValue) -> RestReply
+	{
+		let result = Arc::make_mut(&mut self.store).set_external(key, value, self.current_time);
+		match result {
+			Ok(_) => {
+				self.record_store_delta();
+				RestReply{data: "\"ok\"".to_string(), code:200}
+			},
+			Err(err) => RestReply{data: rustc_serialize::json::encode(&err).unwrap(), code:400},
+		}
+	}
+	
+	// Cancels the active job (if any) and starts a new one. Pattern is set for
+	// /run/until-event jobs.
+	fn start_job(&mut self, target: Time, pattern: Option<glob::Pattern>) -> u64
+	{
+		self.finish_job("cancelled");
+		let id = self.next_job;
+		self.next_job += 1;
+		if self.jobs.len() == MAX_JOBS {
+			self.jobs.pop_front();
+		}
+		self.jobs.push_back(RunJob{id, start: self.current_time, target, num_hits: self.watch_hits.len(), pattern, event: None, result: None});
+		self.update_job();
+		id
+	}
+	
+	fn has_active_job(&self) -> bool
+	{
+		self.jobs.back().map_or(false, |job| job.result.is_none())
+	}
+	
+	// Stops early if a REST watch fires so that GUIs can use watches as breakpoints.
+	fn update_job(&mut self)
+	{
+		if self.has_active_job() {
+			let result = {
+				let job = self.jobs.back().unwrap();
+				if self.exited.is_some() {
+					Some("exited")
+				} else if self.watch_hits.len() > job.num_hits {
+					Some("watch")
+				} else if self.current_time >= job.target {
+					Some("ok")
+				} else {
+					None
+				}
+			};
+			if let Some(result) = result {
+				self.finish_job(result);
+			}
+		}
+	}
+	
+	// Returns true if one of the events about to be dispatched matches a breakpoint.
+	// Note that the check is skipped after a breakpoint is hit so that resuming will
+	// dispatch the slice. Breakpoints only apply when running via REST, and not when
+	// stepping through events.
+	fn at_breakpoint(&mut self, events: &[ScheduledEvent]) -> bool
+	{
+		if self.breakpoints.is_empty() || self.break_slice == Some(self.slice_num) || self.single_event || !(self.running || self.has_active_job()) {
+			return false;
+		}
+		
+		let mut hits = Vec::new();
+		for e in events {
+			let secs = e.time.to_secs(self.config.time_units);
+			let path = self.components.path(e.to);
+			for (i, b) in self.breakpoints.iter().enumerate() {
+				if b.event.matches(&e.event.name) && b.component.matches(path) {
+					let pattern = format!("{} {}", b.event.as_str(), b.component.as_str());
+					hits.push((i, BreakpointHit{time: secs, pattern, event: e.event.name.to_string(), path: path.to_string()}));
+				}
+			}
+		}
+		if hits.is_empty() {
+			return false;
+		}
+		
+		for (i, hit) in hits {
+			self.log(LogLevel::Info, NO_COMPONENT, &format!("hit breakpoint '{}' for '{}' to {}", hit.pattern, hit.event, hit.path));
+			self.breakpoints[i].hits += 1;
+			if self.breakpoint_hits.len() == self.config.max_breakpoint_hits {
+				self.breakpoint_hits.pop_front();
+			}
+			self.breakpoint_hits.push_back(hit);
+		}
+		self.break_slice = Some(self.slice_num);
+		true
+	}
+	
+	// Returns the events that the next time slice would dispatch. This will be empty if
+	// there are no events or the next slice is for daemons.
+	fn next_slice_events(&self) -> Vec<&ScheduledEvent>
+	{
+		if self.scheduled.is_empty() {
+			return Vec::new();
+		}
+		
+		let next = self.scheduled.peek().unwrap().time;
+		if self.daemons.iter().any(|d| d.next.0 <= next.0) {
+			return Vec::new();
+		}
+		
+		let events: Vec<&ScheduledEvent> = self.scheduled.iter().filter(|e| e.time == next).collect();
+		let first = events.iter().map(|e| self.phase_of(e.to)).min().unwrap();
+		events.into_iter().filter(|e| self.phase_of(e.to) == first).collect()
+	}
+	
+	// Used by /run/next-event. Daemon slices are run as needed. Note that when a slice
+	// is stepped through the events later in the slice will see the effects of the
+	// earlier events. Returns None if the simulation exited first.
+	fn step_event(&mut self) -> Option<SteppedEvent>
+	{
+		self.stepped = None;
+		self.single_event = true;
+		while self.stepped.is_none() && self.exited.is_none() {
+			self.run_time_slice();
+		}
+		self.single_event = false;
+		self.stepped.take()
+	}
+	
+	// Runs the next time slice. For /run/until-event jobs slices with a matching event
+	// are stepped through one event at a time instead so that the job stops right after
+	// the matching event is dispatched.
+	fn run_job_slice(&mut self)
+	{
+		let pattern = match self.jobs.back_mut() {
+			Some(job) if job.result.is_none() => job.pattern.take(),
+			_ => None,
+		};
+		match pattern {
+			Some(pattern) => {
+				let stepped = self.step_until_event(&pattern);
+				let job = self.jobs.back_mut().unwrap();
+				job.pattern = Some(pattern);
+				if stepped.is_some() {
+					job.event = stepped;
+					job.result = Some("event".to_string());
+				}
+			},
+			None => self.run_time_slice(),
+		}
+	}
+	
+	// Slices without a matching event are run as usual so only the slice with the
+	// matching event is stepped through. Returns the event once it's dispatched.
+	fn step_until_event(&mut self, pattern: &glob::Pattern) -> Option<SteppedEvent>
+	{
+		if self.next_slice_events().iter().any(|e| pattern.matches(&e.event.name)) {
+			self.step_event().filter(|stepped| pattern.matches(&stepped.name))
+		} else {
+			self.run_time_slice();
+			None
+		}
+	}
+	
+	fn finish_job(&mut self, result: &str)
+	{
+		if self.has_active_job() {
+			self.jobs.back_mut().unwrap().result = Some(result.to_string());
+		}
+	}
+	
+	fn init_components(&mut self)
+	{
+		assert!(self.exited.is_none());
+
+		for i in 0..self.config.num_init_stages {
+			self.schedule_init_stage(i);
+			while !self.scheduled.is_empty() && self.scheduled.peek().unwrap().time.0 == 0 {	// may take multiple slices if phases are being used
+				self.dispatch_events();
+			}
+			assert!(self.current_time.0 == 0);
+			if self.exited.is_some() {
+				self.exited = Some("Effector.exit was called during initialization".to_string());
+			}
+		}
+	}
+	
+	fn run_time_slice(&mut self)
+	{
+		assert!(self.exited.is_none());
+		self.schedule_injected();
+		self.feed_replay();
+
+		let max_time = if self.config.max_secs.is_infinite() {i64::max_value()} else {self.config.tick_rounding.to_ticks(self.config.max_secs*self.config.time_units, self.config.tick_epsilon)};
+		if self.scheduled.is_empty() {
+			self.exited = Some("no events".to_string());
+		
+		} else if self.current_time.0 >= max_time {
+			self.exited = Some("reached config.max_secs".to_string());
+
+		} else {
+			// Daemons are run in their own time slice so that they see the state before
+			// any events at that time are processed.
+			let next = self.scheduled.peek().unwrap().time;
+			let daemon_time = self.daemons.iter().map(|d| d.next.0).min();
+			if self.wait_for_clock(min(next.0, daemon_time.unwrap_or(next.0))) {
+				let ran = match daemon_time {
+					Some(due) if due <= next.0 => {self.run_daemons(Time(due)); true},
+					_ => {
+						let dispatched = self.dispatch_events();
+						if dispatched {
+							self.check_steady_state();
+							self.run_rules();
+						}
+						dispatched
+					}
+				};
+				if ran {
+					self.check_invariants();
+					self.check_stop_conditions();
+				}
+			}
+		}
+	}
+	
+	// Returns true if the time slice at ticks can be processed.
+	fn wait_for_clock(&mut self, ticks: i64) -> bool
+	{
+		if ticks <= self.granted.0 {
+			return true;
+		}
+		
+		let secs = Time(ticks).to_secs(self.config.time_units);
+		let grant = match self.clock {
+			Some(ref mut clock) => clock.request(secs),
+			None => return true,
+		};
+		
+		match grant {
+			ClockGrant::Until(secs) => {
+				let granted = self.config.tick_rounding.to_ticks(secs*self.config.time_units, self.config.tick_epsilon);
+				if granted > self.granted.0 {
+					self.granted = Time(granted);
+				}
+				if granted < ticks {
+					// Time advances even though there is nothing to do yet.
+					self.current_time = Time(max(self.current_time.0, granted));
+					false
+				} else {
+					true
+				}
+			},
+			ClockGrant::Stop => {
+				self.exited = Some("the external clock stopped the simulation".to_string());
+				false
+			},
+			ClockGrant::Error(err) => {
+				self.log(LogLevel::Error, NO_COMPONENT, &err);
+				self.exited = Some("the external clock failed".to_string());
+				false
+			}
+		}
+	}
+	
+	fn run_daemons(&mut self, time: Time)
+	{
+		self.current_time = time;
+		let secs = time.to_secs(self.config.time_units);
+		
+		let mut daemons = mem::replace(&mut self.daemons, Vec::new());
+		for daemon in daemons.iter_mut().filter(|d| d.next == time) {
+			let mut effects = Effector::new();
+			{
+				let state = SimState{store: self.store.clone(), components: self.components.clone(), services: self.services.clone(), time: secs};
+				(daemon.callback)(&state, &mut effects);
+			}
+			self.apply_effects(daemon.id, &mut effects);
+			if effects.exit {
+				self.exited = Some("effector.exit was called by a daemon".to_string());
+			}
+			daemon.next = Time(time.0 + daemon.interval);
+		}
+		self.daemons = daemons;
+		
+		self.record_store_delta();
+	}
+	
+	fn run_rules(&mut self)
+	{
+		let mut fired = Vec::new();
+		for rule in self.rules.iter_mut() {
+			if rule.evaluate(&self.store) {
+				fired.push((rule.text.clone(), rule.action.clone()));
+			}
+		}
+		
+		for (text, action) in fired {
+			self.log(LogLevel::Debug, NO_COMPONENT, &format!("fired '{}'", text));
+			match action {
+				Action::Send(name, path) => {
+					match self.find_active(&path) {
+						Some(id) => {
+							let time = self.add_secs(0.0);
+							self.schedule(Event::new(&name), id, time);
+						},
+						None => self.log(LogLevel::Error, NO_COMPONENT, &format!("'{}' isn't an active component (from '{}')", path, text)),
+					}
+				},
+				Action::Exit => {
+					if self.exited.is_none() {
+						self.exited = Some(format!("rule '{}' fired", text));
+					}
+				},
+				Action::Log(message) => self.log(LogLevel::Info, NO_COMPONENT, &message),
+			}
+		}
+	}
+	
+	// Only the transition from holding to violated is reported so that a long lived
+	// violation doesn't spam the log.
+	fn check_invariants(&mut self)
+	{
+		let mut invariants = mem::replace(&mut self.invariants, Vec::new());
+		for invariant in invariants.iter_mut() {
+			let failures = match invariant.check {
+				InvariantCheck::Store(ref mut check) => if check(&self.store) {Vec::new()} else {vec![String::new()]},
+				InvariantCheck::Keys(ref glob, ref mut check) => self.store.query(glob).into_iter()
+					.filter(|&(ref key, ref value, _)| !check(key, value))
+					.map(|(key, value, _)| match value {
+						StoreValue::Int(value) => format!(" for {} = {}", key, value),
+						StoreValue::Float(value) => format!(" for {} = {}", key, float_to_string(value)),
+						StoreValue::String(value) => format!(" for {} = '{}'", key, value),
+						StoreValue::Bool(value) => format!(" for {} = {}", key, value),
+						list => format!(" for {} = {}", key, list_to_string(&list)),
+					})
+					.collect(),
+			};
+			
+			if !failures.is_empty() && !invariant.violated {
+				for failure in failures.iter() {
+					self.log(LogLevel::Error, NO_COMPONENT, &format!("invariant '{}' was violated{}", invariant.name, failure));
+				}
+				if invariant.action == InvariantAction::Exit && self.exited.is_none() {
+					self.exited = Some(format!("invariant '{}' was violated", invariant.name));
+				}
+			}
+			invariant.violated = !failures.is_empty();
+		}
+		self.invariants = invariants;
+	}
+	
+	fn check_stop_conditions(&mut self)
+	{
+		if self.stop_conditions.is_empty() || self.exited.is_some() {
+			return;
+		}
+		
+		let time = self.current_time.to_secs(self.config.time_units);
+		let state = SimState{store: self.store.clone(), components: self.components.clone(), services: self.services.clone(), time};
+		let mut conditions = mem::replace(&mut self.stop_conditions, Vec::new());
+		for condition in conditions.iter_mut() {
+			let met = match *condition {
+				StopCondition::Predicate(ref mut predicate) => predicate(&state),
+				StopCondition::KeyAtLeast(ref key, value) => self.store_number(key).map_or(false, |v| v >= value),
+				StopCondition::KeyAtMost(ref key, value) => self.store_number(key).map_or(false, |v| v <= value),
+				StopCondition::EventCount(ref name, count) => self.stop_event_counts[name] >= count,
+			};
+			if met {
+				self.exited = Some(condition.reason());
+				break;
+			}
+		}
+		self.stop_conditions = conditions;
+	}
+	
+	fn store_number(&self, key: &str) -> Option<f64>
+	{
+		if let Some(value) = self.store.int_data.get(key) {
+			Some(value.1 as f64)
+		} else {
+			self.store.float_data.get(key).map(|value| value.1)
+		}
+	}
+	
+	fn find_active(&self, path: &str) -> Option<ComponentID>
+	{
+		self.components.id(path).and_then(|id| if self.event_senders[id.0].is_some() {Some(id)} else {None})
+	}
+	
+	fn exit(&mut self)
+	{
+		// TODO: Might want to also print events/sec, maybe at debug
+		let elapsed = (time::get_time() - self.start_time).num_milliseconds();
+		let exited = self.exited.as_ref().unwrap().clone();
+		self.log(LogLevel::Debug, NO_COMPONENT, &format!("exiting sim, run time was {}.{}s ({})",
+			elapsed/1000, elapsed%1000, exited));	// TODO: eventually will need a friendly_duration_str fn
+			
+		if !self.stats.is_empty() {
+			for line in self.stats.summary() {
+				self.log(LogLevel::Info, NO_COMPONENT, &line);
+			}
+		}
+		if self.tick_stats.inexact > 0 {
+			let stats = self.tick_stats;
+			self.log(LogLevel::Info, NO_COMPONENT, &format!("{} of {} conversions into ticks were inexact (net error {} ticks, max error {} ticks)",
+				stats.inexact, stats.conversions, float_to_string(stats.net_error), float_to_string(stats.max_error)));
+		}
+		if !self.time_weighted.is_empty() {
+			let time = self.current_time.to_secs(self.config.time_units);
+			let lines: Vec<String> = self.time_weighted.iter()
+				.flat_map(|t| t.trackers.iter().filter_map(move |(key, tracker)| tracker.summary(time).map(|s| s.describe(key, t.threshold))))
+				.collect();
+			for line in lines {
+				self.log(LogLevel::Info, NO_COMPONENT, &line);
+			}
+		}
+		
+		let finger_print = self.finger_print;
+		self.log(LogLevel::Info, NO_COMPONENT, &format!("finger print = {:X}", finger_print));
+		
+		if let Some(ref mut tracer) = self.tracer {
+			tracer.flush();
+		}
+		if let Some(ref mut writer) = self.delta_writer {
+			writer.flush();
+		}
+		if !self.config.run_report.is_empty() {
+			let path = self.config.run_report.clone();
+			match self.report().write(&path) {
+				Ok(_) => self.log(LogLevel::Info, NO_COMPONENT, &format!("wrote run report to {}", path)),
+				Err(err) => self.log(LogLevel::Error, NO_COMPONENT, &err),
+			}
+		}
+		if !self.config.store_dump.is_empty() {
+			let path = self.config.store_dump.clone();
+			let mut text = format!("# {}\n", self.provenance.summary());
+			for line in self.store_lines() {
+				text += &format!("{}\n", line);
+			}
+			match File::create(&path).and_then(|mut file| file.write_all(text.as_bytes())) {
+				Ok(_) => self.log(LogLevel::Info, NO_COMPONENT, &format!("wrote store to {}", path)),
+				Err(err) => self.log(LogLevel::Error, NO_COMPONENT, &format!("failed to write store to {}: {}", path, err)),
+			}
+		}
+		if self.replaying {
+			let dropped = self.replay_dropped;
+			self.log(LogLevel::Debug, NO_COMPONENT, &format!("replay ignored {} events scheduled during the run", dropped));
+		}
+		
+		self.log_writer.flush();
+		if self.log_writer.dropped > 0 {
+			let dropped = self.log_writer.dropped;
+			self.log(LogLevel::Warning, NO_COMPONENT, &format!("dropped {} log lines (config.log_queue_size is {})", dropped, self.config.log_queue_size));
+			self.log_writer.flush();
+		}
+	}
+	
+	// Returns false if the events weren't dispatched because a breakpoint was hit.
+	fn dispatch_events(&mut self) -> bool
+	{
+		self.feed_replay();
+		let events = self.take_events_at(self.scheduled.peek().unwrap().time);
+		if self.at_breakpoint(&events) {
+			self.scheduled.extend(events);
+			self.break_hit = true;
+			return false;
+		}
+		self.current_time = events[0].time;
+		let mut ids = Vec::new();
+		let limit = match self.config.dispatch {
+			DispatchStrategy::Sequential => 1,
+			DispatchStrategy::Threaded => usize::max_value(),
+			DispatchStrategy::Pooled(n) => max(n, 1),
+		};
+		
+		// Note that it is important that we collect all of the side effects for a time t
+		// before we apply them. That way components executing at t do not affect each other.
+		let mut outstanding = VecDeque::new();
+		let mut effects = Vec::new();
+		if self.single_event {
+			let e = &events[0];
+			self.stepped = Some(SteppedEvent{name: e.event.name.to_string(), path: self.components.path(e.to).to_string(), time: e.time.to_secs(self.config.time_units)});
+		}
+		let limit_events = self.config.storm_slice_events;
+		if limit_events > 0 && events.len() > limit_events {
+			if !self.slice_storm {
+				let reason = format!("{} events were dispatched at one time slice", events.len());
+				self.report_storm(&reason, events.iter());
+				self.slice_storm = true;
+			}
+		} else {
+			self.slice_storm = false;
+		}
+		for e in events {
+			if let Some(ref c) = e.event.correlation {
+				// Replies can be forwarded (e.g. by a Link) so we only settle the request
+				// once the reply or timeout reaches the requester.
+				if c.kind != CorrelationKind::Request && e.to == c.requester && !self.pending_requests.remove(&c.id) {
+					continue;	// the request was already replied to or timed out
+				}
+			}
+			self.update_finger_print(&e);
+			if self.tracer.is_some() {
+				self.trace(&e);
+			}
+			
+			// TODO: If we use speculative execution we'll need to be careful not to do
+			// anything wrong when REST is being used. Maybe just disable speculation.
+			if self.should_log(LogLevel::Excessive, NO_COMPONENT) {
+				let path = self.components.display_path(e.to);
+				let num = self.event_num;
+				let mesg = match self.describe_payload(&e.event) {
+					Some(text) => format!("dispatching #{} '{}' ({}) to {}", num, e.event.name, text, path),
+					None => format!("dispatching #{} '{}' to {}", num, e.event.name, path),
+				};
+				self.log(LogLevel::Excessive, NO_COMPONENT, &mesg);
+			}
+			if !self.config.emergency_dump.is_empty() {
+				self.remember_event(&e);
+			}
+			ids.push(e.to);
+			
+			if self.config.max_event_causes > 0 {
+				self.record_cause(&e);
+			}
+			
+			if let Some(count) = self.stop_event_counts.get_mut(e.event.name.as_str()) {
+				*count += 1;
+			}
+			
+			if self.removed.contains(&e.to) {
+				// Events scheduled before the component was removed are dropped.
+				self.event_num += 1;
+				self.event_counts[e.to.0] += 1;
+				self.undeliverable(e);
+				continue;
+			}
+			
+			if self.handlers.contains_key(&e.to) {
+				let time = self.current_time.to_secs(self.config.time_units);
+				let state = SimState{store: self.store.clone(), components: self.components.clone(), services: self.services.clone(), time};
+				let mut effector = Effector::new();
+				(self.handlers.get_mut(&e.to).unwrap())(e.event, &state, &mut effector);
+				effects.push((e.to, self.event_num, effector));
+				self.event_num += 1;
+				self.event_counts[e.to.0] += 1;
+				continue;
+			}
+			
+			if outstanding.len() >= limit {
+				let (id, num, generation) = outstanding.pop_front().unwrap();
+				effects.push((id, num, self.receive_effects(id, generation)));
+			}
+			outstanding.push_back((e.to, self.event_num, self.generation(e.to)));
+			
+			self.event_num += 1;
+			self.event_counts[e.to.0] += 1;
+			let result = if let Some(ref tx) = self.event_senders[e.to.0] {
+				let time = self.current_time.to_secs(self.config.time_units);
+				let state = SimState{store: self.store.clone(), components: self.components.clone(), services: self.services.clone(), time};
+				tx.send((e.event, state))
+			} else {
+				let c = self.components.get(e.to);
+				panic!("Attempt to send event {} to component {} which isn't an active component", e.event.name, c.name);
+			};
+			if let Err(mpsc::SendError((event, _))) = result {
+				if self.supervisors.contains_key(&e.to) {
+					// The thread died before we could send it the event so re-send it after
+					// init-recover.
+					self.restart_component(e.to, "its thread died");
+					let time = self.add_secs(0.0);
+					self.schedule(event, e.to, time);
+				} else {
+					let c = self.components.get(e.to);
+					panic!("Got an error sending to component {}: sending on a closed channel", c.name);
+				}
+			}
+		}
+		
+		for (id, num, generation) in outstanding.drain(..) {
+			effects.push((id, num, self.receive_effects(id, generation)));
+		}
+		if self.config.event_gauges {
+			self.count_gauge_events(&ids);
+		}
+		if self.config.record_parallelism {
+			self.record_parallelism(ids, limit);
+		}
+		
+		// This isn't terribly important but does keep the log ordering at a time
+		// consistent which is kind of nice.
+		effects.sort_by(|a, b| a.0.cmp(&b.0));
+		
+		for (id, num, mut e) in effects.drain(..) {
+			self.current_parent = Some(num);
+			self.apply_effects(id, &mut e);
+			
+			if e.exit {
+				self.exited = Some("effector.exit was called".to_string())
+			}
+		}
+		self.current_parent = None;
+		self.check_pending_storm();
+		
+		self.slice_num += 1;
+		if self.config.event_gauges {
+			self.record_gauges();
+		}
+		self.record_store_delta();
+		self.finish_finger_print();
+		true
+	}
+	
+	fn check_steady_state(&mut self)
+	{
+		if self.more_slices_at_current_time() {
+			return;		// the estimate is written after the last slice at this time
+		}
+		let estimate = match self.batch_means.as_mut().and_then(|b| b.take_changed()) {
+			Some(estimate) => estimate,
+			None => return,
+		};
+		{
+		let store = Arc::make_mut(&mut self.store);
+		store.set_float("simulation.steady-state.mean", estimate.mean, self.current_time);
+		store.set_float("simulation.steady-state.half-width", estimate.half_width, self.current_time);
+		store.set_int("simulation.steady-state.batches", estimate.count as i64, self.current_time);
+		}
+		
+		if self.exited.is_none() && self.batch_means.as_ref().unwrap().is_precise(&estimate) {
+			let key = self.batch_means.as_ref().unwrap().params.key.clone();
+			let mesg = format!("{}.mean is {} +/- {} (95% CI)", key, float_to_string(estimate.mean), float_to_string(estimate.half_width));
+			self.log(LogLevel::Info, NO_COMPONENT, &mesg);
+			self.exited = Some("steady-state precision was reached".to_string());
+		}
+	}
+	
+	fn check_pending_storm(&mut self)
+	{
+		let limit = self.config.storm_pending;
+		if limit > 0 && self.scheduled.len() > limit {
+			if !self.pending_storm {
+				let reason = format!("{} events are pending", self.scheduled.len());
+				let scheduled = mem::replace(&mut self.scheduled, BinaryHeap::new());
+				self.report_storm(&reason, scheduled.iter());
+				self.scheduled = scheduled;
+				self.pending_storm = true;
+			}
+		} else {
+			self.pending_storm = false;
+		}
+	}
+	
+	// Logs and stores the event names and scheduling components with the most events.
+	fn report_storm<'a, I>(&mut self, reason: &str, events: I)
+		where I: Iterator<Item = &'a ScheduledEvent>
+	{
+		let mut names: HashMap<&str, usize> = HashMap::new();
+		let mut sources: HashMap<ComponentID, usize> = HashMap::new();
+		for e in events {
+			*names.entry(&e.event.name).or_insert(0) += 1;
+			*sources.entry(e.from).or_insert(0) += 1;
+		}
+		let names = top_talkers(names.into_iter().map(|(name, count)| (format!("'{}'", name), count)));
+		let sources = top_talkers(sources.into_iter().map(|(id, count)| {
+			let path = if id == NO_COMPONENT {"simulation".to_string()} else {self.components.full_path(id)};
+			(path, count)
+		}));
+		
+		self.log(LogLevel::Warning, NO_COMPONENT, &format!("event storm: {}", reason));
+		self.log(LogLevel::Warning, NO_COMPONENT, &format!("   top events: {}", names));
+		self.log(LogLevel::Warning, NO_COMPONENT, &format!("   top schedulers: {}", sources));
+		
+		self.storm_report = Some((reason.to_string(), names, sources));
+	}
+	
+	// Both kinds of storm can be reported in one slice (and stepping may split a time into
+	// several slices) so, like metadata, the storm keys are written at the end of the slice
+	// and deferred to the next slice if they have already been set at this time. If there
+	// are several storms before the keys are written then the store has the last one.
+	fn flush_storm_report(&mut self)
+	{
+		let time = self.current_time;
+		if STORM_KEYS.iter().any(|key| self.store.was_set_at(key, time)) {
+			return;
+		}
+		
+		let (reason, names, sources) = self.storm_report.take().unwrap();
+		let store = Arc::make_mut(&mut self.store);
+		store.set_string(STORM_KEYS[0], &reason, time);
+		store.set_string(STORM_KEYS[1], &names, time);
+		store.set_string(STORM_KEYS[2], &sources, time);
+	}
+	
+	// Note that changes made before the first time slice (e.g. via apply) will be
+	// included in the first time slice's delta.
+	fn record_store_delta(&mut self)
+	{
+		if !self.pending_stats.is_empty() {
+			self.flush_stats();
+		}
+		if !self.pending_metadata.is_empty() {
+			self.flush_metadata();
+		}
+		if self.storm_report.is_some() {
+			self.flush_storm_report();
+		}
+		if self.config.max_store_deltas > 0 || !self.watches.is_empty() || !self.time_weighted.is_empty() || self.delta_writer.is_some() {
+			let (changes, edition) = {
+				let store = Arc::make_mut(&mut self.store);
+				(store.take_changes(), store.edition)
+			};
+			if !changes.is_empty() {
+				self.track_time_weighted_changes(&changes);
+				self.run_watches(&changes);
+				let time = self.current_time.to_secs(self.config.time_units);
+				let delta = StoreDelta{time, edition, changes};
+				self.write_delta(&delta);
+				if self.config.max_store_deltas > 0 {
+					if self.store_deltas.len() == self.config.max_store_deltas {
+						let evicted = self.store_deltas.pop_front().unwrap();
+						self.evicted_delta = Some((evicted.time, evicted.edition));
+					}
+					self.store_deltas.push_back(delta);
+				}
+			}
+		}
+	}
+	
+	fn write_delta(&mut self, delta: &StoreDelta)
+	{
+		let result = match self.delta_writer {
+			Some(ref mut writer) => writer.write(delta),
+			None => return,
+		};
+		if let Err(err) = result {
+			self.delta_writer = None;
+			self.log(LogLevel::Error, NO_COMPONENT, &format!("{}, recording deltas has been disabled", err));
+		}
+	}
+	
+	// Compares the slices of the live run that completed since the last comparison with
+	// the golden run.
+	fn compare_to_golden(&mut self) -> Result<(), String>
+	{
+		let path = match self.tracer {
+			Some(ref mut tracer) => {
+				tracer.flush();
+				tracer.path.clone()
+			},
+			None => return Err("the run isn't being traced".to_string()),
+		};
+		match self.golden {
+			Some(ref mut golden) => golden.update(&path, &self.store_deltas, self.evicted_delta, self.current_time),
+			None => Err("compare_with wasn't called".to_string()),
+		}
+	}
+	
+	fn track_time_weighted_changes(&mut self, changes: &Vec<StoreChange>)
+	{
+		let time = self.current_time.to_secs(self.config.time_units);
+		for keys in self.time_weighted.iter_mut() {
+			for change in changes.iter().filter(|c| c.kind == "int" || c.kind == "float") {
+				if keys.pattern.matches(&change.key) {
+					if let Ok(value) = change.new_value.parse::<f64>() {
+						let threshold = keys.threshold;
+						keys.trackers.entry(change.key.clone()).or_insert_with(|| TimeWeighted::new(threshold)).record(time, value);
+					}
+				}
+			}
+		}
+	}
+	
+	fn run_watches(&mut self, changes: &Vec<StoreChange>)
+	{
+		let time = self.current_time.to_secs(self.config.time_units);
+		let mut fired = Vec::new();
+		for watch in self.watches.iter_mut() {
+			for change in changes.iter() {
+				if watch.pattern.matches(&change.key) {
+					match watch.callback {
+						Some(ref mut callback) => fired.push((watch.pattern.as_str().to_string(), change.clone(), callback(change))),
+						None => {
+							// REST watches act as breakpoints.
+							self.watch_hits.push(WatchHit{time, pattern: watch.pattern.as_str().to_string(), change: change.clone()});
+							self.running = false;
+						},
+					}
+				}
+			}
+		}
+		
+		for (pattern, change, action) in fired {
+			match action {
+				WatchAction::Continue => (),
+				WatchAction::Log | WatchAction::Pause => {
+					let old = change.old_value.unwrap_or("none".to_string());
+					self.log(LogLevel::Info, NO_COMPONENT, &format!("watch '{}': {} changed from {} to {}", pattern, change.key, old, change.new_value));
+					if action == WatchAction::Pause {
+						self.pause();
+					}
+				},
+				WatchAction::Exit => {
+					if self.exited.is_none() {
+						self.exited = Some(format!("watch '{}' fired for {}", pattern, change.key));
+					}
+				},
+			}
+		}
+	}
+	
+	fn apply_effects(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		self.current_source = id;
+		self.apply_logs(id, &effects);
+		self.apply_events(effects);
+		self.apply_requests(id, effects);
+		self.apply_broadcasts(id, effects);
+		self.apply_schema(id, effects);
+		
+		// Store writes made by an effector are applied as a unit, see Store::begin_batch.
+		Arc::make_mut(&mut self.store).begin_batch();
+		self.apply_stores(&effects, id);
+		self.apply_transactions(id, effects);
+		self.apply_stats(id, effects);
+		self.apply_metadata(id, effects);
+		self.apply_entities(id, effects);
+		Arc::make_mut(&mut self.store).end_batch();
+		self.apply_assertions(id, effects);
+		self.apply_unhandled(id, effects);
+		self.current_source = NO_COMPONENT;
+
+		if effects.removed {
+			self.remove_components(id);
+		}
+	}
+	
+	// The finger print is used to verify that the simulation is deterministic: things like
+	// the order of hash map iteration or random number generation (assuming the same seed)
+	// should not change what happens during a simulation run. We could only compute the finger
+	// print when told to but it should be quite cheap and non-determinism is annoying enough
+	// that it's worth keeping an eye on.
+	fn update_finger_print(&mut self, sevent: &ScheduledEvent)
+	{
+		let mut delta = sevent.time.0 as u64;
+		delta += sevent.to.0 as u64;
+		
+		let name = &sevent.event.name;
+		for b in name.bytes().take(min(name.len(), 8)) {
+			delta += b as u64;
+		}
+		
+		if self.config.finger_print_payloads {
+			if let Some(ref payload) = sevent.event.payload {
+				if let Some(hash) = self.payloads.hash(&**payload) {
+					delta = delta.wrapping_add(hash);
+				}
+			}
+		}
+		
+		self.finger_print = self.finger_print.wrapping_add(delta);
+	}
+	
+	// Called at the end of each time slice.
+	fn finish_finger_print(&mut self)
+	{
+		if self.config.finger_print_store {
+			// The edition alone would miss components writing different values.
+			let hash = self.store.change_hash.unwrap_or(0);
+			self.finger_print = self.finger_print.wrapping_add(self.store.edition()).wrapping_add(hash);
+		}
+		if self.config.finger_print_logs {
+			self.finger_print = self.finger_print.wrapping_add(self.slice_log_count);
+		}
+		self.slice_log_count = 0;
+		
+		if self.config.max_finger_prints > 0 {
+			if self.finger_prints.len() == self.config.max_finger_prints {
+				self.finger_prints.pop_front();
+			}
+			let time = self.current_time.to_secs(self.config.time_units);
+			self.finger_prints.push_back(SliceFingerPrint{slice: self.slice_num, time, finger_print: self.finger_print});
+		}
+	}
+	
+	fn remove_components(&mut self, id: ComponentID)
+	{
+		{
+		self.release_component(id);
+		
+		let store = Arc::make_mut(&mut self.store);
+		let key = self.components.full_path(id) + ".removed";
+		store.set_int(&key, 1, self.current_time);
+		}
+		
+		let children = self.components.get(id).children.clone();
+		for child_id in children.iter() {
+			self.remove_components(*child_id);
+		}
+	}
+	
+	fn undeliverable(&mut self, e: ScheduledEvent)
+	{
+		let from = e.from;
+		// Handler components keep their event sender so they are notified too.
+		if self.config.notify_undeliverable && from != NO_COMPONENT && self.event_senders[from.0].is_some() {
+			let payload = UndeliveredEvent{to: e.to, event: e.event};
+			let time = self.add_secs(0.0);
+			self.schedule(Event::with_payload("delivery-failed", payload), from, time);
+		}
+	}
+	
+	// Dropping the sender disconnects the component thread's receiver so the thread
+	// exits once it has finished processing its current event.
+	fn release_component(&mut self, id: ComponentID)
+	{
+		self.event_senders[id.0] = None;
+		self.effector_receivers[id.0] = None;
+		self.handlers.remove(&id);
+		self.supervisors.remove(&id);
+		self.removed.insert(id);
+	}
+	
+	fn schedule_init_stage(&mut self, stage: i32)
+	{
+		self.log(LogLevel::Info, NO_COMPONENT, &format!("initializing components at stage {}", stage));
+		let name = format!("init {}", stage);
+		for i in 0..self.event_senders.len() {
+			if let Some(_) = self.event_senders[i] {
+				let event = Event::new(&name);
+				self.schedule(event, ComponentID(i), Time(0));
+			}
+		}
+		assert!(!self.scheduled.is_empty());	// silly to have a simulation with no active components
+	}
+	
+	fn schedule(&mut self, mut event: Event, to: ComponentID, time: Time)
+	{
+//		let path = self.components.full_path(to);
+//		let t = time.to_secs(self.config.time_units);
+//		self.log(LogLevel::Debug, NO_COMPONENT, &format!("scheduling {} for {} to {:.3}", event.name, path, t));
+		
+		if self.replaying {
+			self.replay_dropped += 1;
+			return;
+		}
+		
+		if !self.wired_ports.is_empty() && event.port_name.starts_with(WIRE_PREFIX) {
+			if let Some(name) = self.wired_ports.get(&event.port_name) {
+				event.port_name = name.clone();
+			}
+		}
+		
+		let seq = self.next_seq;
+		self.next_seq += 1;
+		let parent = self.current_parent;
+		let from = self.current_source;
+		self.scheduled.push(ScheduledEvent{event, to, time, seq, parent, from});
+	}
+	
+	// Returns the events to dispatch at the current time. If phases are being used
+	// then only the events for the earliest phase are returned (the remainder will
+	// be dispatched in subsequent time slices at the same time).
+	fn take_events_at(&mut self, time: Time) -> Vec<ScheduledEvent>
+	{
+		let mut events = Vec::new();
+		while !self.scheduled.is_empty() && self.scheduled.peek().unwrap().time == time {	// while let can't have a guard so we use this somewhat ugly syntax
+			events.push(self.scheduled.pop().unwrap());
+		}
+		
+		if !self.phases.is_empty() {
+			let phases: Vec<usize> = events.iter().map(|e| self.phase_of(e.to)).collect();
+			let first = *phases.iter().min().unwrap();
+			let (current, later): (Vec<_>, Vec<_>) = events.into_iter().zip(phases).partition(|&(_, phase)| phase == first);
+			self.scheduled.extend(later.into_iter().map(|(e, _)| e));
+			events = current.into_iter().map(|(e, _)| e).collect();
+		}
+		
+		if self.single_event && events.len() > 1 {
+			let later = events.split_off(1);
+			self.scheduled.extend(later);
+		}
+		events
+	}
+	
+	// Components without a phase (either directly or via a parent) are dispatched
+	// before the named phases.
+	fn phase_of(&self, mut id: ComponentID) -> usize
+	{
+		while id != NO_COMPONENT {
+			if let Some(phase) = self.component_phases.get(&id) {
+				return phase + 1;
+			}
+			id = self.components.get(id).parent;
+		}
+		0
+	}
+	
+	fn schedule_injected(&mut self)
+	{
+		while let Ok(injection) = self.injector_rx.try_recv() {
+			let to = match injection.target {
+				InjectTarget::Id(id) => Some(id),
+				InjectTarget::Path(ref path) => self.find_active(path),
+			};
+			match to {
+				Some(to) => {
+					let time = self.add_secs(injection.secs);
+					self.schedule(injection.event, to, time);
+				},
+				None => {
+					if let InjectTarget::Path(path) = injection.target {
+						self.log(LogLevel::Error, NO_COMPONENT, &format!("dropping injected '{}' ('{}' isn't an active component)", injection.event.name, path));
+					}
+				},
+			}
+		}
+	}
+	
+	// When replaying the scheduled events are fed from the trace one slice at a time.
+	fn feed_replay(&mut self)
+	{
+		if self.replaying && self.scheduled.is_empty() {
+			if let Some(slice) = self.replay.front().map(|e| e.slice) {
+				while self.replay.front().map_or(false, |e| e.slice == slice) {
+					let entry = self.replay.pop_front().unwrap();
+					let to = self.components.id(&entry.to).unwrap();	// replay_trace checked these
+					let payload = match entry.payload_type {
+						Some(ref name) => Some(self.payloads.decode(name, entry.payload_version.unwrap_or(1), entry.payload.as_ref().map(|p| p.as_str()).unwrap_or("null")).unwrap()),
+						None => None,
+					};
+					let event = Event{name: entry.name.into(), port_name: entry.port.into(), payload, payload_type: "", priority: 0, correlation: None};	// events are replayed in dispatch order so priority isn't needed
+					let seq = self.next_seq;
+					self.next_seq += 1;
+					self.scheduled.push(ScheduledEvent{event, to, time: Time(entry.time), seq, parent: None, from: NO_COMPONENT});
+				}
+			}
+		}
+	}
+	
+	fn record_cause(&mut self, e: &ScheduledEvent)
+	{
+		if self.causes.len() == self.config.max_event_causes {
+			self.causes.pop_front();
+		}
+		self.causes.push_back(CauseRecord{num: self.event_num, parent: e.parent, time: e.time, name: e.event.name.clone(), to: e.to});
+	}
+	
+	// Generation is the component's generation when the event was sent. If the thread has
+	// since been restarted then the event was lost and there are no effects.
+	fn receive_effects(&mut self, id: ComponentID, generation: u64) -> Effector
+	{
+		if generation != self.generation(id) {
+			return Effector::new();
+		}
+		
+		let ms = 5000;
+		let result = if let Some(ref rx) = self.effector_receivers[id.0] {
+			rx.recv_timeout(Duration::from_millis(ms))
+		} else {
+			panic!("Failed to receive an effector from component {}", self.components.get(id).name);
+		};
+		match result {
+			Ok(e) => e,
+
+			// 5s should be an ample amount of time for even a complex component to respond
+			Err(mpsc::RecvTimeoutError::Timeout) => panic!("Component {} took longer than {} ms to send back effects", self.components.get(id).name, ms),
+
+			// Components should use Effector.remove if they want to become inactive.
+			Err(mpsc::RecvTimeoutError::Disconnected) => {
+				self.restart_component(id, "its thread died");
+				Effector::new()
+			}
+		}
+	}
+	
+	fn generation(&self, id: ComponentID) -> u64
+	{
+		self.supervisors.get(&id).map_or(0, |s| s.generation)
+	}
+	
+	// Panics if the component isn't supervised or has used up its restarts.
+	fn restart_component(&mut self, id: ComponentID, reason: &str)
+	{
+		let restarted = match self.supervisors.get_mut(&id) {
+			Some(ref mut supervisor) if supervisor.restarts < supervisor.max_restarts => {
+				supervisor.restarts += 1;
+				supervisor.generation += 1;
+				true
+			},
+			_ => false,
+		};
+		if !restarted {
+			panic!("Component {} has disconnected from the simulation", self.components.get(id).name);
+		}
+		
+		self.log(LogLevel::Error, id, &format!("restarting because {}", reason));
+		let (txd, rxd) = mpsc::channel::<(Event, SimState)>();
+		let (txe, rxe) = mpsc::channel::<Effector>();
+		self.event_senders[id.0] = Some(txd);
+		self.effector_receivers[id.0] = Some(rxe);
+		
+		let path = self.components.full_path(id);
+		let seed = get_seed(self.provenance.seed, id.0 as usize);
+		let rng = self.component_rng.stream(&path);
+		(self.supervisors[&id].start)(ThreadData::new(id, rxd, txe, seed, rng, self.config.panic_on_unhandled));
+		
+		self.update_stat(&(path + ".restarts"), StatUpdate::Increment(1), id);
+		let time = self.add_secs(0.0);
+		self.schedule(Event::new("init-recover"), id, time);
+	}
+	
+	// Events sent to the same component are processed one after another so the parallelism
+	// is the number of distinct components (capped by the dispatch strategy).
+	fn record_parallelism(&mut self, mut ids: Vec<ComponentID>, limit: usize)
+	{
+		let num_events = ids.len();
+		ids.sort();
+		ids.dedup();
+		let parallelism = min(ids.len(), limit);
+		
+		self.update_stat("simulation.slice-events", StatUpdate::Sample(num_events as f64), NO_COMPONENT);
+		self.update_stat("simulation.parallelism", StatUpdate::Sample(parallelism as f64), NO_COMPONENT);
+		if limit != usize::max_value() {
+			self.update_stat("simulation.utilization", StatUpdate::Sample(parallelism as f64/limit as f64), NO_COMPONENT);
+		}
+	}
+	
+	fn count_gauge_events(&mut self, ids: &Vec<ComponentID>)
+	{
+		if self.tick_events.0 != self.current_time {
+			self.tick_events = (self.current_time, 0);
+		}
+		self.tick_events.1 += ids.len() as i64;
+		
+		for &id in ids.iter() {
+			if self.components.get(id).parent != NO_COMPONENT {
+				let (top, _) = self.components.get_top(id);
+				*self.subtree_events.entry(top).or_insert(0) += 1;
+			}
+		}
+	}
+	
+	// Phases (and events scheduled at the current time) can cause multiple slices to
+	// run at the same time but store keys can only be set once per time so the gauges
+	// are written after the last slice at a time.
+	fn record_gauges(&mut self)
+	{
+		if self.gauges_time == self.current_time {
+			return;
+		}
+		if self.more_slices_at_current_time() {
+			return;
+		}
+		
+		let time = self.current_time;
+		let store = Arc::make_mut(&mut self.store);
+		store.set_int("simulation.events.pending", self.scheduled.len() as i64, time);
+		store.set_int("simulation.events.dispatched", self.event_num as i64, time);
+		store.set_int("simulation.events.tick", self.tick_events.1, time);
+		for (&id, &count) in self.subtree_events.iter() {
+			let key = format!("simulation.subtree-events.{}", self.components.path(id));
+			store.set_int(&key, count, time);
+		}
+		self.gauges_time = time;
+	}
+	
+	// Phases, stepping, and events scheduled at the current time can all cause multiple
+	// slices to run at one time.
+	fn more_slices_at_current_time(&self) -> bool
+	{
+		self.scheduled.peek().map_or(false, |e| e.time == self.current_time)
+	}
+	
+	fn remember_event(&mut self, e: &ScheduledEvent)
+	{
+		if self.recent_events.len() == self.config.emergency_events {
+			self.recent_events.pop_front();
+		}
+		if self.config.emergency_events > 0 {
+			let secs = e.time.to_secs(self.config.time_units);
+			let line = match self.describe_payload(&e.event) {
+				Some(text) => format!("#{} {:.2$}s '{3}' ({4}) to {5}", self.event_num, secs, self.precision, e.event.name, text, self.components.full_path(e.to)),
+				None => format!("#{} {:.2$}s '{3}' to {4}", self.event_num, secs, self.precision, e.event.name, self.components.full_path(e.to)),
+			};
+			self.recent_events.push_back(line);
+		}
+	}
+	
+	// Falls back to the type name for payloads that weren't registered (which is empty
+	// if the payload was assigned directly instead of via set_payload).
+	fn describe_payload(&self, event: &Event) -> Option<String>
+	{
+		event.payload.as_ref().map(|payload| self.payloads.describe(&**payload).unwrap_or_else(|| {
+			if event.payload_type.is_empty() {"unknown payload".to_string()} else {event.payload_type.to_string()}
+		}))
+	}
+	
+	fn trace(&mut self, e: &ScheduledEvent)
+	{
+		let (payload_type, payload_version, payload) = match e.event.payload {
+			Some(ref payload) => match self.payloads.encode(&**payload) {
+				Some((name, version, text)) => (Some(name), Some(version), Some(text)),
+				None => (Some("unregistered".to_string()), None, None),
+			},
+			None => (None, None, None),
+		};
+		let entry = TraceEntry {
+			slice: self.slice_num,
+			time: e.time.0,
+			to: self.components.full_path(e.to),
+			name: e.event.name.to_string(),
+			port: e.event.port_name.to_string(),
+			payload_type,
+			payload_version,
+			payload,
+			description: self.describe_payload(&e.event),
+		};
+		
+		let result = self.tracer.as_mut().unwrap().write(&entry);
+		if let Err(err) = result {
+			self.tracer = None;
+			self.log(LogLevel::Error, NO_COMPONENT, &format!("{}, tracing has been disabled", err));
+		}
+	}
+
+	fn apply_logs(&mut self, id: ComponentID, effects: &Effector)
+	{
+		for record in effects.logs.iter() {
+			match record.path {
+				Some(ref path) => self.log_as(record.level, path.clone(), &record.message),
+				None => self.log(record.level, id, &record.message),
+			}
+		}
+	}
+
+	fn apply_events(&mut self, effects: &mut Effector)
+	{
+		self.scheduled.reserve(effects.events.len());
+		for (to, event, secs) in effects.events.drain(..) {	// we drain because we want to move the event into our list of scheduled events
+			let time = self.add_secs(secs);
+//			let path = self.components.full_path(to);
+//			self.log(LogLevel::Info, NO_COMPONENT, &format!("scheduling {} to {} at {:.3}", event.name, path, secs));
+			self.schedule(event, to, time);
+		}
+	}
+
+	fn apply_requests(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		for (to, mut event, timeout_secs) in effects.requests.drain(..) {
+			let correlation = Correlation{id: self.next_request_id, request: event.name.to_string(), requester: id, kind: CorrelationKind::Request};
+			self.next_request_id += 1;
+			self.pending_requests.insert(correlation.id);
+			
+			let mut timeout = Event::new("timeout");
+			timeout.correlation = Some(Correlation{kind: CorrelationKind::Timeout, ..correlation.clone()});
+			let time = self.add_secs(timeout_secs);
+			self.schedule(timeout, id, time);
+			
+			event.correlation = Some(correlation);
+			let time = self.add_secs(0.0);
+			self.schedule(event, to, time);
+		}
+	}
+
+	fn apply_broadcasts(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		for (pattern, event, secs) in effects.broadcasts.drain(..) {
+			let targets: Vec<ComponentID> = self.components.iter()
+				.map(|(to, _)| to)
+				.filter(|&to| self.event_senders[to.0].is_some() && pattern.matches(self.components.path(to)))
+				.filter(|&to| !self.store.contains(&(self.components.full_path(to) + ".removed")))
+				.collect();
+			let time = self.add_secs(secs);
+			for (i, &to) in targets.iter().enumerate() {
+				if i + 1 == targets.len() {
+					self.schedule(event, to, time);
+					break;
+				}
+				match self.payloads.clone_event(&event) {
+					Ok(copy) => self.schedule(copy, to, time),
+					Err(err) => {
+						self.log(LogLevel::Error, id, &format!("couldn't broadcast to {}: {}", pattern, err));
+						break;
+					},
+				}
+			}
+		}
+	}
+
+	fn apply_stats(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		let path = self.components.full_path(id);
+		for (name, update) in effects.stats.drain(..) {
+			let key = format!("{}.{}", path, name);
+			self.update_stat(&key, update, id);
+		}
+	}
+
+	fn update_stat(&mut self, key: &str, update: StatUpdate, id: ComponentID)
+	{
+		let time = self.current_time.to_secs(self.config.time_units);
+		if let (&StatUpdate::Sample(value), Some(ref mut batches)) = (&update, self.batch_means.as_mut()) {
+			if batches.params.key == key {
+				batches.record(time, value);
+			}
+		}
+		match self.stats.update(key, update, time) {
+			Ok(value) => {let _ = self.pending_stats.insert(key.to_string(), value);},
+			Err(err) => self.log(LogLevel::Error, id, &err),
+		}
+	}
+	
+	// Multiple events may update the same stat within a time slice but the store only
+	// allows a key to be set once per time so stats are written at the end of the slice.
+	// Like record_gauges this is deferred until the last slice at a time (unless the
+	// simulation is exiting).
+	fn flush_stats(&mut self)
+	{
+		if self.exited.is_none() && self.more_slices_at_current_time() {
+			return;
+		}
+		
+		let store = Arc::make_mut(&mut self.store);
+		for (key, value) in mem::replace(&mut self.pending_stats, BTreeMap::new()) {
+			match value {
+				StatValue::Int(value) => store.set_int(&key, value, self.current_time),
+				StatValue::Float(value) => store.set_float(&key, value, self.current_time),
+				StatValue::Summary(count, mean) => {
+					store.set_int(&(key.clone() + ".count"), count, self.current_time);
+					store.set_float(&(key + ".mean"), mean, self.current_time);
+				},
+			}
+		}
+	}
+
+	fn apply_assertions(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		for message in effects.assertions.drain(..) {
+			let path = self.components.full_path(id);
+			self.log(LogLevel::Error, id, &format!("assertion failed: {}", message));
+			if self.config.exit_on_assertion && self.exited.is_none() {
+				self.exited = Some(format!("assertion failed in {}: {}", path, message));
+			}
+			let time = self.current_time.to_secs(self.config.time_units);
+			self.assertion_failures.push(AssertionFailure{path, time, message});
+		}
+	}
+
+	fn apply_unhandled(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		if let Some(name) = effects.unhandled.take() {
+			// process_events! panics on the component's thread when panic_on_unhandled is
+			// set so we'll only get here in that case if unhandled was called directly.
+			if self.config.panic_on_unhandled {
+				panic!("component {} can't handle event {}", self.components.full_path(id), name);
+			}
+			self.log(LogLevel::Warning, id, &format!("dropping unhandled event '{}'", name));
+			self.update_stat("simulation.dead-letters", StatUpdate::Increment(1), id);
+		}
+	}
+
+	fn apply_entities(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		for update in mem::replace(&mut effects.entities, Vec::new()) {
+			match update {
+				EntityUpdate::Created => {
+					self.live_entities += 1;
+					self.update_stat("simulation.entities.created", StatUpdate::Increment(1), id);
+					let live = self.live_entities as f64;
+					self.update_stat("simulation.entities.population", StatUpdate::Set(live), id);
+				},
+				EntityUpdate::Arrived(entity) => {
+					self.leave_location(entity);
+					self.entity_locations.insert(entity, (id, self.current_time));
+					let path = self.components.full_path(id);
+					self.update_stat(&format!("{}.entities.arrivals", path), StatUpdate::Increment(1), id);
+					self.change_population(id, 1);
+				},
+				EntityUpdate::Departed(entity) => {
+					if self.leave_location(entity) {
+						let path = self.components.full_path(id);
+						self.update_stat(&format!("{}.entities.departures", path), StatUpdate::Increment(1), id);
+					}
+				},
+				EntityUpdate::Disposed(entity, created) => {
+					self.leave_location(entity);
+					self.live_entities -= 1;
+					let lifetime = self.current_time.to_secs(self.config.time_units) - created;
+					self.update_stat("simulation.entities.disposed", StatUpdate::Increment(1), id);
+					self.update_stat("simulation.entities.lifetime", StatUpdate::Sample(lifetime), id);
+					let live = self.live_entities as f64;
+					self.update_stat("simulation.entities.population", StatUpdate::Set(live), id);
+				},
+			}
+		}
+	}
+	
+	// Returns false if the entity was in transit (or unknown).
+	fn leave_location(&mut self, entity: u64) -> bool
+	{
+		match self.entity_locations.remove(&entity) {
+			Some((location, arrived)) => {
+				let residence = (self.current_time - arrived).to_secs(self.config.time_units);
+				let path = self.components.full_path(location);
+				self.update_stat(&format!("{}.entities.residence", path), StatUpdate::Sample(residence), location);
+				self.change_population(location, -1);
+				true
+			},
+			None => false,
+		}
+	}
+	
+	fn change_population(&mut self, id: ComponentID, delta: i64)
+	{
+		let population = {
+			let population = self.entity_populations.entry(id).or_insert(0);
+			*population += delta;
+			*population as f64
+		};
+		let path = self.components.full_path(id);
+		self.update_stat(&format!("{}.entities.population", path), StatUpdate::Set(population), id);
+	}
+	
+	// Effects are applied in component order so, when components disagree about a value,
+	// the component with the smallest id wins.
+	fn apply_metadata(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		for (name, update) in effects.metadata.drain(..) {
+			let mesg = match update {
+				MetadataUpdate::Set(value) => {
+					match self.pending_metadata.get(&name) {
+						Some(&PendingMetadata::Set(other, ref old)) if *old != value => Some(format!("metadata '{}' was also set by {} so '{}' was ignored", name, self.components.path(other), value)),
+						Some(&PendingMetadata::Set(..)) => None,
+						Some(&PendingMetadata::Labels(_)) => Some(format!("metadata '{}' is used for labels", name)),
+						None => {
+							self.pending_metadata.insert(name, PendingMetadata::Set(id, value));
+							None
+						},
+					}
+				},
+				MetadataUpdate::Label(label) => {
+					match *self.pending_metadata.entry(name.clone()).or_insert_with(|| PendingMetadata::Labels(BTreeSet::new())) {
+						PendingMetadata::Labels(ref mut labels) => {labels.insert(label); None},
+						PendingMetadata::Set(..) => Some(format!("metadata '{}' is used for a value", name)),
+					}
+				},
+			};
+			if let Some(mesg) = mesg {
+				self.log(LogLevel::Warning, id, &mesg);
+			}
+		}
+	}
+	
+	// Like stats metadata is written at the end of the slice. If phases are being used the
+	// key may have already been set at this time in which case the update is deferred to
+	// the next slice.
+	fn flush_metadata(&mut self)
+	{
+		let time = self.current_time;
+		let mut deferred = BTreeMap::new();
+		for (name, pending) in mem::replace(&mut self.pending_metadata, BTreeMap::new()) {
+			let key = format!("{}{}", METADATA_PREFIX, name);
+			if self.store.was_set_at(&key, time) {
+				deferred.insert(name, pending);
+				continue;
+			}
+			match pending {
+				PendingMetadata::Set(id, value) => {
+					if self.store.list_data.contains_key(&key) {
+						self.log(LogLevel::Warning, id, &format!("metadata '{}' is used for labels", name));
+					} else if self.store.string_data.get(&key).map_or(true, |old| old.1 != value) {
+						Arc::make_mut(&mut self.store).set_string(&key, &value, time);
+						self.log(LogLevel::Info, id, &format!("metadata {} = '{}'", name, value));
+					}
+				},
+				PendingMetadata::Labels(labels) => {
+					if self.store.string_data.contains_key(&key) {
+						self.log(LogLevel::Warning, NO_COMPONENT, &format!("metadata '{}' is used for a value", name));
+						continue;
+					}
+					let mut all: BTreeSet<String> = match self.store.list_data.get(&key) {
+						Some(&(_, StoreValue::StringList(ref old))) => old.iter().cloned().collect(),
+						_ => BTreeSet::new(),
+					};
+					let count = all.len();
+					all.extend(labels);
+					if all.len() > count {
+						let labels: Vec<String> = all.into_iter().collect();
+						let text = list_to_string(&StoreValue::StringList(labels.clone()));
+						self.log(LogLevel::Info, NO_COMPONENT, &format!("metadata {} = {}", name, text));
+						Arc::make_mut(&mut self.store).set_vec_string(&key, &labels, time);
+					}
+				},
+			}
+		}
+		self.pending_metadata = deferred;
+	}
+	
+	fn apply_stores(&mut self, effects: &Effector, id: ComponentID)
+	{
+		self.apply_stores_to(&effects.store, id);
+	}
+
+	fn apply_schema(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		for (name, update) in effects.schema.drain(..) {
+			let key = format!("{}.{}", self.components.full_path(id), name);
+			match update {
+				SchemaUpdate::Declare(schema) => {
+					self.schema.insert(key, schema);
+				},
+				SchemaUpdate::Hint(hint) => {
+					match self.schema.get_mut(&key) {
+						Some(ref mut schema) if !schema.hints.contains(&hint) => schema.hints.push(hint),
+						Some(_) => (),
+						None => self.log(LogLevel::Warning, id, &format!("can't add hint '{}' to undeclared key {}", hint, name)),
+					}
+				},
+			}
+		}
+	}
+	
+	// Returns the keys in source that don't match their declared types.
+	fn check_schema(&mut self, source: &Store, id: ComponentID) -> Vec<String>
+	{
+		let prefix = self.components.full_path(id) + ".";
+		let mut errors = Vec::new();
+		let mut rejected = Vec::new();
+		for (key, schema) in self.schema.range(prefix.clone()..).take_while(|&(key, _)| key.starts_with(&prefix)) {
+			match source.kind_of(&key[prefix.len()..]) {
+				Some(kind) if kind != schema.kind.name() => {
+					errors.push(format!("{} is declared as {} but was written as {}", key, schema.kind, kind));
+					rejected.push(key.clone());
+				},
+				_ => (),
+			}
+		}
+		for err in errors {
+			self.log(LogLevel::Error, id, &err);
+		}
+		rejected
+	}
+
+	fn apply_stores_to(&mut self, source: &Store, id: ComponentID)
+	{
+		let rejected = if self.schema.is_empty() {Vec::new()} else {self.check_schema(source, id)};
+		let path = self.components.full_path(id);
+		let store = Arc::make_mut(&mut self.store);
+
+		for (key, value) in source.int_data.iter() {
+			let key = format!("{}.{}", path, key);
+			if rejected.contains(&key) {
+				continue;
+			}
+			store.set_int(&key, value.1, self.current_time);
+		}
+		
+		for (key, value) in source.float_data.iter() {
+			let key = format!("{}.{}", path, key);
+			if rejected.contains(&key) {
+				continue;
+			}
+			store.set_float(&key, value.1, self.current_time);
+		}
+		
+		for (key, value) in source.string_data.iter() {
+			let key = format!("{}.{}", path, key);
+			if rejected.contains(&key) {
+				continue;
+			}
+			store.set_string(&key, &value.1, self.current_time);
+		}
+		
+		for (key, value) in source.bool_data.iter() {
+			let key = format!("{}.{}", path, key);
+			if rejected.contains(&key) {
+				continue;
+			}
+			store.set_bool(&key, value.1, self.current_time);
+		}
+		
+		for (key, value) in source.list_data.iter() {
+			let key = format!("{}.{}", path, key);
+			if rejected.contains(&key) {
+				continue;
+			}
+			match value.1 {
+				StoreValue::IntList(ref list) => store.set_vec_int(&key, list, self.current_time),
+				StoreValue::FloatList(ref list) => store.set_vec_float(&key, list, self.current_time),
+				StoreValue::StringList(ref list) => store.set_vec_string(&key, list, self.current_time),
+				_ => panic!("{} isn't a list", key),
+			}
+		}
+	}
+
+	fn apply_transactions(&mut self, id: ComponentID, effects: &mut Effector)
+	{
+		for mut transaction in effects.transactions.drain(..) {
+			match self.check_transaction(&transaction) {
+				Ok(_) => {
+					for (target, store) in transaction.stores.iter() {
+						self.apply_stores_to(store, *target);
+					}
+					for (to, event, secs) in transaction.events.drain(..) {
+						let time = self.add_secs(secs);
+						self.schedule(event, to, time);
+					}
+				},
+				Err(reason) => {
+					self.log(LogLevel::Warning, id, &format!("transaction was rejected: {}", reason));
+					if let Some(mut event) = transaction.failure.take() {
+						event.payload = Some(Box::new(reason));
+						let time = self.add_secs(0.0);
+						self.schedule(event, id, time);
+					}
+				}
+			}
+		}
+	}
+	
+	fn check_transaction(&self, transaction: &Transaction) -> Result<(), String>
+	{
+		for &(target, ref store) in transaction.stores.iter() {
+			let path = self.components.full_path(target);
+			if self.store.contains(&(path.clone() + ".removed")) {
+				return Err(format!("{} was removed", path));
+			}
+			
+			// Keys can only be set once per time slice so if a component or an earlier
+			// transaction has already set a key we have to reject the transaction instead
+			// of partially applying it.
+			for key in store.keys() {
+				let key = format!("{}.{}", path, key);
+				if self.store.was_set_at(&key, self.current_time) {
+					return Err(format!("{} has already been set", key));
+				}
+			}
+		}
+		
+		for &(to, ref event, _) in transaction.events.iter() {
+			let path = self.components.full_path(to);
+			if self.store.contains(&(path.clone() + ".removed")) {
+				return Err(format!("{} was removed (can't send {})", path, event.name));
+			}
+		}
+		
+		if !transaction.constraints.is_empty() {
+			let time = self.current_time.to_secs(self.config.time_units);
+			let state = SimState{store: self.store.clone(), components: self.components.clone(), services: self.services.clone(), time};
+			for constraint in transaction.constraints.iter() {
+				constraint(&state)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn log(&mut self, level: LogLevel, id: ComponentID, message: &str)
+	{
+		// Unless lines are being retained for the REST server there's no need to build
+		// the path for records that are filtered out.
+		if self.config.home_path.is_empty() && !self.should_log(level, id) {
+			return;
+		}
+		let path = if id == NO_COMPONENT {"simulation".to_string()} else {self.components.full_path(id)};
+		self.log_as(level, path, message);
+	}
+	
+	fn log_as(&mut self, level: LogLevel, path: String, message: &str)
+	{
+		if self.should_log_path(level, &path) {
+			self.slice_log_count += 1;
+			self.log_writer.write(self.current_time, level, path.clone(), message.to_string());
+		}
+
+		if !self.config.home_path.is_empty() {
+			// Spilling may log an error so this is done before the offset is assigned.
+			if self.config.max_log_lines > 0 && self.log_lines.len() >= self.config.max_log_lines {
+				let old = self.log_lines.pop_front().unwrap();
+				self.spill_log_line(&old);
+			}
+			let time = self.current_time.to_secs(self.config.time_units);
+			let index = level as u8;
+			let message = message.to_string();
+			let line = LogLine{offset: self.next_log_offset, time, path, level, index, message};
+			self.next_log_offset += 1;
+			self.log_lines.push_back(line);
+		}
+	}
+	
+	fn should_log(&self, level: LogLevel, id: ComponentID) -> bool
+	{
+		if !self.runtime_log_levels.is_empty() {
+			let path = if id == NO_COMPONENT {"simulation"} else {self.components.path(id)};
+			return self.should_log_path(level, path);
+		}
+		let name = if id == NO_COMPONENT {"simulation"} else {&self.components.get(id).name};
+		self.should_log_name(level, name)
+	}
+	
+	fn should_log_path(&self, level: LogLevel, path: &str) -> bool
+	{
+		match self.runtime_log_level(path) {
+			Some(clevel) => level <= clevel,
+			None => self.should_log_name(level, path.rsplit('.').next().unwrap()),
+		}
+	}
+	
+	// Levels set with set_log_level apply to subtrees so ancestor paths are checked too.
+	fn runtime_log_level(&self, mut path: &str) -> Option<LogLevel>
+	{
+		if self.runtime_log_levels.is_empty() {
+			return None;
+		}
+		loop {
+			if let Some(&(_, level)) = self.runtime_log_levels.iter().rev().find(|entry| entry.0.matches(path)) {
+				return Some(level);
+			}
+			match path.rfind('.') {
+				Some(i) => path = &path[..i],
+				None => return None,
+			}
+		}
+	}
+	
+	fn should_log_name(&self, level: LogLevel, name: &str) -> bool
+	{
+		if !self.config.log_levels.is_empty() {	// short circuit some work if we have no overrides
+			for (pattern, clevel) in self.config.log_levels.iter() {
+				if pattern.matches(name) {
+					return level <= *clevel
+				}
+			}
+		}
+
+		level <= self.config.log_level
+	}
+	
+	fn add_secs(&mut self, secs: f64) -> Time
+	{
+		assert!(secs >= 0.0);
+		
+		let delta = self.secs_to_ticks(secs);
+		if delta > 0 {
+			Time(self.current_time.0 + delta)
+		} else {
+			Time(self.current_time.0 + 1)	// event dispatch should always take a bit of time so that all the effects at a time can be applied all at once
+		}
+	}
+
+	fn secs_to_ticks(&mut self, secs: f64) -> i64
+	{
+		let exact = secs*self.config.time_units;
+		let ticks = self.config.tick_rounding.to_ticks(exact, self.config.tick_epsilon);
+		self.tick_stats.record(exact, ticks);
+		if self.config.warn_tick_rounding && (ticks as f64) != exact && self.warned_secs.insert(secs.to_bits()) {
+			self.log(LogLevel::Warning, NO_COMPONENT, &format!("{}s isn't a whole number of ticks ({} became {})", secs, exact, ticks));
+		}
+		ticks
+	}
+	
+	fn query_log_lines(&self, query: &LogQuery) -> Vec<&LogLine>
+	{
+		// Offsets are consecutive so we can skip directly to the first line.
+		let start = self.log_lines.front().map_or(0, |front| query.offset.saturating_sub(front.offset) as usize);
+		(start..self.log_lines.len()).map(|i| &self.log_lines[i])
+			.filter(|line| query.level.map_or(true, |level| line.level <= level))
+			.filter(|line| query.path.as_ref().map_or(true, |glob| glob.matches(&line.path)))
+			.take(query.limit)
+			.collect()
+	}
+	
+	// Errors are logged but only the first error until spilling works again is logged
+	// (this also prevents recursion because logging may spill another line).
+	fn spill_log_line(&mut self, line: &LogLine)
+	{
+		if self.config.log_spill_path.is_empty() {
+			return;
+		}
+		let result = match self.log_spill {
+			Some(ref mut file) => {
+				let text = rustc_serialize::json::encode(line).unwrap();
+				writeln!(file, "{}", text).map_err(|err| format!("couldn't write to {}: {}", self.config.log_spill_path, err))
+			},
+			None => match File::create(&self.config.log_spill_path) {
+				Ok(mut file) => {
+					let text = rustc_serialize::json::encode(line).unwrap();
+					let result = writeln!(file, "{}", text).map_err(|err| format!("couldn't write to {}: {}", self.config.log_spill_path, err));
+					self.log_spill = Some(file);
+					result
+				},
+				Err(err) => Err(format!("couldn't create {}: {}", self.config.log_spill_path, err)),
+			},
+		};
+		match result {
+			Ok(()) => self.spill_failed = false,
+			Err(err) => {
+				if !self.spill_failed {
+					self.spill_failed = true;
+					self.log(LogLevel::Error, NO_COMPONENT, &format!("{} (log line {} was dropped)", err, line.offset));
+				}
+			},
+		}
+	}
+	
+	fn get_log_lines(&self, after_time: f64) -> VecDeque<&LogLine>
+	{
+		let mut result = VecDeque::new();
+		
+		for line in self.log_lines.iter().rev() {
+			if line.time > after_time {
+				result.push_front(line);
+			}
+		}
+		
+		result
+	}
+
+	fn create_component_entry(&self, removed: &Vec<String>, id: ComponentID, component: &Component) -> ComponentEntry
+	{		
+		let mut children = Vec::with_capacity(component.children.len());
+		for child_id in component.children.iter() {
+			let child = self.components.get(*child_id);
+			if !removed.contains(&child.name) {
+				let child_entry = self.create_component_entry(removed, *child_id, child);
+				children.push(child_entry);
+			}
+		}
+		
+		let name = component.name.clone();
+		let path = self.components.full_path(id);
+		let key = format!("{}.display-details", path);
+		let details = if self.store.contains(&key) {self.store.get_string(&key)} else {"".to_string()};
+		ComponentEntry{path, name, details, children}
+	}
+
+	fn get_components(&self) -> ComponentEntry
+	{
+		let mut removed = Vec::new();
+		for (key, value) in self.store.int_data.iter() {
+			if key.ends_with(".removed") && value.1 == 1 {
+				let (prefix, _) = key.split_at(key.len() - ".removed".len());
+				removed.push(prefix.to_string());
+			}
+		}
+
+		let (id, root) = self.components.get_root();
+		self.create_component_entry(&removed, id, root)
+	}
+	
+	fn get_state(&self, path: &glob::Pattern) -> Vec<(String, String, String)>
+	{
+		let mut removed = Vec::new();
+		for (key, value) in self.store.int_data.iter() {
+			if key.ends_with(".removed") && value.1 == 1 {
+				let (prefix, _) = key.split_at(key.len() - "removed".len());
+				removed.push(prefix);
+			}
+		}
+
+		let values = self.store.query_with(|key| path.matches(key) && !removed.iter().any(|r| key.starts_with(r)));
+		values.into_iter().map(|(key, value, _)| format_state(key, value)).collect()
+	}
+}
+
+// Formats values the way the REST state endpoints do: (key, value, kind).
+fn format_state(key: String, value: StoreValue) -> (String, String, String)
+{
+	match value {
+		StoreValue::Int(value) => (key, value.to_string(), "int".to_string()),
+		StoreValue::Float(value) => (key, float_to_fixed(value, 6), "float".to_string()),
+		StoreValue::String(value) => (key, value, "string".to_string()),
+		StoreValue::Bool(value) => (key, value.to_string(), "bool".to_string()),
+		list => (key, list_to_string(&list), list.kind().to_string()),
+	}
+}
+
+/// Returned by [`Simulation`]'s finger_prints method.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SliceFingerPrint
+{
+	/// Number of time slices that have been processed (including this one).
+	pub slice: u64,
+	
+	/// The time of the slice (in seconds).
+	pub time: f64,
+	
+	/// The finger print after the slice was processed.
+	pub finger_print: u64,
+}
+
+/// Returned by the /state/changes REST endpoint.
+#[derive(RustcEncodable)]
+pub struct StateChanges
+{
+	/// Pass this into the next request to get the changes after this one.
+	pub edition: u64,
+	
+	/// Set if the changes since the requested edition are no longer available. Clients
+	/// should re-read the store using the /state endpoint when this happens.
+	pub resync: bool,
+
+	/// (key, value, kind) formatted like the /state endpoint.
+	pub changes: Vec<(String, String, String)>,
+	
+	/// Keys that no longer have values (e.g. because they were renamed).
+	pub removed: Vec<String>,
+}
+
+struct Checkpoint
+{
+	store: Store,
+	current_time: Time,
+	exited: Option<String>,
+	scheduled: BinaryHeap<ScheduledEvent>,
+	next_seq: u64,
+	pending_requests: HashSet<u64>,
+	next_request_id: u64,
+	event_num: u64,
+	finger_print: u64,
+	finger_prints: VecDeque<SliceFingerPrint>,
+	next_log_offset: u64,
+	store_deltas: VecDeque<StoreDelta>,
+	stats: Stats,
+	batch_means: Option<BatchMeans>,
+	daemon_times: Vec<Time>,	// daemons are never removed so these line up with Simulation::daemons
+	rng: Option<Box<Rng + Send>>,	// set for branch_with_seed
+}
+
+struct RegisteredPort
+{
+	owner: ComponentID,
+	name: String,
+	is_input: bool,
+	type_id: TypeId,
+	target: ComponentID,	// only used for InPorts
+	target_port: String,	// only used for InPorts
+	connections: Arc<AtomicUsize>,
+	remote: Option<Arc<Mutex<(ComponentID, String)>>>,	// only used for OutPorts
+	wire: Option<(Arc<AtomicUsize>, EventName)>,		// only used for OutPorts
+}
+
+/// Returned by the callbacks passed into [`Simulation`]'s watch method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchAction
+{
+	/// Do nothing (the callback has handled the change itself).
+	Continue,
+	
+	/// Log the change at info level.
+	Log,
+	
+	/// Log the change and pause the simulation (see [`Simulation`]'s pause method).
+	Pause,
+	
+	/// Stop the simulation.
+	Exit,
+}
+
+/// What [`Simulation`] does when an invariant is violated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvariantAction
+{
+	/// Log an error.
+	Log,
+	
+	/// Log an error and stop the simulation.
+	Exit,
+}
+
+/// Used with the [`Simulation`]'s stop_when method.
+pub enum StopCondition
+{
+	/// Stop once the predicate returns true.
+	Predicate(Box<FnMut (&SimState) -> bool + Send>),
+	
+	/// Stop once the int or float store value is at least the value.
+	KeyAtLeast(String, f64),
+	
+	/// Stop once the int or float store value is at most the value.
+	KeyAtMost(String, f64),
+	
+	/// Stop once the named event has been dispatched count times.
+	EventCount(String, u64),
+}
+
+impl StopCondition
+{
+	// Used for the exit reason.
+	fn reason(&self) -> String
+	{
+		match *self {
+			StopCondition::Predicate(_) => "stop predicate returned true".to_string(),
+			StopCondition::KeyAtLeast(ref key, value) => format!("stop condition '{} >= {}' was met", key, float_to_string(value)),
+			StopCondition::KeyAtMost(ref key, value) => format!("stop condition '{} <= {}' was met", key, float_to_string(value)),
+			StopCondition::EventCount(ref name, count) => format!("stop condition '{} {} events' was met", count, name),
+		}
+	}
+}
+
+/// Payload for "delivery-failed" events (see config.notify_undeliverable).
+pub struct UndeliveredEvent
+{
+	/// The component the event was scheduled for.
+	pub to: ComponentID,
+	
+	pub event: Event,
+}
+
+/// Returned by [`Simulation`]'s causal_chain method and the /trace REST endpoint.
+#[derive(Clone, Debug, RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct EventCause
+{
+	/// Events are numbered as they are dispatched.
+	pub num: u64,
+	
+	/// The number of the event whose handler scheduled this event. None for events
+	/// scheduled outside of a component, e.g. init events and events scheduled by
+	/// daemons, rules, or the REST API.
+	pub parent: Option<u64>,
+	pub time: f64,
+	pub name: String,
+	pub path: String,
+}
+
+struct CauseRecord
+{
+	num: u64,
+	parent: Option<u64>,
+	time: Time,
+	name: EventName,
+	to: ComponentID,
+}
+
+struct Watch
+{
+	pattern: glob::Pattern,
+	callback: Option<Box<FnMut (&StoreChange) -> WatchAction + Send>>,	// None for watches added via REST
+}
+
+const METADATA_PREFIX: &str = "simulation.metadata.";
+
+const STORM_KEYS: [&str; 3] = ["simulation.storm.reason", "simulation.storm.top-events", "simulation.storm.top-schedulers"];
+
+enum PendingMetadata
+{
+	Set(ComponentID, String),
+	Labels(BTreeSet<String>),
+}
+
+struct TimeWeightedKeys
+{
+	pattern: glob::Pattern,
+	threshold: f64,
+	trackers: BTreeMap<String, TimeWeighted>,
+}
+
+struct Breakpoint
+{
+	event: glob::Pattern,
+	component: glob::Pattern,
+	hits: usize,
+}
+
+struct Daemon
+{
+	id: ComponentID,
+	interval: i64,	// in ticks
+	next: Time,
+	callback: Box<FnMut (&SimState, &mut Effector) -> () + Send>,
+}
+
+struct Invariant
+{
+	name: String,
+	action: InvariantAction,
+	check: InvariantCheck,
+	violated: bool,		// true if the check failed after the previous time slice
+}
+
+enum InvariantCheck
+{
+	Store(Box<FnMut (&Store) -> bool + Send>),
+	Keys(glob::Pattern, Box<FnMut (&str, &StoreValue) -> bool + Send>),
+}
+
+struct Supervisor
+{
+	start: Box<Fn (ThreadData) -> () + Send>,
+	max_restarts: usize,
+	restarts: usize,
+	generation: u64,	// incremented each time the thread is restarted
+}
+
+struct ScheduledEvent
+{
+	time: Time,
+	to: ComponentID,
+	event: Event,
+	
+	// Events scheduled for the same time are dispatched in the order in which they were
+	// scheduled. Without this the order would depend upon BinaryHeap internals which
+	// could change the order in which a component processes events.
+	seq: u64,
+	
+	// Number of the event whose handler scheduled this event (see Simulation::causal_chain).
+	parent: Option<u64>,
+	
+	// The component that scheduled the event (NO_COMPONENT for init events, injected
+	// events, etc). Used to report event storms.
+	from: ComponentID,
+}
+
+impl PartialEq for ScheduledEvent
+{
+	fn eq(&self, other: &ScheduledEvent) -> bool
+	{
+		self.time.0 == other.time.0 && self.seq == other.seq
+	}
+}
+
+impl Eq for ScheduledEvent {}
+
+impl PartialOrd for ScheduledEvent
+{
+	fn partial_cmp(&self, other: &ScheduledEvent) -> Option<Ordering>
+	{
+		Some(self.cmp(other))
+	}
+}
+
 impl Ord for ScheduledEvent
 {
-	fn cmp(&self, other: &ScheduledEvent) -> Ordering
+	fn cmp(&self, other: &ScheduledEvent) -> Ordering
+	{
+		// reversed (except for priority) because BinaryHeap returns the largest values first
+		other.time.0.cmp(&self.time.0)
+			.then_with(|| self.event.priority.cmp(&other.event.priority))
+			.then_with(|| other.seq.cmp(&self.seq))
+	}
+}
+
+// The seed is resolved once so that time based runs have a single seed that can be
+// recorded (see Provenance) and used to reproduce the run.
+fn resolve_seed(seed: usize) -> usize
+{
+	if seed != 0 {seed} else {max(time::get_time().nsec as usize, 1)}
+}
+
+fn get_seed(seed: usize, offset: usize) -> usize
+{
+	seed + offset	// offset is used to give each thread its own random stream
+}
+
+// We care about speed much more than we care about a cryptographic RNG so
+// StdRng should be plenty good enough.
+fn new_rng(seed: usize, offset: u32) -> StdRng
+{
+	let seed = get_seed(seed, offset as usize);
+	StdRng::from_seed(&[seed])
+}
+
+/// Returned by the /log REST endpoints.
+#[derive(RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct LogLine
+{
+	/// Number of lines logged before this one (including lines that are no longer
+	/// retained), used to page through the log.
+	pub offset: u64,
+	pub time: f64,
+	pub path: String,
+	pub level: LogLevel,
+	pub index: u8,
+	pub message: String,
+}
+
+/// Returned by the /history REST endpoint.
+#[derive(RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct HistoryEntry
+{
+	pub time: f64,
+	pub value: String,
+}
+
+/// Returned by the /watch/hits REST endpoints.
+#[derive(RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct WatchHit
+{
+	pub time: f64,
+	pub pattern: String,
+	pub change: StoreChange,
+}
+
+/// Returned by the /breakpoints REST endpoint.
+#[derive(RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct BreakpointStatus
+{
+	pub event: String,
+	pub component: String,
+	pub hits: usize,
+}
+
+/// Returned by the /breakpoints/hits REST endpoints.
+#[derive(RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct BreakpointHit
+{
+	pub time: f64,
+	pub pattern: String,
+	pub event: String,
+	pub path: String,
+}
+
+/// Returned by the /run/next-event and /run/until-event REST endpoints.
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SteppedEvent
+{
+	pub name: String,
+	pub path: String,
+	pub time: f64,
+}
+
+/// Returned by the /scheduled REST endpoint.
+#[derive(RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct PendingEvent
+{
+	pub time: f64,
+	pub path: String,
+	pub name: String,
+}
+
+/// Returned by the /job REST endpoint.
+#[derive(RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct JobStatus
+{
+	pub id: u64,
+	
+	/// Current simulation time.
+	pub time: f64,
+	
+	/// The time passed to /run/until. For /run/until-event this is the time at which
+	/// max_secs elapses.
+	pub target: f64,
+	
+	/// From 0.0 to 1.0.
+	pub progress: f64,
+	
+	/// Empty if the job is still running. Otherwise "ok" if the target was reached,
+	/// "event" if a /run/until-event job dispatched a matching event, "exited" if the
+	/// simulation exited, "watch" if a watch fired, "breakpoint" if a breakpoint was hit,
+	/// "paused" if /pause was used, or "cancelled" if another job was started.
+	pub result: String,
+	
+	/// The matching event for /run/until-event jobs.
+	pub event: Option<SteppedEvent>,
+}
+
+const MAX_JOBS: usize = 100;
+
+struct RunJob
+{
+	id: u64,
+	start: Time,
+	target: Time,
+	num_hits: usize,		// watch_hits.len() when the job started
+	pattern: Option<glob::Pattern>,	// set for /run/until-event jobs
+	event: Option<SteppedEvent>,
+	result: Option<String>,
+}
+
+impl RunJob
+{
+	fn status(&self, now: Time, time_units: f64) -> JobStatus
+	{
+		let total = (self.target - self.start).0;
+		let progress = if total > 0 {(((now - self.start).0 as f64)/(total as f64)).min(1.0)} else {1.0};
+		JobStatus {
+			id: self.id,
+			time: now.to_secs(time_units),
+			target: self.target.to_secs(time_units),
+			progress: if self.result.as_ref().map_or(false, |r| r == "ok") {1.0} else {progress},
+			result: self.result.clone().unwrap_or_default(),
+			event: self.event.clone(),
+		}
+	}
+}
+
+/// Returned by the /components REST endpoint.
+#[derive(RustcEncodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ComponentEntry
+{
+	pub path: String,
+	pub name: String,
+	pub details: String,
+	pub children: Vec<ComponentEntry>,
+}
+
+// Returns the entries with the largest counts formatted like "'timer' 9000, 'tx' 12".
+// Ties are broken using the name so that the result is deterministic.
+fn top_talkers<I>(counts: I) -> String
+	where I: Iterator<Item = (String, usize)>
+{
+	let mut counts: Vec<(String, usize)> = counts.collect();
+	counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+	counts.iter()
+		.take(5)
+		.map(|&(ref name, count)| format!("{} {}", name, count))
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+fn panic_message(err: &(Any + Send)) -> String
+{
+	if let Some(text) = err.downcast_ref::<&str>() {
+		text.to_string()
+	} else if let Some(text) = err.downcast_ref::<String>() {
+		text.clone()
+	} else {
+		"unknown".to_string()
+	}
+}
+
+fn is_valid_name_char(ch: char) -> bool
+{
+	!ch.is_whitespace() &&		// no spaces makes it much easier for sdebug to parse commands (paths don't need to be quoted)
+	!ch.is_control() &&			// these are just silly to include in a name
+	ch != '"' && ch != '\'' &&	// parsing is simpler if paths don't have quotes
+	ch != '.'					// allowing periods in a name would cause a lot of confusion when looking at paths
+}
+
+// DOT strings are quoted so embedded quotes (and the escape character) need escaping.
+fn dot_escape(text: &str) -> String
+{
+	text.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::sync::Mutex;
+	use std::thread;
+	
+	#[test]
+	fn same_time_events_are_fifo()
+	{
+		let mut heap = BinaryHeap::new();
+		for (seq, &(time, name)) in [(2, "c"), (1, "a"), (2, "d"), (1, "b")].iter().enumerate() {
+			heap.push(ScheduledEvent{time: Time(time), to: ComponentID(0), event: Event::new(name), seq: seq as u64, parent: None, from: NO_COMPONENT});
+		}
+		
+		let names: Vec<EventName> = (0..4).map(|_| heap.pop().unwrap().event.name).collect();
+		assert_eq!(names, vec!["a", "b", "c", "d"]);
+	}
+	
+	#[test]
+	fn stats_can_be_updated_several_times_in_a_slice()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (_, data) = sim.add_active_component("pinger", world);
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					effector.schedule_after_secs(Event::new("ping"), data.id, 1.0);
+					effector.schedule_after_secs(Event::new("ping"), data.id, 1.0);
+				},
+				"ping" => {
+					effector.counter("pings").inc(1);
+					effector.histogram("sizes").record(2.0);
+				}
+			);
+		});
+		sim.run();
+		assert_eq!(sim.store.get_int("world.pinger.pings"), 2);
+		assert_eq!(sim.store.get_int("world.pinger.sizes.count"), 2);
+	}
+	
+	#[test]
+	fn handler_components_run_without_a_thread()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let sim_thread = thread::current().id();
+		let ticker = sim.add_handler_component("ticker", world, move |event, _, effector| {
+			assert_eq!(thread::current().id(), sim_thread, "handlers should run on the simulation thread");
+			if event.name == "tick" {
+				effector.counter("ticks").inc(1);
+			}
+		});
+		for i in 1..4 {
+			sim.schedule(Event::new("tick"), ticker, Time(i));
+		}
+		sim.run();
+		assert_eq!(sim.store.get_int("world.ticker.ticks"), 3);
+		assert!(sim.event_senders[ticker.0].is_some(), "handlers are active components");
+	}
+	
+	#[test]
+	#[should_panic(expected = "world.ticker runs on the simulation thread so it can't be supervised")]
+	fn handler_components_cant_be_supervised()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let ticker = sim.add_handler_component("ticker", world, |_, _, _| ());
+		sim.supervise(ticker, 3, |_| ());
+	}
+	
+	#[test]
+	fn paths_are_cached()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let sender = sim.add_component("sender", world);
+		
+		assert_eq!(sim.components.path(sender), "world.sender");
+		assert_eq!(sim.components.id("world.sender"), Some(sender));
+		assert_eq!(sim.components.id("world.receiver"), None);
+		
+		let stats = sim.components.cache_stats();
+		assert_eq!(stats.id_lookups, 2);
+		assert_eq!(stats.id_misses, 1);
+	}
+	
+	#[test]
+	fn watches_fire_on_changes()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let fired = Arc::new(Mutex::new(Vec::new()));
+		{
+			let fired = fired.clone();
+			sim.watch("*.depth", move |change| {fired.lock().unwrap().push(change.new_value.clone()); WatchAction::Continue}).unwrap();
+		}
+		
+		for (i, &(key, value)) in [("queue.depth", 1), ("queue.drops", 1), ("queue.depth", 1), ("queue.depth", 3)].iter().enumerate() {
+			Arc::make_mut(&mut sim.store).set_int(key, value, Time(i as i64));
+			sim.record_store_delta();
+		}
+		assert_eq!(*fired.lock().unwrap(), vec!["1", "3"]);
+	}
+	
+	#[test]
+	fn emergency_dump_summarizes_state()
+	{
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		sim.schedule(Event::new("timer"), world, Time(5));
+		sim.schedule(Event::new("timer"), world, Time(3));
+		Arc::make_mut(&mut sim.store).set_int("world.count", 7, Time(0));
+		
+		let text = sim.emergency_dump_text("oops");
+		assert!(text.contains("panic: oops\n"), "{}", text);
+		assert!(text.contains("pending events: 2\n   3s 'timer' to world\n   5s 'timer' to world\n   2 x 'timer'\n"), "{}", text);
+		assert!(text.contains("   world.count = 7 @ 0s\n"), "{}", text);
+	}
+	
+	fn new_pingers(dispatch: DispatchStrategy) -> Simulation
+	{
+		let mut config = Config::with_seed(1);
+		config.dispatch = dispatch;
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		for i in 0..4 {
+			let (_, data) = sim.add_active_component(&format!("pinger{}", i), world);
+			thread::spawn(move || {
+				let mut count = 0;
+				process_events!(data, event, state, effector,
+					"init 0" => {
+						effector.schedule_after_secs(Event::new("ping"), data.id, 1.0);
+						effector.schedule_after_secs(Event::new("ping"), data.id, 1.0);
+					},
+					"ping" => {
+						count += 1;
+						effector.counter("pings").inc(1);
+						if count < 10 {
+							effector.schedule_after_secs(Event::new("ping"), data.id, 1.0);
+						}
+					}
+				);
+			});
+		}
+		sim
+	}
+	
+	fn run_pingers(dispatch: DispatchStrategy) -> (u64, i64)
+	{
+		let mut sim = new_pingers(dispatch);
+		let finger_print = sim.run();
+		(finger_print, sim.store.get_int("world.pinger3.pings"))
+	}
+	
+	#[test]
+	fn dispatch_strategies_agree()
+	{
+		let expected = run_pingers(DispatchStrategy::Threaded);
+		assert_eq!(expected.1, 11);
+		assert_eq!(run_pingers(DispatchStrategy::Sequential), expected);
+		assert_eq!(run_pingers(DispatchStrategy::Pooled(3)), expected);
+	}
+	
+	fn run_payloads(payload: &str, config: Config) -> Simulation
+	{
+		let mut sim = Simulation::new(config);
+		sim.register_payload_hash::<String>();
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (_, data) = sim.add_active_component("sender", world);
+		let payload = payload.to_string();
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					effector.schedule_after_secs(Event::with_payload("message", payload.clone()), data.id, 1.0);
+				},
+				"message" => {
+					effector.set_string("message", &event.take_payload::<String>());
+				}
+			);
+		});
+		sim.run();
+		sim
+	}
+	
+	#[test]
+	fn finger_print_contents_are_configurable()
+	{
+		let finger_print = |payload, enable| {
+			let mut config = Config::with_seed(1);
+			config.finger_print_payloads = enable;
+			config.finger_print_store = enable;
+			config.finger_print_logs = enable;
+			config.max_finger_prints = 10;
+			let sim = run_payloads(payload, config);
+			(sim.finger_print, sim.finger_prints().clone())
+		};
+		
+		let (hello, _) = finger_print("hello", false);
+		let (goodbye, _) = finger_print("goodbye", false);
+		assert_eq!(hello, goodbye);	// by default payloads aren't included
+		
+		let (hello, slices) = finger_print("hello", true);
+		let (goodbye, _) = finger_print("goodbye", true);
+		assert_ne!(hello, goodbye);
+		
+		assert_eq!(slices.len(), 2);	// the init and message slices
+		assert_eq!(slices[0].slice, 1);
+		assert_eq!(slices[1].time, 1.0);
+		assert_eq!(slices[1].finger_print, hello);
+	}
+
+	#[test]
+	fn store_finger_prints_include_values()
+	{
+		let finger_print = |value| {
+			let mut config = Config::with_seed(1);
+			config.finger_print_store = true;
+			let mut sim = Simulation::new(config);
+			let world = sim.add_component("world", NO_COMPONENT);
+			let bot = sim.add_handler_component("bot", world, move |event, _, effector| {
+				if event.name == "set" {
+					effector.set_int("value", value);
+				}
+			});
+			sim.schedule(Event::new("set"), bot, Time(1));
+			sim.run();
+			(sim.finger_print, sim.store.edition())
+		};
+
+		let (one, edition1) = finger_print(1);
+		let (two, edition2) = finger_print(2);
+		assert_eq!(edition1, edition2);
+		assert_ne!(one, two);
+	}
+
+	fn run_unhandled(panic_on_unhandled: bool) -> Simulation
+	{
+		let mut config = Config::new();
+		config.panic_on_unhandled = panic_on_unhandled;
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (_, data) = sim.add_active_component("bot", world);
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					effector.schedule_after_secs(Event::new("bogus"), data.id, 1.0);
+					effector.schedule_after_secs(Event::new("bogus"), data.id, 1.0);
+					effector.schedule_after_secs(Event::new("tick"), data.id, 2.0);
+				},
+				"tick" => {
+					effector.set_bool("ticked", true);
+				}
+			);
+		});
+		sim.run();
+		sim
+	}
+	
+	#[test]
+	fn unhandled_events_can_be_dropped()
+	{
+		let sim = run_unhandled(false);
+		assert_eq!(sim.store.get_int("simulation.dead-letters"), 2);
+		assert!(sim.store.get_bool("world.bot.ticked"));
+	}
+	
+	#[test]
+	#[should_panic(expected = "Component bot has disconnected from the simulation")]	// the component's thread panicked
+	fn unhandled_events_panic_by_default()
+	{
+		run_unhandled(true);
+	}
+	
+	#[test]
+	fn simulations_run_concurrently()
+	{
+		let expected = run_pingers(DispatchStrategy::Threaded);
+		let threads: Vec<_> = (0..4).map(|i| {
+			let dispatch = if i % 2 == 0 {DispatchStrategy::Threaded} else {DispatchStrategy::Sequential};
+			thread::spawn(move || run_pingers(dispatch))
+		}).collect();
+		for thread in threads {
+			assert_eq!(thread.join().unwrap(), expected);
+		}
+	}
+	
+	#[test]
+	fn bad_home_path_stops_only_that_run()
+	{
+		let mut config = Config::new();
+		config.home_path = "/no/such/dir/index.html".to_string();
+		config.log_to_stdout = false;
+		let mut sim = Simulation::new(config);
+		sim.add_component("world", NO_COMPONENT);
+		sim.run();
+		
+		let report = sim.report();
+		assert_eq!(report.exit_reason, "config.home_path '/no/such/dir/index.html' is not a file");
+		assert_eq!(run_pingers(DispatchStrategy::Threaded).1, 11);	// the process is still alive
+	}
+	
+	#[test]
+	fn reports_runs()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Threaded);
+		sim.config.report_values = vec![glob::Pattern::new("*.pinger0.pings").unwrap()];
+		let finger_print = sim.run();
+		
+		let report = sim.report();
+		assert_eq!(report.exit_reason, "no events");
+		assert_eq!(report.finger_print, format!("{:X}", finger_print));
+		assert_eq!(report.component_events.len(), 4);
+		assert_eq!(report.component_events[0], ("world.pinger0".to_string(), 12));	// init + 11 pings
+		assert_eq!(report.events, 48);
+		assert_eq!(report.values.len(), 1);
+		assert_eq!((report.values[0].key.as_str(), report.values[0].value.as_str()), ("world.pinger0.pings", "11"));
+		assert!(report.to_json().contains("\"world.pinger0.pings\""));
+		assert!(report.to_html().contains("<td>world.pinger0</td>"));
+	}
+	
+	#[test]
+	fn event_gauges_are_stored()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Threaded);
+		sim.config.event_gauges = true;
+		sim.run();
+		
+		assert_eq!(sim.store.get_int("simulation.events.pending"), 0);
+		assert_eq!(sim.store.get_int("simulation.events.dispatched"), 48);
+		assert_eq!(sim.store.get_int("simulation.events.tick"), 4);
+		assert_eq!(sim.store.get_int("simulation.subtree-events.world.pinger0"), 12);
+		assert_eq!(sim.store.get_int("simulation.subtree-events.world.pinger3"), 12);
+	}
+	
+	#[test]
+	fn records_causes()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Threaded);
+		sim.run();
+		
+		let last = sim.event_num - 1;
+		let chain = sim.causal_chain(last);
+		let names: Vec<&str> = chain.iter().map(|c| c.name.as_ref()).collect();
+		assert_eq!(names, vec!["ping", "ping", "ping", "ping", "ping", "ping", "init 0"]);	// two pings are scheduled at init so the last one is at 6s
+		assert_eq!(chain[0].num, last);
+		assert_eq!(chain[0].parent, Some(chain[1].num));
+		assert_eq!(chain[6].parent, None);
+		assert!(sim.causal_chain(last + 1).is_empty());
+	}
+	
+	#[test]
+	fn reports_event_storms()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Threaded);
+		sim.config.storm_pending = 7;
+		sim.run();
+		
+		assert_eq!(sim.store.get_string("simulation.storm.reason"), "8 events are pending");
+		assert_eq!(sim.store.get_string("simulation.storm.top-events"), "'ping' 8");
+		assert_eq!(sim.store.get_string("simulation.storm.top-schedulers"), "world.pinger0 2, world.pinger1 2, world.pinger2 2, world.pinger3 2");
+	}
+	
+	#[test]
+	fn storms_can_be_reported_in_one_slice()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Threaded);
+		sim.config.storm_pending = 5;
+		sim.config.storm_slice_events = 2;
+		sim.run();
+		
+		assert!(sim.store.get_string("simulation.storm.reason").ends_with(" events are pending"));
+		assert_eq!(sim.store.get_string("simulation.storm.top-events"), "'ping' 8");
+	}
+	
+	#[test]
+	fn steady_state_runs_stop_when_precise()
+	{
+		let mut params = SteadyState::new("world.sampler.latency", 0.05);
+		params.warm_up = 5.0;
+		params.batch_size = 2;
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		config.run_mode = RunMode::SteadyState(params);
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (_, data) = sim.add_active_component("sampler", world);
+		thread::spawn(move || {
+			let mut count = 0;
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					effector.schedule_after_secs(Event::new("sample"), data.id, 1.0);
+				},
+				"sample" => {
+					count += 1;
+					effector.histogram("latency").record(if count % 2 == 0 {1.0} else {3.0});
+					effector.schedule_after_secs(Event::new("sample"), data.id, 1.0);
+				}
+			);
+		});
+		sim.run();
+		
+		// samples start at 5s and every batch has a mean of 2.0
+		assert_eq!(sim.exited, Some("steady-state precision was reached".to_string()));
+		assert_eq!(sim.current_time.to_secs(sim.config.time_units), 24.0);
+		assert_eq!(sim.store.get_float("simulation.steady-state.mean"), 2.0);
+		assert_eq!(sim.store.get_int("simulation.steady-state.batches"), 10);
+	}
+	
+	fn faulty_thread(data: ThreadData)
+	{
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					effector.schedule_after_secs(Event::new("fault"), data.id, 1.0);
+				},
+				"init-recover" => {
+					effector.set_int("recovered", 1);
+				},
+				"fault" => {
+					panic!("deliberate fault");
+				}
+			);
+		});
+	}
+	
+	#[test]
+	fn supervised_components_restart()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (id, data) = sim.add_active_component("worker", world);
+		faulty_thread(data);
+		sim.supervise(id, 1, faulty_thread);
+		sim.run();
+		
+		assert_eq!(sim.store.get_int("world.worker.restarts"), 1);
+		assert_eq!(sim.store.get_int("world.worker.recovered"), 1);
+	}
+	
+	#[test]
+	fn invariants_stop_the_run()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Threaded);
+		sim.add_invariant("pings are bounded", InvariantAction::Exit, |store| store.get_int("world.pinger0.pings") < 5);
+		sim.add_key_invariant("*.pings", InvariantAction::Log, |_, value| *value != StoreValue::Int(4)).unwrap();
+		sim.run();
+		
+		assert_eq!(sim.exited, Some("invariant 'pings are bounded' was violated".to_string()));
+		assert_eq!(sim.store.get_int("world.pinger0.pings"), 6);	// each pinger has two chains
+	}
+	
+	#[test]
+	fn stop_conditions_end_the_run()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Sequential);
+		sim.stop_when(StopCondition::KeyAtLeast("world.pinger1.pings".to_string(), 3.0));
+		sim.run();
+		assert_eq!(sim.exited, Some("stop condition 'world.pinger1.pings >= 3' was met".to_string()));
+		
+		let mut sim = new_pingers(DispatchStrategy::Sequential);
+		sim.stop_when(StopCondition::EventCount("ping".to_string(), 4));
+		sim.run();
+		assert_eq!(sim.exited, Some("stop condition '4 ping events' was met".to_string()));
+		assert!(sim.stop_event_counts["ping"] >= 4);
+		
+		let mut sim = new_pingers(DispatchStrategy::Sequential);
+		sim.run_until(|state| state.time >= 2.0);
+		assert_eq!(sim.exited, Some("stop predicate returned true".to_string()));
+		assert!(sim.current_time.to_secs(sim.config.time_units) >= 2.0);
+	}
+	
+	#[test]
+	fn removed_components_release_their_threads()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (id, data) = sim.add_active_component("short-lived", world);
+		let (tx, rx) = mpsc::channel();
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					effector.schedule_after_secs(Event::new("bye"), data.id, 1.0);
+					effector.schedule_after_secs(Event::new("late"), data.id, 2.0);
+				},
+				"bye" => {
+					effector.remove();
+				}
+			);
+			tx.send(()).unwrap();
+		});
+		sim.run();
+		
+		assert!(sim.event_senders[id.0].is_none());
+		assert_eq!(sim.store.get_int("world.short-lived.removed"), 1);
+		assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok(), "component thread didn't exit");	// and "late" wasn't delivered
+	}
+	
+	#[test]
+	fn undeliverable_events_are_returned()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		config.notify_undeliverable = true;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let peer = sim.add_handler_component("peer", world, |event, _, effector| {
+			if event.name == "init 0" {
+				effector.remove();
+			}
+		});
+		sim.add_handler_component("sender", world, move |mut event, state, effector| {
+			match event.name.as_ref() {
+				"init 0" => effector.schedule_after_secs(Event::new("ping"), peer, 1.0),
+				"delivery-failed" => {
+					let failed = event.take_payload::<UndeliveredEvent>();
+					effector.set_string("failed", &format!("{} to {}", failed.event.name, state.components.path(failed.to)));
+				},
+				_ => (),
+			}
+		});
+		sim.run();
+		
+		assert_eq!(sim.store.get_string("world.sender.failed"), "ping to world.peer");
+	}
+	
+	#[test]
+	fn injects_events()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Threaded);
+		let injector = sim.injector();
+		thread::spawn(move || {
+			assert!(injector.inject_to_path(Event::new("ping"), "world.pinger0", 0.0));
+		}).join().unwrap();
+		sim.run();
+		
+		assert_eq!(sim.store.get_int("world.pinger0.pings"), 12);	// the injected ping starts a third chain
+		assert_eq!(sim.store.get_int("world.pinger1.pings"), 11);
+	}
+	
+	#[test]
+	fn broadcasts_to_matching_components()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		sim.register_payload::<String>();
+		let world = sim.add_component("world", NO_COMPONENT);
+		let rack = sim.add_component("rack3", world);
+		for name in ["server1", "server2", "switch"].iter() {
+			let parent = if *name == "switch" {world} else {rack};
+			let (_, data) = sim.add_active_component(name, parent);
+			thread::spawn(move || {
+				process_events!(data, event, state, effector,
+					"init 0" => {
+						if state.components.get(data.id).name == "server1" {
+							effector.broadcast(Event::with_payload("power-failure", "rack3".to_string()), "world.rack3.*", 1.0);
+						}
+					},
+					"power-failure" => {
+						let cause = event.payload_ref::<String>("should be a String");
+						effector.set_string("failure", cause);
+					}
+				);
+			});
+		}
+		sim.run();
+		
+		assert_eq!(sim.store.get_string("world.rack3.server1.failure"), "rack3");
+		assert_eq!(sim.store.get_string("world.rack3.server2.failure"), "rack3");
+		assert!(!sim.store.contains("world.switch.failure"));
+	}
+	
+	#[test]
+	fn run_jobs_report_progress()
+	{
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		sim.jobs.push_back(RunJob{id: 1, start: Time(10), target: Time(20), num_hits: 0, pattern: None, event: None, result: None});
+		
+		sim.current_time = Time(15);
+		sim.update_job();
+		let status = sim.jobs[0].status(sim.current_time, 1.0);
+		assert_eq!((status.progress, status.result.as_ref()), (0.5, ""));
+		
+		sim.current_time = Time(20);
+		sim.update_job();
+		assert!(!sim.has_active_job());
+		assert_eq!(sim.jobs[0].status(sim.current_time, 1.0).result, "ok");
+		
+		sim.finish_job("paused");	// no effect once the job has finished
+		assert_eq!(sim.jobs[0].result, Some("ok".to_string()));
+	}
+	
+	#[test]
+	fn rounds_secs_into_ticks()
+	{
+		let mut sim = Simulation::new(Config::new());
+		assert_eq!(sim.add_secs(1.005), Time(1004999));
+		
+		sim.config.tick_epsilon = 1.0e-6;
+		assert_eq!(sim.add_secs(1.005), Time(1005000));
+		
+		sim.config.tick_epsilon = 0.0;
+		sim.config.tick_rounding = TickRounding::Round;
+		assert_eq!(sim.add_secs(1.005), Time(1005000));
+		
+		let stats = sim.tick_rounding_stats();
+		assert_eq!((stats.conversions, stats.inexact), (3, 3));
+		assert!(stats.max_error > 0.99 && stats.max_error < 1.0);	// from the Floor conversion
+	}
+	
+	#[test]
+	fn tracks_time_weighted_values()
+	{
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		Arc::make_mut(&mut sim.store).set_int("world.queue.length", 4, Time(0));
+		sim.track_time_weighted("*.length", 3.0).unwrap();
+		
+		for &(time, value) in [(2, 1), (3, 6), (4, 0)].iter() {
+			sim.current_time = Time(time);
+			Arc::make_mut(&mut sim.store).set_int("world.queue.length", value, sim.current_time);
+			sim.record_store_delta();
+		}
+		sim.current_time = Time(8);
+		let summaries = sim.time_weighted_summaries();
+		assert_eq!(summaries.len(), 1);
+		assert_eq!(summaries[0].0, "world.queue.length");
+		assert_eq!(summaries[0].1, TimeWeightedSummary{mean: 15.0/8.0, max: 6.0, above_secs: 3.0});
+	}
+	
+	#[test]
+	fn components_merge_metadata()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let a = sim.add_component("a", world);
+		let b = sim.add_component("b", world);
+		
+		let mut effects = Effector::new();
+		effects.set_metadata("phase", "ramp-up");
+		effects.add_metadata_label("tags", "baseline");
+		sim.apply_effects(a, &mut effects);
+		
+		let mut effects = Effector::new();
+		effects.set_metadata("phase", "steady");	// a has the smaller id so this is ignored
+		effects.add_metadata_label("tags", "alpha");
+		sim.apply_effects(b, &mut effects);
+		sim.record_store_delta();
+		assert_eq!(sim.metadata(), vec![("phase".to_string(), "ramp-up".to_string()), ("tags".to_string(), "[\"alpha\",\"baseline\"]".to_string())]);
+		
+		sim.current_time = Time(1);
+		let mut effects = Effector::new();
+		effects.set_metadata("phase", "steady");
+		effects.add_metadata_label("tags", "baseline");
+		sim.apply_effects(b, &mut effects);
+		sim.record_store_delta();
+		assert_eq!(sim.store.get_string("simulation.metadata.phase"), "steady");
+		assert_eq!(sim.store.get_vec_string("simulation.metadata.tags"), vec!["alpha".to_string(), "baseline".to_string()]);
+		assert_eq!(sim.report().metadata.len(), 2);
+	}
+	
+	#[test]
+	fn entities_are_tracked()
 	{
-		other.time.0.cmp(&self.time.0)	// reversed because BinaryHeap returns the largest values first
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let source = sim.add_component("source", world);
+		let sink = sim.add_component("sink", world);
+		
+		let mut effects = Effector::new();
+		effects.entities.push(EntityUpdate::Created);
+		effects.entities.push(EntityUpdate::Arrived(1));
+		effects.entities.push(EntityUpdate::Created);
+		effects.entities.push(EntityUpdate::Arrived(2));
+		effects.entities.push(EntityUpdate::Departed(1));
+		sim.apply_effects(source, &mut effects);
+		sim.record_store_delta();
+		assert_eq!(sim.store.get_float("world.source.entities.population"), 1.0);
+		assert_eq!(sim.store.get_float("simulation.entities.population"), 2.0);
+		
+		sim.current_time = Time(3);
+		let mut effects = Effector::new();
+		effects.entities.push(EntityUpdate::Arrived(1));
+		effects.entities.push(EntityUpdate::Disposed(1, 0.0));
+		sim.apply_effects(sink, &mut effects);
+		sim.record_store_delta();
+		assert_eq!(sim.store.get_int("world.source.entities.arrivals"), 2);
+		assert_eq!(sim.store.get_int("world.source.entities.departures"), 1);
+		assert_eq!(sim.store.get_int("world.sink.entities.arrivals"), 1);
+		assert_eq!(sim.store.get_float("world.sink.entities.population"), 0.0);
+		assert_eq!(sim.store.get_float("world.sink.entities.residence.mean"), 0.0);
+		assert_eq!(sim.store.get_float("simulation.entities.lifetime.mean"), 3.0);
+		assert_eq!(sim.store.get_int("simulation.entities.disposed"), 1);
+		assert_eq!(sim.store.get_float("simulation.entities.population"), 1.0);
 	}
-}
 
-fn end_escape() -> &'static str
-{
-	"\x1b[0m"
-}
-
-fn get_seed(seed: usize, offset: usize) -> usize
-{
-	let seed = if seed != 0 {seed} else {time::get_time().nsec as usize};
-	seed + offset	// offset is used to give each thread its own random stream
-}
+	#[test]
+	fn entity_ids_are_unique()
+	{
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (source, _) = sim.add_active_component("source", world);
+		let (sink, _) = sim.add_active_component("sink", world);
 
-// We care about speed much more than we care about a cryptographic RNG so
-// StdRng should be plenty good enough.
-fn new_rng(seed: usize, offset: u32) -> StdRng
-{
-	let seed = get_seed(seed, offset as usize);
-	StdRng::from_seed(&[seed])
-}
+		let input: InPort<Entity> = InPort::new(sink);
+		let received = Arc::new(Mutex::new(Vec::new()));
+		let ids = received.clone();
+		sim.set_handler(sink, Box::new(move |mut event, state, effector| {
+			if event.name == "entity" {
+				let entity = input.receive_entity(&mut event, state, effector);
+				ids.lock().unwrap().push(entity.id);
+				effector.dispose_entity(entity);
+			}
+		}));
 
-fn no_op_thread(rx: mpsc::Receiver<(Event, SimState)>, tx: mpsc::Sender<Effector>)
-{
-	thread::spawn(move || {
-		for dispatched in rx {
-			// We drop all events but we still need to tell the Simulator that we haven't actually done anything.
-			drop(dispatched);
-			let _ = tx.send(Effector::new());
+		let mut output: OutPort<Entity> = OutPort::new();
+		output.connect_to(&InPort::new(sink));
+		sim.set_handler(source, Box::new(move |event, state, effector| {
+			if event.name == "create" {
+				// A fresh source each time is what a restarted component would see.
+				let mut entities = EntitySource::new(source);
+				for _ in 0..2 {
+					let entity = entities.create(state, effector, 1.0);
+					output.send_entity(effector, "entity", entity);
+				}
+			}
+		}));
+		for i in 0..2 {
+			sim.schedule(Event::new("create"), source, Time(i));
 		}
-	});
-}
-
-enum RestCommand
-{
-	GetComponents,
-	GetLog,
-	GetLogAfter(f64),
-	GetState(glob::Pattern),
-	GetExited,
-	GetTime,
-	GetTimePrecision,
-	RunOnce,
-	SetFloatState(String, f64),
-	SetIntState(String, i64),
-	SetStringState(String, String),
-	SetTime(f64),
-}
-
-struct RestReply
-{
-	data: String,
-	code: u16,
-}
-
-#[derive(RustcEncodable)]
-struct LogLine
-{
-	time: f64,
-	path: String,
-	level: LogLevel,
-	index: u8,
-	message: String,
-}
-
-#[derive(RustcEncodable)]
-struct ComponentEntry
-{
-	path: String,
-	name: String,
-	details: String,
-	children: Vec<ComponentEntry>,
-}
+		sim.run();
 
-fn file_response(request: &rouille::Request, path: &Path) -> rouille::Response
-{
-	match File::open(&path) {
-		Ok(file) => rouille::Response::from_file("text/html; charset=utf8", file),
-		Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
-			eprintln!("Didn't find file for {} {}", request.method(), request.url());
-			rouille::Response::empty_404()
-		},
-		Err(ref err) => {
-			let mesg: &str = &format!("{:?}", err.kind());
-			eprintln!("Error reading file for {} {}: {}", request.method(), request.url(), mesg);
-			rouille::Response::text(mesg).with_status_code(403)
-		},
+		let mut ids = received.lock().unwrap().clone();
+		assert_eq!(ids.len(), 4);
+		ids.sort();
+		ids.dedup();
+		assert_eq!(ids.len(), 4);
+		assert_eq!(sim.store.get_int("world.source.entities.departures"), 4);
+		assert_eq!(sim.store.get_int("world.sink.entities.arrivals"), 4);
+		assert_eq!(sim.store.get_int("simulation.entities.disposed"), 4);
 	}
-}
 
-// For debugging can do stuff like:
-//    curl http://127.0.0.1:9000/log/all
-//    curl -X POST http://127.0.0.1:9000/time/10
-fn spin_up_rest(address: &str, home_path: &str, tx_command: mpsc::Sender<RestCommand>, rx_reply: mpsc::Receiver<RestReply>)
-{
-	let addr = address.to_string();
-	let home_path = home_path.to_string();
+	#[test]
+	fn breakpoints_match_events()
+	{
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		config.max_breakpoint_hits = 2;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let a3 = sim.add_handler_component("A3", world, |_, _, _| ());
+		let a4 = sim.add_handler_component("A4", world, |_, _, _| ());
+		sim.break_on("lost-*", "world.A3").unwrap();
+		sim.break_on("*", "world.A3").unwrap();
+		assert!(sim.break_on("[", "*").is_err());
+		sim.running = true;
+		
+		sim.schedule(Event::new("lost-attack"), a4, Time(1));
+		sim.schedule(Event::new("lost-attack"), a3, Time(2));
+		sim.schedule(Event::new("lost-defense"), a3, Time(3));
+		sim.run_time_slice();
+		assert!(!sim.break_hit);
+		assert_eq!(sim.current_time, Time(1));
+		
+		sim.run_time_slice();
+		assert!(sim.break_hit);
+		assert_eq!(sim.current_time, Time(1));
+		assert_eq!(sim.scheduled.len(), 2);
+		
+		sim.break_hit = false;
+		sim.run_time_slice();	// resuming dispatches the slice
+		assert!(!sim.break_hit);
+		assert_eq!(sim.current_time, Time(2));
+		assert_eq!(sim.breakpoints[0].hits, 1);
+		assert_eq!(sim.breakpoint_hits.len(), 2);
+		assert_eq!((sim.breakpoint_hits[0].event.as_str(), sim.breakpoint_hits[0].path.as_str()), ("lost-attack", "world.A3"));
+		
+		sim.run_time_slice();
+		assert!(sim.break_hit);
+		assert_eq!(sim.breakpoints[0].hits, 2);
+		assert_eq!(sim.breakpoint_hits.len(), 2);	// capped by max_breakpoint_hits
+		assert_eq!(sim.breakpoint_hits[0].event, "lost-defense");
+		
+		sim.clear_breakpoints();
+		assert!(sim.breakpoint_hits.is_empty());
+	}
+	
+	#[test]
+	fn clock_errors_exit()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let a = sim.add_handler_component("a", world, |_, _, _| ());
+		sim.set_clock(Box::new(|_| ClockGrant::Error("clock reply failed: reset".to_string())));
+		sim.schedule(Event::new("ping"), a, Time(1));
+		sim.run_time_slice();
+		assert_eq!(sim.exited, Some("the external clock failed".to_string()));
+		assert_eq!(sim.scheduled.len(), 1);
+	}
+	
+	#[test]
+	fn stepping_dispatches_one_event()
+	{
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let a = sim.add_handler_component("a", world, |_, _, effector| effector.counter("events").inc(1));
+		let b = sim.add_handler_component("b", world, |_, _, effector| effector.counter("events").inc(1));
+		sim.schedule(Event::new("ping"), a, Time(1));
+		sim.schedule(Event::new("pong"), b, Time(1));
+		sim.schedule(Event::new("ping"), b, Time(2));
+		
+		let pending: Vec<(Time, ComponentID, &str)> = sim.scheduled_events().collect();
+		assert_eq!(pending, vec![(Time(1), a, "ping"), (Time(1), b, "pong"), (Time(2), b, "ping")]);
+		
+		assert_eq!(sim.step_event(), Some(SteppedEvent{name: "ping".to_string(), path: "world.a".to_string(), time: 1.0}));
+		assert!(!sim.store.contains("world.b.events"));
+		
+		let pattern = glob::Pattern::new("po*").unwrap();
+		sim.start_job(Time(i64::MAX), Some(pattern.clone()));
+		let status = run_job(&mut sim);
+		assert_eq!((status.result.as_str(), status.event), ("event", Some(SteppedEvent{name: "pong".to_string(), path: "world.b".to_string(), time: 1.0})));
+		assert_eq!(sim.store.get_int("world.b.events"), 1);
+		
+		sim.start_job(Time(i64::MAX), Some(pattern));
+		let status = run_job(&mut sim);
+		assert_eq!((status.result.as_str(), status.event), ("exited", None));
+		assert_eq!(sim.store.get_int("world.b.events"), 2);
+	}
+	
+	// Does what the REST loop does while a job is active.
+	fn run_job(sim: &mut Simulation) -> JobStatus
+	{
+		while sim.has_active_job() {
+			sim.run_job_slice();
+			sim.update_job();
+		}
+		sim.jobs.back().unwrap().status(sim.current_time, sim.config.time_units)
+	}
+	
+	#[test]
+	fn event_jobs_are_bounded_and_can_be_paused()
+	{
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let ticker = sim.add_handler_component("ticker", world, |_, state, effector| {
+			let id = state.components.id("world.ticker").unwrap();
+			effector.schedule_after_secs(Event::new("tick"), id, 1.0);
+		});
+		sim.schedule(Event::new("tick"), ticker, Time(1));
+		
+		// Without max_secs this would never finish.
+		let pattern = glob::Pattern::new("never").unwrap();
+		let id = sim.start_job(Time(sim.current_time.0 + 5), Some(pattern.clone()));
+		let status = run_job(&mut sim);
+		assert_eq!((status.id, status.result.as_str(), status.event), (id, "ok", None));
+		assert_eq!(sim.current_time, Time(5));
+		
+		sim.start_job(Time(i64::MAX), Some(pattern));
+		for _ in 0..3 {
+			sim.run_job_slice();
+			sim.update_job();
+		}
+		sim.finish_job("paused");	// what /pause does
+		assert!(!sim.has_active_job());
+		assert_eq!(sim.current_time, Time(8));
+	}
+	
+	#[test]
+	fn stepping_same_time_events_updates_stats()
+	{
+		let mut config = Config::new();
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let a = sim.add_handler_component("a", world, |_, _, effector| effector.counter("events").inc(1));
+		sim.schedule(Event::new("ping"), a, Time(1));
+		sim.schedule(Event::new("pong"), a, Time(1));
+		
+		assert_eq!(sim.step_event().unwrap().name, "ping");
+		assert_eq!(sim.step_event().unwrap().name, "pong");
+		assert_eq!(sim.store.get_int("world.a.events"), 2);
+	}
+	
+	#[test]
+	fn log_levels_apply_to_subtrees()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let bots = sim.add_component("bots", world);
+		let bot = sim.add_component("bot1", bots);
+		let other = sim.add_component("other", world);
+		assert!(!sim.should_log(LogLevel::Excessive, bot));
+		
+		sim.set_log_level("world.bots", LogLevel::Excessive).unwrap();
+		assert!(sim.should_log(LogLevel::Excessive, bots));
+		assert!(sim.should_log(LogLevel::Excessive, bot));
+		assert!(!sim.should_log(LogLevel::Excessive, other));
+		assert!(!sim.should_log(LogLevel::Excessive, NO_COMPONENT));
+		
+		sim.set_log_level("*.bot1", LogLevel::Error).unwrap();
+		assert!(!sim.should_log(LogLevel::Info, bot));
+		assert!(sim.should_log(LogLevel::Excessive, bots));
+		assert!(sim.set_log_level("[", LogLevel::Info).is_err());
+	}
+	
+	#[derive(Clone, RustcEncodable, RustcDecodable)]
+	struct Reading
+	{
+		value: i64,
+	}
+	
+	impl Payload for Reading
+	{
+		fn payload_name() -> &'static str
+		{
+			"reading"
+		}
+		
+		fn payload_version() -> u32
+		{
+			2
+		}
+	}
+	
+	#[test]
+	fn describes_payloads()
+	{
+		let mut sim = Simulation::new(Config::new());
+		sim.register_payload_type::<Reading>();
+		
+		let event = Event::with_payload("sample", Reading{value: 3});
+		assert_eq!(sim.describe_payload(&event), Some("{\"value\":3}".to_string()));
+		let encoded = sim.payloads.encode(&**event.payload.as_ref().unwrap()).unwrap();
+		assert_eq!((encoded.0.as_str(), encoded.1), ("reading", 2));
+		
+		let event = Event::with_port_payload("count", "input", 10u8);
+		assert_eq!(sim.describe_payload(&event), Some("u8".to_string()));
+		assert_eq!(sim.describe_payload(&Event::new("tick")), None);
+		
+		let mut event = Event::new("count");
+		event.set_payload(7i64);
+		assert_eq!(sim.describe_payload(&event), Some("i64".to_string()));
+		
+		let mut event = Event::new("count");
+		event.payload = Some(Box::new(8u8));
+		assert_eq!(sim.describe_payload(&event), Some("unknown payload".to_string()));
+	}
+	
+	#[test]
+	fn parallelism_is_recorded()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Pooled(8));
+		sim.config.record_parallelism = true;
+		sim.run();
+		
+		assert!(sim.store.get_float("simulation.slice-events.mean") > 4.0);	// each pinger has two chains
+		assert_eq!(sim.store.get_float("simulation.parallelism.mean"), 4.0);
+		assert_eq!(sim.store.get_float("simulation.utilization.mean"), 0.5);
+	}
+	
+	#[test]
+	fn priorities_order_simultaneous_events()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (tx, rx) = mpsc::channel();
+		let link = sim.add_handler_component("link", world, move |event, _, _| tx.send(event.name).unwrap());
+		sim.schedule(Event::new("data1"), link, Time(1));
+		sim.schedule(Event::new("link-down").with_priority(10), link, Time(1));
+		sim.schedule(Event::new("data2"), link, Time(1));
+		sim.schedule(Event::new("early"), link, Time(0));
+		
+		let pending: Vec<&str> = sim.scheduled_events().map(|e| e.2).collect();
+		assert_eq!(pending, vec!["early", "link-down", "data1", "data2"]);
+		
+		while sim.exited.is_none() {
+			sim.run_time_slice();
+		}
+		let names: Vec<EventName> = rx.try_iter().collect();
+		assert_eq!(names, vec!["early", "link-down", "data1", "data2"]);
+	}
+	
+	#[test]
+	fn assertions_are_reported()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let bot = sim.add_handler_component("bot", world, |event, _, effector| {
+			if event.name == "move" {
+				let energy = -5;
+				sim_assert!(effector, energy >= 0, "energy is {}", energy);
+			}
+		});
+		sim.schedule(Event::new("move"), bot, Time(2));
+		sim.schedule(Event::new("move"), bot, Time(4));
+		sim.run();
+		
+		assert_eq!(sim.exited, Some("assertion failed in world.bot: energy is -5".to_string()));
+		assert_eq!(sim.assertion_failures().len(), 1);
+		assert_eq!(sim.report().assertion_failures[0], AssertionFailure{path: "world.bot".to_string(), time: sim.assertion_failures()[0].time, message: "energy is -5".to_string()});
+		
+		let mut effector = Effector::new();
+		assert!(sim_assert!(effector, 1 > 0));
+		assert!(!sim_assert!(effector, 1 < 0));
+		assert_eq!(effector.assertions, vec!["sim_assert!(1 < 0) failed".to_string()]);
+	}
+	
+	#[test]
+	fn log_lines_are_bounded_and_paged()
+	{
+		let spill = ::std::env::temp_dir().join(format!("score-log-spill-{}.json", ::std::process::id()));
+		let mut config = Config::new();
+		config.home_path = "unused".to_string();
+		config.max_log_lines = 3;
+		config.log_spill_path = spill.to_str().unwrap().to_string();
+		config.log_level = LogLevel::Error;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let bot = sim.add_component("bot", world);
+		for i in 0..5 {
+			let id = if i % 2 == 0 {world} else {bot};
+			let level = if i == 4 {LogLevel::Warning} else {LogLevel::Info};
+			sim.log(level, id, &format!("line {}", i));
+		}
+		
+		let messages = |query: LogQuery| -> Vec<String> {sim.query_log_lines(&query).iter().map(|l| l.message.clone()).collect()};
+		assert_eq!(messages(LogQuery::all()), vec!["line 2", "line 3", "line 4"]);
+		assert_eq!(messages(LogQuery{offset: 3, ..LogQuery::all()}), vec!["line 3", "line 4"]);
+		assert_eq!(messages(LogQuery{limit: 1, ..LogQuery::all()}), vec!["line 2"]);
+		assert_eq!(messages(LogQuery{level: Some(LogLevel::Warning), ..LogQuery::all()}), vec!["line 4"]);
+		assert_eq!(messages(LogQuery{path: Some(glob::Pattern::new("*.bot").unwrap()), ..LogQuery::all()}), vec!["line 3"]);
+		
+		drop(sim);
+		let mut text = String::new();
+		File::open(&spill).unwrap().read_to_string(&mut text).unwrap();
+		assert_eq!(text.lines().count(), 2);
+		assert!(text.starts_with("{\"offset\":0,"), "{}", text);
+		let _ = ::std::fs::remove_file(&spill);
+	}
+	
+	#[test]
+	fn log_spill_errors_are_logged()
+	{
+		let mut config = Config::new();
+		config.home_path = "unused".to_string();
+		config.max_log_lines = 2;
+		config.log_spill_path = ::std::env::temp_dir().join(format!("score-missing-{}/spill.json", ::std::process::id())).to_str().unwrap().to_string();
+		config.log_to_stdout = false;
+		let mut sim = Simulation::new(config);
+		for i in 0..4 {
+			sim.log(LogLevel::Info, NO_COMPONENT, &format!("line {}", i));
+		}
+		
+		let errors: Vec<&LogLine> = sim.log_lines.iter().filter(|l| l.level == LogLevel::Error).collect();
+		assert_eq!(errors.len(), 1);
+		assert!(errors[0].message.starts_with("couldn't create "), "{}", errors[0].message);
+		assert!(sim.spill_failed);
+		assert!(!sim.config.log_spill_path.is_empty());	// later lines are still spilled if the problem is fixed
+		let offsets: Vec<u64> = sim.log_lines.iter().map(|l| l.offset).collect();
+		assert!(offsets.windows(2).all(|w| w[1] == w[0] + 1), "{:?}", offsets);
+	}
+	
+	#[test]
+	fn writes_are_checked_against_the_schema()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let bot = sim.add_component("bot", world);
+		
+		let mut effects = Effector::new();
+		effects.declare_float("energy", "J", "remaining battery");
+		effects.add_display_hint("energy", "gauge");
+		effects.declare_int("hits", "", "number of times the bot was hit");
+		effects.declare_list_float("samples", "m", "recent range readings");
+		effects.set_float("energy", 100.0);
+		effects.set_float("hits", 1.0);
+		effects.set_vec_float("samples", &[1.0, 2.0]);
+		sim.apply_effects(bot, &mut effects);
+		
+		assert_eq!(sim.store.get_float("world.bot.energy"), 100.0);
+		assert!(!sim.store.contains("world.bot.hits"));
+		assert_eq!(sim.store.get_vec_float("world.bot.samples"), vec![1.0, 2.0]);
+		
+		let mut effects = Effector::new();
+		effects.set_vec_int("samples", &[3]);
+		sim.apply_effects(bot, &mut effects);
+		assert_eq!(sim.store.get_vec_float("world.bot.samples"), vec![1.0, 2.0]);
+		
+		let schema = &sim.schema()["world.bot.energy"];
+		assert_eq!(schema.kind, StoreKind::Float);
+		assert_eq!(schema.units, "J");
+		assert_eq!(schema.hints, vec!["gauge".to_string()]);
+		assert_eq!(sim.schema()["world.bot.samples"].kind, StoreKind::FloatList);
+		
+		let json = rustc_serialize::json::encode(&sim.schema()["world.bot.samples"]).unwrap();
+		assert!(json.contains("\"kind\":\"float-list\""), "{}", json);
+	}
 	
-	// rouille will spawn up a thread for each client that attaches and there's no good
-	// way to clone the channels into them so we need to use a mutex to serialize access.
-	let tx_command = Mutex::new(tx_command);
-	let rx_reply = Mutex::new(rx_reply);
+	#[test]
+	fn requests_are_replied_to_or_time_out()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let mut slow = None;
+		let server = sim.add_handler_component("server", world, move |event, _, effector| {
+			match event.name.as_str() {
+				"ping" => effector.reply(&event, Event::new("pong")),
+				"slow" => slow = Some(event),
+				"poke" => effector.reply(slow.as_ref().unwrap(), Event::new("late")),
+				_ => (),
+			}
+		});
+		let (tx, rx) = mpsc::channel();
+		let client = sim.add_handler_component("client", world, move |event, _, effector| {
+			match event.name.as_str() {
+				"init 0" => {
+					effector.request(Event::new("ping"), server, 1.0);
+					effector.request(Event::new("slow"), server, 1.0);
+				},
+				"timeout" => effector.schedule_immediately(Event::new("poke"), server),
+				_ => (),
+			}
+			if let Some(c) = event.correlation {
+				tx.send(format!("{} {} {}", event.name, c.request, c.id)).unwrap();
+			}
+		});
+		sim.schedule(Event::new("init 0"), client, Time(0));
+		
+		while sim.exited.is_none() {
+			sim.run_time_slice();
+		}
+		let events: Vec<String> = rx.try_iter().collect();
+		assert_eq!(events, vec!["pong ping 1", "timeout slow 2"]);
+	}
 
-	thread::spawn(move|| {rouille::start_server(&addr, move |request| {
-		let path = Path::new(&home_path);
-		let root_dir = path.parent().unwrap();
+	#[test]
+	fn forwarded_replies_reach_the_requester()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (relay, _) = sim.add_active_component("relay", world);
+		let server = sim.add_handler_component("server", world, move |event, _, effector| {
+			if event.name == "ping" {
+				// Like a reply that goes back through a Link.
+				let mut pong = Event::new("pong");
+				pong.correlation = Some(Correlation{kind: CorrelationKind::Reply, ..event.correlation.unwrap()});
+				effector.schedule_immediately(pong, relay);
+			}
+		});
+		sim.set_handler(relay, Box::new(|mut event, _, effector| {
+			if let Some(requester) = event.correlation.as_ref().map(|c| c.requester) {
+				let forwarded = Event{name: event.name.clone(), port_name: event.port_name.clone(), payload: None, payload_type: "", priority: 0, correlation: event.correlation.take()};
+				effector.schedule_immediately(forwarded, requester);
+			}
+		}));
+		let (tx, rx) = mpsc::channel();
+		let client = sim.add_handler_component("client", world, move |event, _, effector| {
+			if event.name == "init 0" {
+				effector.request(Event::new("ping"), server, 1.0);
+			}
+			if let Some(c) = event.correlation {
+				tx.send(format!("{} {}", event.name, c.request)).unwrap();
+			}
+		});
+		sim.schedule(Event::new("init 0"), client, Time(0));
 
-//		println!("{} {}", request.method(), request.url());
-		router!(request,
-			(GET) (/) => {
-				file_response(&request, path)
-			},
-			// In theory REST endpoints can conflict with file names within root_dir but none of
-			// the REST endpoints have an extension so this shouldn't be a problem in practice.
-			(GET) (/components) => {
-				handle_endpoint(RestCommand::GetComponents, &tx_command, &rx_reply)
-			},
-			(GET) (/exited) => {
-				handle_endpoint(RestCommand::GetExited, &tx_command, &rx_reply)
-			},
-			(GET) (/log) => {
-				handle_endpoint(RestCommand::GetLog, &tx_command, &rx_reply)
-			},
-			(GET) (/log/after/{time: f64}) => {
-				handle_endpoint(RestCommand::GetLogAfter(time), &tx_command, &rx_reply)
-			},
-			(POST) (/run/once) => {
-				handle_endpoint(RestCommand::RunOnce, &tx_command, &rx_reply)
-			},
-			(POST) (/run/until/{secs: f64}) => {
-				handle_endpoint(RestCommand::SetTime(secs), &tx_command, &rx_reply)
-			},			
-			// These really should be PUTs but crest doesn't support PUT...
-			(POST) (/state/float/{path: String}/{value: f64}) => {
-				handle_endpoint(RestCommand::SetFloatState(path, value), &tx_command, &rx_reply)
-			},
-			(POST) (/state/int/{path: String}/{value: i64}) => {
-				handle_endpoint(RestCommand::SetIntState(path, value), &tx_command, &rx_reply)
-			},
-			(GET) (/state/{path: String}) => {
-				if let Ok(path) = glob::Pattern::new(&path) {
-					handle_endpoint(RestCommand::GetState(path), &tx_command, &rx_reply)
-				} else {
-					rouille::Response::empty_400()
+		while sim.exited.is_none() {
+			sim.run_time_slice();
+		}
+		let events: Vec<String> = rx.try_iter().collect();
+		assert_eq!(events, vec!["pong ping"]);
+	}
+	
+	// Runs until 3s and then exits.
+	struct ScriptedBackend
+	{
+		replies: Arc<Mutex<Vec<String>>>,
+		thread: Option<thread::JoinHandle<()>>,
+	}
+	
+	impl ControlBackend for ScriptedBackend
+	{
+		fn start(&mut self, commands: mpsc::Sender<(u64, RestCommand)>, replies: mpsc::Receiver<(u64, RestReply)>) -> Result<(), String>
+		{
+			let results = self.replies.clone();
+			self.thread = Some(thread::spawn(move || {
+				let mut seq = 0;
+				let mut send = |command| {
+					seq += 1;
+					commands.send((seq, command)).unwrap();
+					let (s, reply) = replies.recv().unwrap();
+					assert_eq!(s, seq);
+					reply.data
+				};
+				let job = send(RestCommand::SetTime(3.0));
+				let mut status = send(RestCommand::GetJob(1));
+				while status.contains("\"result\":\"\"") {	// the sim advances between commands
+					status = send(RestCommand::GetJob(1));
 				}
-			},
-			(POST) (/state/string/{path: String}/{value: String}) => {
-				handle_endpoint(RestCommand::SetStringState(path, value), &tx_command, &rx_reply)
-			},
-			(GET) (/time) => {
-				handle_endpoint(RestCommand::GetTime, &tx_command, &rx_reply)
-			},
-			(GET) (/time/precision) => {
-				handle_endpoint(RestCommand::GetTimePrecision, &tx_command, &rx_reply)
-			},
-			_ => {
-				let response = rouille::match_assets(&request, &root_dir);
-				if !response.is_success() {
-					eprintln!("Failed to read file for {} {}", request.method(), request.url());
+				let time = send(RestCommand::GetTime);
+				send(RestCommand::Exit);
+				results.lock().unwrap().extend(vec![job, status, time]);
+			}));
+			Ok(())
+		}
+		
+		fn stop(&mut self)
+		{
+			self.thread.take().unwrap().join().unwrap();
+		}
+	}
+	
+	#[test]
+	fn control_backends_drive_the_sim()
+	{
+		let mut sim = new_pingers(DispatchStrategy::Threaded);
+		let replies = Arc::new(Mutex::new(Vec::new()));
+		sim.set_control_backend(Box::new(ScriptedBackend{replies: replies.clone(), thread: None}));
+		sim.run();
+		
+		let replies = replies.lock().unwrap();
+		assert_eq!(replies[0], "1");
+		assert!(replies[1].contains("\"result\":\"ok\""), "{}", replies[1]);
+		assert_eq!(replies[2], "3.0");
+		assert_eq!(sim.store.get_int("world.pinger0.pings"), 6);
+	}
+	
+	#[test]
+	fn validate_reports_unconnected_ports()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let sender = sim.add_component("sender", world);
+		let receiver = sim.add_component("receiver", world);
+		
+		let mut output: OutPort<String> = OutPort::new();
+		let input = InPort::new(receiver);
+		let unused: InPort<String> = InPort::new(receiver);
+		let empty: InPort<String> = InPort::empty();
+		sim.register_out_port(sender, "output", &output);
+		sim.register_in_port(receiver, "input", &input);
+		sim.register_in_port(receiver, "unused", &unused);
+		sim.register_in_port(receiver, "empty", &empty);
+		output.connect_to(&input);
+		
+		assert_eq!(sim.validate(), vec![
+			"InPort world.receiver.unused has no OutPorts connected to it".to_string(),
+			"InPort world.receiver.empty is empty (it was never replaced with a nested component's port)".to_string()]);
+	}
+	
+	#[test]
+	fn ports_can_be_connected_by_path()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let sender = sim.add_component("sender", world);
+		let receiver = sim.add_component("receiver", world);
+		
+		let output: OutPort<String> = OutPort::new();
+		let input: InPort<String> = InPort::with_port_name(receiver, "in");
+		let control: InPort<i64> = InPort::new(receiver);
+		sim.register_out_port(sender, "output", &output);
+		sim.register_in_port(receiver, "input", &input);
+		sim.register_in_port(receiver, "control", &control);
+		
+		assert_eq!(sim.connect("world.sender.output", "world.receiver.control"),
+			Err("can't connect world.sender.output to world.receiver.control: the ports have different payload types".to_string()));
+		assert_eq!(sim.connect("world.sender.output", "world.receiver.bogus"), Err("InPort world.receiver.bogus wasn't registered".to_string()));
+		assert_eq!(sim.connect("world.bogus.output", "world.receiver.input"), Err("there is no component at 'world.bogus'".to_string()));
+		assert!(!output.is_connected());
+		
+		assert_eq!(sim.connect("world.sender.output", "world.receiver.input"), Ok(()));
+		assert_eq!(output.remote(), Some((receiver, "in".to_string())));
+		let (to, port) = output.route().unwrap();
+		sim.schedule(Event::with_port("text", port), to, Time(0));
+		assert_eq!(sim.scheduled.peek().unwrap().event.port_name, "in");
+		assert_eq!(sim.connect("world.sender.output", "world.receiver.input"),
+			Err("can't connect world.sender.output to world.receiver.input: the OutPort is already connected".to_string()));
+		assert_eq!(output.connections.load(atomic::Ordering::Relaxed), 1);
+		assert_eq!(input.connections.load(atomic::Ordering::Relaxed), 1);
+		assert_eq!(sim.validate(), vec!["InPort world.receiver.control has no OutPorts connected to it".to_string()]);
+	}
+	
+	#[test]
+	fn exported_ports_are_validated()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let sender = sim.add_component("sender", world);
+		let device = sim.add_component("device", world);
+		let nested = sim.add_component("nested", device);
+		
+		let input: InPort<String> = InPort::new(nested);
+		let inbound = sim.export_in_port(device, "inbound", &input);
+		assert_eq!(sim.validate(), vec!["InPort world.device.inbound has no OutPorts connected to it".to_string()]);
+		
+		let mut output: OutPort<String> = OutPort::new();
+		output.connect_to(&inbound);
+		assert!(sim.validate().is_empty());
+		assert_eq!(output.remote_id, nested);
+		assert_eq!(input.connections.load(atomic::Ordering::Relaxed), 1);
+		
+		let result = panic::catch_unwind(panic::AssertUnwindSafe(|| sim.export_in_port(sender, "inbound", &input)));
+		assert!(result.is_err());
+	}
+	
+	#[test]
+	fn dot_includes_tree_and_ports()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (sender, _) = sim.add_active_component("sender", world);
+		let receiver = sim.add_component("receiver", world);
+		
+		let mut output: OutPort<String> = OutPort::new();
+		sim.register_out_port(sender, "output", &output);
+		output.connect_to(&InPort::with_port_name(receiver, "input"));
+		
+		assert_eq!(sim.dot_text(), "digraph topology {\n".to_string() +
+			"   \"world\" [label=\"world\", shape=ellipse];\n" +
+			"   \"world.sender\" [label=\"sender\", shape=box];\n" +
+			"   \"world\" -> \"world.sender\" [style=dashed, arrowhead=none];\n" +
+			"   \"world.receiver\" [label=\"receiver\", shape=ellipse];\n" +
+			"   \"world\" -> \"world.receiver\" [style=dashed, arrowhead=none];\n" +
+			"   \"world.sender\" -> \"world.receiver\" [label=\"output -> input\"];\n" +
+			"}\n");
+	}
+	
+	#[test]
+	fn dot_escapes_quotes()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (sender, _) = sim.add_active_component("c:\\bin", world);	// names can't contain quotes but ports can
+		let receiver = sim.add_component("receiver", world);
+		
+		let mut output: OutPort<String> = OutPort::new();
+		sim.register_out_port(sender, "say \"hi\"", &output);
+		output.connect_to(&InPort::with_port_name(receiver, "input"));
+		
+		let text = sim.dot_text();
+		assert!(text.contains("   \"world.c:\\\\bin\" [label=\"c:\\\\bin\", shape=box];\n"), "{}", text);
+		assert!(text.contains("[label=\"say \\\"hi\\\" -> input\"]"), "{}", text);
+	}
+	
+	#[test]
+	fn phases_order_dispatch()
+	{
+		let mut sim = Simulation::new(Config::new());
+		let world = sim.add_component("world", NO_COMPONENT);
+		let actuator = sim.add_component("actuator", world);
+		let sensor = sim.add_component("sensor", world);
+		let other = sim.add_component("other", world);
+		sim.define_phases(&["sensors", "actuators"]);
+		sim.set_phase(actuator, "actuators");
+		sim.set_phase(sensor, "sensors");
+		
+		sim.schedule(Event::new("a"), actuator, Time(0));
+		sim.schedule(Event::new("s"), sensor, Time(0));
+		sim.schedule(Event::new("o"), other, Time(0));
+		
+		let mut names = Vec::new();
+		while !sim.scheduled.is_empty() {
+			let events = sim.take_events_at(Time(0));
+			names.push(events.iter().map(|e| e.event.name.clone()).collect::<Vec<_>>().join(","));
+		}
+		assert_eq!(names, vec!["o", "s", "a"]);
+	}
+	
+	// Returns a simulation with a component that rolls a die every second.
+	fn new_dice_sim() -> Simulation
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let (_, data) = sim.add_active_component("dice", world);
+		let mut rng = data.rng.stream("rolls");
+		thread::spawn(move || {
+			process_events!(data, event, state, effector,
+				"init 0" => {
+					effector.schedule_after_secs(Event::new("roll"), data.id, 1.0);
+				},
+				"roll" => {
+					effector.set_int("roll", rng.gen_range(0, 1_000_000));
+					effector.schedule_after_secs(Event::new("roll"), data.id, 1.0);
 				}
-				response.with_no_cache()	// TODO: might want to do this just in debug (altho the client and server are normally both local so it shouldn't matter much)
-			}
-			)
+			);
 		});
-	});
-}
-
-fn handle_endpoint(command: RestCommand, tx_command: &Mutex<mpsc::Sender<RestCommand>>, rx_reply: &Mutex<mpsc::Receiver<RestReply>>) -> rouille::Response
-{
-	tx_command.lock().unwrap().send(command).unwrap();
-	let reply = rx_reply.lock().unwrap().recv().unwrap();
+		sim.init_components();
+		sim
+	}
+	
+	fn roll_until(sim: &mut Simulation, ticks: i64) -> Vec<i64>
+	{
+		let mut rolls = Vec::new();
+		while sim.current_time.0 < ticks {
+			sim.run_time_slice();
+			rolls.push(sim.store.get_int("world.dice.roll"));
+		}
+		rolls
+	}
 	
-	rouille::Response {
-		status_code: reply.code,
-		headers: vec![("Content-Type".into(), "application/json".into())],
-		data: rouille::ResponseBody::from_data(reply.data),
-		upgrade: None,
+	#[test]
+	fn seeded_branches_diverge()
+	{
+		let expected = roll_until(&mut new_dice_sim(), 6);
+		let mut sim = new_dice_sim();
+		assert_eq!(roll_until(&mut sim, 3), expected[..3].to_vec());
+		
+		let mut branch = |seed| {
+			sim.branch_with_seed(seed).unwrap();
+			let rolls = roll_until(&mut sim, 6);
+			let value = sim.rng().next_u64();
+			sim.end_branch().unwrap();
+			(rolls, value)
+		};
+		let first = branch(2);
+		let second = branch(3);
+		let third = branch(2);
+		assert_ne!(first.0, expected[3..].to_vec());
+		assert_ne!(first, second);
+		assert_eq!(first, third);
+		
+		// Ending the branches restores the original random streams.
+		assert_eq!(roll_until(&mut sim, 6), expected[3..].to_vec());
+	}
+	
+	#[test]
+	fn daemons_run_before_events_and_branch()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Error;
+		config.time_units = 1.0;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let ticker = sim.add_handler_component("ticker", world, |_, _, effector| {
+			effector.counter("ticks").inc(1);
+		});
+		for i in 1..9 {
+			sim.schedule(Event::new("tick"), ticker, Time(i));
+		}
+		let seen = Arc::new(Mutex::new(Vec::new()));
+		let daemon_seen = seen.clone();
+		sim.add_daemon(world, 2.0, move |state, _| {
+			daemon_seen.lock().unwrap().push((state.time, state.store.get_int("world.ticker.ticks")));
+		});
+		let run_until = |sim: &mut Simulation, ticks| {
+			while sim.current_time.0 < ticks {
+				sim.run_time_slice();
+			}
+		};
+		
+		// Daemons run every interval and see the store before that time's events.
+		run_until(&mut sim, 3);
+		assert_eq!(*seen.lock().unwrap(), vec![(2.0, 1)]);
+		
+		sim.branch().unwrap();
+		run_until(&mut sim, 6);
+		let branched = seen.lock().unwrap().split_off(1);
+		assert_eq!(branched, vec![(4.0, 3), (6.0, 5)]);
+		sim.end_branch().unwrap();
+		
+		// Ending the branch restores when the daemons are next due.
+		run_until(&mut sim, 6);
+		assert_eq!(seen.lock().unwrap().split_off(1), branched);
+	}
+	
+	#[test]
+	fn time_seeds_are_recorded()
+	{
+		let mut config = Config::with_seed(0);
+		config.log_level = LogLevel::Error;
+		let sim = Simulation::new(config);
+		let seed = sim.provenance().seed;
+		assert_ne!(seed, 0);
+		assert_eq!(sim.store.get_int("simulation.seed"), seed as i64);
+	}
+	
+	#[test]
+	fn filtered_logs_dont_build_paths()
+	{
+		let mut config = Config::with_seed(1);
+		config.log_level = LogLevel::Info;
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		let lookups = sim.components.path_lookups.load(atomic::Ordering::Relaxed);
+		sim.log(LogLevel::Debug, world, "filtered out");
+		assert_eq!(sim.components.path_lookups.load(atomic::Ordering::Relaxed), lookups);
+	}
+	
+	#[test]
+	fn no_component_has_an_empty_path()
+	{
+		let mut sim = Simulation::new(Config::with_seed(1));
+		sim.add_component("world", NO_COMPONENT);
+		assert_eq!(sim.components.full_path(NO_COMPONENT), "");
+		assert_eq!(sim.components.path(NO_COMPONENT), "");
 	}
-}
-
-fn is_valid_name_char(ch: char) -> bool
-{
-	!ch.is_whitespace() &&		// no spaces makes it much easier for sdebug to parse commands (paths don't need to be quoted)
-	!ch.is_control() &&			// these are just silly to include in a name
-	ch != '"' && ch != '\'' &&	// parsing is simpler if paths don't have quotes
-	ch != '.'					// allowing periods in a name would cause a lot of confusion when looking at paths
 }
@@ -0,0 +1,491 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Components can record statistics using the [`Effector`]'s counter, gauge, histogram,
+//! ewma, and moving_average methods, e.g. `effector.counter("tx_packets").inc(1)`. The `Simulation`
+//! aggregates these using the component's path (so the above would be named something
+//! like "world.sender.tx_packets") and writes the results into the store:
+//! * counters - The total is written as an int.
+//! * gauges - The most recent value is written as a float.
+//! * histograms - "NAME.count" is written as an int and "NAME.mean" as a float.
+//! * ewmas and moving averages - The current average is written as a float.
+//!
+//! A summary table is logged when the simulation exits. Samples aren't retained: gauges
+//! and histograms track running moments and estimate percentiles using the P² algorithm
+//! so memory use and the cost of the summary don't grow with the length of the run.
+use formatting::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::f64;
+
+pub(crate) enum StatUpdate
+{
+	Increment(i64),
+	Set(f64),
+	Sample(f64),
+	Ewma(f64, f64),		// time constant, value
+	Window(f64, f64),	// window secs, value
+}
+
+/// Returned by the [`Effector`]'s counter method. Counters are used to track
+/// totals, e.g. the number of packets sent.
+pub struct Counter<'a>
+{
+	pub(crate) updates: &'a mut Vec<(String, StatUpdate)>,
+	pub(crate) name: &'a str,
+}
+
+impl<'a> Counter<'a>
+{
+	pub fn inc(self, delta: i64)
+	{
+		self.updates.push((self.name.to_string(), StatUpdate::Increment(delta)));
+	}
+}
+
+/// Returned by the [`Effector`]'s gauge method. Gauges are used for values that
+/// go up and down, e.g. queue length.
+pub struct Gauge<'a>
+{
+	pub(crate) updates: &'a mut Vec<(String, StatUpdate)>,
+	pub(crate) name: &'a str,
+}
+
+impl<'a> Gauge<'a>
+{
+	pub fn set(self, value: f64)
+	{
+		self.updates.push((self.name.to_string(), StatUpdate::Set(value)));
+	}
+}
+
+/// Returned by the [`Effector`]'s histogram method. Histograms are used to track
+/// the distribution of a value, e.g. latency.
+pub struct Histogram<'a>
+{
+	pub(crate) updates: &'a mut Vec<(String, StatUpdate)>,
+	pub(crate) name: &'a str,
+}
+
+impl<'a> Histogram<'a>
+{
+	pub fn record(self, value: f64)
+	{
+		self.updates.push((self.name.to_string(), StatUpdate::Sample(value)));
+	}
+}
+
+/// Returned by the [`Effector`]'s ewma method. This is an exponentially weighted
+/// moving average where the weights are based on the time between samples (so samples
+/// do not need to be recorded at regular intervals). Older samples lose 63% of their
+/// weight every time_constant seconds.
+pub struct Ewma<'a>
+{
+	pub(crate) updates: &'a mut Vec<(String, StatUpdate)>,
+	pub(crate) name: &'a str,
+	pub(crate) time_constant: f64,
+}
+
+impl<'a> Ewma<'a>
+{
+	pub fn record(self, value: f64)
+	{
+		self.updates.push((self.name.to_string(), StatUpdate::Ewma(self.time_constant, value)));
+	}
+}
+
+/// Returned by the [`Effector`]'s moving_average method. This is the mean of the
+/// samples recorded within the last window seconds.
+pub struct MovingAverage<'a>
+{
+	pub(crate) updates: &'a mut Vec<(String, StatUpdate)>,
+	pub(crate) name: &'a str,
+	pub(crate) window: f64,
+}
+
+impl<'a> MovingAverage<'a>
+{
+	pub fn record(self, value: f64)
+	{
+		self.updates.push((self.name.to_string(), StatUpdate::Window(self.window, value)));
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Stat
+{
+	Counter(i64, usize),		// total, count
+	Gauge(f64, Distribution),	// last value, samples
+	Histogram(Distribution),
+	Ewma(EwmaState),
+	Window(WindowState),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Distribution
+{
+	count: usize,
+	mean: f64,
+	m2: f64,	// sum of squared differences from the mean (Welford's algorithm)
+	quantiles: [P2Quantile; 3],	// p50, p95, p99
+}
+
+// Estimates a quantile using the P² algorithm from "The P² Algorithm for Dynamic Calculation
+// of Quantiles and Histograms Without Storing Observations" by Jain and Chlamtac. This uses
+// five markers whose heights approximate the minimum, p/2, p, (1+p)/2 quantiles, and maximum.
+#[derive(Clone, Debug, PartialEq)]
+struct P2Quantile
+{
+	p: f64,
+	count: usize,
+	heights: [f64; 5],	// sorted first samples until there are five
+	positions: [f64; 5],
+	desired: [f64; 5],
+	increments: [f64; 5],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct EwmaState
+{
+	time_constant: f64,
+	sum: f64,		// decayed sum of the samples
+	weight: f64,	// decayed number of samples
+	last_time: f64,
+	count: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct WindowState
+{
+	window: f64,
+	samples: VecDeque<(f64, f64)>,	// time, value
+	count: usize,
+}
+
+/// The store values a stat update should produce.
+pub(crate) enum StatValue
+{
+	Int(i64),
+	Float(f64),
+	Summary(i64, f64),	// count, mean
+}
+
+/// Aggregated stats for all components keyed by full path.
+#[derive(Clone)]
+pub(crate) struct Stats
+{
+	stats: BTreeMap<String, Stat>,
+}
+
+impl Stats
+{
+	pub(crate) fn new() -> Stats
+	{
+		Stats{stats: BTreeMap::new()}
+	}
+
+	pub(crate) fn is_empty(&self) -> bool
+	{
+		self.stats.is_empty()
+	}
+
+	/// Time is the current time in seconds. Returns an error if the name was previously
+	/// used for a different kind of stat.
+	pub(crate) fn update(&mut self, key: &str, update: StatUpdate, time: f64) -> Result<StatValue, String>
+	{
+		let stat = self.stats.entry(key.to_string()).or_insert_with(|| match update {
+			StatUpdate::Increment(_) => Stat::Counter(0, 0),
+			StatUpdate::Set(_) => Stat::Gauge(0.0, Distribution::new()),
+			StatUpdate::Sample(_) => Stat::Histogram(Distribution::new()),
+			StatUpdate::Ewma(time_constant, _) => Stat::Ewma(EwmaState{time_constant, sum: 0.0, weight: 0.0, last_time: time, count: 0}),
+			StatUpdate::Window(window, _) => Stat::Window(WindowState{window, samples: VecDeque::new(), count: 0}),
+		});
+
+		match (stat, update) {
+			(&mut Stat::Counter(ref mut total, ref mut count), StatUpdate::Increment(delta)) => {
+				*total += delta;
+				*count += 1;
+				Ok(StatValue::Int(*total))
+			},
+			(&mut Stat::Gauge(ref mut last, ref mut samples), StatUpdate::Set(value)) => {
+				*last = value;
+				samples.add(value);
+				Ok(StatValue::Float(value))
+			},
+			(&mut Stat::Histogram(ref mut samples), StatUpdate::Sample(value)) => {
+				samples.add(value);
+				Ok(StatValue::Summary(samples.count as i64, samples.mean))
+			},
+			(&mut Stat::Ewma(ref mut state), StatUpdate::Ewma(_, value)) => {
+				let decay = (-(time - state.last_time)/state.time_constant).exp();
+				state.sum = state.sum*decay + value;
+				state.weight = state.weight*decay + 1.0;
+				state.last_time = time;
+				state.count += 1;
+				Ok(StatValue::Float(state.sum/state.weight))
+			},
+			(&mut Stat::Window(ref mut state), StatUpdate::Window(_, value)) => {
+				state.samples.push_back((time, value));
+				while state.samples.front().map_or(false, |s| s.0 <= time - state.window) {
+					state.samples.pop_front();
+				}
+				state.count += 1;
+				let sum: f64 = state.samples.iter().map(|s| s.1).sum();
+				Ok(StatValue::Float(sum/(state.samples.len() as f64)))
+			},
+			(stat, _) => Err(format!("{} is already a {}", key, kind(stat))),
+		}
+	}
+
+	/// Returns a table with one line per stat. For counters value is the total, for
+	/// gauges it's the last value, for histograms it's the sum of the samples, and for
+	/// ewmas and moving averages it's the current average. Percentiles are estimates.
+	pub(crate) fn summary(&self) -> Vec<String>
+	{
+		let width = self.stats.keys().map(|k| k.len()).max().unwrap_or(0).max(4);
+		let mut lines = Vec::with_capacity(self.stats.len() + 1);
+		lines.push(format!("{0:<1$}  {2:<9}  {3:>8}  {4:>10}  {5:>10}  {6:>10}  {7:>10}  {8:>10}  {9:>10}",
+			"name", width, "kind", "count", "value", "mean", "stddev", "p50", "p95", "p99"));
+
+		for (key, stat) in self.stats.iter() {
+			let line = match *stat {
+				Stat::Counter(total, count) =>
+					format!("{0:<1$}  {2:<9}  {3:>8}  {4:>10}", key, width, kind(stat), count, total),
+				Stat::Gauge(last, ref samples) =>
+					format!("{0:<1$}  {2:<9}  {3:>8}  {4:>10}  {5}", key, width, kind(stat), samples.count, float_to_fixed(last, 3), samples.describe()),
+				Stat::Histogram(ref samples) => {
+					let sum = samples.mean*(samples.count as f64);
+					format!("{0:<1$}  {2:<9}  {3:>8}  {4:>10}  {5}", key, width, kind(stat), samples.count, float_to_fixed(sum, 3), samples.describe())
+				},
+				Stat::Ewma(ref state) =>
+					format!("{0:<1$}  {2:<9}  {3:>8}  {4:>10}", key, width, kind(stat), state.count, float_to_fixed(state.sum/state.weight, 3)),
+				Stat::Window(ref state) => {
+					let sum: f64 = state.samples.iter().map(|s| s.1).sum();
+					format!("{0:<1$}  {2:<9}  {3:>8}  {4:>10}", key, width, kind(stat), state.count, float_to_fixed(sum/(state.samples.len() as f64), 3))
+				},
+			};
+			lines.push(line);
+		}
+		lines
+	}
+}
+
+fn kind(stat: &Stat) -> &'static str
+{
+	match *stat {
+		Stat::Counter(_, _) => "counter",
+		Stat::Gauge(_, _) => "gauge",
+		Stat::Histogram(_) => "histogram",
+		Stat::Ewma(_) => "ewma",
+		Stat::Window(_) => "moving",
+	}
+}
+
+impl Distribution
+{
+	fn new() -> Distribution
+	{
+		Distribution{count: 0, mean: 0.0, m2: 0.0, quantiles: [P2Quantile::new(0.50), P2Quantile::new(0.95), P2Quantile::new(0.99)]}
+	}
+
+	fn add(&mut self, value: f64)
+	{
+		self.count += 1;
+		let delta = value - self.mean;
+		self.mean += delta/(self.count as f64);
+		self.m2 += delta*(value - self.mean);
+		for quantile in self.quantiles.iter_mut() {
+			quantile.add(value);
+		}
+	}
+
+	fn stddev(&self) -> f64
+	{
+		if self.count > 1 {(self.m2/((self.count - 1) as f64)).sqrt()} else {f64::NAN}
+	}
+
+	fn describe(&self) -> String
+	{
+		let mean = if self.count > 0 {self.mean} else {f64::NAN};
+		format!("{:>10}  {:>10}  {:>10}  {:>10}  {:>10}", float_to_fixed(mean, 3), float_to_fixed(self.stddev(), 3),
+			float_to_fixed(self.quantiles[0].estimate(), 3), float_to_fixed(self.quantiles[1].estimate(), 3), float_to_fixed(self.quantiles[2].estimate(), 3))
+	}
+}
+
+impl P2Quantile
+{
+	fn new(p: f64) -> P2Quantile
+	{
+		P2Quantile{
+			p,
+			count: 0,
+			heights: [0.0; 5],
+			positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+			desired: [1.0, 1.0 + 2.0*p, 1.0 + 4.0*p, 3.0 + 2.0*p, 5.0],
+			increments: [0.0, p/2.0, p, (1.0 + p)/2.0, 1.0],
+		}
+	}
+
+	fn add(&mut self, value: f64)
+	{
+		if self.count < 5 {
+			self.heights[self.count] = value;
+			self.count += 1;
+			if self.count == 5 {
+				self.heights.sort_by(|a, b| a.partial_cmp(b).unwrap());
+			}
+			return;
+		}
+		self.count += 1;
+
+		// Find the cell the value falls into, extending the extreme markers if needed.
+		let k = if value < self.heights[0] {
+			self.heights[0] = value;
+			0
+		} else if value >= self.heights[4] {
+			self.heights[4] = value;
+			3
+		} else {
+			(1..5).find(|&i| value < self.heights[i]).unwrap() - 1
+		};
+		for i in k + 1..5 {
+			self.positions[i] += 1.0;
+		}
+		for i in 0..5 {
+			self.desired[i] += self.increments[i];
+		}
+
+		// Move the middle markers towards their desired positions.
+		for i in 1..4 {
+			let d = self.desired[i] - self.positions[i];
+			if (d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0) || (d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0) {
+				let d = d.signum();
+				let height = self.parabolic(i, d);
+				self.heights[i] = if self.heights[i - 1] < height && height < self.heights[i + 1] {height} else {self.linear(i, d)};
+				self.positions[i] += d;
+			}
+		}
+	}
+
+	fn estimate(&self) -> f64
+	{
+		if self.count < 5 {
+			let mut sorted = self.heights[..self.count].to_vec();
+			sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+			percentile(&sorted, 100.0*self.p)
+		} else {
+			self.heights[2]
+		}
+	}
+
+	fn parabolic(&self, i: usize, d: f64) -> f64
+	{
+		let (q, n) = (&self.heights, &self.positions);
+		q[i] + d/(n[i + 1] - n[i - 1])*((n[i] - n[i - 1] + d)*(q[i + 1] - q[i])/(n[i + 1] - n[i]) + (n[i + 1] - n[i] - d)*(q[i] - q[i - 1])/(n[i] - n[i - 1]))
+	}
+
+	fn linear(&self, i: usize, d: f64) -> f64
+	{
+		let j = if d > 0.0 {i + 1} else {i - 1};
+		self.heights[i] + d*(self.heights[j] - self.heights[i])/(self.positions[j] - self.positions[i])
+	}
+}
+
+// Uses the nearest rank method.
+fn percentile(sorted: &Vec<f64>, p: f64) -> f64
+{
+	if sorted.is_empty() {
+		f64::NAN
+	} else {
+		let rank = ((p/100.0)*(sorted.len() as f64)).ceil() as usize;
+		sorted[rank.max(1) - 1]
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn aggregates()
+	{
+		let mut stats = Stats::new();
+		for i in 1..101 {
+			assert!(stats.update("a.latency", StatUpdate::Sample(i as f64), 0.0).is_ok());
+		}
+		stats.update("a.sent", StatUpdate::Increment(2), 0.0).unwrap();
+		match stats.update("a.sent", StatUpdate::Increment(3), 0.0).unwrap() {
+			StatValue::Int(total) => assert_eq!(total, 5),
+			_ => panic!("expected an int"),
+		}
+		assert!(stats.update("a.sent", StatUpdate::Set(1.0), 0.0).is_err());
+
+		let mut sorted: Vec<f64> = (1..101).map(|i| i as f64).collect();
+		assert_eq!(percentile(&sorted, 50.0), 50.0);
+		assert_eq!(percentile(&sorted, 95.0), 95.0);
+		sorted.truncate(1);
+		assert_eq!(percentile(&sorted, 99.0), 1.0);
+
+		let summary = stats.summary();
+		assert_eq!(summary.len(), 3);
+		assert!(summary[1].starts_with("a.latency  histogram"));
+	}
+	
+	#[test]
+	fn percentiles_are_estimated()
+	{
+		let mut samples = Distribution::new();
+		for i in 0..10000 {
+			samples.add(((i*7919) % 10000) as f64);	// 0..9999 in a scrambled order
+		}
+		
+		assert_eq!(samples.count, 10000);
+		assert!((samples.mean - 4999.5).abs() < 1.0e-6);
+		assert!((samples.stddev() - 2886.9).abs() < 1.0);
+		for &(i, expected) in [(0, 5000.0), (1, 9500.0), (2, 9900.0)].iter() {
+			let estimate = samples.quantiles[i].estimate();
+			assert!((estimate - expected).abs() < 100.0, "p{} was {}", 100.0*samples.quantiles[i].p, estimate);
+		}
+		
+		let mut few = Distribution::new();
+		few.add(3.0);
+		few.add(1.0);
+		assert_eq!(few.quantiles[0].estimate(), 1.0);	// exact until there are five samples
+		assert_eq!(few.quantiles[2].estimate(), 3.0);
+	}
+	
+	fn float(value: StatValue) -> f64
+	{
+		match value {
+			StatValue::Float(value) => value,
+			_ => panic!("expected a float"),
+		}
+	}
+	
+	#[test]
+	fn averages()
+	{
+		let mut stats = Stats::new();
+		assert_eq!(float(stats.update("a.ewma", StatUpdate::Ewma(1.0, 10.0), 5.0).unwrap()), 10.0);
+		assert_eq!(float(stats.update("a.ewma", StatUpdate::Ewma(1.0, 20.0), 5.0).unwrap()), 15.0);	// same time so equal weights
+		let value = float(stats.update("a.ewma", StatUpdate::Ewma(1.0, 20.0), 105.0).unwrap());
+		assert!((value - 20.0).abs() < 1.0e-6);	// old samples have decayed away
+		
+		assert_eq!(float(stats.update("a.window", StatUpdate::Window(10.0, 1.0), 0.0).unwrap()), 1.0);
+		assert_eq!(float(stats.update("a.window", StatUpdate::Window(10.0, 3.0), 5.0).unwrap()), 2.0);
+		assert_eq!(float(stats.update("a.window", StatUpdate::Window(10.0, 5.0), 12.0).unwrap()), 4.0);
+	}
+}
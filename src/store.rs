@@ -13,8 +13,15 @@
 // You should have received a copy of the GNU General Public License
 // along with this program; if not, write to the Free Software Foundation,
 // Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use formatting::*;
+use glob::Pattern;
+use history::*;
+use persistent_map::*;
+use rustc_serialize::{json, Encodable, Encoder};
+use sim_rng::fnv1a;
 use sim_time::*;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
 
 /// This is used to persist all of the significant state within a simulation.
 /// It is a write-once temporal store, i.e. new values can be written to the
@@ -22,19 +29,200 @@ use std::collections::HashMap;
 /// normally written to disk to allow for off-line analysis of the results and
 /// to allow the simulation to be replayed.
 ///
-/// _Getters_ take a &str key and return either an i64, an f64, or a &str. The key
+/// _Getters_ take a &str key and return an i64, an f64, a String, a bool, or a Vec of
+/// i64, f64, or String (lists are useful for things like packet queues). The key
 /// is normally a path from the root component through the inner components to a
 /// data name. The value returned is that for the current time.
 ///
 /// _Setters_ set a value for the current time. To ensure thread safety and to allow
 /// speculative execution setters are invoked by the [`Simulation`] using the information
 /// [`Component`]s recorded within an [`Effector`].
+///
+/// Clones share structure (the maps are hash tries) so components can cheaply hold
+/// onto the store from a [`SimState`] without blocking the `Simulation` from writing
+/// new editions. A write while a clone is alive copies only the O(log n) nodes along
+/// the path to the key, and the same is true for the retained history.
+///
+/// Keys can be renamed using rename_key. Afterwards the old key acts as an alias for
+/// the new key so code (and GUIs) using the old name continue to work and history
+/// recorded under the old name is stitched onto the new key's history.
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Store
 {
-	pub(crate) edition: u32,
-	pub(crate) int_data: HashMap<String, (Time, i64)>,	// TODO: probably want [(Time, i64)]
-	pub(crate) float_data: HashMap<String, (Time, f64)>,
-	pub(crate) string_data: HashMap<String, (Time, String)>,
+	pub(crate) edition: u64,
+	pub(crate) int_data: PersistentMap<String, (Time, i64)>,	// TODO: probably want [(Time, i64)]
+	pub(crate) float_data: PersistentMap<String, (Time, f64)>,
+	pub(crate) string_data: PersistentMap<String, (Time, String)>,
+	#[cfg_attr(feature = "serialize", serde(default))]
+	pub(crate) bool_data: PersistentMap<String, (Time, bool)>,
+	#[cfg_attr(feature = "serialize", serde(default))]
+	pub(crate) list_data: PersistentMap<String, (Time, StoreValue)>,	// only the list variants are used
+	#[cfg_attr(feature = "serialize", serde(default))]
+	pub(crate) aliases: PersistentMap<String, String>,	// old key => new key
+	#[cfg_attr(feature = "serialize", serde(skip))]
+	pub(crate) changes: Option<Vec<StoreChange>>,	// only the Simulation's store records changes
+	#[cfg_attr(feature = "serialize", serde(skip))]
+	pub(crate) history: Option<StoreHistory>,		// set if config.store_history isn't empty
+	#[cfg_attr(feature = "serialize", serde(skip))]
+	pub(crate) external: PersistentMap<String, Time>,	// keys written by set_external (at the time they were written) that a component may write once more
+	#[cfg_attr(feature = "serialize", serde(skip))]
+	pub(crate) change_log: Option<ChangeLog>,	// only used by the Simulation's store
+	#[cfg_attr(feature = "serialize", serde(skip))]
+	pub(crate) change_hash: Option<u64>,	// set if config.finger_print_store is set
+	#[cfg_attr(feature = "serialize", serde(skip))]
+	batch: Option<bool>,	// set while a batch is open, true once the batch has bumped the edition
+}
+
+/// Records a change to a value within the [`Store`]. Values are formatted the same way
+/// as they are for the REST state endpoints.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct StoreChange
+{
+	pub key: String,
+	
+	/// None if the key was added.
+	pub old_value: Option<String>,
+	pub new_value: String,
+	
+	/// "int", "float", "string", "bool", "int-list", "float-list", or "string-list".
+	pub kind: String,
+}
+
+/// All of the changes made to the [`Store`] during a time slice.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct StoreDelta
+{
+	/// Seconds into the simulation at which the changes were made.
+	pub time: f64,
+	
+	/// The store's edition after the changes were applied.
+	pub edition: u64,
+	
+	pub changes: Vec<StoreChange>,
+}
+
+/// Returned by [`Store`]'s changes_since method.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangesSince
+{
+	/// Set if the changes after the edition are no longer available (or the edition
+	/// is newer than the store's edition, e.g. because a branch was ended). Clients
+	/// should re-read the whole store when this happens.
+	pub resync: bool,
+	
+	/// The current values of the keys that changed (sorted by key).
+	pub changed: Vec<(String, StoreValue, Time)>,
+	
+	/// The keys that no longer have values, e.g. because they were renamed (sorted).
+	pub removed: Vec<String>,
+}
+
+/// A value within the [`Store`], see the query method.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum StoreValue
+{
+	Int(i64),
+	Float(f64),
+	String(String),
+	Bool(bool),
+	IntList(Vec<i64>),
+	FloatList(Vec<f64>),
+	StringList(Vec<String>),
+}
+
+impl StoreValue
+{
+	/// Returns the kind used by [`StoreChange`] and the REST state endpoints.
+	pub fn kind(&self) -> &'static str
+	{
+		match *self {
+			StoreValue::Int(_) => "int",
+			StoreValue::Float(_) => "float",
+			StoreValue::String(_) => "string",
+			StoreValue::Bool(_) => "bool",
+			StoreValue::IntList(_) => "int-list",
+			StoreValue::FloatList(_) => "float-list",
+			StoreValue::StringList(_) => "string-list",
+		}
+	}
+}
+
+/// The type of a declared store key, see [`KeySchema`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreKind
+{
+	Int,
+	Float,
+	String,
+	Bool,
+	IntList,
+	FloatList,
+	StringList,
+}
+
+impl StoreKind
+{
+	/// Returns the same names as [`StoreValue`]'s kind method, e.g. "float".
+	pub fn name(&self) -> &'static str
+	{
+		match *self {
+			StoreKind::Int => "int",
+			StoreKind::Float => "float",
+			StoreKind::String => "string",
+			StoreKind::Bool => "bool",
+			StoreKind::IntList => "int-list",
+			StoreKind::FloatList => "float-list",
+			StoreKind::StringList => "string-list",
+		}
+	}
+}
+
+impl fmt::Display for StoreKind
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "{}", self.name())
+	}
+}
+
+// Encoded using the name so that the /schema endpoint uses the same kinds as the
+// state endpoints.
+impl Encodable for StoreKind
+{
+	fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error>
+	{
+		s.emit_str(self.name())
+	}
+}
+
+/// Describes a store key, see [`Effector`]'s declare methods.
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+pub struct KeySchema
+{
+	pub kind: StoreKind,
+	
+	/// E.g. "J" or "m/s". Empty if the value has no units.
+	pub units: String,
+	
+	pub description: String,
+	
+	/// Hints for GUIs, e.g. "percent" or "hidden".
+	pub hints: Vec<String>,
+}
+
+// Lists are formatted as JSON arrays. JSON has no NaN or infinity so those are null.
+pub(crate) fn list_to_string(value: &StoreValue) -> String
+{
+	match *value {
+		StoreValue::IntList(ref list) => json::encode(list).unwrap(),
+		StoreValue::FloatList(ref list) => format!("[{}]", list.iter().map(|&v| if v.is_finite() {float_to_fixed(v, 6)} else {"null".to_string()}).collect::<Vec<_>>().join(",")),
+		StoreValue::StringList(ref list) => json::encode(list).unwrap(),
+		_ => panic!("{:?} isn't a list", value),
+	}
 }
 
 pub trait ReadableStore
@@ -44,6 +232,10 @@ pub trait ReadableStore
 	fn get_int(&self, key: &str) -> i64;
 	fn get_float(&self, key: &str) -> f64;
 	fn get_string(&self, key: &str) -> String;
+	fn get_bool(&self, key: &str) -> bool;
+	fn get_vec_int(&self, key: &str) -> Vec<i64>;
+	fn get_vec_float(&self, key: &str) -> Vec<f64>;
+	fn get_vec_string(&self, key: &str) -> Vec<String>;
 }
 
 pub trait WriteableStore
@@ -51,12 +243,19 @@ pub trait WriteableStore
 	fn set_int(&mut self, key: &str, value: i64, time: Time);
 	fn set_float(&mut self, key: &str, value: f64, time: Time);
 	fn set_string(&mut self, key: &str, value: &str, time: Time);
+	fn set_bool(&mut self, key: &str, value: bool, time: Time);
+	fn set_vec_int(&mut self, key: &str, value: &[i64], time: Time);
+	fn set_vec_float(&mut self, key: &str, value: &[f64], time: Time);
+	fn set_vec_string(&mut self, key: &str, value: &[String], time: Time);
 }
 
 impl ReadableStore for Store
 {
 	fn contains(&self, key: &str) -> bool
 	{
+		if let Some(new) = self.renamed(key) {
+			return self.contains(&new);
+		}
 		if let Some(_) = self.int_data.get(key) {
 			return true
 		}
@@ -66,11 +265,14 @@ impl ReadableStore for Store
 		if let Some(_) = self.string_data.get(key) {
 			return true
 		}
-		false
+		self.bool_data.contains_key(key) || self.list_data.contains_key(key)
 	}
 
 	fn get_int(&self, key: &str) -> i64
 	{
+		if let Some(new) = self.renamed(key) {
+			return self.get_int(&new);
+		}
 		match self.int_data.get(key) {
 			Some(ref value) => return value.1,
 			_ => panic!("int key '{}' is missing", key)
@@ -79,6 +281,9 @@ impl ReadableStore for Store
 
 	fn get_float(&self, key: &str) -> f64
 	{
+		if let Some(new) = self.renamed(key) {
+			return self.get_float(&new);
+		}
 		match self.float_data.get(key) {
 			Some(ref value) => return value.1,
 			_ => panic!("float key '{}' is missing", key)
@@ -87,11 +292,58 @@ impl ReadableStore for Store
 
 	fn get_string(&self, key: &str) -> String
 	{
+		if let Some(new) = self.renamed(key) {
+			return self.get_string(&new);
+		}
 		match self.string_data.get(key) {
 			Some(ref value) => return value.1.clone(),
 			_ => panic!("string key '{}' is missing", key)
 		}
 	}
+
+	fn get_bool(&self, key: &str) -> bool
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.get_bool(&new);
+		}
+		match self.bool_data.get(key) {
+			Some(ref value) => return value.1,
+			_ => panic!("bool key '{}' is missing", key)
+		}
+	}
+
+	fn get_vec_int(&self, key: &str) -> Vec<i64>
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.get_vec_int(&new);
+		}
+		match self.list_data.get(key) {
+			Some(&(_, StoreValue::IntList(ref value))) => value.clone(),
+			_ => panic!("int list key '{}' is missing", key)
+		}
+	}
+
+	fn get_vec_float(&self, key: &str) -> Vec<f64>
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.get_vec_float(&new);
+		}
+		match self.list_data.get(key) {
+			Some(&(_, StoreValue::FloatList(ref value))) => value.clone(),
+			_ => panic!("float list key '{}' is missing", key)
+		}
+	}
+
+	fn get_vec_string(&self, key: &str) -> Vec<String>
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.get_vec_string(&new);
+		}
+		match self.list_data.get(key) {
+			Some(&(_, StoreValue::StringList(ref value))) => value.clone(),
+			_ => panic!("string list key '{}' is missing", key)
+		}
+	}
 }
 
 impl WriteableStore for Store
@@ -99,49 +351,108 @@ impl WriteableStore for Store
 	fn set_int(&mut self, key: &str, value: i64, time: Time)
 	{
 		assert!(!key.is_empty(), "key should not be empty");
+		if let Some(new) = self.renamed(key) {
+			return self.set_int(&new, value, time);
+		}
 		if let Some(old) = self.int_data.insert(key.to_string(), (time, value)) {
-			if old.0 == time {
+			if old.0 == time && !self.take_external(key, time) {
 				panic!("int key '{}' has already been set", key)
 			}
 			if old.1 != value {
 				// Edition is used by REST to detect changes to values in the store so we
 				// don't want to increment it when the same value is added again.
-				self.edition = self.edition.wrapping_add(1);
+				self.bump_edition();
+				self.record_change(key, Some(old.1.to_string()), value.to_string(), "int");
+				if let Some(ref mut history) = self.history {history.record_int(key, time, value);}
 			}
 		} else {
-			self.edition = self.edition.wrapping_add(1);
+			self.bump_edition();
+			self.record_change(key, None, value.to_string(), "int");
+			if let Some(ref mut history) = self.history {history.record_int(key, time, value);}
 		}
 	}
 	
 	fn set_float(&mut self, key: &str, value: f64, time: Time)
 	{
 		assert!(!key.is_empty(), "key should not be empty");
+		if let Some(new) = self.renamed(key) {
+			return self.set_float(&new, value, time);
+		}
 		if let Some(old) = self.float_data.insert(key.to_string(), (time, value)) {
-			if old.0 == time {
+			if old.0 == time && !self.take_external(key, time) {
 				panic!("float key '{}' has already been set", key)
 			}
 			if old.1 != value {
-				self.edition = self.edition.wrapping_add(1);
+				self.bump_edition();
+				self.record_change(key, Some(float_to_fixed(old.1, 6)), float_to_fixed(value, 6), "float");
+				if let Some(ref mut history) = self.history {history.record_float(key, time, value);}
 			}
 		} else {
-			self.edition = self.edition.wrapping_add(1);
+			self.bump_edition();
+			self.record_change(key, None, float_to_fixed(value, 6), "float");
+			if let Some(ref mut history) = self.history {history.record_float(key, time, value);}
 		}
 	}
 		
 	fn set_string(&mut self, key: &str, value: &str, time: Time)
 	{
 		assert!(!key.is_empty(), "key should not be empty");
+		if let Some(new) = self.renamed(key) {
+			return self.set_string(&new, value, time);
+		}
 		if let Some(old) = self.string_data.insert(key.to_string(), (time, value.to_string())) {
-			if old.0 == time {
+			if old.0 == time && !self.take_external(key, time) {
 				panic!("string key '{}' has already been set", key)
 			}
 			if old.1 != value {
-				self.edition = self.edition.wrapping_add(1);
+				self.bump_edition();
+				self.record_change(key, Some(old.1), value.to_string(), "string");
+				if let Some(ref mut history) = self.history {history.record_string(key, time, value);}
 			}
 		} else {
-			self.edition = self.edition.wrapping_add(1);
+			self.bump_edition();
+			self.record_change(key, None, value.to_string(), "string");
+			if let Some(ref mut history) = self.history {history.record_string(key, time, value);}
 		}
 	}
+	
+	// Note that history isn't retained for bools and lists.
+	fn set_bool(&mut self, key: &str, value: bool, time: Time)
+	{
+		assert!(!key.is_empty(), "key should not be empty");
+		if let Some(new) = self.renamed(key) {
+			return self.set_bool(&new, value, time);
+		}
+		if let Some(old) = self.bool_data.insert(key.to_string(), (time, value)) {
+			if old.0 == time && !self.take_external(key, time) {
+				panic!("bool key '{}' has already been set", key)
+			}
+			if old.1 != value {
+				self.bump_edition();
+				self.record_change(key, Some(old.1.to_string()), value.to_string(), "bool");
+				if let Some(ref mut history) = self.history {history.record_bool(key, time, value);}
+			}
+		} else {
+			self.bump_edition();
+			self.record_change(key, None, value.to_string(), "bool");
+			if let Some(ref mut history) = self.history {history.record_bool(key, time, value);}
+		}
+	}
+	
+	fn set_vec_int(&mut self, key: &str, value: &[i64], time: Time)
+	{
+		self.set_list(key, StoreValue::IntList(value.to_vec()), time);
+	}
+	
+	fn set_vec_float(&mut self, key: &str, value: &[f64], time: Time)
+	{
+		self.set_list(key, StoreValue::FloatList(value.to_vec()), time);
+	}
+	
+	fn set_vec_string(&mut self, key: &str, value: &[String], time: Time)
+	{
+		self.set_list(key, StoreValue::StringList(value.to_vec()), time);
+	}
 }
 
 impl Store
@@ -150,9 +461,341 @@ impl Store
 	{
 		Store{
 			edition: 0,
-			int_data: HashMap::new(),
-			float_data: HashMap::new(),
-			string_data: HashMap::new()
+			int_data: PersistentMap::new(),
+			float_data: PersistentMap::new(),
+			string_data: PersistentMap::new(),
+			bool_data: PersistentMap::new(),
+			list_data: PersistentMap::new(),
+			aliases: PersistentMap::new(),
+			changes: None,
+			history: None,
+			external: PersistentMap::new(),
+			change_log: None,
+			change_hash: None,
+			batch: None,
+		}
+	}
+	
+	/// Returns the retained values for the key (oldest first). This will be empty
+	/// if the key doesn't match one of the config.store_history patterns. With All and
+	/// Last the current value is included but with Every it's only included if it was
+	/// sampled, i.e. if it was set at least the Every interval after the previous sample.
+	pub fn int_history(&self, key: &str) -> Vec<(Time, i64)>
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.int_history(&new);
+		}
+		self.history.as_ref().and_then(|h| h.ints.get(key)).map_or(Vec::new(), |v| v.iter().cloned().collect())
+	}
+	
+	pub fn float_history(&self, key: &str) -> Vec<(Time, f64)>
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.float_history(&new);
+		}
+		self.history.as_ref().and_then(|h| h.floats.get(key)).map_or(Vec::new(), |v| v.iter().cloned().collect())
+	}
+	
+	pub fn string_history(&self, key: &str) -> Vec<(Time, String)>
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.string_history(&new);
+		}
+		self.history.as_ref().and_then(|h| h.strings.get(key)).map_or(Vec::new(), |v| v.iter().cloned().collect())
+	}
+	
+	pub fn bool_history(&self, key: &str) -> Vec<(Time, bool)>
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.bool_history(&new);
+		}
+		self.history.as_ref().and_then(|h| h.bools.get(key)).map_or(Vec::new(), |v| v.iter().cloned().collect())
+	}
+	
+	/// The values are one of the StoreValue list variants.
+	pub fn list_history(&self, key: &str) -> Vec<(Time, StoreValue)>
+	{
+		if let Some(new) = self.renamed(key) {
+			return self.list_history(&new);
+		}
+		self.history.as_ref().and_then(|h| h.lists.get(key)).map_or(Vec::new(), |v| v.iter().cloned().collect())
+	}
+	
+	/// Declares that old was renamed to new. The old key's value (if new doesn't have
+	/// one) and history are moved to the new key and afterwards reads and writes using
+	/// old are redirected to new.
+	pub fn rename_key(&mut self, old: &str, new: &str)
+	{
+		assert!(!old.is_empty() && !new.is_empty(), "keys should not be empty");
+		let new = self.renamed(new).unwrap_or(new.to_string());
+		assert!(old != new, "renaming '{}' would create an alias cycle", old);
+		
+		let moved = self.kind_of(old).is_some();
+		move_value(&mut self.int_data, old, &new);
+		move_value(&mut self.float_data, old, &new);
+		move_value(&mut self.string_data, old, &new);
+		move_value(&mut self.bool_data, old, &new);
+		move_value(&mut self.list_data, old, &new);
+		if let Some(ref mut history) = self.history {
+			history.rename(old, &new);
+		}
+		
+		let chained: Vec<String> = self.aliases.iter().filter(|&(_, target)| target == old).map(|(key, _)| key.clone()).collect();
+		for key in chained {
+			self.aliases.insert(key, new.clone());	// keep chains one level deep
+		}
+		self.aliases.insert(old.to_string(), new.clone());
+		self.bump_edition();
+		if let Some(ref mut hash) = self.change_hash {
+			*hash = hash.wrapping_add(hash_change(old, &new));
+		}
+		if let Some(ref mut log) = self.change_log {
+			if moved {
+				log.push(self.edition, old, true);
+				log.push(self.edition, &new, false);
+			}
+		}
+	}
+	
+	/// Helper for loading stores saved before keys were renamed: renames is a list of
+	/// (old, new) keys in the order in which the renames happened.
+	pub fn migrate(&mut self, renames: &[(&str, &str)])
+	{
+		for &(old, new) in renames.iter() {
+			self.rename_key(old, new);
+		}
+	}
+	
+	/// Returns the key that old was renamed to.
+	pub fn renamed(&self, old: &str) -> Option<String>
+	{
+		if self.aliases.is_empty() {
+			None
+		} else {
+			self.aliases.get(old).cloned()
+		}
+	}
+	
+	/// Returns the current value of each key matching the glob (sorted by key) along
+	/// with the time at which the value was set.
+	pub fn query(&self, glob: &Pattern) -> Vec<(String, StoreValue, Time)>
+	{
+		self.query_with(|key| glob.matches(key))
+	}
+	
+	pub(crate) fn query_with<F>(&self, matches: F) -> Vec<(String, StoreValue, Time)>
+		where F: Fn (&str) -> bool
+	{
+		let mut result = Vec::new();
+		for (key, value) in self.int_data.iter().filter(|e| matches(e.0)) {
+			result.push((key.clone(), StoreValue::Int(value.1), value.0));
+		}
+		for (key, value) in self.float_data.iter().filter(|e| matches(e.0)) {
+			result.push((key.clone(), StoreValue::Float(value.1), value.0));
+		}
+		for (key, value) in self.string_data.iter().filter(|e| matches(e.0)) {
+			result.push((key.clone(), StoreValue::String(value.1.clone()), value.0));
+		}
+		for (key, value) in self.bool_data.iter().filter(|e| matches(e.0)) {
+			result.push((key.clone(), StoreValue::Bool(value.1), value.0));
+		}
+		for (key, value) in self.list_data.iter().filter(|e| matches(e.0)) {
+			result.push((key.clone(), value.1.clone(), value.0));
+		}
+		result.sort_by(|a, b| a.0.cmp(&b.0));
+		result
+	}
+	
+	pub(crate) fn keys(&self) -> Vec<&String>
+	{
+		self.int_data.keys().chain(self.float_data.keys()).chain(self.string_data.keys())
+			.chain(self.bool_data.keys()).chain(self.list_data.keys()).collect()
+	}
+	
+	pub(crate) fn was_set_at(&self, key: &str, time: Time) -> bool
+	{
+		self.int_data.get(key).map_or(false, |v| v.0 == time) ||
+		self.float_data.get(key).map_or(false, |v| v.0 == time) ||
+		self.string_data.get(key).map_or(false, |v| v.0 == time) ||
+		self.bool_data.get(key).map_or(false, |v| v.0 == time) ||
+		self.list_data.get(key).map_or(false, |v| v.0 == time)
+	}
+	
+	/// Used for writes from outside the simulation, e.g. the REST state endpoints. These
+	/// can arrive at any time so, unlike the set methods, this doesn't panic if the key
+	/// was already set at time (and a component may set the key once more at time). Returns
+	/// an error if the key exists with a different type.
+	pub(crate) fn set_external(&mut self, key: &str, value: StoreValue, time: Time) -> Result<(), String>
+	{
+		if key.is_empty() {
+			return Err("key should not be empty".to_string());
+		}
+		let key = self.renamed(key).unwrap_or(key.to_string());
+		if let Some(kind) = self.kind_of(&key) {
+			if kind != value.kind() {
+				return Err(format!("'{}' has type {} not {}", key, kind, value.kind()));
+			}
+		}
+		
+		let stale: Vec<String> = self.external.iter().filter(|&(_, &t)| t != time).map(|(key, _)| key.clone()).collect();
+		for old in stale {
+			self.external.remove(&old);
+		}
+		self.external.insert(key.clone(), time);
+		match value {
+			StoreValue::Int(value) => self.set_int(&key, value, time),
+			StoreValue::Float(value) => self.set_float(&key, value, time),
+			StoreValue::String(value) => self.set_string(&key, &value, time),
+			StoreValue::Bool(value) => self.set_bool(&key, value, time),
+			list => self.set_list(&key, list, time),
+		}
+		self.external.insert(key, time);	// the write above may have used up the exemption
+		Ok(())
+	}
+	
+	// An external write exempts only the next write to the key at the same time.
+	fn take_external(&mut self, key: &str, time: Time) -> bool
+	{
+		if self.external.is_empty() || self.external.get(key) != Some(&time) {
+			return false;
+		}
+		self.external.remove(key);
+		true
+	}
+	
+	pub(crate) fn kind_of(&self, key: &str) -> Option<&'static str>
+	{
+		if self.int_data.contains_key(key) {
+			Some("int")
+		} else if self.float_data.contains_key(key) {
+			Some("float")
+		} else if self.string_data.contains_key(key) {
+			Some("string")
+		} else if self.bool_data.contains_key(key) {
+			Some("bool")
+		} else {
+			self.list_data.get(key).map(|v| v.1.kind())
+		}
+	}
+	
+	/// The store's edition is incremented whenever a value changes. For the `Simulation`'s
+	/// store all of the changes made by a component's [`Effector`] share one edition.
+	pub fn edition(&self) -> u64
+	{
+		self.edition
+	}
+	
+	/// Returns the keys that changed after edition. This is only supported for the
+	/// `Simulation`'s store and only the last config.max_state_changes changes are
+	/// retained so resync will be set if the changes after edition aren't all available.
+	pub fn changes_since(&self, edition: u64) -> ChangesSince
+	{
+		let keys = match self.change_log {
+			Some(ref log) if edition <= self.edition => log.since(edition),
+			_ => None,
+		};
+		match keys {
+			Some(keys) => {
+				let mut changes = ChangesSince{resync: false, changed: Vec::new(), removed: Vec::new()};
+				for (key, removed) in keys {
+					match self.get_value(key) {
+						Some((value, time)) if !removed => changes.changed.push((key.clone(), value, time)),
+						_ => changes.removed.push(key.clone()),
+					}
+				}
+				changes
+			},
+			None => ChangesSince{resync: true, changed: Vec::new(), removed: Vec::new()},
+		}
+	}
+	
+	fn get_value(&self, key: &str) -> Option<(StoreValue, Time)>
+	{
+		if let Some(v) = self.int_data.get(key) {
+			Some((StoreValue::Int(v.1), v.0))
+		} else if let Some(v) = self.float_data.get(key) {
+			Some((StoreValue::Float(v.1), v.0))
+		} else if let Some(v) = self.string_data.get(key) {
+			Some((StoreValue::String(v.1.clone()), v.0))
+		} else if let Some(v) = self.bool_data.get(key) {
+			Some((StoreValue::Bool(v.1), v.0))
+		} else {
+			self.list_data.get(key).map(|v| (v.1.clone(), v.0))
+		}
+	}
+	
+	/// Returns the changes made since the last call to this method (or since changes
+	/// were enabled).
+	pub(crate) fn take_changes(&mut self) -> Vec<StoreChange>
+	{
+		match self.changes {
+			Some(ref mut changes) => changes.drain(..).collect(),
+			None => Vec::new(),
+		}
+	}
+	
+	// Value should be one of the list variants.
+	fn set_list(&mut self, key: &str, value: StoreValue, time: Time)
+	{
+		assert!(!key.is_empty(), "key should not be empty");
+		if let Some(new) = self.renamed(key) {
+			return self.set_list(&new, value, time);
+		}
+		let kind = value.kind();
+		let text = list_to_string(&value);
+		if let Some(old) = self.list_data.insert(key.to_string(), (time, value.clone())) {
+			if old.0 == time && !self.take_external(key, time) {
+				panic!("{} key '{}' has already been set", kind, key)
+			}
+			if old.1 != value {
+				self.bump_edition();
+				self.record_change(key, Some(list_to_string(&old.1)), text, kind);
+				if let Some(ref mut history) = self.history {history.record_list(key, time, &value);}
+			}
+		} else {
+			self.bump_edition();
+			self.record_change(key, None, text, kind);
+			if let Some(ref mut history) = self.history {history.record_list(key, time, &value);}
+		}
+	}
+	
+	/// Changes made until end_batch is called share a single edition so clients that
+	/// poll using the edition (e.g. via changes_since) see either all of the changes
+	/// or none of them.
+	pub(crate) fn begin_batch(&mut self)
+	{
+		assert!(self.batch.is_none(), "store batches can't be nested");
+		self.batch = Some(false);
+	}
+	
+	pub(crate) fn end_batch(&mut self)
+	{
+		assert!(self.batch.is_some(), "begin_batch wasn't called");
+		self.batch = None;
+	}
+	
+	fn bump_edition(&mut self)
+	{
+		match self.batch {
+			Some(true) => (),
+			Some(false) => {
+				self.edition += 1;
+				self.batch = Some(true);
+			},
+			None => self.edition += 1,
+		}
+	}
+	
+	fn record_change(&mut self, key: &str, old_value: Option<String>, new_value: String, kind: &str)
+	{
+		if let Some(ref mut log) = self.change_log {
+			log.push(self.edition, key, false);
+		}
+		if let Some(ref mut hash) = self.change_hash {
+			*hash = hash.wrapping_add(hash_change(key, &new_value));
+		}
+		if let Some(ref mut changes) = self.changes {
+			changes.push(StoreChange{key: key.to_string(), old_value, new_value, kind: kind.to_string()});
 		}
 	}
 			
@@ -168,7 +811,7 @@ impl Store
 		for (key, value) in self.float_data.iter() {
 			if !key.contains("display-") {
 				let t = ((value.0).0 as f64)/time_units;
-				println!("   {} = {:.3} @ {:.3$}s", key, value.1, t, precision);
+				println!("   {} = {} @ {:.3$}s", key, float_to_fixed(value.1, 3), t, precision);
 			}
 		}
 		for (key, value) in self.string_data.iter() {
@@ -177,13 +820,96 @@ impl Store
 				println!("   {} = '{}' @ {:.3$}s", key, value.1, t, precision);
 			}
 		}
+		for (key, value) in self.bool_data.iter() {
+			let t = ((value.0).0 as f64)/time_units;
+			println!("   {} = {} @ {:.3$}s", key, value.1, t, precision);
+		}
+		for (key, value) in self.list_data.iter() {
+			let t = ((value.0).0 as f64)/time_units;
+			println!("   {} = {} @ {:.3$}s", key, list_to_string(&value.1), t, precision);
+		}
+	}
+}
+
+// The keys that changed at each edition (oldest first). Like HistoryLog this is keyed
+// by sequence number so that clones share structure and appending and dropping the
+// oldest entry copy O(log n) nodes.
+#[derive(Clone)]
+pub(crate) struct ChangeLog
+{
+	entries: PersistentMap<u64, (u64, String, bool)>,	// sequence number => (edition, key, removed)
+	start: u64,		// sequence number of the oldest entry
+	end: u64,		// sequence number of the next entry
+	capacity: usize,
+	dropped: Option<u64>,	// edition of the newest entry that was dropped
+}
+
+impl ChangeLog
+{
+	pub(crate) fn new(capacity: usize) -> ChangeLog
+	{
+		assert!(capacity > 0);
+		ChangeLog{entries: PersistentMap::new(), start: 0, end: 0, capacity, dropped: None}
+	}
+	
+	fn push(&mut self, edition: u64, key: &str, removed: bool)
+	{
+		if (self.end - self.start) as usize == self.capacity {
+			let oldest = self.entries.remove(&self.start).unwrap();
+			self.dropped = Some(oldest.0);
+			self.start += 1;
+		}
+		self.entries.insert(self.end, (edition, key.to_string(), removed));
+		self.end += 1;
+	}
+	
+	// Returns the keys that changed after edition (sorted by key) and whether the key
+	// was removed by the last change. Returns None if some of those changes were dropped.
+	fn since(&self, edition: u64) -> Option<Vec<(&String, bool)>>
+	{
+		if self.dropped.map_or(false, |dropped| dropped > edition) {
+			return None;
+		}
+		
+		// Editions increase with the sequence numbers so we can binary search for the
+		// first entry after edition.
+		let (mut lo, mut hi) = (self.start, self.end);
+		while lo < hi {
+			let mid = lo + (hi - lo)/2;
+			if self.entries[&mid].0 > edition {hi = mid} else {lo = mid + 1}
+		}
+		
+		let mut keys: BTreeMap<&String, bool> = BTreeMap::new();
+		for seq in lo..self.end {
+			let &(_, ref key, removed) = &self.entries[&seq];
+			keys.insert(key, removed);
+		}
+		Some(keys.into_iter().collect())
 	}
 }
 
+fn move_value<T: Clone>(data: &mut PersistentMap<String, T>, old: &str, new: &str)
+{
+	if let Some(value) = data.remove(old) {
+		if !data.contains_key(new) {
+			data.insert(new.to_string(), value);
+		}
+	}
+}
+
+// Order independent so that the hash doesn't depend on which component's effects
+// are applied first.
+fn hash_change(key: &str, value: &str) -> u64
+{
+	fnv1a(key).wrapping_mul(31).wrapping_add(fnv1a(value))
+}
+
 #[cfg(test)]
 mod tests
 {
 	use super::*;
+	use config::*;
+	use std::panic::{self, AssertUnwindSafe};
 	
 	#[test]
 	#[should_panic(expected = "key 'foo' is missing")]
@@ -212,6 +938,65 @@ mod tests
 		assert_eq!(weight, 130);
 	}
 	
+	#[test]
+	fn records_changes()
+	{
+		let mut store = Store::new();
+		store.changes = Some(Vec::new());
+		store.set_int("weight", 120, Time(0));
+		store.set_int("weight", 120, Time(1));	// same value so no change
+		store.set_int("weight", 130, Time(2));
+		
+		let changes = store.take_changes();
+		assert_eq!(changes.len(), 2);
+		assert_eq!(changes[0].old_value, None);
+		assert_eq!(changes[1].old_value, Some("120".to_string()));
+		assert_eq!(changes[1].new_value, "130");
+		assert!(store.take_changes().is_empty());
+	}
+	
+	#[test]
+	fn snapshots_are_copy_on_write()
+	{
+		let mut store = Store::new();
+		store.set_int("weight", 120, Time(0));
+		store.set_string("name", "bob", Time(0));
+		
+		let snapshot = store.clone();
+		store.set_int("weight", 130, Time(1));
+		assert_eq!(snapshot.get_int("weight"), 120);
+		assert_eq!(store.get_int("weight"), 130);
+		assert!(snapshot.string_data.ptr_eq(&store.string_data));	// untouched maps are still shared
+	}
+	
+	#[test]
+	fn write_cost_doesnt_grow_with_the_store()
+	{
+		// Count the nodes a write copies while a snapshot is alive.
+		let copied = |count: i64| {
+			let policies = vec![(Pattern::new("*").unwrap(), RetentionPolicy::All)];
+			let mut store = Store::new();
+			store.history = Some(StoreHistory::new(&policies, &Config::new()));
+			for i in 0..count {
+				store.set_int(&format!("key{}", i), i, Time(0));
+			}
+			
+			let snapshot = store.clone();
+			store.set_int("key0", -1, Time(1));
+			assert_eq!(snapshot.get_int("key0"), 0);
+			assert_eq!(store.get_int("key0"), -1);
+			
+			let (history, old_history) = (store.history.as_ref().unwrap(), snapshot.history.as_ref().unwrap());
+			(store.int_data.unshared_nodes(&snapshot.int_data), history.ints.unshared_nodes(&old_history.ints))
+		};
+		
+		// Depth is logarithmic (base 32) so 10x and 100x the keys adds at most a level or two.
+		let (small, small_history) = copied(1000);
+		let (large, large_history) = copied(100000);
+		assert!(small <= 4 && large <= 6, "{} and {} nodes were copied", small, large);
+		assert!(small_history <= 4 && large_history <= 6, "{} and {} history nodes were copied", small_history, large_history);
+	}
+	
 	#[test]
 	#[should_panic(expected = "already been set")]
 	fn changing_value()
@@ -220,4 +1005,154 @@ mod tests
 		store.set_int("weight", 120, Time(1));
 		store.set_int("weight", 130, Time(1));
 	}
+	
+	#[test]
+	fn changes_since_edition()
+	{
+		let mut store = Store::new();
+		store.change_log = Some(ChangeLog::new(4));
+		store.set_int("world.bot.energy", 10, Time(1));
+		store.set_string("world.bot.state", "idle", Time(1));
+		let edition = store.edition();
+		
+		store.set_int("world.bot.energy", 9, Time(2));
+		store.set_string("world.bot.state", "idle", Time(2));	// unchanged
+		store.set_bool("world.bot.alive", true, Time(2));
+		let changes = store.changes_since(edition);
+		assert!(!changes.resync);
+		assert_eq!(changes.changed, vec![
+			("world.bot.alive".to_string(), StoreValue::Bool(true), Time(2)),
+			("world.bot.energy".to_string(), StoreValue::Int(9), Time(2)),
+		]);
+		assert!(store.changes_since(store.edition()).changed.is_empty());
+		assert_eq!(store.changes_since(0).changed.len(), 3);
+		assert!(store.changes_since(store.edition() + 1).resync);
+		
+		let edition = store.edition();
+		store.rename_key("world.bot.state", "world.bot.mode");
+		let changes = store.changes_since(edition);
+		assert_eq!(changes.changed, vec![("world.bot.mode".to_string(), StoreValue::String("idle".to_string()), Time(2))]);
+		assert_eq!(changes.removed, vec!["world.bot.state".to_string()]);
+		
+		assert!(store.changes_since(0).resync);	// only the last 4 changes are retained
+		assert!(!store.changes_since(edition - 1).resync);
+	}
+	
+	#[test]
+	fn batches_share_an_edition()
+	{
+		let mut store = Store::new();
+		store.change_log = Some(ChangeLog::new(100));
+		store.set_int("world.bot.energy", 10, Time(1));
+		let edition = store.edition();
+		
+		store.begin_batch();
+		store.set_int("world.bot.energy", 9, Time(2));
+		store.set_float("world.bot.x", 1.5, Time(2));
+		store.set_float("world.bot.y", 2.5, Time(2));
+		store.end_batch();
+		assert_eq!(store.edition(), edition + 1);
+		assert_eq!(store.changes_since(edition).changed.len(), 3);
+		
+		store.begin_batch();	// batches without changes don't bump the edition
+		store.set_int("world.bot.energy", 9, Time(3));
+		store.end_batch();
+		assert_eq!(store.edition(), edition + 1);
+	}
+	
+	#[test]
+	fn external_writes_dont_conflict()
+	{
+		let mut store = Store::new();
+		store.set_int("weight", 120, Time(1));
+		store.set_external("weight", StoreValue::Int(125), Time(1)).unwrap();
+		assert_eq!(store.get_int("weight"), 125);
+		
+		store.set_int("weight", 130, Time(1));	// a component can still write the key
+		assert_eq!(store.get_int("weight"), 130);
+		let result = panic::catch_unwind(AssertUnwindSafe(|| store.set_int("weight", 135, Time(1))));
+		assert!(result.is_err(), "only one component write should be exempt");
+		assert!(store.set_external("weight", StoreValue::Float(1.0), Time(1)).is_err());
+		assert!(store.set_external("", StoreValue::Int(1), Time(1)).is_err());
+	}
+	
+	#[test]
+	fn query()
+	{
+		let mut store = Store::new();
+		store.set_int("world.sender.tx", 10, Time(1));
+		store.set_float("world.sender.rate", 2.5, Time(2));
+		store.set_string("world.receiver.state", "idle", Time(3));
+		
+		let values = store.query(&Pattern::new("world.sender.*").unwrap());
+		assert_eq!(values, vec![
+			("world.sender.rate".to_string(), StoreValue::Float(2.5), Time(2)),
+			("world.sender.tx".to_string(), StoreValue::Int(10), Time(1)),
+		]);
+		assert!(store.query(&Pattern::new("*.bogus").unwrap()).is_empty());
+	}
+	
+	#[test]
+	fn renamed_keys_keep_their_history()
+	{
+		let policies = vec![(Pattern::new("*").unwrap(), RetentionPolicy::All)];
+		let mut store = Store::new();
+		store.history = Some(StoreHistory::new(&policies, &Config::new()));
+		store.set_int("bot.power", 10, Time(0));
+		store.set_int("bot.power", 8, Time(1));
+		
+		store.rename_key("bot.power", "bot.energy");
+		store.set_int("bot.power", 5, Time(2));		// writes via the alias go to the new key
+		assert_eq!(store.get_int("bot.energy"), 5);
+		assert_eq!(store.get_int("bot.power"), 5);
+		assert_eq!(store.int_history("bot.energy"), vec![(Time(0), 10), (Time(1), 8), (Time(2), 5)]);
+		
+		store.rename_key("bot.energy", "bot.charge");
+		assert_eq!(store.renamed("bot.power"), Some("bot.charge".to_string()));
+		assert_eq!(store.query(&Pattern::new("bot.*").unwrap()), vec![("bot.charge".to_string(), StoreValue::Int(5), Time(2))]);
+	}
+	
+	#[test]
+	fn bools_and_lists()
+	{
+		let mut store = Store::new();
+		store.changes = Some(Vec::new());
+		store.set_bool("link.up", true, Time(0));
+		store.set_vec_int("link.queue", &[3, 1], Time(0));
+		store.set_vec_string("link.names", &["a".to_string()], Time(0));
+		store.set_vec_int("link.queue", &[1], Time(1));
+		
+		assert!(store.get_bool("link.up"));
+		assert_eq!(store.get_vec_int("link.queue"), vec![1]);
+		assert_eq!(store.get_vec_string("link.names"), vec!["a".to_string()]);
+		
+		let changes = store.take_changes();
+		assert_eq!(changes[1].new_value, "[3,1]");
+		assert_eq!((changes[3].old_value.clone(), changes[3].kind.as_ref()), (Some("[3,1]".to_string()), "int-list"));
+		assert_eq!(store.query(&Pattern::new("link.up").unwrap()), vec![("link.up".to_string(), StoreValue::Bool(true), Time(0))]);
+	}
+	
+	#[test]
+	fn bools_and_lists_have_history()
+	{
+		let policies = vec![(Pattern::new("link.*").unwrap(), RetentionPolicy::All)];
+		let mut store = Store::new();
+		store.history = Some(StoreHistory::new(&policies, &Config::new()));
+		store.set_bool("link.up", true, Time(0));
+		store.set_bool("link.up", false, Time(1));
+		store.set_vec_float("link.rates", &[1.0], Time(0));
+		store.set_vec_float("link.rates", &[1.0, 2.0], Time(1));
+		
+		assert_eq!(store.bool_history("link.up"), vec![(Time(0), true), (Time(1), false)]);
+		assert_eq!(store.list_history("link.rates"), vec![(Time(0), StoreValue::FloatList(vec![1.0])), (Time(1), StoreValue::FloatList(vec![1.0, 2.0]))]);
+	}
+	
+	#[test]
+	fn non_finite_list_floats_are_null()
+	{
+		use std::f64::{INFINITY, NAN};
+		let text = list_to_string(&StoreValue::FloatList(vec![1.0, NAN, INFINITY]));
+		assert_eq!(text, "[1.000000,null,null]");
+		assert!(json::Json::from_str(&text).is_ok());
+	}
 }
@@ -0,0 +1,337 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! `Sweep` is used for parameter studies: it runs a simulation for every point in the
+//! cross product of a set of parameter axes and collects the results.
+use formatting::*;
+use run_mode::*;
+use runner::*;
+use sim_time::*;
+use simulation::*;
+use store::*;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// One combination of parameter values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepPoint
+{
+	/// Index of the point within the cross product.
+	pub index: usize,
+
+	/// Axis names and the values to use for this point (in the order the axes were added).
+	pub values: Vec<(String, f64)>,
+}
+
+impl SweepPoint
+{
+	/// Returns the value of the named axis. Panics if there is no such axis.
+	pub fn get(&self, name: &str) -> f64
+	{
+		match self.values.iter().find(|v| v.0 == name) {
+			Some(value) => value.1,
+			None => panic!("sweep has no '{}' axis", name),
+		}
+	}
+
+	/// Returns something like "error_rate=50,num_repeaters=2". This is useful for
+	/// tagging outputs, e.g. the name of a run report.
+	pub fn label(&self) -> String
+	{
+		let parts: Vec<String> = self.values.iter().map(|&(ref name, value)| format!("{}={}", name, float_to_string(value))).collect();
+		parts.join(",")
+	}
+}
+
+/// The results of one replication at one sweep point.
+#[derive(Clone, Debug)]
+pub struct SweepResult
+{
+	pub point: SweepPoint,
+	pub result: RunResult,
+}
+
+/// Runs a simulation for every combination of parameter values. Replications at
+/// different points use the same seeds so that comparisons between points aren't
+/// swamped by random noise. The point's label is written to the
+/// "simulation.metadata.sweep-point" store key before each run.
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+///
+/// fn build(point: &SweepPoint, seed: usize) -> Simulation
+/// {
+/// 	let mut config = Config::with_seed(seed);
+/// 	config.max_secs = 10.0;
+/// 	let _error_rate = point.get("error_rate");
+/// 	let mut sim = Simulation::new(config);
+/// 	// add components here
+/// 	sim
+/// }
+///
+/// fn study()
+/// {
+/// 	let results = Sweep::new(build)
+/// 		.range("error_rate", 50.0, 500.0, 50.0)
+/// 		.range("num_repeaters", 1.0, 10.0, 1.0)
+/// 		.replications(5)
+/// 		.threads(4)
+/// 		.keys(&["world.receiver.err_percent"])
+/// 		.run();
+/// 	write_sweep_csv("sweep.csv", &["world.receiver.err_percent"], &results).unwrap();
+/// }
+/// ```
+pub struct Sweep<F>
+	where F: Fn (&SweepPoint, usize) -> Simulation + Send + Sync + 'static
+{
+	build: Arc<F>,
+	axes: Vec<(String, Vec<f64>)>,
+	replications: usize,
+	base_seed: usize,
+	threads: usize,
+	keys: Vec<String>,
+}
+
+impl<F> Sweep<F>
+	where F: Fn (&SweepPoint, usize) -> Simulation + Send + Sync + 'static
+{
+	/// Build is called with the point to use and the seed to use (which should be
+	/// used to create the [`Config`]).
+	pub fn new(build: F) -> Sweep<F>
+	{
+		Sweep{build: Arc::new(build), axes: Vec::new(), replications: 1, base_seed: 1, threads: 1, keys: Vec::new()}
+	}
+
+	/// Adds an axis with explicit values.
+	pub fn axis(mut self, name: &str, values: &[f64]) -> Sweep<F>
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		assert!(!values.is_empty(), "values should not be empty");
+		assert!(self.axes.iter().all(|a| a.0 != name), "axis '{}' was already added", name);
+		self.axes.push((name.to_string(), values.to_vec()));
+		self
+	}
+
+	/// Adds an axis with values from start to end (inclusive) spaced by step.
+	pub fn range(self, name: &str, start: f64, end: f64, step: f64) -> Sweep<F>
+	{
+		assert!(step > 0.0, "step ({:.3}) is not positive", step);
+		assert!(end >= start, "end ({:.3}) is less than start ({:.3})", end, start);
+		let count = ((end - start)/step + 1.0e-9).floor() as usize + 1;	// computed this way to avoid accumulating rounding errors
+		let values: Vec<f64> = (0..count).map(|i| start + (i as f64)*step).collect();
+		self.axis(name, &values)
+	}
+
+	/// Number of times to run each point. Defaults to 1.
+	pub fn replications(mut self, count: usize) -> Sweep<F>
+	{
+		assert!(count > 0, "count should be positive");
+		self.replications = count;
+		self
+	}
+
+	/// Replication i uses base_seed + i as its seed. Defaults to 1.
+	pub fn base_seed(mut self, seed: usize) -> Sweep<F>
+	{
+		assert!(seed > 0, "seed should be positive (zero means seed with entropy)");
+		self.base_seed = seed;
+		self
+	}
+
+	/// Number of simulations to run at once. Defaults to 1.
+	pub fn threads(mut self, count: usize) -> Sweep<F>
+	{
+		assert!(count > 0, "count should be positive");
+		self.threads = count;
+		self
+	}
+
+	/// Store keys whose final values should be collected.
+	pub fn keys(mut self, keys: &[&str]) -> Sweep<F>
+	{
+		self.keys = keys.iter().map(|k| k.to_string()).collect();
+		self
+	}
+
+	/// Returns the cross product of the axes. The last axis varies fastest.
+	pub fn points(&self) -> Vec<SweepPoint>
+	{
+		let mut points = vec![Vec::new()];
+		for &(ref name, ref values) in self.axes.iter() {
+			points = points.into_iter()
+				.flat_map(|point: Vec<(String, f64)>| values.iter().map(move |&v| {let mut p = point.clone(); p.push((name.clone(), v)); p}))
+				.collect();
+		}
+		points.into_iter().enumerate().map(|(index, values)| SweepPoint{index, values}).collect()
+	}
+
+	/// Runs every replication of every point and returns the results sorted by point
+	/// and then replication.
+	pub fn run(self) -> Vec<SweepResult>
+	{
+		assert!(!self.axes.is_empty(), "sweep has no axes");
+		let points = self.points();
+		let count = points.len()*self.replications;
+		let points = Arc::new(points);
+		let next = Arc::new(Mutex::new(0));
+		let results = Arc::new(Mutex::new(Vec::with_capacity(count)));
+		let keys = Arc::new(self.keys);
+
+		let mut handles = Vec::with_capacity(self.threads);
+		for _ in 0..self.threads {
+			let points = points.clone();
+			let next = next.clone();
+			let results = results.clone();
+			let build = self.build.clone();
+			let keys = keys.clone();
+			let (replications, base_seed) = (self.replications, self.base_seed);
+			handles.push(thread::spawn(move || {
+				loop {
+					let job = {
+						let mut next = next.lock().unwrap();
+						if *next == count {
+							break;
+						}
+						*next += 1;
+						*next - 1
+					};
+
+					let point = &points[job/replications];
+					let replication = job % replications;
+					let seed = base_seed + replication;
+					let mut sim = build(point, seed);
+					Arc::make_mut(&mut sim.store).set_string("simulation.metadata.sweep-point", &point.label(), Time(0));
+					let finger_print = sim.run();
+					let values = keys.iter().map(|k| value_string(&sim.store, k)).collect();
+					let result = RunResult{replication, seed, finger_print, values};
+					results.lock().unwrap().push(SweepResult{point: point.clone(), result});
+				}
+			}));
+		}
+
+		for handle in handles {
+			if let Err(err) = handle.join() {
+				panic!("sweep thread panicked: {:?}", err);
+			}
+		}
+
+		let mut results = Arc::try_unwrap(results).unwrap().into_inner().unwrap();
+		results.sort_by_key(|r| (r.point.index, r.result.replication));
+		results
+	}
+}
+
+/// Writes a CSV file with a column for each axis, replication, seed, and finger print
+/// columns, and a column for each key (keys should be the same as those passed into
+/// [`Sweep`]'s keys method).
+pub fn write_sweep_csv(path: &str, keys: &[&str], results: &Vec<SweepResult>) -> io::Result<()>
+{
+	let mut file = File::create(path)?;
+	file.write_all(sweep_csv(keys, results).as_bytes())
+}
+
+/// Returns the mean and 95% confidence interval of a key across the replications of
+/// each point. Index is the index of the key within the keys passed into [`Sweep`]'s
+/// keys method.
+pub fn sweep_estimates(results: &Vec<SweepResult>, index: usize) -> Vec<(SweepPoint, Estimate)>
+{
+	let mut estimates = Vec::new();
+	let mut start = 0;
+	while start < results.len() {
+		let point = &results[start].point;
+		let end = start + results[start..].iter().take_while(|r| r.point.index == point.index).count();
+		let runs: Vec<RunResult> = results[start..end].iter().map(|r| r.result.clone()).collect();
+		estimates.push((point.clone(), replication_estimate(&runs, index)));
+		start = end;
+	}
+	estimates
+}
+
+fn sweep_csv(keys: &[&str], results: &Vec<SweepResult>) -> String
+{
+	let mut text = String::new();
+	if let Some(first) = results.first() {
+		for &(ref name, _) in first.point.values.iter() {
+			text += &csv_cell(name);
+			text += ",";
+		}
+	}
+	text += "replication,seed,finger_print";
+	for key in keys.iter() {
+		text += ",";
+		text += &csv_cell(key);
+	}
+	text += "\n";
+
+	for r in results.iter() {
+		for &(_, value) in r.point.values.iter() {
+			text += &float_to_string(value);
+			text += ",";
+		}
+		text += &format!("{},{},{:X}", r.result.replication, r.result.seed, r.result.finger_print);
+		for value in r.result.values.iter() {
+			text += ",";
+			if let Some(ref value) = *value {
+				text += &csv_cell(value);
+			}
+		}
+		text += "\n";
+	}
+	text
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use component::*;
+	use config::*;
+
+	fn build(point: &SweepPoint, seed: usize) -> Simulation
+	{
+		let mut sim = Simulation::new(Config::with_seed(seed));
+		sim.add_handler_component("world", NO_COMPONENT, |_, _, _| ());
+		Arc::make_mut(&mut sim.store).set_float("rate", point.get("rate")*point.get("count"), Time(0));
+		sim
+	}
+
+	#[test]
+	fn runs_cross_product()
+	{
+		let sweep = Sweep::new(build).range("rate", 50.0, 150.0, 50.0).axis("count", &[1.0, 2.0]).replications(2).threads(3).keys(&["rate"]);
+		let points = sweep.points();
+		assert_eq!(points.len(), 6);
+		assert_eq!(points[1].label(), "rate=50,count=2");
+		assert_eq!(points[5].label(), "rate=150,count=2");
+
+		let results = sweep.run();
+		assert_eq!(results.len(), 12);
+		assert_eq!((results[3].point.index, results[3].result.replication, results[3].result.seed), (1, 1, 2));
+		assert_eq!(results[3].result.values, vec![Some("100".to_string())]);
+
+		let estimates = sweep_estimates(&results, 0);
+		assert_eq!(estimates.len(), 6);
+		assert_eq!((estimates[5].1.mean, estimates[5].1.count), (300.0, 2));
+
+		let text = sweep_csv(&["rate"], &results[0..1].to_vec());
+		assert_eq!(text, format!("rate,count,replication,seed,finger_print,rate\n50,1,0,1,{:X},50\n", results[0].result.finger_print));
+	}
+}
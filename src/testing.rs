@@ -0,0 +1,35 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! The [`TestBench`] and [`Effects`] types used to test a single component in isolation.
+//! These are defined in testkit (alongside [`TestSim`]) and are re-exported here and
+//! from the crate root.
+//!
+//! # Examples
+//!
+//! ```
+//! use score::Event;
+//! use score::testing::TestBench;
+//!
+//! let mut bench = TestBench::new("echo", |event, _, effector| {
+//! 	if event.name == "ping" {
+//! 		effector.set_string("last", "ping");
+//! 	}
+//! });
+//! bench.push(Event::new("ping"), 1.0);
+//! assert!(bench.run().iter().any(|e| e.event == "ping" && !e.store.is_empty()));
+//! ```
+pub use testkit::{Effects, TestBench};
@@ -0,0 +1,369 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Support for deterministic tests (and runnable documentation examples). [`TestSim`]
+//! wraps a `Simulation` using a fixed seed and components whose handlers run on the
+//! simulation thread so tests don't need to spin up threads. [`TestBench`] hosts a
+//! single component and records what it did in response to each event (these are also
+//! available from the testing module).
+use component::*;
+use config::*;
+use effector::*;
+use event::*;
+use logging::*;
+use sim_component::*;
+use sim_state::*;
+use simulation::*;
+use store::*;
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use thread_data::*;
+
+/// A small synchronous simulation for tests.
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+///
+/// let mut test = TestSim::new();
+/// let receiver = test.add("receiver", |event, _, effector| {
+/// 	if event.name == "ping" {
+/// 		effector.counter("pings").inc(1);
+/// 	}
+/// });
+///
+/// let mut output: OutPort<()> = OutPort::new();
+/// output.connect_to(&InPort::new(receiver));
+/// test.add("sender", move |event, _, effector| {
+/// 	if event.name == "init 0" {
+/// 		output.send(effector, "ping");
+/// 		output.send_after_secs(effector, "ping", 2.0);
+/// 	}
+/// });
+///
+/// test.run();
+/// assert_eq!(test.int("world.receiver.pings"), 2);
+/// assert_eq!(test.time(), 2.0);
+/// ```
+pub struct TestSim
+{
+	pub sim: Simulation,
+
+	/// The root component: components added via add are children of this.
+	pub world: ComponentID,
+}
+
+impl TestSim
+{
+	/// Uses seed 1, sequential dispatch, and only logs errors.
+	pub fn new() -> TestSim
+	{
+		let mut config = Config::with_seed(1);
+		config.dispatch = DispatchStrategy::Sequential;
+		config.log_level = LogLevel::Error;
+		TestSim::with_config(config)
+	}
+
+	pub fn with_config(config: Config) -> TestSim
+	{
+		let mut sim = Simulation::new(config);
+		let world = sim.add_component("world", NO_COMPONENT);
+		TestSim{sim, world}
+	}
+
+	/// Adds a component under world whose events are processed by handler. Note that,
+	/// unlike process_events, events the handler doesn't care about are ignored.
+	pub fn add<H>(&mut self, name: &str, handler: H) -> ComponentID
+		where H: FnMut (Event, &SimState, &mut Effector) -> () + Send + 'static
+	{
+		let world = self.world;
+		self.sim.add_handler_component(name, world, handler)
+	}
+
+	/// Schedules an event to be sent to a component secs after the run starts. If secs
+	/// is zero the event is sent right after initialization.
+	pub fn send(&mut self, event: Event, to: ComponentID, secs: f64)
+	{
+		let mut effector = Effector::new();
+		if secs > 0.0 {
+			effector.schedule_after_secs(event, to, secs);
+		} else {
+			effector.schedule_immediately(event, to);
+		}
+		let world = self.world;
+		self.sim.apply(world, effector);
+	}
+
+	/// Runs until there are no more events (or config.max_secs is reached) and returns
+	/// the run's finger print.
+	pub fn run(&mut self) -> u64
+	{
+		self.sim.run()
+	}
+
+	/// Seconds into the run.
+	pub fn time(&self) -> f64
+	{
+		self.sim.current_secs()
+	}
+
+	pub fn store(&self) -> &Store
+	{
+		&self.sim.store
+	}
+
+	pub fn int(&self, key: &str) -> i64
+	{
+		self.sim.store.get_int(key)
+	}
+
+	pub fn float(&self, key: &str) -> f64
+	{
+		self.sim.store.get_float(key)
+	}
+
+	pub fn string(&self, key: &str) -> String
+	{
+		self.sim.store.get_string(key)
+	}
+}
+
+/// What a [`TestBench`] component did when it processed an event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Effects
+{
+	/// Seconds into the run.
+	pub time: f64,
+
+	/// Name of the event that was processed.
+	pub event: String,
+
+	pub logs: Vec<(LogLevel, String)>,
+
+	/// Keys are relative to the component, e.g. "count".
+	pub store: Vec<(String, StoreValue)>,
+
+	/// Target, event name, and delay in seconds.
+	pub events: Vec<(ComponentID, String, f64)>,
+}
+
+/// Hosts a single component so that it can be tested without building a model around
+/// it. Events pushed by the test are sent to the component and run returns the effects
+/// of each event the component processed (including the init events). Events sent by
+/// the component should go to itself or to peer (which ignores them).
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+///
+/// let mut bench = TestBench::new("counter", |event, state, effector| {
+/// 	if event.name == "tick" {
+/// 		let count = if state.store.contains("world.counter.count") {state.store.get_int("world.counter.count")} else {0};
+/// 		effector.set_int("count", count + 1);
+/// 	}
+/// });
+/// bench.push(Event::new("tick"), 1.0);
+/// bench.push(Event::new("tick"), 2.0);
+///
+/// let effects: Vec<Effects> = bench.run().into_iter().filter(|e| e.event == "tick").collect();
+/// assert_eq!(effects.len(), 2);
+/// assert_eq!(effects[1].time, 2.0);
+/// assert_eq!(effects[1].store, vec![("count".to_string(), StoreValue::Int(2))]);
+/// ```
+pub struct TestBench
+{
+	pub test: TestSim,
+
+	/// The component being tested, "world.<name>".
+	pub id: ComponentID,
+
+	/// Component that ignores the events sent to it, "world.peer".
+	pub peer: ComponentID,
+
+	effects: Arc<Mutex<Vec<Effects>>>,
+}
+
+impl TestBench
+{
+	/// Uses the same config as [`TestSim`]'s new method.
+	pub fn new<H>(name: &str, mut handler: H) -> TestBench
+		where H: FnMut (Event, &SimState, &mut Effector) -> () + Send + 'static
+	{
+		let mut test = TestSim::new();
+		let effects = Arc::new(Mutex::new(Vec::new()));
+		let recorded = effects.clone();
+		let id = test.add(name, move |event, state, effector| {
+			let name = event.name.clone();
+			handler(event, state, effector);
+			recorded.lock().unwrap().push(summarize(&name, state.time, effector));
+		});
+		let peer = test.add("peer", |_, _, _| ());
+		TestBench{test, id, peer, effects}
+	}
+
+	/// Hosts an active component with its own thread. Start is called like it is for
+	/// [`SimulationBuilder`]'s active method and should spin up the component's thread.
+	pub fn with_thread<F>(name: &str, start: F) -> TestBench
+		where F: FnOnce (ThreadData)
+	{
+		let mut test = TestSim::new();
+		let world = test.world;
+		let (id, data) = test.sim.add_active_component(name, world);
+
+		// The component's thread talks to a proxy thread which records the effects before
+		// passing them along to the simulation.
+		let (event_tx, event_rx) = mpsc::channel();
+		let (effector_tx, effector_rx) = mpsc::channel();
+		start(ThreadData::new(id, event_rx, effector_tx, data.seed, data.rng, data.panic_on_unhandled));
+
+		let effects = Arc::new(Mutex::new(Vec::new()));
+		let recorded = effects.clone();
+		let (rx, tx) = (data.rx, data.tx);
+		thread::spawn(move || {
+			for (event, state) in rx.iter() {
+				let (name, time) = (event.name.to_string(), state.time);
+				if event_tx.send((event, state)).is_err() {
+					break;
+				}
+				let effector = match effector_rx.recv() {
+					Ok(effector) => effector,
+					Err(_) => break,
+				};
+				recorded.lock().unwrap().push(summarize(&name, time, &effector));
+				let _ = tx.send(effector);
+			}
+		});
+		let peer = test.add("peer", |_, _, _| ());
+		TestBench{test, id, peer, effects}
+	}
+
+	/// Hosts a component written using [`SimComponent`].
+	pub fn with_component<C: SimComponent>(name: &str, mut component: C) -> TestBench
+	{
+		TestBench::new(name, move |event, state, effector| component.on_event(&event, state, effector))
+	}
+
+	/// Sends event to the component secs after the run starts (zero secs means right
+	/// after initialization).
+	pub fn push(&mut self, event: Event, secs: f64)
+	{
+		let id = self.id;
+		self.test.send(event, id, secs);
+	}
+
+	/// Runs until there are no more events and returns the effects of each event the
+	/// component processed (in dispatch order).
+	pub fn run(&mut self) -> Vec<Effects>
+	{
+		self.test.run();
+		mem::replace(&mut *self.effects.lock().unwrap(), Vec::new())
+	}
+}
+
+fn summarize(name: &str, time: f64, effector: &Effector) -> Effects
+{
+	Effects {
+		time,
+		event: name.to_string(),
+		logs: effector.logs.iter().map(|r| (r.level, r.message.clone())).collect(),
+		store: effector.store.query_with(|_| true).into_iter().map(|(key, value, _)| (key, value)).collect(),
+		events: effector.events.iter().map(|&(to, ref event, secs)| (to, event.name.to_string(), secs)).collect(),
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn counting_run() -> (u64, i64)
+	{
+		let mut test = TestSim::new();
+		let counter = test.add("counter", |event, state, effector| {
+			if event.name == "tick" {
+				let count = if state.store.contains("world.counter.ticks") {state.store.get_int("world.counter.ticks")} else {0};
+				effector.set_int("ticks", count + 1);
+			}
+		});
+		for i in 0..5 {
+			test.send(Event::new("tick"), counter, i as f64);
+		}
+		let finger_print = test.run();
+		(finger_print, test.int("world.counter.ticks"))
+	}
+
+	struct Echo;
+
+	impl SimComponent for Echo
+	{
+		fn on_event(&mut self, event: &Event, _state: &SimState, effector: &mut Effector)
+		{
+			if event.name == "ping" {
+				log_info!(effector, "echoing");
+				effector.schedule_after_secs(Event::new("pong"), ComponentID(2), 0.5);
+			}
+		}
+	}
+
+	#[test]
+	fn bench_records_effects()
+	{
+		let mut bench = TestBench::with_component("echo", Echo);
+		assert_eq!(bench.peer, ComponentID(2));
+		bench.push(Event::new("ping"), 3.0);
+
+		let effects = bench.run();
+		let names: Vec<&str> = effects.iter().map(|e| e.event.as_str()).collect();
+		assert_eq!(names, vec!["init 0", "ping"]);
+		assert_eq!(effects[1], Effects{time: 3.0, event: "ping".to_string(), logs: vec![(LogLevel::Info, "echoing".to_string())], store: Vec::new(), events: vec![(ComponentID(2), "pong".to_string(), 0.5)]});
+	}
+
+	#[test]
+	fn bench_hosts_threads()
+	{
+		let mut bench = TestBench::with_thread("counter", |data| {
+			thread::spawn(move || {
+				let mut count = 0;
+				process_events!(data, event, state, effector,
+					"tick" => {
+						count += 1;
+						effector.set_int("count", count);
+					}
+				);
+			});
+		});
+		bench.push(Event::new("tick"), 1.0);
+		bench.push(Event::new("tick"), 2.0);
+
+		let effects = bench.run();
+		let names: Vec<&str> = effects.iter().map(|e| e.event.as_str()).collect();
+		assert_eq!(names, vec!["init 0", "tick", "tick"]);
+		assert_eq!(effects[2].time, 2.0);
+		assert_eq!(effects[2].store, vec![("count".to_string(), StoreValue::Int(2))]);
+		assert_eq!(bench.test.int("world.counter.count"), 2);
+	}
+
+	#[test]
+	fn runs_are_reproducible()
+	{
+		let first = counting_run();
+		assert_eq!(first.1, 5);
+		assert_eq!(first, counting_run());
+	}
+}
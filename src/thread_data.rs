@@ -16,6 +16,7 @@
 use component::*;
 use effector::*;
 use event::*;
+use sim_rng::*;
 use sim_state::*;
 use std::sync::mpsc;
 
@@ -35,7 +36,8 @@ pub struct ThreadData
 	
 	/// In order to have deterministic simuluations randomness has to be carefully
 	/// controlled. Each component thread is given its own random number generator
-	/// seed which should be the only source of randomness used by the thread.
+	/// which should be the only source of randomness used by the thread. Use
+	/// stream to split off independent generators for different purposes.
 	///
 	/// # Examples
 	///
@@ -44,13 +46,13 @@ pub struct ThreadData
 	/// extern crate score;
 	/// extern crate rand;
 	///
-	/// use rand::{Rng, SeedableRng, StdRng};
+	/// use rand::Rng;
 	/// use score::*;
 	/// use std::thread;
 	///
 	/// fn component_thread(data: ThreadData)
 	/// {
-	/// 	let mut rng = StdRng::from_seed(&[data.seed]);
+	/// 	let mut rng = data.rng.stream("coin");
 	/// 	thread::spawn(move || {
 	/// 		process_events!(data, event, state, effector,
 	/// 			"init 0" => {
@@ -66,13 +68,21 @@ pub struct ThreadData
 	/// # fn main() {
 	/// # }
 	/// ```
-	pub seed: usize,	// TODO: document stuff to be careful of, eg HashMap iteration
+	pub rng: SimRng,	// TODO: document stuff to be careful of, eg HashMap iteration
+	
+	/// Seed based on the simulation seed and the component's index. Prefer rng which
+	/// is based on the component's path and so isn't affected by adding components.
+	pub seed: usize,
+	
+	/// Copied from config.panic_on_unhandled. If set process_events! panics (on the
+	/// component's thread) when an event doesn't match any of its arms.
+	pub panic_on_unhandled: bool,
 }
 
 impl ThreadData
 {
-	pub(crate) fn new(id: ComponentID, rx: mpsc::Receiver<(Event, SimState)>, tx: mpsc::Sender<Effector>, seed: usize) -> ThreadData
+	pub(crate) fn new(id: ComponentID, rx: mpsc::Receiver<(Event, SimState)>, tx: mpsc::Sender<Effector>, seed: usize, rng: SimRng, panic_on_unhandled: bool) -> ThreadData
 	{
-		ThreadData{id, rx, tx, seed: seed}
+		ThreadData{id, rx, tx, rng, seed: seed, panic_on_unhandled}
 	}
 }
\ No newline at end of file
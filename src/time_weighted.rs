@@ -0,0 +1,126 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Time weighted statistics for store values. For something like a queue length
+//! the mean of the samples is misleading because a value that persists for an hour
+//! counts the same as one that lasts a microsecond. Use the `Simulation`'s
+//! track_time_weighted method to have the store keys matching a glob tracked and
+//! summarized when the simulation exits.
+use formatting::*;
+use std::f64;
+
+/// Accumulates the area under a step function: each value is assumed to persist
+/// until the next value is recorded.
+#[derive(Clone, Debug)]
+pub struct TimeWeighted
+{
+	threshold: f64,
+	start: f64,
+	last: Option<(f64, f64)>,	// time, value
+	area: f64,
+	max: f64,
+	above: f64,
+}
+
+/// Returned by [`TimeWeighted`]'s summary method.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeWeightedSummary
+{
+	/// Values weighted by how long they persisted.
+	pub mean: f64,
+
+	pub max: f64,
+
+	/// Number of seconds the value was larger than the threshold.
+	pub above_secs: f64,
+}
+
+impl TimeWeighted
+{
+	/// Threshold is used to compute how long the value was above it.
+	pub fn new(threshold: f64) -> TimeWeighted
+	{
+		TimeWeighted{threshold, start: 0.0, last: None, area: 0.0, max: f64::NEG_INFINITY, above: 0.0}
+	}
+
+	/// Time is in seconds and should not go backwards.
+	pub fn record(&mut self, time: f64, value: f64)
+	{
+		match self.last {
+			Some((last_time, _)) => {
+				assert!(time >= last_time, "time ({:.3}) is before the last time ({:.3})", time, last_time);
+				self.accumulate(time);
+			},
+			None => self.start = time,
+		}
+		self.last = Some((time, value));
+		self.max = self.max.max(value);
+	}
+
+	/// Returns None if nothing was recorded. Otherwise the last value is assumed to
+	/// persist until time.
+	pub fn summary(&self, time: f64) -> Option<TimeWeightedSummary>
+	{
+		let mut copy = self.clone();
+		copy.accumulate(time);
+		copy.last.map(|_| {
+			let elapsed = time - copy.start;
+			let mean = if elapsed > 0.0 {copy.area/elapsed} else {copy.last.unwrap().1};
+			TimeWeightedSummary{mean, max: copy.max, above_secs: copy.above}
+		})
+	}
+
+	fn accumulate(&mut self, time: f64)
+	{
+		if let Some((last_time, value)) = self.last {
+			let elapsed = (time - last_time).max(0.0);
+			self.area += value*elapsed;
+			if value > self.threshold {
+				self.above += elapsed;
+			}
+			self.last = Some((time, value));
+		}
+	}
+}
+
+impl TimeWeightedSummary
+{
+	pub(crate) fn describe(&self, key: &str, threshold: f64) -> String
+	{
+		format!("{} time-weighted mean = {}, max = {}, above {} for {}s", key,
+			float_to_string(self.mean), float_to_string(self.max), float_to_string(threshold), float_to_string(self.above_secs))
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn weights_by_duration()
+	{
+		let mut tracker = TimeWeighted::new(2.0);
+		assert_eq!(tracker.summary(1.0), None);
+
+		tracker.record(1.0, 1.0);
+		tracker.record(2.0, 5.0);	// 1 for 1s
+		tracker.record(2.0, 3.0);	// 5 for no time
+		tracker.record(5.0, 0.0);	// 3 for 3s
+		let summary = tracker.summary(11.0).unwrap();	// 0 for 6s
+		assert_eq!(summary, TimeWeightedSummary{mean: 1.0, max: 5.0, above_secs: 3.0});
+	}
+}
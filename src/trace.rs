@@ -0,0 +1,131 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use provenance::*;
+use rustc_serialize::{json, Decodable, Encodable};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+/// Traces are written as JSON lines, a `{"provenance":{...}}` header followed by
+/// one line per dispatched event.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub(crate) struct TraceEntry
+{
+	pub(crate) slice: u64,	// events dispatched together have the same slice number
+	pub(crate) time: i64,	// in ticks
+	pub(crate) to: String,	// component path
+	pub(crate) name: String,
+	pub(crate) port: String,
+	pub(crate) payload_type: Option<String>,	// "unregistered" if the payload type wasn't registered with register_traced_payload
+	pub(crate) payload_version: Option<u32>,	// None for traces written before payloads were versioned (treated as version 1)
+	pub(crate) payload: Option<String>,		// JSON
+	pub(crate) description: Option<String>,	// from PayloadDebug, the JSON, or the type name (informational only)
+}
+
+pub(crate) struct TraceWriter
+{
+	pub(crate) path: String,
+	writer: BufWriter<File>,
+}
+
+impl TraceWriter
+{
+	pub(crate) fn new(path: &str, provenance: &Provenance) -> Result<TraceWriter, String>
+	{
+		let file = File::create(path).map_err(|err| format!("Couldn't create {}: {}", path, err))?;
+		let mut writer = TraceWriter{path: path.to_string(), writer: BufWriter::new(file)};
+		writer.write(&JsonHeader{provenance})?;
+		Ok(writer)
+	}
+
+	// Also used to write store deltas, see Simulation::record_deltas.
+	pub(crate) fn write<T: Encodable>(&mut self, entry: &T) -> Result<(), String>
+	{
+		let line = json::encode(entry).unwrap() + "\n";
+		self.writer.write_all(line.as_bytes()).map_err(|err| format!("Couldn't write to {}: {}", self.path, err))
+	}
+
+	pub(crate) fn flush(&mut self)
+	{
+		let _ = self.writer.flush();
+	}
+}
+
+#[derive(RustcEncodable)]
+struct JsonHeader<'a>
+{
+	provenance: &'a Provenance,
+}
+
+pub(crate) fn read_trace(path: &str) -> Result<Vec<TraceEntry>, String>
+{
+	read_json_lines(path)
+}
+
+// The provenance header (if present) is skipped.
+pub(crate) fn read_json_lines<T: Decodable>(path: &str) -> Result<Vec<T>, String>
+{
+	let file = File::open(path).map_err(|err| format!("Couldn't open {}: {}", path, err))?;
+	let mut entries = Vec::new();
+	for (i, line) in BufReader::new(file).lines().enumerate() {
+		let line = line.map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+		if !line.trim().is_empty() && !line.starts_with("{\"provenance\":") {
+			let entry = json::decode(&line).map_err(|err| format!("{} line {}: {}", path, i + 1, err))?;
+			entries.push(entry);
+		}
+	}
+	Ok(entries)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use config::*;
+	use std::env;
+
+	#[test]
+	fn round_trips()
+	{
+		let path = env::temp_dir().join("score-round-trips.trace");
+		let path = path.to_str().unwrap();
+		let entries = vec![
+			TraceEntry{slice: 0, time: 0, to: "world.sender".to_string(), name: "init 0".to_string(), port: "".to_string(), payload_type: None, payload_version: None, payload: None, description: None},
+			TraceEntry{slice: 1, time: 10, to: "world.receiver".to_string(), name: "text".to_string(), port: "input".to_string(), payload_type: Some("string".to_string()), payload_version: Some(1), payload: Some("\"hello\"".to_string()), description: Some("hello".to_string())}];
+
+		{
+			let mut writer = TraceWriter::new(path, &Provenance::new(&Config::with_seed(3), 3)).unwrap();
+			for entry in entries.iter() {
+				writer.write(entry).unwrap();
+			}
+			writer.flush();
+		}
+
+		assert_eq!(read_trace(path).unwrap(), entries);
+		
+		let mut header = String::new();
+		BufReader::new(File::open(path).unwrap()).read_line(&mut header).unwrap();
+		assert!(header.starts_with("{\"provenance\":{"), "header: {}", header);
+		assert!(header.contains("\"seed\":3"), "header: {}", header);
+	}
+
+	#[test]
+	fn reads_unversioned_traces()
+	{
+		let line = r#"{"slice":1,"time":10,"to":"world.receiver","name":"text","port":"input","payload_type":"string","payload":"\"hello\"","description":null}"#;
+		let entry: TraceEntry = json::decode(line).unwrap();
+		assert_eq!(entry.payload_version, None);
+	}
+}
@@ -0,0 +1,231 @@
+// Copyright (C) 2017 Jesse Jones
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 3, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+use component::*;
+use event::*;
+use sim_state::*;
+use sim_time::*;
+use store::*;
+use std::f64::EPSILON;
+
+pub type Constraint = Box<Fn (&SimState) -> Result<(), String> + Send>;
+
+/// Normally [`Component`]s can only change their own state. Transactions allow a
+/// component (typically some sort of coordinator) to change the state of multiple
+/// components and to schedule a group of events with all-or-nothing semantics: when
+/// the transaction is applied by the [`Simulation`] either all of the changes happen
+/// or, if a target component was removed or a constraint fails, none of them do.
+///
+/// # Examples
+///
+/// ```
+/// use score::*;
+///
+/// fn allocate(effector: &mut Effector, pool: ComponentID, client: ComponentID, amount: i64, free: i64)
+/// {
+/// 	let mut tx = Transaction::new();
+/// 	tx.set_int(pool, "free", free - amount);
+/// 	tx.set_int(client, "allocated", amount);
+/// 	tx.schedule_immediately(Event::new("allocated"), client);
+///
+/// 	// Constraints are checked using the state at the time the transaction is applied.
+/// 	tx.require(move |state| if state.get_int(pool, "free") >= amount {Ok(())} else {Err("pool is exhausted".to_string())});
+/// 	tx.on_failure(Event::new("allocation-failed"));
+/// 	effector.commit(tx);
+/// }
+/// ```
+pub struct Transaction
+{
+	pub(crate) stores: Vec<(ComponentID, Store)>,
+	pub(crate) events: Vec<(ComponentID, Event, f64)>,
+	pub(crate) constraints: Vec<Constraint>,
+	pub(crate) failure: Option<Event>,
+}
+
+impl Transaction
+{
+	pub fn new() -> Transaction
+	{
+		Transaction{stores: Vec::new(), events: Vec::new(), constraints: Vec::new(), failure: None}
+	}
+
+	/// Like [`Effector`]'s set_int except that the value is for the id component.
+	pub fn set_int(&mut self, id: ComponentID, name: &str, value: i64)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store(id).set_int(name, value, Time(0));
+	}
+
+	pub fn set_float(&mut self, id: ComponentID, name: &str, value: f64)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store(id).set_float(name, value, Time(0));
+	}
+
+	pub fn set_string(&mut self, id: ComponentID, name: &str, value: &str)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store(id).set_string(name, value, Time(0));
+	}
+
+	pub fn set_bool(&mut self, id: ComponentID, name: &str, value: bool)
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store(id).set_bool(name, value, Time(0));
+	}
+
+	pub fn set_vec_int(&mut self, id: ComponentID, name: &str, value: &[i64])
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store(id).set_vec_int(name, value, Time(0));
+	}
+
+	pub fn set_vec_float(&mut self, id: ComponentID, name: &str, value: &[f64])
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store(id).set_vec_float(name, value, Time(0));
+	}
+
+	pub fn set_vec_string(&mut self, id: ComponentID, name: &str, value: &[String])
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		self.store(id).set_vec_string(name, value, Time(0));
+	}
+
+	/// Dispatch an event to a component after secs time elapses.
+	pub fn schedule_after_secs(&mut self, event: Event, to: ComponentID, secs: f64)
+	{
+		assert!(to != NO_COMPONENT);
+		assert!(secs > 0.0, "secs ({:.3}) is not positive", secs);
+
+		self.events.push((to, event, secs));
+	}
+
+	pub fn schedule_immediately(&mut self, event: Event, to: ComponentID)
+	{
+		assert!(to != NO_COMPONENT);
+
+		self.events.push((to, event, EPSILON));
+	}
+
+	/// Adds a check that must pass for the transaction to be applied. The callback
+	/// should return an error message if the check fails.
+	pub fn require<F>(&mut self, constraint: F)
+		where F: Fn (&SimState) -> Result<(), String> + Send + 'static
+	{
+		self.constraints.push(Box::new(constraint));
+	}
+
+	/// If the transaction is rejected then this event will be sent to the component
+	/// that committed the transaction with a String payload describing why. If this
+	/// isn't used then rejections are only logged.
+	pub fn on_failure(&mut self, event: Event)
+	{
+		self.failure = Some(event);
+	}
+
+	fn store(&mut self, id: ComponentID) -> &mut Store
+	{
+		assert!(id != NO_COMPONENT);
+
+		let index = match self.stores.iter().position(|s| s.0 == id) {
+			Some(index) => index,
+			None => {
+				self.stores.push((id, Store::new()));
+				self.stores.len() - 1
+			}
+		};
+		&mut self.stores[index].1
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use testkit::*;
+
+	// The coordinator uses a transaction to move amount from the pool to the client.
+	fn allocate(free: i64, amount: i64, remove_pool: bool) -> TestSim
+	{
+		let mut test = TestSim::new();
+		let pool = test.add("pool", move |event, _, effector| {
+			match event.name.as_str() {
+				"init 0" => effector.set_int("free", free),
+				"remove" => effector.remove(),
+				_ => (),
+			}
+		});
+		let client = test.add("client", |event, _, effector| {
+			if event.name == "allocated" {
+				effector.counter("allocations").inc(1);
+			}
+		});
+		let coordinator = test.add("coordinator", move |mut event, _, effector| {
+			match event.name.as_str() {
+				"allocate" => {
+					let mut tx = Transaction::new();
+					tx.set_int(pool, "free", free - amount);
+					tx.set_int(client, "allocated", amount);
+					tx.schedule_immediately(Event::new("allocated"), client);
+					tx.require(move |state| if state.get_int(pool, "free") >= amount {Ok(())} else {Err("pool is exhausted".to_string())});
+					tx.on_failure(Event::new("allocation-failed"));
+					effector.commit(tx);
+				},
+				"allocation-failed" => {
+					let reason = event.take_payload::<String>();
+					effector.set_string("rejected", &reason);
+				},
+				_ => (),
+			}
+		});
+		if remove_pool {
+			test.send(Event::new("remove"), pool, 0.5);
+		}
+		test.send(Event::new("allocate"), coordinator, 1.0);
+		test.run();
+		test
+	}
+
+	#[test]
+	fn accepted_transactions_apply_everything()
+	{
+		let test = allocate(10, 4, false);
+		assert_eq!(test.int("world.pool.free"), 6);
+		assert_eq!(test.int("world.client.allocated"), 4);
+		assert_eq!(test.int("world.client.allocations"), 1);
+		assert!(!test.store().contains("world.coordinator.rejected"));
+	}
+
+	#[test]
+	fn failed_constraints_apply_nothing()
+	{
+		let test = allocate(2, 4, false);
+		assert_eq!(test.int("world.pool.free"), 2);
+		assert!(!test.store().contains("world.client.allocated"));
+		assert!(!test.store().contains("world.client.allocations"));
+		assert_eq!(test.string("world.coordinator.rejected"), "pool is exhausted");
+	}
+
+	#[test]
+	fn removed_targets_apply_nothing()
+	{
+		let test = allocate(10, 4, true);
+		assert_eq!(test.int("world.pool.free"), 10);
+		assert!(!test.store().contains("world.client.allocated"));
+		assert!(!test.store().contains("world.client.allocations"));
+		assert_eq!(test.string("world.coordinator.rejected"), "world.pool was removed");
+	}
+}
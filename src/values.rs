@@ -17,47 +17,121 @@
 //! `IntValue` and `FloatValue` and `StringValue` are simple wrappers around an
 //! [`Effector`]. They don't do very much but they assist in creating type safe
 //! [`Component`] structs. See the [`set_value`] macro for an example.
+use component::*;
 use effector::*;
+use sim_state::*;
 
+/// Declares an int valued key in the store for a component.
 pub struct IntValue
 {
+	/// The component the value is associated with.
+	pub id: ComponentID,
+	
+	/// The name of the key (this is appended onto the component's path).
+	pub name: String,
 }
 
 pub struct FloatValue
 {
+	pub id: ComponentID,
+	pub name: String,
 }
 
 pub struct StringValue
 {
+	pub id: ComponentID,
+	pub name: String,
+}
+
+/// Implemented by the value types so that [`SimState`]'s read method can be used to
+/// read them in a type safe way.
+pub trait ReadableValue
+{
+	type Value;
+	
+	fn read_value(&self, state: &SimState) -> Self::Value;
 }
 
 impl IntValue
 {
+	/// Name should normally be the name of the field the value is stored in
+	/// (set_value! relies on this).
+	pub fn new(id: ComponentID, name: &str) -> IntValue
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		IntValue{id, name: name.to_string()}
+	}
+	
 	/// This is normally called via the set_value! macro.
 	pub fn set_value(&self, effector: &mut Effector, name: &str, value: i64)
 	{
+		debug_assert!(name == self.name, "set_value! was used with {} but the value was declared as {}", name, self.name);
 		effector.set_int(name, value);
 	}
 }
 
 impl FloatValue
 {
+	pub fn new(id: ComponentID, name: &str) -> FloatValue
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		FloatValue{id, name: name.to_string()}
+	}
+	
 	/// This is normally called via the set_value! macro.
 	pub fn set_value(&self, effector: &mut Effector, name: &str, value: f64)
 	{
+		debug_assert!(name == self.name, "set_value! was used with {} but the value was declared as {}", name, self.name);
 		effector.set_float(name, value);
 	}
 }
 
 impl StringValue
 {
+	pub fn new(id: ComponentID, name: &str) -> StringValue
+	{
+		assert!(!name.is_empty(), "name should not be empty");
+		StringValue{id, name: name.to_string()}
+	}
+	
 	/// This is normally called via the set_value! macro.
 	pub fn set_value(&self, effector: &mut Effector, name: &str, value: &str)
 	{
+		debug_assert!(name == self.name, "set_value! was used with {} but the value was declared as {}", name, self.name);
 		effector.set_string(name, value);
 	}
 }
 
+impl ReadableValue for IntValue
+{
+	type Value = i64;
+	
+	fn read_value(&self, state: &SimState) -> i64
+	{
+		state.get_int(self.id, &self.name)
+	}
+}
+
+impl ReadableValue for FloatValue
+{
+	type Value = f64;
+	
+	fn read_value(&self, state: &SimState) -> f64
+	{
+		state.get_float(self.id, &self.name)
+	}
+}
+
+impl ReadableValue for StringValue
+{
+	type Value = String;
+	
+	fn read_value(&self, state: &SimState) -> String
+	{
+		state.get_string(self.id, &self.name)
+	}
+}
+
 /// Type safe way to update the simulation [`Store`].
 ///
 /// # Examples
@@ -70,6 +144,11 @@ impl StringValue
 /// 	tx_packets: IntValue,
 /// }
 ///
+/// fn new_iface(id: ComponentID) -> InterfaceComponent
+/// {
+/// 	InterfaceComponent {tx_packets: IntValue::new(id, "tx_packets")}
+/// }
+///
 /// fn reset(iface: InterfaceComponent, mut effector: Effector)
 /// {
 /// 	// The effector is actually the object that is updated.
@@ -77,6 +156,13 @@ impl StringValue
 /// 	// the simulation will apply all the effects from effectors.
 /// 	set_value!(effector, iface.tx_packets = 0);
 /// }
+///
+/// fn sent(iface: &InterfaceComponent, state: &SimState) -> i64
+/// {
+/// 	// Values can also be used to read from the store (the compiler will
+/// 	// catch attempts to read the value as the wrong type).
+/// 	state.read(&iface.tx_packets)
+/// }
 /// ```
 #[macro_export]
 macro_rules! set_value